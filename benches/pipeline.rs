@@ -0,0 +1,135 @@
+//! Benchmarks for the frame pipeline, run with `cargo bench`.
+//!
+//! Synthetic frames are used so the suite runs anywhere, set `FYLVUR_BENCH_VIDEO`
+//! to a video path to also benchmark whole thumbnails and atlases from a real file.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use ffmpeg_next::format::Pixel;
+use ffmpeg_next::software::scaling::{context::Context as ScalingCtx, flag::Flags};
+use ffmpeg_next::util::frame::video::Video as VideoFrame;
+
+use fylvur::{config, math, video};
+
+const SOURCE_WIDTH: u32 = 1920;
+const SOURCE_HEIGHT: u32 = 1080;
+
+/// 1080p yuv420p frame with gradients in every plane
+fn source_frame() -> VideoFrame {
+  let mut frame = VideoFrame::new(Pixel::YUV420P, SOURCE_WIDTH, SOURCE_HEIGHT);
+  for plane in 0..frame.planes() {
+    let stride = frame.stride(plane);
+    for (i, byte) in frame.data_mut(plane).iter_mut().enumerate() {
+      *byte = ((i % stride + i / stride) % 256) as u8;
+    }
+  }
+  frame
+}
+
+fn scale(source: &VideoFrame, width: u32, height: u32, flags: Flags) -> VideoFrame {
+  let mut scaler = ScalingCtx::get(
+    source.format(),
+    source.width(),
+    source.height(),
+    Pixel::RGBA,
+    width,
+    height,
+    flags,
+  ).expect("Could not create scaler");
+  let mut scaled = VideoFrame::empty();
+  scaler.run(source, &mut scaled).expect("Could not scale frame");
+  scaled
+}
+
+fn bench_scaling(c: &mut Criterion) {
+  let source = source_frame();
+  let mut group = c.benchmark_group("scale");
+  group.bench_function("tile_sinc", |b| b.iter(|| scale(&source, 80, 45, Flags::SINC)));
+  group.bench_function("tile_area", |b| b.iter(|| scale(&source, 80, 45, Flags::AREA)));
+  group.bench_function("thumbnail_sinc", |b| b.iter(|| scale(&source, 320, 180, Flags::SINC)));
+  group.bench_function("native_point", |b| {
+    b.iter(|| scale(&source, SOURCE_WIDTH, SOURCE_HEIGHT, Flags::POINT))
+  });
+  group.finish();
+}
+
+fn bench_fix_img_data(c: &mut Criterion) {
+  let source = source_frame();
+  // Odd widths leave the rows padded to the stride
+  let padded = scale(&source, 81, 45, Flags::AREA);
+  let packed = scale(&source, 320, 180, Flags::AREA);
+
+  let mut group = c.benchmark_group("fix_img_data");
+  group.bench_function("padded", |b| {
+    b.iter_batched(|| padded.clone(), |mut frame| video::fix_img_data(&mut frame), BatchSize::SmallInput)
+  });
+  group.bench_function("packed", |b| {
+    b.iter_batched(|| packed.clone(), |mut frame| video::fix_img_data(&mut frame), BatchSize::SmallInput)
+  });
+  group.finish();
+}
+
+fn bench_rotate_frame(c: &mut Criterion) {
+  let frame = scale(&source_frame(), 320, 180, Flags::AREA);
+  // 90 degrees clockwise, with the offset convert_frame sets after scaling
+  let transform = [0, 1, 0, -1, 0, 0, frame.height() as i32 - 1, 0, 1];
+  let mut rotated = VideoFrame::new(Pixel::RGBA, frame.height(), frame.width());
+
+  c.bench_function("rotate_frame", |b| {
+    b.iter(|| math::rotate_frame(black_box(&frame), &mut rotated, &transform))
+  });
+}
+
+fn bench_encode(c: &mut Criterion) {
+  let source = source_frame();
+  let mut tile = scale(&source, 80, 45, Flags::AREA);
+  video::fix_img_data(&mut tile);
+  let mut thumbnail = scale(&source, 320, 180, Flags::SINC);
+  video::fix_img_data(&mut thumbnail);
+
+  let mut group = c.benchmark_group("encode_webp");
+  group.bench_function("tile", |b| b.iter(|| video::encode_webp_from_frame(black_box(&tile))));
+  group.bench_function("thumbnail", |b| {
+    b.iter(|| video::encode_webp_from_frame(black_box(&thumbnail)))
+  });
+  group.finish();
+}
+
+fn bench_fixture(c: &mut Criterion) {
+  let path = match std::env::var_os("FYLVUR_BENCH_VIDEO") {
+    Some(path) => std::path::PathBuf::from(path),
+    None => return,
+  };
+  let mut group = c.benchmark_group("fixture");
+  group.sample_size(10);
+  group.bench_function("thumbnail", |b| {
+    b.iter(|| video::get_video_thumbnail(&path, 320, video::SeekTime::Percentage(0.5), None, video::WEBP_QUALITY))
+  });
+  group.bench_function("atlas", |b| {
+    b.iter(|| {
+      let (layout, format) = (video::TileLayout::RowMajor, video::ImageFormat::Webp);
+      video::get_video_atlas(&path, 0, 1, layout, format, false, video::AtlasPreset::Balanced, None)
+    })
+  });
+  group.finish();
+}
+
+fn setup() -> Criterion {
+  // Decoding limits and retries come from the config, the defaults are what's measured
+  let folder = std::env::temp_dir().join(format!("fylvur-bench-{}", std::process::id()));
+  std::fs::create_dir_all(&folder).expect("Could not create temp dir");
+  let config_path = folder.join("fylvur-cfg.toml");
+  std::fs::write(&config_path, format!(
+    "public_folder = {folder:?}\nmedia_folder = {folder:?}\nhost = \"127.0.0.1\"\nport = 0\ndata_folder = {:?}\n",
+    folder.join("data"),
+  )).expect("Could not write bench config");
+  config::init(config::load(&config_path).expect("Invalid bench config")).expect("Could not set the bench config");
+  video::init().expect("Could not initialize video API");
+  Criterion::default()
+}
+
+criterion_group! {
+  name = benches;
+  config = setup();
+  targets = bench_scaling, bench_fix_img_data, bench_rotate_frame, bench_encode, bench_fixture
+}
+criterion_main!(benches);
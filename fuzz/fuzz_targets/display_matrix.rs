@@ -0,0 +1,25 @@
+#![no_main]
+
+use ffmpeg_next::format::Pixel;
+use ffmpeg_next::util::frame::video::Video as VideoFrame;
+use libfuzzer_sys::fuzz_target;
+
+use fylvur::math;
+
+fuzz_target!(|data: &[u8]| {
+  let Ok(mut matrix) = math::parse_display_matrix(data) else { return };
+  let _ = math::av_display_rotation_get(&matrix);
+
+  // Rotate a small frame the way decode_frame does, sizes come from the input too
+  let width = data.get(36).map_or(8, |n| *n as u32 % 32 + 1);
+  let height = data.get(37).map_or(8, |n| *n as u32 % 32 + 1);
+  let src_frame = VideoFrame::new(Pixel::RGBA, width, height);
+  let mut dst_frame = VideoFrame::new(Pixel::RGBA, height, width);
+  if matrix[6] != 0 {
+    matrix[6] = height as i32 - 1;
+  }
+  if matrix[7] != 0 {
+    matrix[7] = width as i32 - 1;
+  }
+  math::rotate_frame(&src_frame, &mut dst_frame, &matrix);
+});
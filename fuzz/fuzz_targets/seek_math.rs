@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use fylvur::math;
+
+fuzz_target!(|input: (i64, u32, u32, u32, f32)| {
+  let (duration, page, frame_step, tiles_per_page, percentage) = input;
+
+  let (_, count) = math::atlas_tiles(duration, page, frame_step, tiles_per_page);
+  assert!(count <= tiles_per_page as usize);
+
+  assert!(math::seek_position(percentage, duration) >= 0);
+});
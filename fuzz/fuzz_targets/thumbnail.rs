@@ -0,0 +1,36 @@
+#![no_main]
+
+use std::sync::Once;
+
+use libfuzzer_sys::fuzz_target;
+
+use fylvur::{config, video};
+
+static INIT: Once = Once::new();
+
+// Mutated containers go through a file because that's the only input the video API takes
+fuzz_target!(|data: &[u8]| {
+  INIT.call_once(|| {
+    // Decoding limits and retries come from the config
+    let folder = std::env::temp_dir().join(format!("fylvur-fuzz-{}.data", std::process::id()));
+    std::fs::create_dir_all(&folder).expect("Could not create temp dir");
+    let config_path = folder.join("fylvur-cfg.toml");
+    std::fs::write(&config_path, format!(
+      "public_folder = {folder:?}\nmedia_folder = {folder:?}\nhost = \"127.0.0.1\"\nport = 0\ndata_folder = {folder:?}\n",
+    )).expect("Could not write fuzz config");
+    config::init(config::load(&config_path).expect("Invalid fuzz config")).expect("Could not set the fuzz config");
+    video::init().expect("Could not initialize video API");
+    ffmpeg_next::log::set_level(ffmpeg_next::log::Level::Quiet);
+  });
+  let path = std::env::temp_dir().join(format!("fylvur-fuzz-{}", std::process::id()));
+  if std::fs::write(&path, data).is_err() {
+    return
+  }
+
+  let _ = video::get_duration_from_path(&path);
+  let _ = video::get_info(&path);
+  let _ = video::get_video_thumbnail(&path, 64, video::SeekTime::Percentage(0.5), None, video::WEBP_QUALITY);
+  let _ = video::get_video_atlas(
+    &path, 0, 1, video::TileLayout::RowMajor, video::ImageFormat::Webp, false, video::AtlasPreset::Balanced, None,
+  );
+});
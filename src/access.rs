@@ -0,0 +1,258 @@
+use std::any::Any;
+use std::future::{ready, Ready};
+use std::path::{Component, Path};
+use std::sync::OnceLock;
+
+use actix_web::{dev::{Extensions, Payload}, http::header, FromRequest, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::{config, file, session, sidecar, trash, video};
+
+pub const TOKEN_COOKIE: &str = "fylvur_token";
+/// Users with this role can use the admin endpoints
+pub const ADMIN_ROLE: &str = "admin";
+/// Users with this role can only see downscaled and watermarked previews
+pub const GUEST_ROLE: &str = "guest";
+
+static WATERMARK: OnceLock<Option<video::Watermark>> = OnceLock::new();
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct User {
+  pub name: String,
+  /// API key, can be used directly or exchanged for a session
+  #[serde(skip_serializing)]
+  pub token: String,
+  #[serde(skip_serializing)]
+  pub password: Option<String>,
+  #[serde(default)]
+  pub roles: Vec<String>,
+}
+
+impl User {
+  /// Whether `password` can be used to log in as this user
+  pub fn accepts_password(&self, password: &str) -> bool {
+    !password.is_empty() && (self.password.as_deref() == Some(password) || self.token == password)
+  }
+
+  pub fn has_role(&self, role: &str) -> bool {
+    self.roles.iter().any(|name| name == role)
+  }
+}
+
+/// Restriction applied to every media path matching `path`
+///
+/// `path` is a pattern relative to the media folder where `*` and `?` match
+/// within a single path component and `**` matches any number of components
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AccessRule {
+  pub path: String,
+  #[serde(default)]
+  pub hidden: bool,
+  pub role: Option<String>,
+}
+
+impl AccessRule {
+  pub fn matches(&self, path: &Path) -> bool {
+    matches_pattern(&self.path, path)
+  }
+}
+
+/// Whether `path` matches `pattern` the way access rule paths do
+pub fn matches_pattern(pattern: &str, path: &Path) -> bool {
+  let pattern: Vec<&str> = pattern
+  .split('/')
+  .filter(|c| !c.is_empty())
+  .collect();
+  let components: Vec<&str> = path.components().filter_map(|c| match c {
+    Component::Normal(name) => name.to_str(),
+    _ => None,
+  }).collect();
+  match_components(&pattern, &components)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Denied {
+  /// Path is hidden or escapes the media folder, respond as if it didn't exist
+  NotFound,
+  /// Path requires a role and the request is not authenticated
+  Unauthorized,
+  /// Path requires a role the authenticated user doesn't have
+  Forbidden,
+}
+
+impl From<Denied> for HttpResponse {
+  fn from(denied: Denied) -> Self {
+    match denied {
+      Denied::NotFound => HttpResponse::NotFound().finish(),
+      Denied::Unauthorized => HttpResponse::Unauthorized().finish(),
+      Denied::Forbidden => HttpResponse::Forbidden().finish(),
+    }
+  }
+}
+
+/// User making the request, resolved from the `Authorization: Bearer <token>`
+/// header or the `fylvur_token` cookie. The token is either a user's API key
+/// or a session token
+#[derive(Debug, Default, Clone)]
+pub struct Identity {
+  pub user: Option<&'static User>,
+  /// Id of the session used to authenticate, if any
+  pub session: Option<String>,
+  /// Request came through the admin socket, only the server's own machine can reach it
+  pub local: bool,
+}
+
+/// Connection data set on connections accepted from the admin socket
+#[derive(Debug, Clone, Copy)]
+struct AdminSocket;
+
+/// Tells connections to the admin socket apart from the rest, meant for `HttpServer::on_connect`
+pub fn on_connect(connection: &dyn Any, data: &mut Extensions) {
+  #[cfg(unix)]
+  if connection.is::<actix_web::rt::net::UnixStream>() {
+    data.insert(AdminSocket);
+  }
+}
+
+impl Identity {
+  /// Checks whether the media path (relative to the media folder) can be accessed.
+  /// Rules apply to the matched path and everything inside of it, requests made through the
+  /// admin socket have every role. Sidecars are never accessed directly, they'd leak the titles
+  /// and artwork of files the rules hide
+  pub fn check(&self, path: &str) -> Result<(), Denied> {
+    let path = Path::new(path);
+    let escapes = path.components().any(|c| !matches!(c, Component::Normal(_)));
+    if escapes || trash::contains(path) || sidecar::is_sidecar(path) {
+      return Err(Denied::NotFound)
+    }
+
+    for ancestor in path.ancestors() {
+      for rule in config::get().access.iter().filter(|rule| rule.matches(ancestor)) {
+        if rule.hidden {
+          return Err(Denied::NotFound)
+        }
+        if let Some(role) = &rule.role {
+          if self.local {
+            continue
+          }
+          match self.user {
+            Some(user) if user.has_role(role) => {}
+            Some(_) => return Err(Denied::Forbidden),
+            None => return Err(Denied::Unauthorized),
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Checks whether the media path can be changed, it has to pass `check` and be outside
+  /// read only mounts. Only identities that `can_write` change anything
+  pub fn check_writable(&self, path: &str) -> Result<(), Denied> {
+    self.check(path)?;
+    if !self.can_write() || file::is_read_only(path) {
+      return Err(Denied::Forbidden)
+    }
+    Ok(())
+  }
+
+  /// Checks whether the media path can be downloaded, it has to pass `check`. Guests only
+  /// preview files, never get the originals or anything with them at full size
+  pub fn check_downloadable(&self, path: &str) -> Result<(), Denied> {
+    self.check(path)?;
+    if self.is_guest() {
+      return Err(Denied::Forbidden)
+    }
+    Ok(())
+  }
+
+  /// Signed in users that aren't guests and requests made through the admin socket
+  pub fn can_write(&self) -> bool {
+    self.local || self.user.is_some_and(|user| !user.has_role(GUEST_ROLE))
+  }
+
+  /// Same as `check` but only tells if the path can be shown at all
+  pub fn can_see(&self, path: &str) -> bool {
+    self.check(path).is_ok()
+  }
+
+  /// Users with the admin role and requests made through the admin socket
+  pub fn is_admin(&self) -> bool {
+    self.local || self.user.is_some_and(|user| user.has_role(ADMIN_ROLE))
+  }
+
+  pub fn is_guest(&self) -> bool {
+    self.user.is_some_and(|user| user.has_role(GUEST_ROLE))
+  }
+
+  /// Watermark that must be applied to media generated for this identity
+  pub fn watermark(&self) -> Option<&'static video::Watermark> {
+    if !self.is_guest() {
+      return None
+    }
+    WATERMARK.get_or_init(|| {
+      let path = config::get().guest_watermark.as_ref()?;
+      let bytes = std::fs::read(path).ok()?;
+      video::Watermark::from_webp(&bytes)
+    }).as_ref()
+  }
+}
+
+impl FromRequest for Identity {
+  type Error = actix_web::Error;
+  type Future = Ready<Result<Self, Self::Error>>;
+
+  fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+    // Whoever can open the socket can already reach the server's files, tokens aren't needed
+    if req.conn_data::<AdminSocket>().is_some() {
+      return ready(Ok(Self { local: true, ..Self::default() }))
+    }
+
+    let token = req.headers()
+    .get(header::AUTHORIZATION)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.strip_prefix("Bearer "))
+    .map(|token| token.trim().to_string())
+    .or_else(|| req.cookie(TOKEN_COOKIE).map(|c| c.value().to_string()));
+
+    let token = match token {
+      Some(token) if !token.is_empty() => token,
+      _ => return ready(Ok(Self::default())),
+    };
+
+    if let Some(user) = config::get().users.iter().find(|user| user.token == token) {
+      return ready(Ok(Self { user: Some(user), ..Self::default() }))
+    }
+
+    let ip = req.connection_info().realip_remote_addr().map(String::from);
+    ready(Ok(match session::resolve(&token, ip) {
+      Some((user, session)) => Self { user: Some(user), session: Some(session), local: false },
+      None => Self::default(),
+    }))
+  }
+}
+
+fn match_components(pattern: &[&str], path: &[&str]) -> bool {
+  match pattern.split_first() {
+    None => path.is_empty(),
+    Some((&"**", rest)) => {
+      (0..=path.len()).any(|skip| match_components(rest, &path[skip..]))
+    }
+    Some((first, rest)) => match path.split_first() {
+      Some((name, path_rest)) => {
+        match_wildcard(first.as_bytes(), name.as_bytes()) &&
+        match_components(rest, path_rest)
+      }
+      None => false,
+    }
+  }
+}
+
+fn match_wildcard(pattern: &[u8], name: &[u8]) -> bool {
+  match pattern.split_first() {
+    None => name.is_empty(),
+    Some((b'*', rest)) => (0..=name.len()).any(|skip| match_wildcard(rest, &name[skip..])),
+    Some((b'?', rest)) => !name.is_empty() && match_wildcard(rest, &name[1..]),
+    Some((c, rest)) => name.first() == Some(c) && match_wildcard(rest, &name[1..]),
+  }
+}
@@ -0,0 +1,141 @@
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use actix_web::HttpRequest;
+use serde::{Deserialize, Serialize};
+
+use crate::{access, config, f, util};
+
+/// Serializes writes and rotations of the log files
+static LOG_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+  Download,
+  Delete,
+  Restore,
+  Move,
+  Copy,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+  /// Unix time in seconds
+  pub time: u64,
+  pub action: Action,
+  pub path: String,
+  pub user: Option<String>,
+  pub ip: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+  user: Option<String>,
+  /// Only entries for this path or anything inside of it
+  path: Option<String>,
+  action: Option<Action>,
+  /// Unix time in seconds
+  since: Option<u64>,
+  limit: Option<usize>,
+}
+
+/// Appends an entry to the audit log, rotating it when it grows past the configured size.
+/// Failing to write the log must never fail the request so errors are only reported
+pub fn record(action: Action, path: &str, identity: &access::Identity, req: &HttpRequest) {
+  let entry = Entry {
+    time: util::now(),
+    action,
+    path: path.to_string(),
+    user: identity.user.map(|user| user.name.to_string()),
+    ip: req.connection_info().realip_remote_addr().map(String::from),
+  };
+
+  if let Err(err) = append(&entry) {
+    eprintln!("Could not write audit log - {err:?}");
+  }
+}
+
+/// Returns the most recent entries matching `query`, newest first
+pub fn query(query: &AuditQuery) -> std::io::Result<Vec<Entry>> {
+  let _lock = LOG_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+  let mut entries = Vec::new();
+
+  // Oldest rotation first so entries end up in chronological order
+  for i in (0..config::get().audit_log_files).rev() {
+    let file = match fs::File::open(log_path(i)) {
+      Ok(file) => file,
+      Err(_) => continue,
+    };
+    for line in BufReader::new(file).lines() {
+      let entry: Entry = match serde_json::from_str(&line?) {
+        Ok(entry) => entry,
+        Err(_) => continue,
+      };
+      if query.matches(&entry) {
+        entries.push(entry);
+      }
+    }
+  }
+
+  entries.reverse();
+  entries.truncate(query.limit.unwrap_or(100));
+  Ok(entries)
+}
+
+impl AuditQuery {
+  fn matches(&self, entry: &Entry) -> bool {
+    if self.user.is_some() && self.user != entry.user {
+      return false
+    }
+    if let Some(path) = &self.path {
+      let path = path.trim_end_matches('/');
+      if entry.path != path && !entry.path.starts_with(&f!("{path}/")) {
+        return false
+      }
+    }
+    if self.action.is_some_and(|action| action != entry.action) {
+      return false
+    }
+    self.since.is_none_or(|since| entry.time >= since)
+  }
+}
+
+fn append(entry: &Entry) -> std::io::Result<()> {
+  let _lock = LOG_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+  let path = log_path(0);
+
+  if fs::metadata(&path).is_ok_and(|m| m.len() >= config::get().audit_log_max_bytes) {
+    rotate()?;
+  }
+
+  let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+  let mut line = serde_json::to_string(entry)?;
+  line.push('\n');
+  file.write_all(line.as_bytes())
+}
+
+/// Shifts `log.N` to `log.N+1`, dropping the oldest file
+fn rotate() -> std::io::Result<()> {
+  let files = config::get().audit_log_files;
+  if files <= 1 {
+    return fs::remove_file(log_path(0))
+  }
+  for i in (0..files - 1).rev() {
+    let from = log_path(i);
+    if from.exists() {
+      fs::rename(from, log_path(i + 1))?;
+    }
+  }
+  Ok(())
+}
+
+fn log_path(rotation: u32) -> PathBuf {
+  if rotation == 0 {
+    PathBuf::from(&config::get().audit_log)
+  } else {
+    PathBuf::from(f!("{}.{rotation}", config::get().audit_log))
+  }
+}
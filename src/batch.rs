@@ -0,0 +1,172 @@
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::transfer::{self, Mode, OnConflict, Transfer};
+use crate::trash::{self, TrashEntry};
+use crate::{access, f, file};
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+  Move { path: String, to: String },
+  Copy { path: String, to: String },
+  Delete { path: String },
+}
+
+impl Operation {
+  pub fn path(&self) -> &str {
+    match self {
+      Self::Move { path, .. } | Self::Copy { path, .. } | Self::Delete { path } => path,
+    }
+  }
+
+  /// Folder the entry goes into, if it goes anywhere
+  pub fn to(&self) -> Option<&str> {
+    match self {
+      Self::Move { to, .. } | Self::Copy { to, .. } => Some(to),
+      Self::Delete { .. } => None,
+    }
+  }
+
+  fn transfer(&self) -> Option<(Transfer, Mode)> {
+    let (path, to, mode) = match self {
+      Self::Move { path, to } => (path, to, Mode::Move),
+      Self::Copy { path, to } => (path, to, Mode::Copy),
+      Self::Delete { .. } => return None,
+    };
+    Some((Transfer { paths: vec![path.clone()], to: to.clone() }, mode))
+  }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Batch {
+  pub operations: Vec<Operation>,
+  /// Check every operation before starting and undo the ones done if any fails
+  #[serde(default)]
+  pub atomic: bool,
+  /// Applies to every move and copy, `overwrite` can't be undone so atomic batches don't take it
+  #[serde(default)]
+  pub on_conflict: OnConflict,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Outcome {
+  pub path: String,
+  pub ok: bool,
+  /// Where the entry ended up when moved or copied
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub to: Option<String>,
+  /// The destination was taken and `on_conflict` is `skip`
+  pub skipped: bool,
+  /// Trash entry to restore a deleted entry
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub trashed: Option<TrashEntry>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchReport {
+  /// Every operation succeeded
+  pub completed: bool,
+  /// An atomic batch failed and the operations done before were undone
+  pub rolled_back: bool,
+  /// One per operation in the same order, operations after a failure in atomic batches are missing
+  pub results: Vec<Outcome>,
+}
+
+/// Runs the operations of `batch` in order. Non atomic batches go on after failures,
+/// atomic ones fail with `InvalidInput` if any operation can't be done as planned
+pub fn run(batch: &Batch, identity: &access::Identity) -> io::Result<BatchReport> {
+  if batch.atomic {
+    if batch.on_conflict == OnConflict::Overwrite {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "Atomic batches can't overwrite"))
+    }
+    for operation in &batch.operations {
+      check(operation, batch.on_conflict).map_err(|err| {
+        io::Error::new(io::ErrorKind::InvalidInput, f!("{} - {err}", operation.path()))
+      })?;
+    }
+  }
+
+  let mut report = BatchReport { completed: true, rolled_back: false, results: Vec::new() };
+  for operation in &batch.operations {
+    let mut outcome = Outcome { path: operation.path().to_string(), ..Outcome::default() };
+    match apply(operation, batch.on_conflict, identity, &mut outcome) {
+      Ok(()) => outcome.ok = true,
+      Err(err) => {
+        outcome.error = Some(err.to_string());
+        report.completed = false;
+      }
+    }
+    report.results.push(outcome);
+    if batch.atomic && !report.completed {
+      undo(&batch.operations, &report.results, identity);
+      report.rolled_back = true;
+      break
+    }
+  }
+  Ok(report)
+}
+
+/// Whether `operation` could be done right now
+fn check(operation: &Operation, on_conflict: OnConflict) -> io::Result<()> {
+  match operation.transfer() {
+    Some((transfer, mode)) => transfer::plan(&transfer, mode, on_conflict).map(|_| ()),
+    None => std::fs::symlink_metadata(file::get_media_path(operation.path())).map(|_| ()),
+  }
+}
+
+fn apply(
+  operation: &Operation,
+  on_conflict: OnConflict,
+  identity: &access::Identity,
+  outcome: &mut Outcome,
+) -> io::Result<()> {
+  if file::is_root(operation.path()) {
+    let message = "The media folder and mounts themselves can't be moved, copied or deleted";
+    return Err(io::Error::new(io::ErrorKind::InvalidInput, message))
+  }
+  match operation.transfer() {
+    Some((transfer, mode)) => {
+      let done = transfer::run(&transfer, mode, on_conflict, identity)?;
+      if let Some(done) = done.into_iter().next() {
+        outcome.skipped = done.skipped;
+        outcome.to = Some(done.to);
+      }
+    }
+    None => {
+      outcome.trashed = Some(trash::delete(operation.path(), identity)?);
+    }
+  }
+  Ok(())
+}
+
+/// Reverts the operations that succeeded, newest first. Failing to revert one is only logged
+fn undo(operations: &[Operation], results: &[Outcome], identity: &access::Identity) {
+  for (operation, outcome) in operations.iter().zip(results).rev() {
+    if !outcome.ok || outcome.skipped {
+      continue
+    }
+    let reverted = match (operation, &outcome.to, &outcome.trashed) {
+      (Operation::Move { path, .. }, Some(to), _) => transfer::move_back(&transfer::Operation {
+        from: path.trim_matches('/').to_string(),
+        to: to.clone(),
+        skipped: false,
+        replaces: false,
+      }),
+      // Copies are removed for good, they didn't exist before the batch
+      (Operation::Copy { .. }, Some(to), _) => {
+        trash::delete(to, identity).and_then(|entry| trash::remove(&entry.id))
+      }
+      (Operation::Delete { .. }, _, Some(entry)) => {
+        trash::restore(&entry.id, trash::Conflict::Fail, identity).map(|_| ())
+      }
+      _ => Ok(()),
+    };
+    if let Err(err) = reverted {
+      eprintln!("Could not undo batch operation on {} - {err:?}", outcome.path);
+    }
+  }
+}
@@ -0,0 +1,147 @@
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::path::Path;
+
+use crate::{collate, f};
+
+/// Comic archives and ebooks, CBR is RAR but often a renamed CBZ and only those can be read
+const BOOK_EXTENSIONS: [&str; 3] = ["cbz", "cbr", "epub"];
+const IMAGE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "webp", "gif", "bmp"];
+/// Covers are a single image, anything larger is likely a broken or malicious archive
+const MAX_COVER_BYTES: u64 = 32 * 1024 * 1024;
+/// What `META-INF/container.xml` and the package document are expected to stay under
+const MAX_XML_BYTES: u64 = 1024 * 1024;
+const RAR_MAGIC: &[u8] = b"Rar!";
+
+/// Whether `path` is a comic archive or an ebook by its extension
+pub fn is_book(path: &Path) -> bool {
+  path.extension()
+  .and_then(|ext| ext.to_str())
+  .is_some_and(|ext| BOOK_EXTENSIONS.iter().any(|book| ext.eq_ignore_ascii_case(book)))
+}
+
+/// Image shown for the book at `path`: the cover an EPUB declares or the first page of a comic,
+/// by natural name order. Fails with `NotFound` when there's no image and `Unsupported` for RAR
+pub fn cover(path: &Path) -> io::Result<Vec<u8>> {
+  let mut file = File::open(path)?;
+  let mut magic = [0; 4];
+  if file.read_exact(&mut magic).is_ok() && magic == RAR_MAGIC {
+    return Err(io::Error::new(io::ErrorKind::Unsupported, "RAR archives can't be read, only ZIP"))
+  }
+  file.rewind()?;
+  let mut archive = zip::ZipArchive::new(file).map_err(zip_error)?;
+
+  let is_epub = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("epub"));
+  let name = is_epub
+  .then(|| epub_cover(&mut archive))
+  .flatten()
+  .or_else(|| first_image(&archive))
+  .ok_or(io::ErrorKind::NotFound)?;
+  read_entry(&mut archive, &name, MAX_COVER_BYTES)
+}
+
+/// Image the package document of an EPUB points to as the cover. EPUB 3 marks it in the manifest
+/// and EPUB 2 with a `<meta name="cover">` holding the id of the manifest item
+fn epub_cover(archive: &mut zip::ZipArchive<File>) -> Option<String> {
+  let container = String::from_utf8(read_entry(archive, "META-INF/container.xml", MAX_XML_BYTES).ok()?).ok()?;
+  let package_path = tags(&container, "rootfile").find_map(|tag| attribute(tag, "full-path"))?.to_string();
+  let package = String::from_utf8(read_entry(archive, &package_path, MAX_XML_BYTES).ok()?).ok()?;
+
+  let items: Vec<&str> = tags(&package, "item").collect();
+  let cover = items
+  .iter()
+  .find(|item| {
+    attribute(item, "properties").is_some_and(|properties| {
+      properties.split_whitespace().any(|property| property == "cover-image")
+    })
+  })
+  .or_else(|| {
+    let id = tags(&package, "meta")
+    .find(|meta| attribute(meta, "name") == Some("cover"))
+    .and_then(|meta| attribute(meta, "content"))?;
+    items.iter().find(|item| attribute(item, "id") == Some(id))
+  })?;
+  // Hrefs are relative to the package document
+  let href = attribute(cover, "href")?;
+  let folder = package_path.rsplit_once('/').map_or("", |(folder, _)| folder);
+  Some(resolve(folder, href))
+}
+
+/// First image by natural name order, which is the first page of comics
+fn first_image(archive: &zip::ZipArchive<File>) -> Option<String> {
+  archive
+  .file_names()
+  .filter(|name| {
+    // Metadata macOS adds to archives has the same names as the pages
+    !name.starts_with("__MACOSX/") &&
+    name.rsplit_once('.').is_some_and(|(_, ext)| {
+      IMAGE_EXTENSIONS.iter().any(|image| ext.eq_ignore_ascii_case(image))
+    })
+  })
+  .min_by(|a, b| collate::compare(a, b))
+  .map(String::from)
+}
+
+fn read_entry(archive: &mut zip::ZipArchive<File>, name: &str, max_bytes: u64) -> io::Result<Vec<u8>> {
+  let entry = archive.by_name(name).map_err(zip_error)?;
+  let mut bytes = Vec::new();
+  entry.take(max_bytes + 1).read_to_end(&mut bytes)?;
+  if bytes.len() as u64 > max_bytes {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, f!("{name} is larger than {max_bytes} bytes")))
+  }
+  Ok(bytes)
+}
+
+fn zip_error(err: zip::result::ZipError) -> io::Error {
+  match err {
+    zip::result::ZipError::Io(err) => err,
+    zip::result::ZipError::FileNotFound => io::ErrorKind::NotFound.into(),
+    err => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+  }
+}
+
+/// Start tags named `name` in `xml`, from the name up to the closing `>`. Good enough for the
+/// few attributes read here, comments and CDATA aren't skipped
+fn tags<'a>(xml: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> {
+  xml
+  .split('<')
+  .skip(1)
+  .filter_map(move |tag| {
+    let rest = tag.strip_prefix(name)?;
+    rest.starts_with(|c: char| c.is_whitespace() || c == '/' || c == '>').then_some(tag)
+  })
+  .map(|tag| tag.split_once('>').map_or(tag, |(tag, _)| tag))
+}
+
+/// Value of the attribute `name` of a start tag from `tags`
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+  let mut rest = tag;
+  while let Some(at) = rest.find(name) {
+    let before = rest[..at].chars().next_back();
+    let after = rest[at + name.len()..].trim_start();
+    rest = &rest[at + name.len()..];
+    if !before.is_some_and(char::is_whitespace) {
+      continue
+    }
+    let Some(value) = after.strip_prefix('=') else { continue };
+    let value = value.trim_start();
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    return value[1..].split_once(quote).map(|(value, _)| value)
+  }
+  None
+}
+
+/// `href` relative to `folder`, both inside the archive
+fn resolve(folder: &str, href: &str) -> String {
+  let mut parts: Vec<&str> = folder.split('/').filter(|part| !part.is_empty()).collect();
+  for part in href.split('/') {
+    match part {
+      "" | "." => {}
+      ".." => {
+        parts.pop();
+      }
+      part => parts.push(part),
+    }
+  }
+  parts.join("/")
+}
@@ -0,0 +1,753 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::watch;
+
+use crate::metadata::MediaKind;
+use crate::{book, config, f, file, library, sidecar, thumbhash, video};
+
+/// Max thumbnails waiting to be pre-generated, anything past this is dropped
+const PREWARM_QUEUE_LEN: usize = 256;
+/// How often the pre-generation worker saves what's left to do while it's busy
+const QUEUE_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+/// Photos converted for browsers that can't show the original are seen full size, not as thumbnails
+const CONVERTED_QUALITY: u8 = 85;
+/// Files being written end with this until they're complete
+pub const PARTIAL_SUFFIX: &str = ".partial";
+/// Width of the thumbnails ThumbHash placeholders are made from
+const PLACEHOLDER_WIDTH: u32 = 64;
+/// Images in a folder used as the album art of its audio files without any, best first
+const FOLDER_COVERS: [&str; 6] = ["cover.jpg", "cover.png", "folder.jpg", "folder.png", "front.jpg", "front.png"];
+
+static THUMBNAILS: OnceLock<Mutex<ThumbnailCache>> = OnceLock::new();
+static PREWARM: OnceLock<Prewarm> = OnceLock::new();
+static IN_FLIGHT: OnceLock<Mutex<HashMap<ThumbnailKey, InFlight>>> = OnceLock::new();
+/// Bytes stored in the disk cache, `None` until the folder is first read
+static DISK_BYTES: Mutex<Option<u64>> = Mutex::new(None);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ThumbnailKey {
+  /// Media path relative to the media folder
+  pub path: String,
+  pub width: u32,
+  /// Bits of the requested `f32` seek
+  pub seek: u32,
+  pub watermark: bool,
+  pub letterbox: Option<video::Letterbox>,
+  /// WebP quality from 1 to 100, `video::WEBP_QUALITY` when `None`
+  pub quality: Option<u8>,
+}
+
+impl ThumbnailKey {
+  /// Images and book covers have a single frame, every seek gets the same thumbnail
+  pub fn new(path: &str, width: u32, seek: f32, watermark: bool) -> Self {
+    let seek = if is_still(Path::new(path)) { 0. } else { seek };
+    Self { path: path.to_string(), width, seek: seek.to_bits(), watermark, letterbox: None, quality: None }
+  }
+
+  /// Full size WebP of an image, for browsers that can't show its format
+  pub fn converted(path: &str) -> Self {
+    Self::new(path, 0, 0., false).at_quality(Some(CONVERTED_QUALITY))
+  }
+
+  pub fn letterboxed(mut self, letterbox: Option<video::Letterbox>) -> Self {
+    self.letterbox = letterbox;
+    self
+  }
+
+  pub fn at_quality(mut self, quality: Option<u8>) -> Self {
+    self.quality = quality;
+    self
+  }
+
+  /// Everything but the path, what tells apart thumbnails of the same file
+  fn params(&self) -> impl Debug {
+    (self.width, self.seek, self.watermark, self.letterbox, self.quality)
+  }
+
+  pub fn seek_time(&self) -> video::SeekTime {
+    video::SeekTime::from(f32::from_bits(self.seek))
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AtlasKey {
+  /// Media path relative to the media folder
+  pub path: String,
+  pub page: u32,
+  pub step: u32,
+  pub layout: video::TileLayout,
+  pub format: video::ImageFormat,
+  pub burn_timestamps: bool,
+  /// Only used by video atlases
+  pub preset: video::AtlasPreset,
+  pub watermark: bool,
+}
+
+impl AtlasKey {
+  /// Everything but the path, what tells apart atlas pages of the same file
+  fn params(&self) -> impl Debug {
+    (self.page, self.step, self.layout, self.format, self.burn_timestamps, self.preset, self.watermark)
+  }
+}
+
+/// In memory thumbnails, the oldest entries are dropped once full
+#[derive(Default)]
+struct ThumbnailCache {
+  /// Thumbnails along the `Stamp` of the file they were made from
+  entries: HashMap<ThumbnailKey, (Arc<Vec<u8>>, Option<Stamp>)>,
+  order: VecDeque<ThumbnailKey>,
+}
+
+impl ThumbnailCache {
+  /// Thumbnail of `key` unless its file changed since, e.g. replaced by a move or restored from the trash
+  fn get(&self, key: &ThumbnailKey, stamp: Option<Stamp>) -> Option<Arc<Vec<u8>>> {
+    let (thumbnail, made_from) = self.entries.get(key)?;
+    (*made_from == stamp).then(|| thumbnail.clone())
+  }
+}
+
+/// Modification time and size of a file, what tells apart files written at the same path
+type Stamp = (SystemTime, u64);
+
+fn stamp(path: &str) -> Option<Stamp> {
+  let meta = std::fs::metadata(file::get_media_path(path)).ok()?;
+  Some((meta.modified().ok()?, meta.len()))
+}
+
+/// Result of a thumbnail being generated, `None` until it's done
+type InFlight = watch::Receiver<Option<Result<Arc<Vec<u8>>, video::VideoError>>>;
+
+/// Takes the key out of the in-flight thumbnails once its request is done or dropped
+struct InFlightGuard(ThumbnailKey);
+
+impl Drop for InFlightGuard {
+  fn drop(&mut self) {
+    in_flight().remove(&self.0);
+  }
+}
+
+/// What `regenerate` or `purge` cleared and queued
+#[derive(Debug, Default, Serialize)]
+pub struct Regeneration {
+  /// Thumbnails dropped from memory
+  pub dropped: usize,
+  /// Thumbnails and atlases removed from the disk cache
+  pub removed_files: usize,
+  pub removed_bytes: u64,
+  /// Videos whose default thumbnail will be generated again
+  pub queued: usize,
+}
+
+struct Prewarm {
+  sender: SyncSender<ThumbnailKey>,
+  /// Keys queued or being generated, used to avoid queueing the same thumbnail twice
+  pending: Arc<Mutex<HashSet<ThumbnailKey>>>,
+  /// Paths waiting for room in the queue, fed to the worker as it catches up
+  backlog: Arc<Mutex<VecDeque<String>>>,
+}
+
+/// Returns the webp thumbnail for `key` from memory or the disk cache or generates and caches it,
+/// uploaded artwork is used instead of the file when there's any
+pub fn thumbnail(
+  key: &ThumbnailKey,
+  watermark: Option<&video::Watermark>,
+) -> Result<Arc<Vec<u8>>, video::VideoError> {
+  // Taken before generating so a file changing meanwhile gets a new thumbnail next time
+  let stamp = stamp(&key.path);
+  if let Some(thumbnail) = thumbnails().get(key, stamp) {
+    return Ok(thumbnail)
+  }
+
+  let video_path = file::get_media_path(&key.path);
+  let (video_path, is_image) = match sidecar::artwork(&video_path) {
+    Some(artwork) => (artwork, true),
+    None if MediaKind::from_path(&video_path) == MediaKind::Audio => {
+      let album_art = album_art(&video_path).ok_or_else(|| {
+        video::VideoError::from((f!("No album art for {video_path:?}"), std::io::ErrorKind::NotFound))
+      })?;
+      (album_art, true)
+    }
+    None => {
+      let is_image = is_still(&video_path);
+      (video_path, is_image)
+    }
+  };
+  let disk_name = disk_name(&key.path, &video_path, &key.params());
+  let thumbnail = match disk_name.as_deref().and_then(disk_read) {
+    Some(thumbnail) => thumbnail,
+    None => {
+      let quality = key.quality.map_or(video::WEBP_QUALITY, f32::from);
+      let thumbnail = match key.letterbox {
+        _ if is_image => {
+          video::get_image_thumbnail(&video_path, key.width, key.letterbox, watermark, quality)?
+        }
+        Some(letterbox) => video::get_letterboxed_thumbnail(
+          &video_path,
+          key.width,
+          letterbox,
+          key.seek_time(),
+          watermark,
+          quality,
+        )?,
+        None => video::get_video_thumbnail(
+          &video_path,
+          key.width,
+          key.seek_time(),
+          watermark,
+          quality,
+        )?,
+      }.to_vec();
+      if let Some(name) = &disk_name {
+        disk_write(name, &thumbnail);
+      }
+      thumbnail
+    }
+  };
+  let thumbnail = Arc::new(thumbnail);
+
+  let mut cache = thumbnails();
+  if cache.entries.insert(key.clone(), (thumbnail.clone(), stamp)).is_none() {
+    cache.order.push_back(key.clone());
+  }
+  while cache.order.len() > config::get().thumbnail_cache_entries {
+    if let Some(oldest) = cache.order.pop_front() {
+      cache.entries.remove(&oldest);
+    }
+  }
+  Ok(thumbnail)
+}
+
+/// Whether the thumbnail of `path` is a single picture, images, album art and the covers of books
+fn is_still(path: &Path) -> bool {
+  matches!(MediaKind::from_path(path), MediaKind::Image | MediaKind::Audio) || book::is_book(path)
+}
+
+/// Picture embedded in the audio file at `path`, otherwise a cover image in its folder
+fn album_art(path: &Path) -> Option<PathBuf> {
+  if video::has_attached_picture(path) {
+    return Some(path.to_path_buf())
+  }
+  let folder = std::fs::read_dir(path.parent()?).ok()?;
+  let mut covers: Vec<(usize, PathBuf)> = folder
+  .flatten()
+  .filter_map(|entry| {
+    let name = entry.file_name().to_string_lossy().to_lowercase();
+    let rank = FOLDER_COVERS.iter().position(|cover| *cover == name)?;
+    Some((rank, entry.path()))
+  })
+  .collect();
+  covers.sort_by_key(|(rank, _)| *rank);
+  covers.into_iter().map(|(_, path)| path).find(|path| path.is_file())
+}
+
+/// Same as `thumbnail` but off the async workers, concurrent requests for the same key
+/// wait for the first one instead of decoding the video again
+pub async fn coalesced_thumbnail(
+  key: ThumbnailKey,
+  watermark: Option<&'static video::Watermark>,
+) -> Result<Arc<Vec<u8>>, video::VideoError> {
+  let joined = {
+    let mut in_flight = in_flight();
+    match in_flight.get(&key) {
+      Some(receiver) => Err(receiver.clone()),
+      None => {
+        let (sender, receiver) = watch::channel(None);
+        in_flight.insert(key.clone(), receiver);
+        Ok(sender)
+      }
+    }
+  };
+
+  let sender = match joined {
+    Ok(sender) => sender,
+    Err(mut receiver) => {
+      loop {
+        if let Some(result) = receiver.borrow().clone() {
+          return result
+        }
+        // The first request went away before finishing, generate it here instead
+        if receiver.changed().await.is_err() {
+          break
+        }
+      }
+      return video::unblocked(move || thumbnail(&key, watermark)).await
+    }
+  };
+
+  let _guard = InFlightGuard(key.clone());
+  let result = video::unblocked(move || thumbnail(&key, watermark)).await;
+  sender.send_replace(Some(result.clone()));
+  result
+}
+
+/// Returns the atlas page for `key` from the disk cache or generates and stores it
+pub fn atlas(
+  key: &AtlasKey,
+  watermark: Option<&video::Watermark>,
+) -> Result<Vec<u8>, video::VideoError> {
+  let video_path = file::get_media_path(&key.path);
+  let disk_name = disk_name(&key.path, &video_path, &key.params());
+  if let Some(atlas) = disk_name.as_deref().and_then(disk_read) {
+    return Ok(atlas)
+  }
+  let atlas = video::get_video_atlas(
+    &video_path,
+    key.page,
+    key.step,
+    key.layout,
+    key.format,
+    key.burn_timestamps,
+    key.preset,
+    watermark,
+  )?;
+  if let Some(name) = &disk_name {
+    disk_write(name, &atlas);
+  }
+  Ok(atlas)
+}
+
+/// Returns the waveform atlas page for `key` from the disk cache or generates and caches it
+pub fn waveform_atlas(
+  key: &AtlasKey,
+  watermark: Option<&video::Watermark>,
+) -> Result<Vec<u8>, video::VideoError> {
+  let audio_path = file::get_media_path(&key.path);
+  let disk_name = disk_name(&key.path, &audio_path, &("waveform", key.params()));
+  if let Some(atlas) = disk_name.as_deref().and_then(disk_read) {
+    return Ok(atlas)
+  }
+  let atlas = video::get_waveform_atlas(
+    &audio_path,
+    key.page,
+    key.step,
+    key.layout,
+    key.format,
+    key.burn_timestamps,
+    watermark,
+  )?;
+  if let Some(name) = &disk_name {
+    disk_write(name, &atlas);
+  }
+  Ok(atlas)
+}
+
+/// Drops every cached thumbnail of `path`, e.g. after its artwork changed
+pub fn forget(path: &str) {
+  let mut cache = thumbnails();
+  cache.entries.retain(|key, _| key.path != path);
+  cache.order.retain(|key| key.path != path);
+}
+
+/// Queues generation of the default thumbnail of every video in `paths` in the background.
+/// Thumbnails that are already cached or queued are skipped
+pub fn prewarm(paths: impl IntoIterator<Item = String>) {
+  let prewarm = PREWARM.get_or_init(start_prewarm_worker);
+  let mut queued = false;
+  {
+    let cache = thumbnails();
+    let mut pending = prewarm.pending.lock().unwrap_or_else(|err| err.into_inner());
+
+    for path in paths {
+      let Some(key) = prewarm_key(&path) else { continue };
+      if cache.get(&key, stamp(&path)).is_some() || pending.contains(&key) {
+        continue
+      }
+      // Queue is full, the rest will be generated on demand
+      if prewarm.sender.try_send(key.clone()).is_err() {
+        break
+      }
+      pending.insert(key);
+      queued = true;
+    }
+  }
+  // Listings prewarm their whole folder, most of the time it's all there already
+  if queued {
+    store_queue(prewarm);
+  }
+}
+
+/// Drops every cached thumbnail and atlas, in memory and on disk, so none rendered with older
+/// settings is served, then regenerates the default thumbnail of every video in `paths` in the
+/// background. Unlike `prewarm` the whole list is queued, it's fed to the worker as it catches up
+pub fn regenerate(paths: Vec<String>) -> Regeneration {
+  let regeneration = Regeneration { queued: paths.len(), ..purge() };
+  queue_all(paths);
+  regeneration
+}
+
+/// Drops every cached thumbnail and atlas, in memory and on disk, they're generated again on demand
+pub fn purge() -> Regeneration {
+  let mut regeneration = Regeneration::default();
+  {
+    let mut cache = thumbnails();
+    regeneration.dropped = cache.entries.len();
+    *cache = ThumbnailCache::default();
+  }
+  let mut total = DISK_BYTES.lock().unwrap_or_else(|err| err.into_inner());
+  for (path, len, _) in disk_usage(&disk_folder()) {
+    match std::fs::remove_file(&path) {
+      Ok(()) => {
+        regeneration.removed_files += 1;
+        regeneration.removed_bytes += len;
+      }
+      Err(err) => eprintln!("Could not remove {path:?} from the disk cache - {err:?}"),
+    }
+  }
+  // Counted again on the next write
+  *total = None;
+  regeneration
+}
+
+/// Thumbnails left to pre-generate, queued or waiting for room in the queue
+pub fn prewarm_queued() -> usize {
+  let Some(prewarm) = PREWARM.get() else { return 0 };
+  let pending = prewarm.pending.lock().unwrap_or_else(|err| err.into_inner()).len();
+  pending + prewarm.backlog.lock().unwrap_or_else(|err| err.into_inner()).len()
+}
+
+/// Stops pre-generating thumbnails, the one being generated is finished. Returns how many
+/// were left
+pub fn cancel_prewarm() -> usize {
+  let Some(prewarm) = PREWARM.get() else { return 0 };
+  let cancelled = {
+    let mut pending = prewarm.pending.lock().unwrap_or_else(|err| err.into_inner());
+    let mut backlog = prewarm.backlog.lock().unwrap_or_else(|err| err.into_inner());
+    let cancelled = pending.len() + backlog.len();
+    // Keys still in the channel are skipped by the worker once they're no longer pending
+    pending.clear();
+    backlog.clear();
+    cancelled
+  };
+  if cancelled > 0 {
+    store_queue(prewarm);
+  }
+  cancelled
+}
+
+/// Cleans up after a crash, only meant to be called when the server starts since files being
+/// written can't be told apart from the ones a crash left behind
+pub fn recover() {
+  remove_partials(Path::new(&config::get().data_folder));
+  remove_partials(&disk_folder());
+}
+
+/// Queues again the thumbnails that were left to pre-generate when the server last stopped
+pub fn resume_prewarm() {
+  let paths: Vec<String> = match std::fs::read(queue_path()) {
+    Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+      eprintln!("Could not read the pre-generation queue - {err:?}");
+      Vec::new()
+    }),
+    Err(_) => return,
+  };
+  if !paths.is_empty() {
+    println!("Resuming pre-generation of {} thumbnails", paths.len());
+    queue_all(paths);
+  }
+}
+
+/// Saves the thumbnails left to pre-generate so they're resumed on the next start
+pub fn save_queue() {
+  if let Some(prewarm) = PREWARM.get() {
+    store_queue(prewarm);
+  }
+}
+
+/// Queues every video in `paths` without dropping any, a thread hands them to the worker as it catches up
+fn queue_all(paths: Vec<String>) {
+  if paths.is_empty() {
+    return
+  }
+  let prewarm = PREWARM.get_or_init(start_prewarm_worker);
+  prewarm.backlog.lock().unwrap_or_else(|err| err.into_inner()).extend(paths);
+  store_queue(prewarm);
+
+  let (sender, pending, backlog) = (prewarm.sender.clone(), prewarm.pending.clone(), prewarm.backlog.clone());
+  std::thread::spawn(move || loop {
+    let Some(path) = backlog.lock().unwrap_or_else(|err| err.into_inner()).pop_front() else { break };
+    let Some(key) = prewarm_key(&path) else { continue };
+    if !pending.lock().unwrap_or_else(|err| err.into_inner()).insert(key.clone()) {
+      continue
+    }
+    if sender.send(key).is_err() {
+      break
+    }
+  });
+}
+
+/// Writes the paths queued, being generated or waiting in the backlog to the data folder
+fn store_queue(prewarm: &Prewarm) {
+  let mut paths: Vec<String> = prewarm
+  .pending
+  .lock()
+  .unwrap_or_else(|err| err.into_inner())
+  .iter()
+  .map(|key| key.path.clone())
+  .collect();
+  paths.extend(prewarm.backlog.lock().unwrap_or_else(|err| err.into_inner()).iter().cloned());
+
+  let path = queue_path();
+  let saved = path
+  .parent()
+  .map_or(Ok(()), std::fs::create_dir_all)
+  .and_then(|_| write_atomic(&path, &serde_json::to_vec(&paths)?));
+  if let Err(err) = saved {
+    eprintln!("Could not save the pre-generation queue - {err:?}");
+  }
+}
+
+fn queue_path() -> PathBuf {
+  PathBuf::from(&config::get().data_folder).join("prewarm-queue.json")
+}
+
+/// Default thumbnail of `path`, `None` while it's still being written or its mount doesn't
+/// pre-generate thumbnails
+fn prewarm_key(path: &str) -> Option<ThumbnailKey> {
+  let file_path = file::get_media_path(path);
+  if !file::is_stable(&file_path) || file::thumbnail_policy(path) != config::ThumbnailPolicy::Prewarm {
+    return None
+  }
+  let defaults = sidecar::thumbnail_defaults_for(&file_path);
+  Some(
+    ThumbnailKey::new(path, config::get().thumbnail_prewarm_width, default_seek(&file_path), false)
+    .at_quality(defaults.quality)
+  )
+}
+
+/// Where the default thumbnail of a file is taken, its poster or the folder's default seek
+fn default_seek(file_path: &Path) -> f32 {
+  let defaults = sidecar::thumbnail_defaults_for(file_path);
+  sidecar::load(file_path).poster_seek.or(defaults.seek).unwrap_or(0.)
+}
+
+/// Base64 ThumbHash of the default thumbnail of `path` for clients to show while it loads,
+/// the small thumbnail it's made from is cached like any other
+pub fn placeholder(path: &str) -> Option<String> {
+  let file_path = file::get_media_path(path);
+  let key = ThumbnailKey::new(path, PLACEHOLDER_WIDTH, default_seek(&file_path), false);
+  thumbhash::from_webp(&thumbnail(&key, None).ok()?)
+}
+
+fn start_prewarm_worker() -> Prewarm {
+  let (sender, receiver) = mpsc::sync_channel::<ThumbnailKey>(PREWARM_QUEUE_LEN);
+  let pending = Arc::new(Mutex::new(HashSet::new()));
+  let backlog = Arc::new(Mutex::new(VecDeque::new()));
+
+  std::thread::spawn(move || {
+    let mut saved = Instant::now();
+    for key in receiver {
+      let Some(prewarm) = PREWARM.get() else { continue };
+      // Cancelled while it was queued
+      if !prewarm.pending.lock().unwrap_or_else(|err| err.into_inner()).contains(&key) {
+        continue
+      }
+      // Browsing comes first, pre-generation only decodes while no request is
+      if let Err(err) = video::in_background(|| thumbnail(&key, None)) {
+        eprintln!("Could not pre-generate thumbnail for \"{}\" - {err:?}", key.path);
+      }
+      let done = {
+        let mut pending = prewarm.pending.lock().unwrap_or_else(|err| err.into_inner());
+        pending.remove(&key);
+        pending.is_empty() && prewarm.backlog.lock().unwrap_or_else(|err| err.into_inner()).is_empty()
+      };
+      if done || saved.elapsed() >= QUEUE_SAVE_INTERVAL {
+        store_queue(prewarm);
+        saved = Instant::now();
+      }
+    }
+  });
+
+  Prewarm { sender, pending, backlog }
+}
+
+fn in_flight() -> MutexGuard<'static, HashMap<ThumbnailKey, InFlight>> {
+  IN_FLIGHT
+  .get_or_init(|| Mutex::new(HashMap::new()))
+  .lock()
+  .unwrap_or_else(|err| err.into_inner())
+}
+
+fn thumbnails() -> MutexGuard<'static, ThumbnailCache> {
+  THUMBNAILS
+  .get_or_init(|| Mutex::new(ThumbnailCache::default()))
+  .lock()
+  .unwrap_or_else(|err| err.into_inner())
+}
+
+fn disk_folder() -> PathBuf {
+  Path::new(&config::get().data_folder).join("cache")
+}
+
+/// Cache file name for the `params` variant of `path` generated from `source`. Files the index
+/// has a checksum for are named after their contents so copies and renamed files share entries,
+/// others after their path, size and modification time. Either way the name changes along with
+/// the source so stale entries are never read and get evicted instead
+fn disk_name(path: &str, source: &Path, params: &impl Debug) -> Option<String> {
+  if config::get().disk_cache_max_bytes == 0 {
+    return None
+  }
+  let origin = match content_hash(path, source) {
+    Some(checksum) => f!("sha256:{checksum}"),
+    None => {
+      let meta = std::fs::metadata(source).ok()?;
+      let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+      f!("{source:?}\n{modified}\n{}", meta.len())
+    }
+  };
+  let mut hasher = Sha256::new();
+  hasher.update(f!("{origin}\n{params:?}"));
+  Some(hasher.finalize().iter().map(|byte| f!("{byte:02x}")).collect())
+}
+
+/// Checksum of `path` from the index, only while it's unchanged and `source` is the file
+/// itself rather than its artwork
+fn content_hash(path: &str, source: &Path) -> Option<String> {
+  if source != file::get_media_path(path) {
+    return None
+  }
+  library::checksum(path).unwrap_or_else(|err| {
+    eprintln!("Could not read checksum of {path} - {err:?}");
+    None
+  })
+}
+
+/// Reads a cached file and marks it as recently used. Files that aren't a whole image are
+/// removed so they're generated again
+fn disk_read(name: &str) -> Option<Vec<u8>> {
+  let path = disk_folder().join(name);
+  let bytes = std::fs::read(&path).ok()?;
+  if !is_intact(&bytes) {
+    eprintln!("Dropping damaged {path:?} from the disk cache");
+    if std::fs::remove_file(&path).is_ok() {
+      if let Some(total) = DISK_BYTES.lock().unwrap_or_else(|err| err.into_inner()).as_mut() {
+        *total = total.saturating_sub(bytes.len() as u64);
+      }
+    }
+    return None
+  }
+  if let Ok(file) = File::options().write(true).open(&path) {
+    let _ = file.set_modified(SystemTime::now());
+  }
+  Some(bytes)
+}
+
+/// Whether `bytes` are a whole WebP or JPEG image, which is what every disk cache entry is.
+/// Entries cut short or damaged on disk fail this
+fn is_intact(bytes: &[u8]) -> bool {
+  let riff_len = bytes.get(4..8).map(|len| u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize);
+  let is_webp = bytes.starts_with(b"RIFF") &&
+    bytes.get(8..12) == Some(b"WEBP") &&
+    riff_len.is_some_and(|len| len + 8 == bytes.len());
+  let is_jpeg = bytes.starts_with(&[0xFF, 0xD8, 0xFF]) && bytes.ends_with(&[0xFF, 0xD9]);
+  is_webp || is_jpeg
+}
+
+/// Stores `bytes` and evicts the least recently used files when over `disk_cache_max_bytes`.
+/// Failing to cache doesn't fail the request, it's only logged
+fn disk_write(name: &str, bytes: &[u8]) {
+  if bytes.len() as u64 > config::get().disk_cache_max_bytes {
+    return
+  }
+  let folder = disk_folder();
+  let path = folder.join(name);
+  let written = std::fs::create_dir_all(&folder).and_then(|_| write_atomic(&path, bytes));
+  if let Err(err) = written {
+    eprintln!("Could not write {path:?} to the disk cache - {err:?}");
+    return
+  }
+
+  let mut total = DISK_BYTES.lock().unwrap_or_else(|err| err.into_inner());
+  let bytes = match *total {
+    Some(total) => total + bytes.len() as u64,
+    None => disk_usage(&folder).iter().map(|(_, len, _)| len).sum(),
+  };
+  let max_bytes = config::get().disk_cache_max_bytes;
+  *total = Some(if bytes > max_bytes {evict(&folder, max_bytes)} else {bytes});
+}
+
+/// Writes `bytes` to `path` through a file of its own that's renamed over `path` once it's
+/// on disk, so readers and a crash leave either the old contents or the new ones
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+  static PARTIAL_FILES: AtomicUsize = AtomicUsize::new(0);
+  let id = PARTIAL_FILES.fetch_add(1, Ordering::Relaxed);
+  let mut partial = path.as_os_str().to_owned();
+  partial.push(f!(".{}-{id}{PARTIAL_SUFFIX}", std::process::id()));
+  let partial = PathBuf::from(partial);
+
+  let written = File::create(&partial)
+  .and_then(|mut file| file.write_all(bytes))
+  .and_then(|_| persist(&partial, path));
+  if written.is_err() {
+    let _ = std::fs::remove_file(&partial);
+  }
+  written
+}
+
+/// Flushes the finished file at `partial` to disk and renames it to `path`. The folder is
+/// flushed as well where that's possible, or the rename could be lost in a crash
+pub fn persist(partial: &Path, path: &Path) -> io::Result<()> {
+  File::options().write(true).open(partial)?.sync_all()?;
+  std::fs::rename(partial, path)?;
+  #[cfg(unix)]
+  if let Some(folder) = path.parent() {
+    File::open(folder)?.sync_all()?;
+  }
+  Ok(())
+}
+
+/// Removes the files a crash left half written in `folder`, whoever was writing them is gone
+pub fn remove_partials(folder: &Path) {
+  let Ok(dir) = std::fs::read_dir(folder) else { return };
+  for entry in dir.flatten() {
+    if !entry.file_name().to_string_lossy().ends_with(PARTIAL_SUFFIX) {
+      continue
+    }
+    match std::fs::remove_file(entry.path()) {
+      Ok(()) => println!("Removed {:?} left over from a crash", entry.path()),
+      Err(err) => eprintln!("Could not remove {:?} - {err:?}", entry.path()),
+    }
+  }
+}
+
+/// Removes the least recently used files of `folder` until they fit in `max_bytes`, returns the bytes left
+pub fn evict(folder: &Path, max_bytes: u64) -> u64 {
+  let mut files = disk_usage(folder);
+  let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+  files.sort_unstable_by_key(|(_, _, used)| *used);
+  for (path, len, _) in files {
+    if total <= max_bytes {
+      break
+    }
+    match std::fs::remove_file(&path) {
+      Ok(()) => total -= len,
+      Err(err) => eprintln!("Could not evict {path:?} from the disk cache - {err:?}"),
+    }
+  }
+  total
+}
+
+/// Path, size and last use of every cached file
+fn disk_usage(folder: &Path) -> Vec<(PathBuf, u64, SystemTime)> {
+  let Ok(dir) = std::fs::read_dir(folder) else { return Vec::new() };
+  dir
+  .flatten()
+  .filter_map(|entry| {
+    let meta = entry.metadata().ok().filter(|meta| meta.is_file())?;
+    // Still being written by another request
+    if entry.file_name().to_string_lossy().ends_with(PARTIAL_SUFFIX) {
+      return None
+    }
+    Some((entry.path(), meta.len(), meta.modified().unwrap_or(UNIX_EPOCH)))
+  })
+  .collect()
+}
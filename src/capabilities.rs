@@ -0,0 +1,141 @@
+use std::sync::OnceLock;
+
+use ffmpeg::channel_layout::ChannelLayout;
+use ffmpeg::codec::context::Context as CodecCtx;
+use ffmpeg::format::{self, sample};
+use ffmpeg::util::frame::{audio::Audio as AudioFrame, video::Video as VideoFrame};
+use ffmpeg::{encoder, Codec, Packet};
+use serde::Serialize;
+
+/// Encoders transcoding profiles can choose from, hardware encoders first
+const ENCODERS: [&str; 11] = [
+  "h264_nvenc",
+  "h264_qsv",
+  "h264_vaapi",
+  "h264_videotoolbox",
+  "libx264",
+  "hevc_nvenc",
+  "hevc_qsv",
+  "hevc_vaapi",
+  "libx265",
+  "aac",
+  "libopus",
+];
+/// Hardware encoders refuse frames smaller than a couple hundred pixels
+const TEST_WIDTH: u32 = 320;
+const TEST_HEIGHT: u32 = 240;
+/// `AV_PIX_FMT_FLAG_HWACCEL`, set on the formats of frames kept in GPU memory
+const PIX_FMT_FLAG_HWACCEL: u64 = 1 << 3;
+
+static ENCODER_SUPPORT: OnceLock<Vec<EncoderSupport>> = OnceLock::new();
+
+#[derive(Debug, Serialize)]
+pub struct EncoderSupport {
+  pub name: &'static str,
+  pub available: bool,
+  /// Why the encoder can't be used, e.g. missing from the FFmpeg build or no GPU
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+}
+
+/// Encodes a test frame with every known encoder the first time it's called,
+/// later calls return the same results
+pub fn encoders() -> &'static [EncoderSupport] {
+  ENCODER_SUPPORT.get_or_init(|| {
+    ENCODERS.iter().map(|&name| {
+      let result = match encoder::find_by_name(name) {
+        Some(codec) if codec.is_video() => match software_format(codec) {
+          Some(format) => encode_video_frame(codec, format).map_err(|err| err.to_string()),
+          // Transcoding scales frames in memory, it can't give these any
+          None => Err("Only takes frames in GPU memory".to_string()),
+        },
+        Some(codec) => encode_audio_frame(codec).map_err(|err| err.to_string()),
+        None => Err(ffmpeg::Error::EncoderNotFound.to_string()),
+      };
+      EncoderSupport { name, available: result.is_ok(), error: result.err() }
+    }).collect()
+  })
+}
+
+/// Whether `name` encoded its test frame, false for unknown encoders
+pub fn is_available(name: &str) -> bool {
+  encoders().iter().any(|support| support.name == name && support.available)
+}
+
+/// Probes encoders in the background so the first request asking for them doesn't wait
+pub fn start_probe() {
+  std::thread::spawn(|| {
+    let available: Vec<&str> = encoders()
+    .iter()
+    .filter(|support| support.available)
+    .map(|support| support.name)
+    .collect();
+    println!("Available encoders - {}", available.join(", "));
+  });
+}
+
+/// Pixel format `codec` takes frames in memory in, YUV420P when it's one of them. `None` for
+/// encoders that only take frames already in GPU memory, like VAAPI ones
+pub fn software_format(codec: Codec) -> Option<format::Pixel> {
+  let formats: Vec<format::Pixel> = codec.video().ok()?.formats().map(Iterator::collect).unwrap_or_default();
+  if formats.is_empty() || formats.contains(&format::Pixel::YUV420P) {
+    return Some(format::Pixel::YUV420P)
+  }
+  formats.into_iter().find(|&format| !is_hardware(format))
+}
+
+fn is_hardware(format: format::Pixel) -> bool {
+  format.descriptor().is_some_and(|descriptor| {
+    unsafe { (*descriptor.as_ptr()).flags } & PIX_FMT_FLAG_HWACCEL != 0
+  })
+}
+
+fn encode_video_frame(codec: Codec, format: format::Pixel) -> Result<(), ffmpeg::Error> {
+  let mut encoder = CodecCtx::new().encoder().video()?;
+  encoder.set_width(TEST_WIDTH);
+  encoder.set_height(TEST_HEIGHT);
+  encoder.set_format(format);
+  encoder.set_time_base((1, 25));
+  let mut encoder = encoder.open_as(codec)?;
+
+  let mut frame = VideoFrame::new(format, TEST_WIDTH, TEST_HEIGHT);
+  for plane in 0..frame.planes() {
+    frame.data_mut(plane).fill(0);
+  }
+  frame.set_pts(Some(0));
+  encoder.send_frame(&frame)?;
+  encoder.send_eof()?;
+  encoder.receive_packet(&mut Packet::empty())
+}
+
+fn encode_audio_frame(codec: Codec) -> Result<(), ffmpeg::Error> {
+  let audio = codec.audio()?;
+  let format = audio.formats()
+  .and_then(|mut formats| formats.next())
+  .unwrap_or(format::Sample::F32(sample::Type::Planar));
+  let rate = audio.rates()
+  .and_then(|mut rates| rates.next())
+  .unwrap_or(48000);
+
+  let mut encoder = CodecCtx::new().encoder().audio()?;
+  encoder.set_rate(rate);
+  encoder.set_format(format);
+  encoder.set_channel_layout(ChannelLayout::STEREO);
+  encoder.set_channels(2);
+  encoder.set_time_base((1, rate));
+  let mut encoder = encoder.open_as(codec)?;
+
+  let samples = match encoder.frame_size() {
+    0 => 1024,
+    size => size as usize,
+  };
+  let mut frame = AudioFrame::new(format, samples, ChannelLayout::STEREO);
+  for plane in 0..frame.planes() {
+    frame.data_mut(plane).fill(0);
+  }
+  frame.set_rate(rate as u32);
+  frame.set_pts(Some(0));
+  encoder.send_frame(&frame)?;
+  encoder.send_eof()?;
+  encoder.receive_packet(&mut Packet::empty())
+}
@@ -0,0 +1,125 @@
+use serde::Serialize;
+
+use crate::envelope::Warning;
+use crate::metadata::MediaKind;
+use crate::{config, f, file, sidecar, util, video};
+
+/// Containers the default media receiver plays, by MIME type
+const CONTAINERS: [&str; 10] = [
+  "video/mp4", "video/webm", "video/mp2t", "audio/mp4", "audio/aac",
+  "audio/mpeg", "audio/ogg", "audio/wav", "audio/webm", "audio/flac",
+];
+/// HEVC and VP9 need a recent receiver (Chromecast Ultra, Google TV), still listed
+/// since sending the original beats waiting for a transcode on the ones that have it
+const VIDEO_CODECS: [&str; 4] = ["h264", "vp8", "vp9", "hevc"];
+const AUDIO_CODECS: [&str; 7] = ["aac", "mp3", "opus", "vorbis", "flac", "pcm_s16le", "pcm_s24le"];
+const IMAGE_TYPES: [&str; 5] = ["image/jpeg", "image/png", "image/gif", "image/webp", "image/bmp"];
+const THUMBNAIL_WIDTH: u32 = 480;
+
+/// `MediaInformation` as the Cast SDK expects it in a load request
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CastMedia {
+  content_id: String,
+  content_url: String,
+  content_type: String,
+  stream_type: &'static str,
+  /// Seconds
+  #[serde(skip_serializing_if = "Option::is_none")]
+  duration: Option<f64>,
+  metadata: CastMetadata,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CastMetadata {
+  /// `GENERIC` (0), `MUSIC_TRACK` (3) or `PHOTO` (4)
+  metadata_type: u8,
+  title: String,
+  images: Vec<CastImage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CastImage {
+  url: String,
+}
+
+/// Playback descriptor for `path` with URLs under `base_url`, they have to be absolute
+/// because the receiver fetches them itself. Videos the receiver likely can't play are sent
+/// through `/api/transcode` when transcoding is enabled, anything else it may not play is warned about
+pub fn describe(path: &str, base_url: &str) -> std::io::Result<(CastMedia, Vec<Warning>)> {
+  let file_path = file::get_media_path(path);
+  if !file_path.is_file() {
+    return Err(std::io::ErrorKind::NotFound.into())
+  }
+  let extension = file_path.extension()
+  .and_then(|ext| ext.to_str())
+  .unwrap_or_default();
+  let mut content_type = actix_files::file_extension_to_mime(extension).essence_str().to_string();
+  let encoded = util::encode_path(path);
+  let mut content_url = f!("{base_url}/file/{encoded}");
+  let title = sidecar::load(&file_path).title.unwrap_or_else(|| {
+    file_path.file_stem().unwrap_or_default().to_string_lossy().to_string()
+  });
+
+  let mut warnings = Vec::new();
+  let mut duration = None;
+  let (metadata_type, images) = match MediaKind::from_path(&file_path) {
+    MediaKind::Image => {
+      if !IMAGE_TYPES.contains(&content_type.as_str()) {
+        warnings.push(unsupported("container", &content_type));
+      }
+      (4, vec![CastImage { url: content_url.clone() }])
+    }
+    kind @ (MediaKind::Video | MediaKind::Audio) => {
+      let mut unplayable = Vec::new();
+      if !CONTAINERS.contains(&content_type.as_str()) {
+        unplayable.push(unsupported("container", &content_type));
+      }
+      match video::get_stream_codecs(&file_path) {
+        Ok(codecs) => {
+          let video = codecs.video.filter(|codec| !VIDEO_CODECS.contains(&codec.as_str()));
+          let audio = codecs.audio.filter(|codec| !AUDIO_CODECS.contains(&codec.as_str()));
+          unplayable.extend(video.map(|codec| unsupported("video codec", &codec)));
+          unplayable.extend(audio.map(|codec| unsupported("audio codec", &codec)));
+        }
+        Err(err) => warnings.push(Warning::new("cast_unknown_codec", f!("Could not read codecs - {err}"))),
+      }
+      // Transcodes are H.264 and AAC in MP4, which every receiver plays
+      if !unplayable.is_empty() && kind == MediaKind::Video && config::get().features.transcode {
+        content_url = f!("{base_url}/api/transcode/{encoded}");
+        content_type = "video/mp4".to_string();
+      } else {
+        warnings.extend(unplayable);
+      }
+      duration = video::get_duration_from_path(&file_path).ok().map(|ms| ms as f64 / 1000.);
+      if kind == MediaKind::Video {
+        let url = f!("{base_url}/api/thumbnail/{encoded}?width={THUMBNAIL_WIDTH}&seek=0.1");
+        (0, vec![CastImage { url }])
+      } else {
+        (3, Vec::new())
+      }
+    }
+    MediaKind::Other => {
+      warnings.push(unsupported("file type", &content_type));
+      (0, Vec::new())
+    }
+  };
+
+  let media = CastMedia {
+    content_id: content_url.clone(),
+    content_url,
+    content_type,
+    stream_type: "BUFFERED",
+    duration,
+    metadata: CastMetadata { metadata_type, title, images },
+  };
+  Ok((media, warnings))
+}
+
+fn unsupported(what: &str, name: &str) -> Warning {
+  Warning::new(
+    "cast_unsupported",
+    f!("Cast receivers may not play {what} {name}"),
+  )
+}
@@ -0,0 +1,41 @@
+use std::cmp::Ordering;
+
+use icu_collator::{Collator, CollatorOptions, Numeric, Strength};
+use icu_locid::Locale;
+
+use crate::config;
+
+thread_local! {
+  // Collators can't be shared between threads, each worker builds its own
+  static COLLATOR: Option<Collator> = new_collator();
+}
+
+fn new_collator() -> Option<Collator> {
+  let config = config::get();
+  let locale: Locale = match config.collation_locale.parse() {
+    Ok(locale) => locale,
+    Err(err) => {
+      eprintln!("Invalid collation locale {:?}, sorting by code point - {err:?}", config.collation_locale);
+      return None
+    }
+  };
+  let mut options = CollatorOptions::new();
+  options.strength = Some(Strength::Tertiary);
+  if config.collation_numeric {
+    options.numeric = Some(Numeric::On);
+  }
+  Collator::try_new(&locale.into(), options)
+  .map_err(|err| {
+    eprintln!("Could not load collation for {:?}, sorting by code point - {err:?}", config.collation_locale);
+  })
+  .ok()
+}
+
+/// Orders names the way readers of `collation_locale` expect. Accents and case only
+/// break ties and bidirectional marks in right-to-left names are ignored
+pub fn compare(a: &str, b: &str) -> Ordering {
+  COLLATOR.with(|collator| match collator {
+    Some(collator) => collator.compare(a, b),
+    None => a.cmp(b),
+  })
+}
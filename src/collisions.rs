@@ -0,0 +1,54 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use icu_normalizer::ComposingNormalizer;
+use rusqlite::Connection;
+use serde::Serialize;
+
+use crate::{f, library};
+
+/// Paths that only differ in case or Unicode normalization. On case-insensitive or
+/// normalizing filesystems, and for clients that normalize URLs, they open the same entry
+#[derive(Debug, Serialize)]
+pub struct Collision {
+  /// Normalized href the paths share
+  pub href: String,
+  pub paths: Vec<String>,
+}
+
+/// Colliding files and folders in the index, folders are only reported once
+/// instead of for every file inside them
+pub fn report() -> rusqlite::Result<Vec<Collision>> {
+  report_in(&*library::open()?)
+}
+
+/// Same as `report` for the index at `connection`
+pub fn report_in(connection: &Connection) -> rusqlite::Result<Vec<Collision>> {
+  let paths: Vec<String> = {
+    let mut statement = connection.prepare("SELECT path FROM files")?;
+    let rows = statement.query_map([], |row| row.get(0))?;
+    rows.collect::<rusqlite::Result<_>>()?
+  };
+
+  let normalizer = ComposingNormalizer::new_nfc();
+  let mut hrefs: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+  for path in &paths {
+    // Folders aren't indexed, they collide when the start of their files' paths do
+    let prefixes = path.match_indices('/').map(|(i, _)| &path[..i]).chain([path.as_str()]);
+    for prefix in prefixes {
+      let href = normalizer.normalize(&prefix.to_lowercase());
+      hrefs.entry(href).or_default().insert(prefix.to_string());
+    }
+  }
+
+  let mut collisions: Vec<Collision> = hrefs
+  .into_iter()
+  .filter(|(_, paths)| paths.len() > 1)
+  .map(|(href, paths)| Collision { href, paths: paths.into_iter().collect() })
+  .collect();
+  // Files inside colliding folders are already covered by the folders
+  let folders: Vec<String> = collisions.iter().map(|collision| collision.href.clone()).collect();
+  collisions.retain(|collision| {
+    !folders.iter().any(|folder| collision.href.starts_with(&f!("{folder}/")))
+  });
+  Ok(collisions)
+}
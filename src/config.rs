@@ -0,0 +1,447 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{OnceLock, RwLock};
+use std::time::{Duration, SystemTime};
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use toml::value::{Table, Value};
+
+use crate::{access, f, tags};
+
+/// Read from the working directory when the server starts, unless `--config` says otherwise
+pub const CONFIG_PATH: &str = "./fylvur-cfg.toml";
+
+/// How often the config file is checked for changes and for a SIGHUP
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Running config. Reloads are rare and configs are small, replaced ones are leaked
+/// so references handed out by `get` stay valid
+static CONFIG: RwLock<Option<&'static Config>> = RwLock::new(None);
+/// How the config was loaded, reloads load it the same way
+static ARGS: OnceLock<Args> = OnceLock::new();
+static HANGUP: AtomicBool = AtomicBool::new(false);
+
+/// Settings from `fylvur-cfg.toml`, see `fylvur-cfg.example.toml` for what each one does
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Config {
+  pub public_folder: String,
+  pub media_folder: String,
+  /// Other folders shown as folders at the top of the media folder
+  #[serde(default)]
+  pub mounts: Vec<Mount>,
+  pub host: String,
+  pub port: u16,
+  /// Unix socket the API is also served on, requests through it act as an admin
+  pub admin_socket: Option<String>,
+  #[serde(default)]
+  pub users: Vec<access::User>,
+  #[serde(default)]
+  pub access: Vec<access::AccessRule>,
+  #[serde(default)]
+  pub tag_rules: Vec<tags::TagRule>,
+  #[serde(default = "default_guest_max_width")]
+  pub guest_max_width: u32,
+  pub guest_watermark: Option<String>,
+  #[serde(default = "default_data_folder")]
+  pub data_folder: String,
+  #[serde(default = "default_scan_interval_secs")]
+  pub scan_interval_secs: u64,
+  #[serde(default)]
+  pub index_checksums: bool,
+  #[serde(default = "default_frame_memory_budget")]
+  pub frame_memory_budget: usize,
+  #[serde(default = "default_frame_memory_wait_ms")]
+  pub frame_memory_wait_ms: u64,
+  #[serde(default = "default_decode_queue_size")]
+  pub decode_queue_size: usize,
+  #[serde(default = "default_io_retry_attempts")]
+  pub io_retry_attempts: u32,
+  #[serde(default = "default_io_retry_backoff_ms")]
+  pub io_retry_backoff_ms: u64,
+  #[serde(default = "default_media_read_bytes")]
+  pub media_read_bytes: usize,
+  #[serde(default = "default_stable_after_secs")]
+  pub stable_after_secs: u64,
+  #[serde(default = "default_thumbnail_cache_entries")]
+  pub thumbnail_cache_entries: usize,
+  #[serde(default)]
+  pub thumbnail_prewarm: bool,
+  #[serde(default = "default_thumbnail_prewarm_width")]
+  pub thumbnail_prewarm_width: u32,
+  #[serde(default = "default_disk_cache_max_bytes")]
+  pub disk_cache_max_bytes: u64,
+  #[serde(default = "default_transcode_cache_max_bytes")]
+  pub transcode_cache_max_bytes: u64,
+  #[serde(default = "default_file_chunk_bytes")]
+  pub file_chunk_bytes: usize,
+  #[serde(default = "default_file_stream_min_bytes")]
+  pub file_stream_min_bytes: u64,
+  #[serde(default = "default_storage_warn_free_bytes")]
+  pub storage_warn_free_bytes: u64,
+  #[serde(default = "default_storage_warn_free_percent")]
+  pub storage_warn_free_percent: f64,
+  #[serde(default = "default_trash_retention_days")]
+  pub trash_retention_days: u64,
+  #[serde(default = "default_collation_locale")]
+  pub collation_locale: String,
+  #[serde(default = "default_collation_numeric")]
+  pub collation_numeric: bool,
+  #[serde(default = "default_audit_log")]
+  pub audit_log: String,
+  #[serde(default = "default_audit_log_max_bytes")]
+  pub audit_log_max_bytes: u64,
+  #[serde(default = "default_audit_log_files")]
+  pub audit_log_files: u32,
+  #[serde(default)]
+  pub hardware_decode: HardwareDecode,
+  /// Device opened for `hardware_decode`, e.g. `/dev/dri/renderD128`, FFmpeg picks one when unset
+  pub hardware_decode_device: Option<String>,
+  #[serde(default)]
+  pub features: Features,
+}
+
+/// GPU API thumbnails, atlases and sprites are decoded with. Files the GPU can't decode and
+/// failed decodes fall back to the CPU
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HardwareDecode {
+  /// Decode on the CPU
+  #[default]
+  None,
+  Vaapi,
+  Nvdec,
+  Qsv,
+}
+
+/// Endpoint groups that can be turned off, their routes answer 404 while they are.
+/// Everything is on by default
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Features {
+  /// Uploading and removing artwork
+  pub uploads: bool,
+  /// Deleting, moving and copying files, the trash, resolving duplicates and editing sidecars:
+  /// metadata, markers, posters and folder order
+  pub file_management: bool,
+  /// HLS, DASH and progressive MP4 streaming
+  pub transcode: bool,
+  /// Atlases, storyboards and sprites
+  pub atlas: bool,
+}
+
+impl Default for Features {
+  fn default() -> Self {
+    Self { uploads: true, file_management: true, transcode: true, atlas: true }
+  }
+}
+
+/// Folder served under `name` as if it were in the media folder, hiding anything there with that name
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Mount {
+  /// Single folder name, e.g. `movies` for `/movies/...`
+  pub name: String,
+  pub path: String,
+  /// Nothing in it is moved, deleted or gets sidecars written, e.g. a camera card
+  #[serde(default)]
+  pub read_only: bool,
+  /// Left out of the media folder listing, still reachable by its path
+  #[serde(default)]
+  pub hidden: bool,
+  /// Seconds between scans of this mount instead of `scan_interval_secs`, 0 only scans it
+  /// on start and when a scan is requested
+  pub scan_interval_secs: Option<u64>,
+  #[serde(default)]
+  pub thumbnails: ThumbnailPolicy,
+}
+
+/// When thumbnails, atlases and sprites of a mount's files are made, from the most to the least made
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailPolicy {
+  /// Default thumbnails of videos are made in the background when their folder is listed,
+  /// as long as `thumbnail_prewarm` is on
+  #[default]
+  Prewarm,
+  /// Only when they're asked for
+  OnDemand,
+  /// Never, its files are only served as they are
+  Off,
+}
+
+/// Command line options, every one can also be set with its `FYLVUR_*` environment variable.
+/// They take precedence over the config file, and the command line over the environment
+#[derive(Debug, Clone, Parser)]
+#[command(version, about = "File explorer to access and preview media files over a local network")]
+pub struct Args {
+  /// Config file with the rest of the settings, it may be missing if everything required is set here
+  #[arg(long, env = "FYLVUR_CONFIG", default_value = CONFIG_PATH)]
+  pub config: PathBuf,
+  /// Folder with the media files to serve
+  #[arg(long, env = "FYLVUR_MEDIA_DIR")]
+  pub media_dir: Option<String>,
+  /// Folder with the web UI
+  #[arg(long, env = "FYLVUR_PUBLIC_DIR")]
+  pub public_dir: Option<String>,
+  /// Folder for the library index, user data and caches
+  #[arg(long, env = "FYLVUR_DATA_DIR")]
+  pub data_dir: Option<String>,
+  #[arg(long, env = "FYLVUR_HOST")]
+  pub host: Option<String>,
+  #[arg(long, env = "FYLVUR_PORT")]
+  pub port: Option<u16>,
+}
+
+impl Args {
+  /// Reads the config file and applies the options set over it
+  pub fn load(&self) -> io::Result<Config> {
+    let mut table: Table = match std::fs::read_to_string(&self.config) {
+      Ok(content) => toml::from_str(&content).map_err(invalid_config)?,
+      Err(err) if err.kind() == io::ErrorKind::NotFound => Table::new(),
+      Err(err) => return Err(err),
+    };
+    let overrides = [
+      ("media_folder", self.media_dir.clone().map(Value::String)),
+      ("public_folder", self.public_dir.clone().map(Value::String)),
+      ("data_folder", self.data_dir.clone().map(Value::String)),
+      ("host", self.host.clone().map(Value::String)),
+      ("port", self.port.map(|port| Value::Integer(port.into()))),
+    ];
+    for (key, value) in overrides {
+      if let Some(value) = value {
+        table.insert(key.to_string(), value);
+      }
+    }
+    let config: Config = Value::Table(table).try_into().map_err(invalid_config)?;
+    config.check()
+  }
+}
+
+impl Config {
+  /// Catches what deserializing can't
+  fn check(self) -> io::Result<Self> {
+    if self.file_chunk_bytes == 0 {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "file_chunk_bytes must be at least 1"))
+    }
+    for mount in &self.mounts {
+      if mount.name.is_empty() || mount.name.starts_with('.') || mount.name.contains(['/', '\\']) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, f!("Invalid mount name {:?}", mount.name)))
+      }
+    }
+    for rule in &self.tag_rules {
+      if let Err(err) = rule.check() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, f!("Invalid tag rule {:?} - {err}", rule.tag)))
+      }
+    }
+    Ok(self)
+  }
+
+  /// Puts back the settings that are only read when the server starts,
+  /// returns the names of the ones that differed from `running`
+  fn keep_startup_settings(&mut self, running: &Config) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    keep("public_folder", &mut self.public_folder, &running.public_folder, &mut changed);
+    keep("media_folder", &mut self.media_folder, &running.media_folder, &mut changed);
+    keep("mounts", &mut self.mounts, &running.mounts, &mut changed);
+    keep("host", &mut self.host, &running.host, &mut changed);
+    keep("port", &mut self.port, &running.port, &mut changed);
+    keep("admin_socket", &mut self.admin_socket, &running.admin_socket, &mut changed);
+    keep("guest_watermark", &mut self.guest_watermark, &running.guest_watermark, &mut changed);
+    keep("data_folder", &mut self.data_folder, &running.data_folder, &mut changed);
+    keep("collation_locale", &mut self.collation_locale, &running.collation_locale, &mut changed);
+    keep("collation_numeric", &mut self.collation_numeric, &running.collation_numeric, &mut changed);
+    changed
+  }
+}
+
+fn keep<T: Clone + PartialEq>(name: &'static str, value: &mut T, running: &T, changed: &mut Vec<&'static str>) {
+  if value != running {
+    value.clone_from(running);
+    changed.push(name);
+  }
+}
+
+/// Uses `config` from now on, has to be called before anything reads the config
+pub fn init(config: Config) -> io::Result<()> {
+  let mut current = CONFIG.write().unwrap_or_else(|err| err.into_inner());
+  if current.is_some() {
+    return Err(io::Error::new(io::ErrorKind::AlreadyExists, "The config was already loaded"))
+  }
+  *current = Some(Box::leak(Box::new(config)));
+  Ok(())
+}
+
+/// The running config, `fylvur-cfg.toml` is read on first use if `init` wasn't called.
+/// Values read from it may change between calls when the config is reloaded
+pub fn get() -> &'static Config {
+  if let Some(config) = *CONFIG.read().unwrap_or_else(|err| err.into_inner()) {
+    return config
+  }
+  let mut current = CONFIG.write().unwrap_or_else(|err| err.into_inner());
+  // Another thread may have loaded it while this one waited for the lock
+  if let Some(config) = *current {
+    return config
+  }
+  let config = load(Path::new(CONFIG_PATH))
+  .unwrap_or_else(|err| panic!("Could not load {CONFIG_PATH} - {err}"));
+  let config: &'static Config = Box::leak(Box::new(config));
+  *current = Some(config);
+  config
+}
+
+/// Loads the config again the way `args` did and swaps it in. Folders, host, port, the guest
+/// watermark and collation keep their running values since they're only read at startup.
+/// The running config stays in use if the new one can't be loaded
+pub fn reload() -> io::Result<&'static Config> {
+  let mut config = match ARGS.get() {
+    Some(args) => args.load()?,
+    None => load(Path::new(CONFIG_PATH))?,
+  };
+  for name in config.keep_startup_settings(get()) {
+    eprintln!("Changing {name} needs a restart, keeping the running value");
+  }
+  let config: &'static Config = Box::leak(Box::new(config));
+  *CONFIG.write().unwrap_or_else(|err| err.into_inner()) = Some(config);
+  Ok(config)
+}
+
+/// Reloads the config in the background whenever its file changes or, on Unix, the process
+/// gets a SIGHUP. Requests keep being served while it's reloaded
+pub fn watch(args: Args) {
+  let path = args.config.clone();
+  let _ = ARGS.set(args);
+  #[cfg(unix)]
+  unsafe {
+    libc::signal(libc::SIGHUP, on_hangup as extern "C" fn(libc::c_int) as libc::sighandler_t);
+  }
+
+  std::thread::spawn(move || {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    let mut last_modified: Option<SystemTime> = modified(&path);
+    loop {
+      std::thread::sleep(WATCH_INTERVAL);
+      let current = modified(&path);
+      if !HANGUP.swap(false, Ordering::Relaxed) && current == last_modified {
+        continue
+      }
+      last_modified = current;
+      match reload() {
+        Ok(_) => println!("Reloaded {path:?}"),
+        Err(err) => eprintln!("Could not reload {path:?}, keeping the running config - {err}"),
+      }
+    }
+  });
+}
+
+#[cfg(unix)]
+extern "C" fn on_hangup(_: libc::c_int) {
+  // Only async-signal-safe work here, the watcher thread does the reload
+  HANGUP.store(true, Ordering::Relaxed);
+}
+
+pub fn load(path: &Path) -> io::Result<Config> {
+  let content = std::fs::read_to_string(path)?;
+  let config: Config = toml::from_str(&content).map_err(invalid_config)?;
+  config.check()
+}
+
+fn invalid_config(err: toml::de::Error) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, f!("{err}"))
+}
+
+fn default_guest_max_width() -> u32 {
+  320
+}
+
+fn default_data_folder() -> String {
+  "./fylvur-data".into()
+}
+
+fn default_scan_interval_secs() -> u64 {
+  3600
+}
+
+fn default_frame_memory_budget() -> usize {
+  256 * 1024 * 1024
+}
+
+fn default_frame_memory_wait_ms() -> u64 {
+  5000
+}
+
+fn default_decode_queue_size() -> usize {
+  32
+}
+
+fn default_io_retry_attempts() -> u32 {
+  3
+}
+
+fn default_io_retry_backoff_ms() -> u64 {
+  200
+}
+
+fn default_media_read_bytes() -> usize {
+  256 * 1024
+}
+
+fn default_stable_after_secs() -> u64 {
+  30
+}
+
+fn default_thumbnail_cache_entries() -> usize {
+  1000
+}
+
+fn default_thumbnail_prewarm_width() -> u32 {
+  320
+}
+
+fn default_disk_cache_max_bytes() -> u64 {
+  1024 * 1024 * 1024
+}
+
+fn default_transcode_cache_max_bytes() -> u64 {
+  10 * 1024 * 1024 * 1024
+}
+
+fn default_file_chunk_bytes() -> usize {
+  1024 * 1024
+}
+
+fn default_file_stream_min_bytes() -> u64 {
+  64 * 1024 * 1024
+}
+
+fn default_storage_warn_free_bytes() -> u64 {
+  5 * 1024 * 1024 * 1024
+}
+
+fn default_storage_warn_free_percent() -> f64 {
+  5.
+}
+
+fn default_trash_retention_days() -> u64 {
+  30
+}
+
+fn default_collation_locale() -> String {
+  "und".into()
+}
+
+fn default_collation_numeric() -> bool {
+  true
+}
+
+fn default_audit_log() -> String {
+  "./fylvur-audit.log".into()
+}
+
+fn default_audit_log_max_bytes() -> u64 {
+  10 * 1024 * 1024
+}
+
+fn default_audit_log_files() -> u32 {
+  5
+}
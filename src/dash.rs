@@ -0,0 +1,66 @@
+use crate::{f, segment, video};
+
+/// Codec of the transcoded video, H.264 high profile
+const VIDEO_CODEC: &str = "avc1.640028";
+/// Codec of the transcoded audio, AAC LC. Segments only have audio when the source does
+const AUDIO_CODEC: &str = "mp4a.40.2";
+
+/// Starts a playback of `path` and returns its manifest. Segments are templated relative to it as
+/// `init.mp4?playback=<id>` and `{n}.m4s?playback=<id>`, video and audio muxed in one representation
+pub fn manifest(path: &str, duration_ms: i64, has_audio: bool) -> String {
+  let id = segment::start(path);
+  let codecs = if has_audio {f!("{VIDEO_CODEC},{AUDIO_CODEC}")} else {VIDEO_CODEC.to_string()};
+  let duration = duration_ms.max(0) as f64 / 1000.;
+  let segment_ms = segment::SEGMENT_SECS * 1000;
+  f!(
+    r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" profiles="urn:mpeg:dash:profile:isoff-live:2011" type="static" mediaPresentationDuration="PT{duration:.3}S" minBufferTime="PT{}S">
+  <Period start="PT0S">
+    <AdaptationSet mimeType="video/mp4" segmentAlignment="true" startWithSAP="1">
+      <Representation id="0" codecs="{codecs}">
+        <SegmentTemplate timescale="1000" duration="{segment_ms}" startNumber="0" initialization="init.mp4?playback={id}" media="$Number$.m4s?playback={id}"/>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>
+"#,
+    segment::SEGMENT_SECS,
+  )
+}
+
+/// Initialization segment of `path`, the `ftyp` and `moov` boxes of its first fragmented segment
+pub async fn init(path: String, playback: Option<String>) -> Result<Vec<u8>, video::VideoError> {
+  let first = segment::get(path, 0, segment::SegmentFormat::Fmp4, playback).await?;
+  Ok(first[..fragments_start(&first)].to_vec())
+}
+
+/// Media segment `index` of `path`, its `moof` and `mdat` boxes without the initialization part
+pub async fn media(path: String, index: u32, playback: Option<String>) -> Result<Vec<u8>, video::VideoError> {
+  let segment = segment::get(path, index, segment::SegmentFormat::Fmp4, playback).await?;
+  Ok(segment[fragments_start(&segment)..].to_vec())
+}
+
+/// Offset of the first top level `moof` box, or the end of `data` if there's none
+fn fragments_start(data: &[u8]) -> usize {
+  let mut offset = 0;
+  while data.len() - offset >= 8 {
+    let size = u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]) as u64;
+    if &data[offset + 4..offset + 8] == b"moof" {
+      return offset
+    }
+    let size = match size {
+      // Box goes on to the end of the file
+      0 => break,
+      // Actual size is in the 64 bit field after the type
+      1 if data.len() - offset >= 16 => {
+        u64::from_be_bytes(data[offset + 8..offset + 16].try_into().unwrap_or_default())
+      }
+      size => size,
+    };
+    if size < 8 {
+      break
+    }
+    offset = offset.saturating_add(size.min(usize::MAX as u64) as usize).min(data.len());
+  }
+  data.len()
+}
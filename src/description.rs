@@ -0,0 +1,75 @@
+use std::path::Path;
+
+use pulldown_cmark::{html, Event, Options, Parser, Tag};
+use serde::Serialize;
+
+use crate::envelope::Warning;
+use crate::{access, f, file};
+
+/// Checked in this order, names are matched ignoring case
+const FILE_NAMES: [&str; 3] = ["readme.md", "about.md", "about.txt"];
+/// Larger files are left out, they're likely not meant as a description
+const MAX_BYTES: u64 = 64 * 1024;
+
+/// Curated text shown along a folder's contents
+#[derive(Debug, Serialize)]
+pub struct Description {
+  /// File the description was read from
+  name: String,
+  /// Text as written in the file
+  text: String,
+  /// Rendered markdown with raw HTML escaped, missing for plain text files
+  #[serde(skip_serializing_if = "Option::is_none")]
+  html: Option<String>,
+}
+
+/// Reads the description file of `folder`, if it has one `identity` can see
+pub fn read(folder: &Path, identity: &access::Identity) -> Result<Option<Description>, Warning> {
+  let Ok(dir) = std::fs::read_dir(folder) else { return Ok(None) };
+  let mut found: Vec<(usize, std::fs::DirEntry)> = dir
+  .flatten()
+  .filter_map(|entry| {
+    let name = entry.file_name().to_string_lossy().to_lowercase();
+    FILE_NAMES.iter().position(|&candidate| candidate == name).map(|rank| (rank, entry))
+  })
+  .filter(|(_, entry)| file::get_relative_path(&entry.path()).is_some_and(|path| identity.can_see(&path)))
+  .collect();
+  found.sort_unstable_by_key(|(rank, _)| *rank);
+  let Some((_, entry)) = found.into_iter().next() else { return Ok(None) };
+
+  let name = entry.file_name().to_string_lossy().to_string();
+  let invalid = |message: String| Warning::new("description_unreadable", message).at(name.clone());
+  let size = entry.metadata().map(|meta| meta.len()).unwrap_or_default();
+  if size > MAX_BYTES {
+    return Err(invalid(f!("Description is over {} KiB and was left out", MAX_BYTES / 1024)))
+  }
+  let text = std::fs::read_to_string(entry.path())
+  .map_err(|err| invalid(f!("Could not read description - {err}")))?;
+
+  let html = name.to_lowercase().ends_with(".md").then(|| render_markdown(&text));
+  Ok(Some(Description { name, text, html }))
+}
+
+/// Descriptions are written by whoever manages the files, so raw HTML is shown
+/// as text and script links are dropped
+fn render_markdown(text: &str) -> String {
+  let parser = Parser::new_ext(text, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH)
+  .map(|event| match event {
+    Event::Html(html) => Event::Text(html),
+    Event::Start(Tag::Link(kind, url, title)) if is_script(&url) => {
+      Event::Start(Tag::Link(kind, "".into(), title))
+    }
+    Event::Start(Tag::Image(kind, url, title)) if is_script(&url) => {
+      Event::Start(Tag::Image(kind, "".into(), title))
+    }
+    event => event,
+  });
+  let mut rendered = String::new();
+  html::push_html(&mut rendered, parser);
+  rendered
+}
+
+fn is_script(url: &str) -> bool {
+  let url = url.trim_start().to_lowercase();
+  url.starts_with("javascript:") || url.starts_with("vbscript:") || url.starts_with("data:text/html")
+}
@@ -0,0 +1,140 @@
+use std::io;
+
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+
+use crate::trash::{self, TrashEntry};
+use crate::{access, f, library};
+
+/// Indexed files with the same contents, only found when `index_checksums` is on
+#[derive(Debug, Serialize)]
+pub struct DuplicateGroup {
+  /// SHA-256 shared by every file in the group
+  pub id: String,
+  /// Size of each file
+  pub size: u64,
+  pub paths: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Resolution {
+  /// Path of the copy to keep, the rest go to the trash
+  pub keep: String,
+  /// Only report what would be removed
+  #[serde(default)]
+  pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResolveReport {
+  pub kept: String,
+  pub removed: Vec<String>,
+  pub reclaimed_bytes: u64,
+  pub dry_run: bool,
+  /// Trash entries to restore the removed copies, empty on dry runs
+  pub trashed: Vec<TrashEntry>,
+}
+
+/// Groups of two or more identical files `identity` can see, largest first
+pub fn groups(identity: &access::Identity) -> rusqlite::Result<Vec<DuplicateGroup>> {
+  let rows: Vec<(String, u64, String)> = {
+    let connection = library::open()?;
+    let mut statement = connection.prepare(
+      "SELECT checksum, size, path FROM files WHERE checksum IN (
+        SELECT checksum FROM files WHERE checksum IS NOT NULL
+        GROUP BY checksum HAVING COUNT(*) > 1
+      )
+      ORDER BY size DESC, checksum, path",
+    )?;
+    let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    rows.collect::<rusqlite::Result<_>>()?
+  };
+
+  let mut groups: Vec<DuplicateGroup> = Vec::new();
+  for (id, size, path) in rows {
+    if !identity.can_see(&path) {
+      continue
+    }
+    match groups.last_mut() {
+      Some(group) if group.id == id => group.paths.push(path),
+      _ => groups.push(DuplicateGroup { id, size, paths: vec![path] }),
+    }
+  }
+  groups.retain(|group| group.paths.len() > 1);
+  Ok(groups)
+}
+
+pub fn group(id: &str) -> rusqlite::Result<Option<DuplicateGroup>> {
+  let connection = library::open()?;
+  let mut statement = connection.prepare(
+    "SELECT size, path FROM files WHERE checksum = ?1 ORDER BY path",
+  )?;
+  let rows: Vec<(u64, String)> = statement
+  .query_map(params![id], |row| Ok((row.get(0)?, row.get(1)?)))?
+  .collect::<rusqlite::Result<_>>()?;
+  Ok(rows.first().map(|(size, _)| DuplicateGroup {
+    id: id.to_string(),
+    size: *size,
+    paths: rows.iter().map(|(_, path)| path.clone()).collect(),
+  }))
+}
+
+/// Keeps one copy and moves the others to the trash, all of them or none: copies
+/// already trashed are restored when one of them can't be
+pub fn resolve(
+  id: &str,
+  resolution: &Resolution,
+  identity: &access::Identity,
+) -> io::Result<ResolveReport> {
+  let group = group(id)
+  .map_err(io::Error::other)?
+  .filter(|group| group.paths.len() > 1)
+  .ok_or(io::ErrorKind::NotFound)?;
+  if !group.paths.contains(&resolution.keep) {
+    return Err(io::Error::new(io::ErrorKind::InvalidInput, "The copy to keep is not in the group"))
+  }
+  // Checked up front so dry runs don't count copies on read only mounts `trash::delete` refuses
+  let denied = group.paths.iter().any(|path| {
+    let checked = if *path == resolution.keep {identity.check(path)} else {identity.check_writable(path)};
+    checked.is_err()
+  });
+  if denied {
+    return Err(io::ErrorKind::PermissionDenied.into())
+  }
+  // Files edited since the last scan may not be duplicates anymore
+  for path in &group.paths {
+    if library::checksum(path).map_err(io::Error::other)?.as_deref() != Some(id) {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        f!("{path} changed since it was indexed, wait for the next scan"),
+      ))
+    }
+  }
+
+  let removed: Vec<String> = group.paths.into_iter().filter(|path| *path != resolution.keep).collect();
+  let mut report = ResolveReport {
+    kept: resolution.keep.clone(),
+    reclaimed_bytes: group.size * removed.len() as u64,
+    removed,
+    dry_run: resolution.dry_run,
+    trashed: Vec::new(),
+  };
+  if resolution.dry_run {
+    return Ok(report)
+  }
+
+  for path in &report.removed {
+    match trash::delete(path, identity) {
+      Ok(entry) => report.trashed.push(entry),
+      Err(err) => {
+        for entry in report.trashed.iter().rev() {
+          if let Err(err) = trash::restore(&entry.id, trash::Conflict::Fail, identity) {
+            eprintln!("Could not restore {} after a failed resolve - {err:?}", entry.path);
+          }
+        }
+        return Err(err)
+      }
+    }
+  }
+  Ok(report)
+}
@@ -0,0 +1,56 @@
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use serde::Serialize;
+
+use crate::description::Description;
+
+/// Something that went wrong without failing the request, e.g. metadata that had to be ignored
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Warning {
+  /// Stable identifier clients can match on, the message is only meant for people
+  pub code: &'static str,
+  pub message: String,
+  /// File the warning is about when the response covers more than one
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub path: Option<String>,
+}
+
+impl Warning {
+  pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+    Self { code, message: message.into(), path: None }
+  }
+
+  pub fn at(mut self, path: impl Into<String>) -> Self {
+    self.path = Some(path.into());
+    self
+  }
+}
+
+/// Body of JSON responses, `data` is what was asked for and `warnings` lists non-fatal issues
+#[derive(Debug, Serialize)]
+pub struct Envelope<T> {
+  pub data: T,
+  pub warnings: Vec<Warning>,
+  /// Description of the folder being listed
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub description: Option<Description>,
+}
+
+impl<T: Serialize> Envelope<T> {
+  pub fn new(data: T, warnings: Vec<Warning>) -> Self {
+    Self { data, warnings, description: None }
+  }
+
+  pub fn with_description(mut self, description: Option<Description>) -> Self {
+    self.description = description;
+    self
+  }
+
+  pub fn respond(self, mut response: HttpResponseBuilder) -> HttpResponse {
+    response.json(self)
+  }
+}
+
+/// `200 OK` with `data` and `warnings` in an envelope
+pub fn ok<T: Serialize>(data: T, warnings: Vec<Warning>) -> HttpResponse {
+  Envelope::new(data, warnings).respond(HttpResponse::Ok())
+}
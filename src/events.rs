@@ -0,0 +1,150 @@
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::rt::time::{interval, Interval};
+use actix_web::web::Bytes;
+use actix_web::HttpResponse;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::{access, f};
+
+/// Events a client can fall behind by before newer ones are dropped for it
+const CLIENT_QUEUE: usize = 256;
+/// Comment lines sent while idle so proxies don't close the connection
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+  Added,
+  Updated,
+  Removed,
+}
+
+impl EventKind {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Self::Added => "added",
+      Self::Updated => "updated",
+      Self::Removed => "removed",
+    }
+  }
+}
+
+/// Change to a file under the media folder
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+  pub kind: EventKind,
+  /// Path relative to the media folder
+  pub path: String,
+}
+
+/// Folder a client wants events for, relative to the media folder
+#[derive(Debug, Clone)]
+pub struct Watch {
+  pub folder: String,
+  /// Also send events for everything in subfolders
+  pub recursive: bool,
+}
+
+impl Watch {
+  pub fn new(folder: &str, recursive: bool) -> Self {
+    Self { folder: folder.trim_matches('/').to_string(), recursive }
+  }
+
+  pub fn matches(&self, path: &str) -> bool {
+    let rest = if self.folder.is_empty() {
+      path
+    } else {
+      match path.strip_prefix(&self.folder).and_then(|rest| rest.strip_prefix('/')) {
+        Some(rest) => rest,
+        None => return false,
+      }
+    };
+    self.recursive || !rest.contains('/')
+  }
+}
+
+struct Subscriber {
+  watches: Vec<Watch>,
+  identity: access::Identity,
+  sender: mpsc::Sender<Event>,
+}
+
+/// Sends `event` to every client watching a folder it happened in
+pub fn publish(event: Event) {
+  let mut subscribers = SUBSCRIBERS.lock().unwrap_or_else(|err| err.into_inner());
+  subscribers.retain(|subscriber| !subscriber.sender.is_closed());
+  for subscriber in subscribers.iter() {
+    if subscriber.watches.iter().any(|watch| watch.matches(&event.path)) &&
+    subscriber.identity.can_see(&event.path) {
+      // A full queue means the client stopped reading, it'll have to list the folder again
+      let _ = subscriber.sender.try_send(event.clone());
+    }
+  }
+}
+
+/// Ends the streams of clients that signed in with the session `id`, once it's revoked they
+/// mustn't hear about changes anymore
+pub fn close_session(id: &str) {
+  SUBSCRIBERS
+  .lock()
+  .unwrap_or_else(|err| err.into_inner())
+  .retain(|subscriber| subscriber.identity.session.as_deref() != Some(id));
+}
+
+/// Server-sent events stream with the changes under `watches` that `identity` can see
+pub fn subscribe(watches: Vec<Watch>, identity: access::Identity) -> HttpResponse {
+  let (sender, receiver) = mpsc::channel(CLIENT_QUEUE);
+  SUBSCRIBERS
+  .lock()
+  .unwrap_or_else(|err| err.into_inner())
+  .push(Subscriber { watches, identity, sender });
+
+  HttpResponse::Ok()
+    .content_type("text/event-stream")
+    .insert_header(("Cache-Control", "no-cache"))
+    .body(EventBody { receiver, keep_alive: interval(KEEP_ALIVE) })
+}
+
+struct EventBody {
+  receiver: mpsc::Receiver<Event>,
+  keep_alive: Interval,
+}
+
+impl MessageBody for EventBody {
+  type Error = serde_json::Error;
+
+  fn size(&self) -> BodySize {
+    BodySize::Stream
+  }
+
+  fn poll_next(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+    let body = self.get_mut();
+    match body.receiver.poll_recv(cx) {
+      Poll::Ready(Some(event)) => {
+        body.keep_alive.reset();
+        let data = match serde_json::to_string(&event) {
+          Ok(data) => data,
+          Err(err) => return Poll::Ready(Some(Err(err))),
+        };
+        let kind = event.kind.as_str();
+        Poll::Ready(Some(Ok(Bytes::from(f!("event: {kind}\ndata: {data}\n\n")))))
+      }
+      Poll::Ready(None) => Poll::Ready(None),
+      Poll::Pending => match body.keep_alive.poll_tick(cx) {
+        Poll::Ready(_) => Poll::Ready(Some(Ok(Bytes::from_static(b":\n\n")))),
+        Poll::Pending => Poll::Pending,
+      },
+    }
+  }
+}
@@ -0,0 +1,100 @@
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::http::header::HttpDate;
+
+use crate::access::Identity;
+use crate::metadata::MediaKind;
+use crate::{f, file, sidecar, util};
+
+/// Feeds only list the newest episodes, podcast apps rarely look further back
+const MAX_ITEMS: usize = 300;
+
+struct Episode {
+  /// Relative to the media folder
+  path: String,
+  file_path: PathBuf,
+  size: u64,
+  modified: SystemTime,
+}
+
+/// RSS feed of the audio and video files right inside `folder_path`, newest first, for podcast
+/// apps to subscribe to. Enclosures point at `/file/...` under `base_url`, which has to be
+/// absolute since the apps fetch them on their own
+pub fn rss(folder_path: &Path, base_url: &str, identity: &Identity) -> std::io::Result<String> {
+  if !folder_path.is_dir() {
+    return Err(std::io::ErrorKind::NotFound.into())
+  }
+  let folder = file::get_relative_path(folder_path).unwrap_or_default();
+  // Taken from the Host header, whoever sends it picks it
+  let base_url = escape(base_url);
+  let mut episodes: Vec<Episode> = std::fs::read_dir(folder_path)?
+  .flatten()
+  .filter_map(|entry| {
+    let file_path = entry.path();
+    let meta = entry.metadata().ok().filter(|meta| meta.is_file())?;
+    let is_media = matches!(MediaKind::from_path(&file_path), MediaKind::Audio | MediaKind::Video);
+    if !is_media || sidecar::is_sidecar(&file_path) {
+      return None
+    }
+    let path = file::get_relative_path(&file_path).filter(|path| identity.can_see(path))?;
+    let modified = meta.modified().unwrap_or(UNIX_EPOCH);
+    Some(Episode { path, file_path, size: meta.len(), modified })
+  })
+  .collect();
+  episodes.sort_unstable_by_key(|episode| Reverse(episode.modified));
+  episodes.truncate(MAX_ITEMS);
+
+  let title = folder_path.file_name().map_or_else(|| "Media".into(), |name| name.to_string_lossy());
+  let mut rss = f!(
+    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+    <rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n<channel>\n\
+    <title>{title}</title>\n<link>{base_url}/{folder_url}</link>\n\
+    <description>Audio and video in {title}</description>\n\
+    <atom:link href=\"{base_url}/api/feed/{folder_url}\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+    title = escape(&title),
+    folder_url = escape(&util::encode_path(&folder)),
+  );
+  if let Some(newest) = episodes.first() {
+    rss.push_str(&f!("<lastBuildDate>{}</lastBuildDate>\n", HttpDate::from(newest.modified)));
+  }
+  for episode in &episodes {
+    let sidecar = sidecar::load(&episode.file_path);
+    let title = sidecar.title.unwrap_or_else(|| {
+      episode.file_path.file_stem().unwrap_or_default().to_string_lossy().to_string()
+    });
+    let extension = episode.file_path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    rss.push_str(&f!(
+      "<item>\n<title>{}</title>\n<guid isPermaLink=\"false\">{}</guid>\n<pubDate>{}</pubDate>\n\
+      <enclosure url=\"{base_url}/file/{}\" length=\"{}\" type=\"{}\"/>\n",
+      escape(&title),
+      escape(&episode.path),
+      HttpDate::from(episode.modified),
+      escape(&util::encode_path(&episode.path)),
+      episode.size,
+      actix_files::file_extension_to_mime(extension).essence_str(),
+    ));
+    if let Some(description) = &sidecar.description {
+      rss.push_str(&f!("<description>{}</description>\n", escape(description)));
+    }
+    rss.push_str("</item>\n");
+  }
+  rss.push_str("</channel>\n</rss>\n");
+  Ok(rss)
+}
+
+fn escape(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      '\'' => escaped.push_str("&apos;"),
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
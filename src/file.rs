@@ -1,41 +1,299 @@
+use std::cmp::Reverse;
 use std::path;
 
 use serde::Serialize;
+use sha2::{Digest, Sha256};
 use actix_files as actix_fs;
+use actix_web::http::header::HttpDate;
 
-use crate::{f, video, MEDIA_FOLDER};
+use crate::envelope::Warning;
+use crate::{access, book, cache, collate, config, f, library, metadata, sidecar, stream, subtitle, video};
 
-pub fn get_media_path(path: &String) -> path::PathBuf {
-  path::Path::new(&MEDIA_FOLDER).join(path)
+/// Suffixes used by browsers and download clients for files still being written
+const PARTIAL_SUFFIXES: [&str; 6] = [".part", ".partial", ".!qb", ".crdownload", ".download", ".tmp"];
+
+/// Where `path` is on disk, paths starting with a mount name are in that mount
+pub fn get_media_path(path: &str) -> path::PathBuf {
+  let config = config::get();
+  let trimmed = path.trim_start_matches('/');
+  let (first, rest) = trimmed.split_once('/').unwrap_or((trimmed, ""));
+  match config.mounts.iter().find(|mount| mount.name == first) {
+    Some(mount) if rest.is_empty() => path::PathBuf::from(&mount.path),
+    Some(mount) => path::Path::new(&mount.path).join(rest),
+    None => path::Path::new(&config.media_folder).join(trimmed),
+  }
+}
+
+/// Media folder or mount `path` is in
+pub fn get_root_path(path: &str) -> &'static path::Path {
+  let config = config::get();
+  let first = path.trim_start_matches('/').split('/').next().unwrap_or_default();
+  match config.mounts.iter().find(|mount| mount.name == first) {
+    Some(mount) => path::Path::new(&mount.path),
+    None => path::Path::new(&config.media_folder),
+  }
+}
+
+/// Whether `path` is the media folder or the folder of a mount, whichever way it's reached
+pub fn is_root(path: &str) -> bool {
+  let config = config::get();
+  let file_path = get_media_path(path);
+  file_path == path::Path::new(&config.media_folder) ||
+  config.mounts.iter().any(|mount| file_path == path::Path::new(&mount.path))
+}
+
+/// Whether `path` is inside a read only mount, whichever way it's reached
+pub fn is_read_only(path: &str) -> bool {
+  let file_path = get_media_path(path);
+  config::get().mounts.iter().any(|mount| mount.read_only && file_path.starts_with(&mount.path))
+}
+
+/// Thumbnail policy of the mount `path` is inside, whichever way it's reached. The strictest
+/// one wins when mounts are nested and the media folder's files are pre-generated
+pub fn thumbnail_policy(path: &str) -> config::ThumbnailPolicy {
+  let file_path = get_media_path(path);
+  config::get()
+  .mounts
+  .iter()
+  .filter(|mount| file_path.starts_with(&mount.path))
+  .map(|mount| mount.thumbnails)
+  .max()
+  .unwrap_or_default()
+}
+
+/// Whether `folder` is a folder of the media folder hidden by a mount with the same name
+pub fn is_shadowed(folder: &path::Path) -> bool {
+  let config = config::get();
+  folder.parent() == Some(path::Path::new(&config.media_folder)) &&
+  config.mounts.iter().any(|mount| folder.file_name() == Some(mount.name.as_ref()))
 }
 
-pub fn get_folder_contents(path: &String) -> std::io::Result<Vec<FileInfo>> {
-  let folder = get_media_path(&path);
+pub fn get_folder_contents(
+  path: &str,
+  identity: &access::Identity,
+) -> std::io::Result<Vec<FileInfo>> {
+  let folder = get_media_path(path);
 
   let dir = std::fs::read_dir(&folder)?;
 
-  let mut paths: Vec<FileInfo> = dir.map(|p| {
+  let mut paths: Vec<FileInfo> = dir.filter(|p| {
+    // Restricted entries and sidecar files are left out of the listing entirely
+    match p {
+      Ok(dir_entry) => {
+        let entry_path = dir_entry.path();
+        !sidecar::is_sidecar(&entry_path) &&
+        identity.can_see(&get_relative_path_lossy(&entry_path))
+      }
+      Err(_) => true,
+    }
+  }).map(|p| {
     if let Ok(dir_entry) = p {
-      FileInfo::from_path(&dir_entry.path()).unwrap()
+      let entry_path = dir_entry.path();
+      FileInfo::from_path(&entry_path).unwrap_or_else(|err| FileInfo::unavailable(&entry_path, err))
     } else {FileInfo::default()}
   }).collect();
 
-  paths.sort_unstable_by_key(|f| !f.is_folder);
+  if folder == path::Path::new(&config::get().media_folder) {
+    let mounts = &config::get().mounts;
+    paths.retain(|file| !mounts.iter().any(|mount| mount.name == file.name));
+    paths.extend(mounts.iter().filter(|mount| !mount.hidden && identity.can_see(&mount.name)).map(|mount| {
+      let mount_path = path::PathBuf::from(&mount.path);
+      let mut file = FileInfo::from_path(&mount_path).unwrap_or_else(|err| FileInfo::unavailable(&mount_path, err));
+      file.name.clone_from(&mount.name);
+      file
+    }));
+  }
+
+  paths.sort_by(|a, b| b.is_folder.cmp(&a.is_folder).then_with(|| collate::compare(&a.name, &b.name)));
 
   Ok(paths)
 }
 
+/// First of `name (1).ext`, `name (2).ext`... that doesn't exist and isn't `taken`
+pub fn free_path(path: &path::Path, taken: impl Fn(&path::Path) -> bool) -> path::PathBuf {
+  let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+  let extension = path.extension().map(|ext| f!(".{}", ext.to_string_lossy())).unwrap_or_default();
+  (1..)
+  .map(|n| path.with_file_name(f!("{stem} ({n}){extension}")))
+  .find(|path| std::fs::symlink_metadata(path).is_err() && !taken(path))
+  .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Moves pinned entries to the top, then with `manual` the ones in the stored order.
+/// Everything else keeps its place after them
+pub fn apply_order(files: &mut [FileInfo], order: &sidecar::FolderOrder, manual: bool) {
+  let position = |names: &[String], name: &str| names.iter().position(|pinned| pinned == name);
+  files.sort_by_cached_key(|file| {
+    if let Some(index) = position(&order.pinned, &file.name) {
+      (0, index)
+    } else if let Some(index) = position(&order.order, &file.name).filter(|_| manual) {
+      (1, index)
+    } else {
+      (2, 0)
+    }
+  });
+}
+
+/// Orders entries the way a grid is likely looked at so their thumbnails load first: folders,
+/// then videos from the most recently modified, then the rest. Pinned entries stay on top and
+/// entries that tie keep their place
+pub fn apply_viewport_order(files: &mut [FileInfo], order: &sidecar::FolderOrder) {
+  files.sort_by_cached_key(|file| {
+    if order.pinned.contains(&file.name) {
+      return (0, 0, Reverse(None))
+    }
+    if file.is_folder {
+      return (1, 0, Reverse(None))
+    }
+    if file.file_type == "video" {
+      let modified = std::fs::metadata(get_media_path(file.path())).and_then(|meta| meta.modified());
+      return (1, 1, Reverse(modified.ok()))
+    }
+    (1, 2, Reverse(None))
+  });
+}
+
+/// Whether `file_path` looks done being written, that is it doesn't have a download
+/// suffix and hasn't been modified in the last `stable_after_secs`
+pub fn is_stable(file_path: &path::Path) -> bool {
+  let name = file_path
+  .file_name()
+  .map(|name| name.to_string_lossy().to_lowercase())
+  .unwrap_or_default();
+  if PARTIAL_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)) {
+    return false
+  }
+  match std::fs::metadata(file_path).and_then(|meta| meta.modified()) {
+    // Modification times in the future are left to the clock skew
+    Ok(modified) => modified.elapsed().map_or(true, |elapsed| elapsed.as_secs() >= config::get().stable_after_secs),
+    Err(_) => false,
+  }
+}
+
+/// Returns `file_path` relative to the media folder using `/` as separator, starting with
+/// the mount name for files in mounts. `None` when it isn't valid UTF-8 and so can't be part of a URL
+pub fn get_relative_path(file_path: &path::Path) -> Option<String> {
+  match strip_root(file_path) {
+    Some((mount, path)) => path.to_str().map(|path| with_mount(mount, path.replace('\\', "/"))),
+    None => Some(String::new()),
+  }
+}
+
+/// Like `get_relative_path` with invalid UTF-8 replaced, only meant for display and matching
+pub fn get_relative_path_lossy(file_path: &path::Path) -> String {
+  match strip_root(file_path) {
+    Some((mount, path)) => with_mount(mount, path.to_string_lossy().replace('\\', "/")),
+    None => String::new(),
+  }
+}
+
+/// Splits `file_path` into the name of the mount it's in, `None` for the media folder,
+/// and the rest of the path. Mounts inside the media folder or other mounts win
+fn strip_root(file_path: &path::Path) -> Option<(Option<&'static str>, &path::Path)> {
+  let config = config::get();
+  config.mounts
+  .iter()
+  .filter_map(|mount| Some((mount, file_path.strip_prefix(&mount.path).ok()?)))
+  .max_by_key(|(mount, _)| path::Path::new(&mount.path).components().count())
+  .map(|(mount, path)| (Some(mount.name.as_str()), path))
+  .or_else(|| Some((None, file_path.strip_prefix(&config.media_folder).ok()?)))
+}
+
+fn with_mount(mount: Option<&str>, path: String) -> String {
+  match mount {
+    Some(mount) if path.is_empty() => mount.to_string(),
+    Some(mount) => f!("{mount}/{path}"),
+    None => path,
+  }
+}
+
+/// Hex SHA-256 of the contents of `file_path`
+pub fn sha256(file_path: &path::Path) -> std::io::Result<String> {
+  let mut file = std::fs::File::open(file_path)?;
+  let mut hasher = Sha256::new();
+  std::io::copy(&mut file, &mut hasher)?;
+  Ok(hasher.finalize().iter().map(|byte| f!("{byte:02x}")).collect())
+}
+
+/// What download managers need to fetch a file reliably and verify it
+#[derive(Debug, Serialize)]
+pub struct DownloadInfo {
+  size: u64,
+  /// `Last-Modified` the file is served with
+  #[serde(skip_serializing_if = "Option::is_none")]
+  last_modified: Option<String>,
+  /// `ETag` the file is served with, send it in `If-Range` when resuming and the rest of the file
+  /// is only sent if it didn't change in between, otherwise it's sent whole
+  #[serde(skip_serializing_if = "Option::is_none")]
+  etag: Option<String>,
+  accept_ranges: &'static str,
+  /// Only known for files hashed by the library index
+  #[serde(skip_serializing_if = "Option::is_none")]
+  sha256: Option<String>,
+  url: String,
+}
+
+impl DownloadInfo {
+  /// `url` is where the file is served from, it stays the same until the file is moved
+  pub fn from_path(path: &str, url: String) -> std::io::Result<Self> {
+    let metadata = std::fs::metadata(get_media_path(path))?;
+    if !metadata.is_file() {
+      return Err(std::io::ErrorKind::NotFound.into())
+    }
+    let sha256 = library::checksum(path).unwrap_or_else(|err| {
+      eprintln!("Could not read checksum of {path} - {err:?}");
+      None
+    });
+    let modified = metadata.modified().ok();
+    Ok(Self {
+      size: metadata.len(),
+      last_modified: modified.map(|modified| HttpDate::from(modified).to_string()),
+      etag: modified.map(|modified| stream::entity_tag(metadata.len(), modified).to_string()),
+      accept_ranges: "bytes",
+      sha256,
+      url,
+    })
+  }
+}
+
 #[derive(Debug, Default, Serialize)]
 pub struct FileMetadata {
   duration_ms: i64,
+  subtitles: Vec<subtitle::SubtitleFile>,
+  /// Camera details of images with EXIF data
+  #[serde(skip_serializing_if = "Option::is_none")]
+  photo: Option<metadata::PhotoDetails>,
+  /// Artist, album and such of tagged audio files
+  #[serde(skip_serializing_if = "Option::is_none")]
+  music: Option<metadata::MusicTags>,
+  #[serde(flatten)]
+  sidecar: sidecar::Sidecar,
 }
 
 impl FileMetadata {
-  pub fn from_path(path: &path::PathBuf) -> Self {
-    let duration_ms = video::get_duration_from_path(
-      &path.to_str().unwrap_or_default().to_string()
-    ).unwrap_or_default();
-    Self { duration_ms }
+  /// Metadata of the file along with anything that had to be ignored while reading it
+  pub fn from_path(path: &path::PathBuf) -> (Self, Vec<Warning>) {
+    let mut warnings = Vec::new();
+    let duration_ms = match video::get_checked_duration(path) {
+      Ok((duration_ms, warning)) => {
+        warnings.extend(warning);
+        duration_ms
+      }
+      Err(_) => 0,
+    };
+    let subtitles = subtitle::find_sidecars(path);
+    let kind = metadata::MediaKind::from_path(path);
+    let photo = match kind {
+      metadata::MediaKind::Image => metadata::photo_details(path),
+      _ => None,
+    };
+    let music = match kind {
+      metadata::MediaKind::Audio => metadata::music_tags(path),
+      _ => None,
+    };
+    let (sidecar, warning) = sidecar::load_checked(path);
+    warnings.extend(warning);
+    (Self { duration_ms, subtitles, photo, music, sidecar }, warnings)
   }
 }
 
@@ -47,18 +305,102 @@ pub struct FileInfo {
   is_folder: bool,
   name: String,
   mime: String,
+  #[serde(flatten)]
+  details: Option<FileDetails>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  error: Option<String>,
+}
+
+/// Bare listing entry for clients that only need to navigate
+#[derive(Debug, Serialize)]
+pub struct MinimalFileInfo<'a> {
+  href: &'a str,
+  is_folder: bool,
+  name: &'a str,
+}
+
+/// Extra information that requires reading the file, only sent when asked for
+#[derive(Debug, Default, Serialize)]
+pub struct FileDetails {
+  size: u64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  duration_ms: Option<i64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  width: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  height: Option<u32>,
+  /// Base64 ThumbHash of the thumbnail, files without one don't get it
+  #[serde(skip_serializing_if = "Option::is_none")]
+  thumbhash: Option<String>,
 }
 
 impl FileInfo {
+  /// Path relative to the media folder
+  pub fn path(&self) -> &str {
+    self.href.trim_start_matches('/')
+  }
+
+  pub fn file_type(&self) -> &str {
+    &self.file_type
+  }
+
+  pub fn minimal(&self) -> MinimalFileInfo<'_> {
+    MinimalFileInfo { href: &self.href, is_folder: self.is_folder, name: &self.name }
+  }
+
+  /// Issue that kept this entry from being linked to
+  pub fn warning(&self) -> Option<Warning> {
+    let error = self.error.as_ref()?;
+    Some(Warning::new("unavailable", error.clone()).at(&self.name))
+  }
+
+  /// Fills in the size, duration, dimensions and thumbnail placeholder of the file, returning
+  /// what couldn't be trusted
+  pub fn enrich(&mut self) -> Vec<Warning> {
+    if self.is_folder || self.details.is_some() {
+      return Vec::new()
+    }
+    let mut warnings = Vec::new();
+    let file_path = get_media_path(self.path());
+    let mut details = FileDetails {
+      size: std::fs::metadata(&file_path).map(|meta| meta.len()).unwrap_or_default(),
+      ..Default::default()
+    };
+
+    match self.file_type.as_str() {
+      "video" => {
+        if let Ok(info) = video::get_info(&file_path) {
+          details.duration_ms = Some(info.duration_ms);
+          details.width = Some(info.width);
+          details.height = Some(info.height);
+          let path = self.path().to_string();
+          warnings.extend(info.warnings.into_iter().map(|warning| warning.at(path.clone())));
+        }
+      }
+      "image" => {
+        if let Some((width, height)) = metadata::image_dimensions(&file_path) {
+          details.width = Some(width);
+          details.height = Some(height);
+        }
+      }
+      _ => {}
+    }
+    if self.api_href.starts_with("/api/thumbnail/") {
+      details.thumbhash = cache::placeholder(self.path());
+    }
+    self.details = Some(details);
+    warnings
+  }
+
   pub fn from_path(file_path: &path::PathBuf) -> std::io::Result<Self> {
     let name = file_path
     .file_name().unwrap_or_default()
-    .to_str().unwrap_or_default();
+    .to_string_lossy();
     let is_folder = file_path.is_dir();
-    let url_path = match file_path.strip_prefix(MEDIA_FOLDER) {
-      Ok(path) => path.to_str().unwrap_or_default(),
-      Err(_) => "",
-    }.replace("\\", "/");
+    let url_path = get_relative_path(file_path).ok_or_else(|| std::io::Error::new(
+      std::io::ErrorKind::InvalidData,
+      "Name is not valid UTF-8 and can't be linked to",
+    ))?;
 
     if is_folder {
       return Ok(Self {
@@ -68,17 +410,22 @@ impl FileInfo {
         is_folder,
         name: name.to_string(),
         mime: "application/json".into(),
+        details: None,
+        error: None,
       })
     }
 
     let named_file = actix_fs::NamedFile::open(&file_path)?;
     let content_type = named_file.content_type();
     let mut file_type = content_type.type_().to_string();
-    if file_type == "application" {
+    if book::is_book(file_path) {
+      file_type = "book".into();
+    } else if file_type == "application" {
       file_type = content_type.subtype().to_string();
     }
 
-    let endpoint = if file_type == "video" {
+    let has_thumbnail = file_type == "video" || file_type == "book" || sidecar::artwork(&file_path).is_some();
+    let endpoint = if has_thumbnail && thumbnail_policy(&url_path) != config::ThumbnailPolicy::Off {
       "api/thumbnail"
     } else {"file"}.to_string();
 
@@ -89,10 +436,24 @@ impl FileInfo {
       is_folder,
       name: name.to_string(),
       mime: content_type.to_string(),
+      details: None,
+      error: None,
     })
   }
 }
 
+impl FileInfo {
+  /// Entry for a file that's listed but can't be opened or linked to
+  pub fn unavailable(file_path: &path::Path, err: std::io::Error) -> Self {
+    Self {
+      is_folder: file_path.is_dir(),
+      name: file_path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+      error: Some(err.to_string()),
+      ..Default::default()
+    }
+  }
+}
+
 impl Default for FileInfo {
   fn default() -> Self {
     Self {
@@ -102,6 +463,8 @@ impl Default for FileInfo {
       is_folder: false,
       name: "unknown".into(),
       mime: "text/plain".into(),
+      details: None,
+      error: None,
     }
   }
 }
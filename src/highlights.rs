@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::SystemTime;
+
+use serde::Serialize;
+
+use crate::video::{self, VideoError};
+use crate::{f, file, util};
+
+/// Points of the video scored, spread evenly over it
+const SAMPLES: u32 = 120;
+pub const DEFAULT_COUNT: usize = 8;
+pub const MAX_COUNT: usize = 30;
+/// Videos whose scores are kept in memory, the whole lot is dropped past this
+const CACHED_SCORES: usize = 64;
+
+/// Motion scores by file, with the modification time they were made for
+type Scores = HashMap<PathBuf, (SystemTime, Arc<Vec<(u32, f32)>>)>;
+
+static SCORES: OnceLock<Mutex<Scores>> = OnceLock::new();
+
+#[derive(Debug, Serialize)]
+pub struct Moment {
+  pub seconds: u32,
+  /// How much the picture changes there, from 0 to 1
+  pub score: f32,
+  /// Thumbnail URL of the moment
+  pub thumbnail: String,
+}
+
+/// The `count` moments of the video at `path` with the most motion, in the order they happen.
+/// They're kept apart so a single long action scene doesn't take every spot
+pub fn find(path: &str, count: usize) -> Result<Vec<Moment>, VideoError> {
+  let scores = scores(&file::get_media_path(path))?;
+  let last_second = scores.last().map_or(0, |&(seconds, _)| seconds);
+  let spacing = last_second / (count as u32 * 2).max(1);
+
+  let mut ranked = scores.to_vec();
+  ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+  let mut picked: Vec<(u32, f32)> = Vec::with_capacity(count);
+  for (seconds, score) in ranked {
+    if picked.len() == count {
+      break
+    }
+    if picked.iter().all(|&(other, _)| other.abs_diff(seconds) >= spacing) {
+      picked.push((seconds, score));
+    }
+  }
+  picked.sort_by_key(|&(seconds, _)| seconds);
+
+  Ok(picked.into_iter().map(|(seconds, score)| Moment {
+    seconds,
+    score,
+    thumbnail: f!("/api/thumbnail/{}?seek={seconds}", util::encode_path(path)),
+  }).collect())
+}
+
+fn scores(file_path: &Path) -> Result<Arc<Vec<(u32, f32)>>, VideoError> {
+  let modified = std::fs::metadata(file_path)
+  .and_then(|meta| meta.modified())
+  .map_err(|err| (f!("Could not read {file_path:?}"), err))?;
+  if let Some((cached, scores)) = cached_scores().get(file_path) {
+    if *cached == modified {
+      return Ok(scores.clone())
+    }
+  }
+
+  let scores = Arc::new(video::get_motion_scores(file_path, SAMPLES)?);
+  let mut cache = cached_scores();
+  if cache.len() >= CACHED_SCORES {
+    cache.clear();
+  }
+  cache.insert(file_path.to_path_buf(), (modified, scores.clone()));
+  Ok(scores)
+}
+
+fn cached_scores() -> MutexGuard<'static, Scores> {
+  SCORES
+  .get_or_init(|| Mutex::new(HashMap::new()))
+  .lock()
+  .unwrap_or_else(|err| err.into_inner())
+}
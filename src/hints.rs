@@ -0,0 +1,88 @@
+use std::future::{ready, Ready};
+
+use actix_web::{dev::Payload, http::header, web, FromRequest, HttpRequest, HttpResponseBuilder};
+use serde::Deserialize;
+
+/// Thumbnail widths picked from client hints, so devices asking for similar sizes share cache entries
+pub const WIDTH_BUCKETS: [u32; 9] = [160, 240, 320, 480, 640, 960, 1280, 1920, 2560];
+/// Hints the server asks browsers for, also what thumbnails vary on
+const ACCEPT_CH: &str = "Sec-CH-Width, Sec-CH-Viewport-Width, Sec-CH-DPR";
+const MAX_DPR: f32 = 4.;
+
+/// Device pixel ratio and layout widths sent by the client, from `?dpr=` or the
+/// `Sec-CH-DPR`, `Sec-CH-Width` and `Sec-CH-Viewport-Width` headers (or their unprefixed names).
+/// The query wins over the header
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ClientHints {
+  pub dpr: Option<f32>,
+  /// Width of the image in device pixels
+  pub width: Option<u32>,
+  /// Width of the viewport in CSS pixels
+  pub viewport_width: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct DprQuery {
+  dpr: Option<f32>,
+}
+
+impl ClientHints {
+  /// Bucketed thumbnail width for a `requested` width in CSS pixels, `None` when there's nothing to
+  /// go on and the requested width should be used as is
+  pub fn thumbnail_width(&self, requested: Option<u32>) -> Option<u32> {
+    let dpr = self.dpr.unwrap_or(1.);
+    let wanted = match requested.filter(|&width| width > 0) {
+      Some(width) => width as f32 * self.dpr?,
+      None => match self.width {
+        Some(width) => width as f32,
+        None => self.viewport_width? as f32 * dpr,
+      },
+    };
+    Some(bucket(wanted.ceil() as u32))
+  }
+
+  /// Asks browsers to keep sending hints and tells caches the response depends on them
+  pub fn advertise(response: &mut HttpResponseBuilder) {
+    response
+    .insert_header(("Accept-CH", ACCEPT_CH))
+    .insert_header((header::VARY, ACCEPT_CH));
+  }
+}
+
+/// Smallest bucket at least `width` wide, or the largest one
+pub fn bucket(width: u32) -> u32 {
+  WIDTH_BUCKETS
+  .into_iter()
+  .find(|&bucket| bucket >= width)
+  .unwrap_or(WIDTH_BUCKETS[WIDTH_BUCKETS.len() - 1])
+}
+
+fn parse_dpr(dpr: f32) -> Option<f32> {
+  (dpr.is_finite() && dpr > 0.).then(|| dpr.min(MAX_DPR))
+}
+
+fn header<T: std::str::FromStr>(req: &HttpRequest, names: [&str; 2]) -> Option<T> {
+  names
+  .into_iter()
+  .filter_map(|name| req.headers().get(name))
+  .filter_map(|value| value.to_str().ok())
+  .find_map(|value| value.trim().parse().ok())
+}
+
+impl FromRequest for ClientHints {
+  type Error = actix_web::Error;
+  type Future = Ready<Result<Self, Self::Error>>;
+
+  fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+    let from_query = web::Query::<DprQuery>::from_query(req.query_string())
+    .ok()
+    .and_then(|query| query.dpr)
+    .and_then(parse_dpr);
+
+    ready(Ok(Self {
+      dpr: from_query.or_else(|| header(req, ["Sec-CH-DPR", "DPR"]).and_then(parse_dpr)),
+      width: header(req, ["Sec-CH-Width", "Width"]).filter(|&width| width > 0),
+      viewport_width: header(req, ["Sec-CH-Viewport-Width", "Viewport-Width"]).filter(|&width| width > 0),
+    }))
+  }
+}
@@ -0,0 +1,17 @@
+use crate::{f, segment};
+
+/// Starts a playback of `path` and returns its playlist. Segments are listed relative to it as
+/// `{n}.ts?playback=<id>`, so they're fetched next to the playlist and cached for this playback
+pub fn playlist(path: &str, duration_ms: i64) -> String {
+  let id = segment::start(path);
+  let mut playlist = f!(
+    "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-PLAYLIST-TYPE:VOD\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:0\n",
+    segment::SEGMENT_SECS
+  );
+  for index in 0..segment::count(duration_ms) {
+    let length = segment::length(duration_ms, index);
+    playlist.push_str(&f!("#EXTINF:{length:.3},\n{index}.ts?playback={id}\n"));
+  }
+  playlist.push_str("#EXT-X-ENDLIST\n");
+  playlist
+}
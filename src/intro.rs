@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::SystemTime;
+
+use serde::Serialize;
+
+use crate::metadata::MediaKind;
+use crate::{access, collate, f, file, math, sidecar, video};
+
+/// Audio is fingerprinted at this rate, plenty for the bands compared
+const FINGERPRINT_RATE: u32 = 8000;
+/// Samples per fingerprint frame, 128 ms at `FINGERPRINT_RATE`
+pub const FRAME_LEN: usize = 1024;
+const FRAME_SECS: f64 = FRAME_LEN as f64 / FINGERPRINT_RATE as f64;
+/// Frequency range split into 33 bands, each bit of a frame compares two neighboring bands
+const MIN_FREQ: f32 = 250.;
+const MAX_FREQ: f32 = 2500.;
+const BANDS: usize = 33;
+/// Frames quieter than this RMS are silence, which every episode shares and never matches
+const SILENCE_RMS: f32 = 0.005;
+/// Seconds searched from the start of each episode for the intro and from its end for the
+/// outro, ten minutes
+const SEARCH_SECS: f64 = 600.;
+/// Frames whose fingerprints differ in at most this many bits match
+const MAX_BIT_ERRORS: u32 = 6;
+/// Frames that don't match allowed inside a match, about a second
+const MAX_GAP: usize = 8;
+/// Shorter matches are more likely a shared sound effect than an intro
+const MIN_MATCH_SECS: f64 = 15.;
+/// Episodes compared, the closest ones by name
+const EPISODES_COMPARED: usize = 4;
+/// Fingerprints kept in memory, the whole lot is dropped past this
+const CACHED_FINGERPRINTS: usize = 256;
+
+/// Fingerprints by file and part, with the modification time they were made for
+type Fingerprints = HashMap<(PathBuf, Part), (SystemTime, Arc<Fingerprint>)>;
+
+static FINGERPRINTS: OnceLock<Mutex<Fingerprints>> = OnceLock::new();
+
+/// Seconds of an episode
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SkipRange {
+  pub start: f64,
+  pub end: f64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Analysis {
+  pub intro: Option<SkipRange>,
+  pub outro: Option<SkipRange>,
+  /// Episodes the file was compared with, relative to the media folder
+  pub compared: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Part {
+  Intro,
+  Outro,
+}
+
+/// Audio fingerprint of part of a file, one 32 bit hash per frame
+struct Fingerprint {
+  /// Second of the file the first frame starts at
+  start_secs: f64,
+  /// `None` for silent frames
+  frames: Vec<Option<u32>>,
+}
+
+/// Finds the intro and outro `path` shares with the other episodes in its folder by comparing the
+/// audio of their first and last ten minutes. Either is `None` when no long enough match is found
+pub fn detect(path: &str, identity: &access::Identity) -> Result<Analysis, video::VideoError> {
+  let file_path = file::get_media_path(path);
+  let episodes = episodes(&file_path, identity);
+  let mut analysis = Analysis {
+    compared: episodes.iter().map(|episode| file::get_relative_path_lossy(episode)).collect(),
+    ..Analysis::default()
+  };
+  if episodes.is_empty() {
+    return Ok(analysis)
+  }
+
+  for part in [Part::Intro, Part::Outro] {
+    let target = fingerprint(&file_path, part)?;
+    let ranges: Vec<SkipRange> = episodes
+    .iter()
+    .filter_map(|episode| match fingerprint(episode, part) {
+      Ok(other) => longest_match(&target.frames, &other.frames),
+      Err(err) => {
+        eprintln!("Could not fingerprint {episode:?} - {err:?}");
+        None
+      }
+    })
+    .map(|(first, len)| SkipRange {
+      start: target.start_secs + first as f64 * FRAME_SECS,
+      end: target.start_secs + (first + len) as f64 * FRAME_SECS,
+    })
+    .collect();
+    let range = consensus(&ranges);
+    match part {
+      Part::Intro => analysis.intro = range,
+      Part::Outro => analysis.outro = range,
+    }
+  }
+  Ok(analysis)
+}
+
+/// Files of the same kind as `file_path` in its folder closest to it by name, excluding itself
+fn episodes(file_path: &Path, identity: &access::Identity) -> Vec<PathBuf> {
+  let kind = MediaKind::from_path(file_path);
+  let Some(folder) = file_path.parent() else { return Vec::new() };
+  let Ok(dir) = std::fs::read_dir(folder) else { return Vec::new() };
+  let mut files: Vec<PathBuf> = dir
+  .flatten()
+  .map(|entry| entry.path())
+  .filter(|path| {
+    path.as_path() == file_path || (
+      path.is_file() &&
+      MediaKind::from_path(path) == kind &&
+      !sidecar::is_sidecar(path) &&
+      identity.can_see(&file::get_relative_path_lossy(path))
+    )
+  })
+  .collect();
+  files.sort_by(|a, b| collate::compare(&a.to_string_lossy(), &b.to_string_lossy()));
+
+  let Some(position) = files.iter().position(|path| path.as_path() == file_path) else { return Vec::new() };
+  let mut episodes: Vec<(usize, PathBuf)> = files
+  .into_iter()
+  .enumerate()
+  .filter(|(index, _)| *index != position)
+  .map(|(index, path)| (index.abs_diff(position), path))
+  .collect();
+  episodes.sort_by_key(|(distance, _)| *distance);
+  episodes.into_iter().take(EPISODES_COMPARED).map(|(_, path)| path).collect()
+}
+
+fn fingerprint(file_path: &Path, part: Part) -> Result<Arc<Fingerprint>, video::VideoError> {
+  let modified = std::fs::metadata(file_path)
+  .and_then(|meta| meta.modified())
+  .map_err(|err| (f!("Could not read {file_path:?}"), err))?;
+  let key = (file_path.to_path_buf(), part);
+  if let Some((cached, fingerprint)) = fingerprints().get(&key) {
+    if *cached == modified {
+      return Ok(fingerprint.clone())
+    }
+  }
+
+  let duration_secs = video::get_duration_from_path(file_path)? as f64 / 1000.;
+  let start_secs = match part {
+    Part::Intro => 0.,
+    Part::Outro => (duration_secs - SEARCH_SECS).max(0.),
+  };
+  let samples = video::decode_audio(file_path, start_secs, SEARCH_SECS, FINGERPRINT_RATE)?;
+  let fingerprint = Arc::new(Fingerprint { start_secs, frames: hash_frames(&samples) });
+
+  let mut fingerprints = fingerprints();
+  if fingerprints.len() >= CACHED_FINGERPRINTS {
+    fingerprints.clear();
+  }
+  fingerprints.insert(key, (modified, fingerprint.clone()));
+  Ok(fingerprint)
+}
+
+/// Hashes every `FRAME_LEN` samples of mono audio at `FINGERPRINT_RATE`, each bit tells whether the
+/// energy difference between two neighboring bands grew since the previous frame, which survives
+/// volume and encoding changes. Silent frames are `None`
+pub fn hash_frames(samples: &[f32]) -> Vec<Option<u32>> {
+  let bin_hz = FINGERPRINT_RATE as f32 / FRAME_LEN as f32;
+  // Band edges spaced evenly in pitch, as bins of the spectrum
+  let edges: Vec<usize> = (0..=BANDS)
+  .map(|band| {
+    let freq = MIN_FREQ * (MAX_FREQ / MIN_FREQ).powf(band as f32 / BANDS as f32);
+    (freq / bin_hz) as usize
+  })
+  .collect();
+
+  let mut previous = [0f32; BANDS];
+  samples
+  .chunks_exact(FRAME_LEN)
+  .map(|frame| {
+    let spectrum = math::power_spectrum(frame);
+    let mut energies = [0f32; BANDS];
+    for (band, energy) in energies.iter_mut().enumerate() {
+      *energy = spectrum[edges[band]..edges[band + 1].max(edges[band] + 1)].iter().sum();
+    }
+    let hash = (0..BANDS - 1).fold(0u32, |hash, band| {
+      let grew = energies[band] - energies[band + 1] > previous[band] - previous[band + 1];
+      hash | (u32::from(grew) << band)
+    });
+    previous = energies;
+
+    let rms = (frame.iter().map(|sample| sample * sample).sum::<f32>() / FRAME_LEN as f32).sqrt();
+    (rms >= SILENCE_RMS).then_some(hash)
+  })
+  .collect()
+}
+
+/// First frame in `a` and length of the longest stretch it shares with `b` at any offset,
+/// `None` if it's shorter than `MIN_MATCH_SECS`
+pub fn longest_match(a: &[Option<u32>], b: &[Option<u32>]) -> Option<(usize, usize)> {
+  let matches = |i: usize, j: usize| match (a[i], b[j]) {
+    (Some(a), Some(b)) => (a ^ b).count_ones() <= MAX_BIT_ERRORS,
+    _ => false,
+  };
+  let mut best: Option<(usize, usize)> = None;
+  let mut keep = |first: usize, last: usize| {
+    let len = last - first + 1;
+    if best.is_none_or(|(_, best_len)| len > best_len) {
+      best = Some((first, len));
+    }
+  };
+
+  // Offset of `b` against `a`, frame `i` of `a` is compared with frame `i + offset` of `b`
+  for offset in -(a.len() as isize - 1)..b.len() as isize {
+    let first_i = (-offset).max(0) as usize;
+    let last_i = (b.len() as isize - offset).min(a.len() as isize) as usize;
+    // First and last matching frame of the stretch being followed
+    let mut stretch: Option<(usize, usize)> = None;
+    for i in first_i..last_i {
+      let j = (i as isize + offset) as usize;
+      if matches(i, j) {
+        stretch = Some(stretch.map_or((i, i), |(first, _)| (first, i)));
+      } else if let Some((first, last)) = stretch.filter(|&(_, last)| i - last > MAX_GAP) {
+        keep(first, last);
+        stretch = None;
+      }
+    }
+    if let Some((first, last)) = stretch {
+      keep(first, last);
+    }
+  }
+  best.filter(|&(_, len)| len as f64 * FRAME_SECS >= MIN_MATCH_SECS)
+}
+
+/// Range most of the others overlap, the longest one on ties
+fn consensus(ranges: &[SkipRange]) -> Option<SkipRange> {
+  let overlaps = |range: &SkipRange| {
+    ranges
+    .iter()
+    .filter(|other| other.start < range.end && range.start < other.end)
+    .count()
+  };
+  ranges.iter().copied().max_by(|a, b| {
+    overlaps(a)
+    .cmp(&overlaps(b))
+    .then_with(|| (a.end - a.start).total_cmp(&(b.end - b.start)))
+  })
+}
+
+fn fingerprints() -> MutexGuard<'static, Fingerprints> {
+  FINGERPRINTS
+  .get_or_init(|| Mutex::new(HashMap::new()))
+  .lock()
+  .unwrap_or_else(|err| err.into_inner())
+}
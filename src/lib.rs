@@ -0,0 +1,49 @@
+extern crate ffmpeg_next as ffmpeg;
+
+use format as f;
+
+pub mod access;
+pub mod audit;
+pub mod batch;
+pub mod book;
+pub mod cache;
+pub mod capabilities;
+pub mod cast;
+pub mod collate;
+pub mod collisions;
+pub mod config;
+pub mod dash;
+pub mod description;
+pub mod duplicates;
+pub mod envelope;
+pub mod events;
+pub mod feed;
+pub mod file;
+pub mod highlights;
+pub mod hints;
+pub mod hls;
+pub mod intro;
+pub mod library;
+pub mod manifest;
+pub mod math;
+pub mod metadata;
+pub mod perf;
+pub mod prefer;
+pub mod raw;
+pub mod segment;
+pub mod session;
+pub mod sidecar;
+pub mod spectrogram;
+pub mod storage;
+pub mod storyboard;
+pub mod stream;
+pub mod subtitle;
+pub mod tags;
+pub mod thumbhash;
+pub mod transcode;
+pub mod transfer;
+pub mod trash;
+pub mod userdata;
+pub mod util;
+pub mod video;
+pub mod waveform;
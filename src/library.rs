@@ -0,0 +1,736 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::Serialize;
+
+use crate::metadata::{self, MediaKind};
+use crate::{access, config, events, f, file, sidecar, tags, trash, util};
+
+static CONNECTION: OnceLock<Mutex<Connection>> = OnceLock::new();
+static SCANNER: OnceLock<Mutex<ScanStatus>> = OnceLock::new();
+/// Wakes the background scanner up when a scan is requested
+static SCAN_REQUESTED: Condvar = Condvar::new();
+
+/// Changes made to the index in order, its `user_version` is how many were applied. Applied ones
+/// must never change, anything new goes in a new migration
+const MIGRATIONS: [&str; 2] = [
+  "
+  CREATE TABLE IF NOT EXISTS files (
+    path TEXT PRIMARY KEY,
+    kind TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    mtime INTEGER NOT NULL,
+    taken_at INTEGER,
+    lat REAL,
+    lon REAL,
+    checksum TEXT,
+    width INTEGER,
+    height INTEGER,
+    duration_ms INTEGER
+  );
+  CREATE INDEX IF NOT EXISTS files_taken_at ON files(taken_at);
+  CREATE TABLE IF NOT EXISTS tags (
+    path TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (path, tag)
+  );
+  CREATE INDEX IF NOT EXISTS tags_tag ON tags(tag);
+  ",
+  // Ids are never reused so they work as cursors even after old changes are dropped
+  "
+  CREATE TABLE IF NOT EXISTS changes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    path TEXT NOT NULL,
+    change TEXT NOT NULL
+  );
+  ",
+];
+
+/// Columns indexes made before there were migrations may lack, they get them added
+/// and every file probed again before the first migration
+const LEGACY_COLUMNS: [(&str, &str); 6] = [
+  ("lat", "REAL"),
+  ("lon", "REAL"),
+  ("checksum", "TEXT"),
+  ("width", "INTEGER"),
+  ("height", "INTEGER"),
+  ("duration_ms", "INTEGER"),
+];
+
+/// How long to wait for another connection, e.g. from another process, to finish writing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+/// Changes kept for clients mirroring the index, the ones behind get the whole index again
+const MAX_CHANGES: i64 = 100_000;
+pub const CHANGES_PER_PAGE: usize = 1000;
+pub const MAX_CHANGES_PER_PAGE: usize = 10_000;
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ScanStats {
+  pub added: usize,
+  pub updated: usize,
+  pub removed: usize,
+  /// Files still being written, left for the next scan
+  pub pending: usize,
+}
+
+/// What the background scanner is up to
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ScanStatus {
+  pub running: bool,
+  /// A scan was asked for, it starts once the running one is done
+  pub requested: bool,
+  /// Unix time the last scan ended at, successful or not
+  pub finished_at: Option<u64>,
+  /// Of the last scan when it succeeded
+  pub stats: Option<ScanStats>,
+  /// Why the last scan failed
+  pub error: Option<String>,
+}
+
+/// Index entries that changed after a cursor, for clients that mirror the library
+#[derive(Debug, Default, Serialize)]
+pub struct ChangeSet {
+  /// Pass it as `since` to get the changes after these
+  pub cursor: i64,
+  /// The cursor was too old or unknown and `changes` is the whole index, drop everything
+  /// mirrored before
+  pub reset: bool,
+  /// There are more changes after `cursor`
+  pub more: bool,
+  pub changes: Vec<Change>,
+}
+
+/// Last change of an entry, along with what it's like now unless it was removed
+#[derive(Debug, Serialize)]
+pub struct Change {
+  pub path: String,
+  /// `added`, `updated` or `removed`
+  pub change: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub kind: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub size: Option<u64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub mtime: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagCount {
+  tag: String,
+  count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimelineItem {
+  path: String,
+  kind: MediaKind,
+  taken_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimelineGroup {
+  /// Year, or month when listing a single year
+  key: u32,
+  count: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Timeline {
+  Groups(Vec<TimelineGroup>),
+  Items(Vec<TimelineItem>),
+}
+
+pub fn open() -> rusqlite::Result<MutexGuard<'static, Connection>> {
+  if let Some(connection) = CONNECTION.get() {
+    return Ok(connection.lock().unwrap_or_else(|err| err.into_inner()))
+  }
+
+  let path = Path::new(&config::get().data_folder).join("index.sqlite");
+  if let Some(parent) = path.parent() {
+    let _ = std::fs::create_dir_all(parent);
+  }
+  let mut connection = Connection::open(path)?;
+  connection.busy_timeout(BUSY_TIMEOUT)?;
+  // Readers in other processes don't block writes, nor writes them
+  connection.pragma_update(None, "journal_mode", "WAL")?;
+  migrate(&mut connection)?;
+
+  Ok(
+    CONNECTION
+    .get_or_init(|| Mutex::new(connection))
+    .lock()
+    .unwrap_or_else(|err| err.into_inner())
+  )
+}
+
+/// Applies the migrations the index at `connection` is missing. They run in a single exclusive
+/// transaction that's rolled back if any fails, a server and a command opening the index at once
+/// take turns and the second one finds nothing left to do. Indexes made by a newer version
+/// aren't touched and fail to open
+pub fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+  let transaction = connection.transaction_with_behavior(TransactionBehavior::Exclusive)?;
+  let version: usize = transaction.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+  if version > MIGRATIONS.len() {
+    return Err(rusqlite::Error::SqliteFailure(
+      rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_ERROR),
+      Some(f!("Index is at schema version {version}, this version of the server only knows up to {}", MIGRATIONS.len())),
+    ))
+  }
+  if version == 0 {
+    add_legacy_columns(&transaction)?;
+  }
+  for migration in &MIGRATIONS[version..] {
+    transaction.execute_batch(migration)?;
+  }
+  if version < MIGRATIONS.len() {
+    transaction.pragma_update(None, "user_version", MIGRATIONS.len())?;
+  }
+  transaction.commit()
+}
+
+/// Brings a `files` table made before there were migrations up to the first one
+fn add_legacy_columns(connection: &Connection) -> rusqlite::Result<()> {
+  let columns: HashSet<String> = connection
+  .prepare("SELECT name FROM pragma_table_info('files')")?
+  .query_map([], |row| row.get(0))?
+  .collect::<rusqlite::Result<_>>()?;
+  // Nothing to bring up to date in a new index
+  if columns.is_empty() {
+    return Ok(())
+  }
+
+  let mut added = false;
+  for (name, column_type) in LEGACY_COLUMNS {
+    if !columns.contains(name) {
+      connection.execute_batch(&f!("ALTER TABLE files ADD COLUMN {name} {column_type}"))?;
+      added = true;
+    }
+  }
+  if added {
+    connection.execute("UPDATE files SET mtime = -1", [])?;
+  }
+  Ok(())
+}
+
+/// Scans the media folder and each mount in the background every `scan_interval_secs` of its
+/// own, sooner when files were skipped because they were still being written or a scan was
+/// requested. Roots with an interval of 0 are only scanned on start and when requested
+pub fn start_scanner() {
+  std::thread::spawn(|| {
+    // When each root is scanned next, `None` when only on request. Roots missing from it are due
+    let mut next_scans: HashMap<PathBuf, Option<Instant>> = HashMap::new();
+    let mut status = scanner();
+    loop {
+      let requested = std::mem::take(&mut status.requested);
+      let roots = roots();
+      next_scans.retain(|root, _| roots.iter().any(|(known, _)| known == root));
+      let now = Instant::now();
+      let due: Vec<PathBuf> = roots
+      .iter()
+      .filter(|(root, _)| requested || next_scans.get(root).is_none_or(|next| next.is_some_and(|next| next <= now)))
+      .map(|(root, _)| root.clone())
+      .collect();
+
+      if !due.is_empty() {
+        status.running = true;
+        drop(status);
+        let scanned = scan_roots(&due);
+        status = scanner();
+        status.running = false;
+        status.finished_at = Some(util::now());
+        let finished = Instant::now();
+        let pending = scanned.as_ref().is_ok_and(|stats| stats.pending > 0);
+        for (root, interval) in roots.iter().filter(|(root, _)| due.contains(root)) {
+          let mut next = (*interval > 0).then(|| finished + Duration::from_secs(*interval));
+          if pending {
+            let soon = finished + Duration::from_secs(config::get().stable_after_secs.max(1));
+            next = Some(next.map_or(soon, |next| next.min(soon)));
+          }
+          next_scans.insert(root.clone(), next);
+        }
+        match scanned {
+          Ok(stats) => {
+            println!(
+              "Library scan of {} folders finished - {} added, {} updated, {} removed, {} pending",
+              due.len(), stats.added, stats.updated, stats.removed, stats.pending,
+            );
+            status.stats = Some(stats);
+            status.error = None;
+          }
+          Err(err) => {
+            eprintln!("Library scan failed - {err:?}");
+            status.stats = None;
+            status.error = Some(f!("{err:?}"));
+          }
+        }
+      }
+
+      status = match next_scans.values().flatten().min() {
+        Some(next) => {
+          SCAN_REQUESTED
+          .wait_timeout_while(status, next.saturating_duration_since(Instant::now()), |status| !status.requested)
+          .unwrap_or_else(|err| err.into_inner())
+          .0
+        }
+        None => SCAN_REQUESTED
+          .wait_while(status, |status| !status.requested)
+          .unwrap_or_else(|err| err.into_inner()),
+      };
+    }
+  });
+}
+
+/// Has the background scanner scan every root now instead of waiting for their
+/// `scan_interval_secs`, or right after the scan it's running
+pub fn request_scan() {
+  scanner().requested = true;
+  SCAN_REQUESTED.notify_all();
+}
+
+pub fn scan_status() -> ScanStatus {
+  scanner().clone()
+}
+
+/// Brings the index up to date with the media folder and every mount, only files that
+/// changed since the last scan are probed again
+pub fn scan() -> rusqlite::Result<ScanStats> {
+  let roots: Vec<PathBuf> = roots().into_iter().map(|(root, _)| root).collect();
+  scan_roots(&roots)
+}
+
+/// Same as `scan` for only the media folder or mounts in `roots`, what's indexed from the
+/// rest is left as is
+fn scan_roots(roots: &[PathBuf]) -> rusqlite::Result<ScanStats> {
+  let mut found = Vec::new();
+  for root in roots {
+    walk(root, &mut found);
+  }
+
+  let known: HashMap<String, (u64, i64)> = {
+    let connection = open()?;
+    let mut statement = connection.prepare("SELECT path, size, mtime FROM files")?;
+    let rows = statement.query_map([], |row| {
+      Ok((row.get::<_, String>(0)?, (row.get::<_, u64>(1)?, row.get::<_, i64>(2)?)))
+    })?;
+    rows.collect::<rusqlite::Result<_>>()?
+  };
+
+  let mut stats = ScanStats::default();
+  let mut changed = Vec::new();
+  let mut changes = Vec::new();
+  let mut seen = HashSet::new();
+
+  // Probing is slow so it's done without holding the connection
+  for file_path in found {
+    // Files that can't be linked to are left out of the index
+    let Some(path) = file::get_relative_path(&file_path) else { continue };
+    if !file::is_stable(&file_path) {
+      // Keep whatever was indexed before it started changing
+      stats.pending += 1;
+      seen.insert(path);
+      continue
+    }
+    let (size, mtime) = match std::fs::metadata(&file_path) {
+      Ok(meta) => (meta.len(), metadata::modified_time(&file_path).unwrap_or_default()),
+      Err(_) => continue,
+    };
+    if known.get(&path) != Some(&(size, mtime)) {
+      let kind = if known.contains_key(&path) {
+        stats.updated += 1;
+        events::EventKind::Updated
+      } else {
+        stats.added += 1;
+        events::EventKind::Added
+      };
+      changes.push(events::Event { kind, path: path.clone() });
+      let kind = MediaKind::from_path(&file_path);
+      let probe = match kind {
+        MediaKind::Image | MediaKind::Video => metadata::probe(&file_path, kind),
+        _ => metadata::Probe::default(),
+      };
+      let checksum = if config::get().index_checksums {file::sha256(&file_path).ok()} else {None};
+      changed.push((path.clone(), kind, size, mtime, probe, checksum));
+    }
+    seen.insert(path);
+  }
+
+  let mut connection = open()?;
+  // Takes the write lock from the start, a read first could find another process wrote since
+  let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+  for (path, kind, size, mtime, probe, checksum) in &changed {
+    transaction.execute(
+      "INSERT OR REPLACE INTO files (path, kind, size, mtime, taken_at, lat, lon, checksum, width, height, duration_ms)
+      VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+      params![
+        path,
+        kind.as_str(),
+        size,
+        mtime,
+        probe.taken_at,
+        probe.location.map(|(lat, _)| lat),
+        probe.location.map(|(_, lon)| lon),
+        checksum,
+        probe.dimensions.map(|(width, _)| width),
+        probe.dimensions.map(|(_, height)| height),
+        probe.duration_ms,
+      ],
+    )?;
+  }
+  let in_roots = |path: &str| roots.iter().any(|root| root == file::get_root_path(path));
+  for path in known.keys().filter(|path| !seen.contains(*path) && in_roots(path)) {
+    transaction.execute("DELETE FROM files WHERE path = ?1", params![path])?;
+    stats.removed += 1;
+    changes.push(events::Event { kind: events::EventKind::Removed, path: path.clone() });
+  }
+  apply_tag_rules(&transaction)?;
+  record_changes(&transaction, &changes)?;
+  transaction.commit()?;
+  changes.into_iter().for_each(events::publish);
+
+  Ok(stats)
+}
+
+/// Tags every indexed file with what `tag_rules` give it, only the tags that changed are written
+fn apply_tag_rules(connection: &Connection) -> rusqlite::Result<()> {
+  let rules = &config::get().tag_rules;
+  let mut wanted: HashSet<(String, String)> = HashSet::new();
+  let mut statement = connection.prepare("SELECT path, kind, size, width, height, duration_ms FROM files")?;
+  let mut rows = statement.query([])?;
+  while let Some(row) = rows.next()? {
+    let path: String = row.get(0)?;
+    let facts = tags::Facts {
+      path: &path,
+      kind: MediaKind::parse(&row.get::<_, String>(1)?),
+      size: row.get(2)?,
+      width: row.get(3)?,
+      height: row.get(4)?,
+      duration_ms: row.get(5)?,
+    };
+    for rule in rules.iter().filter(|rule| rule.matches(&facts)) {
+      wanted.insert((path.clone(), rule.tag.clone()));
+    }
+  }
+
+  let tagged: HashSet<(String, String)> = connection
+  .prepare("SELECT path, tag FROM tags")?
+  .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+  .collect::<rusqlite::Result<_>>()?;
+  for (path, tag) in tagged.difference(&wanted) {
+    connection.execute("DELETE FROM tags WHERE path = ?1 AND tag = ?2", params![path, tag])?;
+  }
+  for (path, tag) in wanted.difference(&tagged) {
+    connection.execute("INSERT INTO tags (path, tag) VALUES (?1, ?2)", params![path, tag])?;
+  }
+  Ok(())
+}
+
+/// SHA-256 of `path` if it was hashed while indexing and hasn't changed since
+pub fn checksum(path: &str) -> rusqlite::Result<Option<String>> {
+  let indexed: Option<(u64, i64, Option<String>)> = open()?
+  .query_row(
+    "SELECT size, mtime, checksum FROM files WHERE path = ?1",
+    params![path],
+    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+  )
+  .optional()?;
+  let Some((size, mtime, checksum)) = indexed else { return Ok(None) };
+
+  let file_path = file::get_media_path(path);
+  let current_size = std::fs::metadata(&file_path).map(|meta| meta.len()).ok();
+  let current_mtime = metadata::modified_time(&file_path);
+  if current_size != Some(size) || current_mtime != Some(mtime) {
+    return Ok(None)
+  }
+  Ok(checksum)
+}
+
+/// Drops `path` and everything inside of it from the index
+pub fn forget(path: &str) -> rusqlite::Result<()> {
+  let connection = open()?;
+  let removed: Vec<events::Event> = connection
+  .prepare("SELECT path FROM files WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2")?
+  .query_map(params![path, f!("{path}/")], |row| {
+    Ok(events::Event { kind: events::EventKind::Removed, path: row.get(0)? })
+  })?
+  .collect::<rusqlite::Result<_>>()?;
+  record_changes(&connection, &removed)?;
+  for table in ["files", "tags"] {
+    connection.execute(
+      &f!("DELETE FROM {table} WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2"),
+      params![path, f!("{path}/")],
+    )?;
+  }
+  Ok(())
+}
+
+/// Adds `changes` to the ones clients mirroring the index catch up with, dropping the oldest
+/// past `MAX_CHANGES`
+pub fn record_changes(connection: &Connection, changes: &[events::Event]) -> rusqlite::Result<()> {
+  if changes.is_empty() {
+    return Ok(())
+  }
+  let mut statement = connection.prepare("INSERT INTO changes (path, change) VALUES (?1, ?2)")?;
+  for change in changes {
+    statement.execute(params![change.path, change.kind.as_str()])?;
+  }
+  connection.execute(
+    "DELETE FROM changes WHERE id <= (SELECT MAX(id) FROM changes) - ?1",
+    params![MAX_CHANGES],
+  )?;
+  Ok(())
+}
+
+/// Entries `identity` can see that changed after the cursor `since`, at most `limit` of them.
+/// A cursor of 0, or one from before the oldest change kept, gets the whole index instead
+pub fn changes(identity: &access::Identity, since: i64, limit: usize) -> rusqlite::Result<ChangeSet> {
+  let mut changes = changes_since(&*open()?, since, limit)?;
+  changes.changes.retain(|change| identity.can_see(&change.path));
+  Ok(changes)
+}
+
+/// Same as `changes` for whoever can see everything in the index at `connection`
+pub fn changes_since(connection: &Connection, since: i64, limit: usize) -> rusqlite::Result<ChangeSet> {
+  let (oldest, latest): (Option<i64>, Option<i64>) = connection.query_row(
+    "SELECT MIN(id), MAX(id) FROM changes",
+    [],
+    |row| Ok((row.get(0)?, row.get(1)?)),
+  )?;
+  let latest = latest.unwrap_or_default();
+  // Changes after the cursor were dropped, or it's from another index
+  let reset = since <= 0 || since > latest || oldest.is_some_and(|oldest| since < oldest - 1);
+  if reset {
+    let changes = connection
+    .prepare("SELECT path, kind, size, mtime FROM files ORDER BY path")?
+    .query_map([], |row| Ok(Change {
+      path: row.get(0)?,
+      change: events::EventKind::Added.as_str().into(),
+      kind: row.get(1)?,
+      size: row.get(2)?,
+      mtime: row.get(3)?,
+    }))?
+    .collect::<rusqlite::Result<_>>()?;
+    return Ok(ChangeSet { cursor: latest, reset, more: false, changes })
+  }
+
+  // The change picked for each path is the one with the highest id
+  let mut rows: Vec<(i64, Change)> = connection
+  .prepare(
+    "SELECT MAX(changes.id), changes.path, changes.change, files.kind, files.size, files.mtime
+    FROM changes LEFT JOIN files ON files.path = changes.path
+    WHERE changes.id > ?1
+    GROUP BY changes.path
+    ORDER BY MAX(changes.id)
+    LIMIT ?2",
+  )?
+  .query_map(params![since, limit as i64 + 1], |row| Ok((row.get(0)?, Change {
+    path: row.get(1)?,
+    change: row.get(2)?,
+    kind: row.get(3)?,
+    size: row.get(4)?,
+    mtime: row.get(5)?,
+  })))?
+  .collect::<rusqlite::Result<_>>()?;
+  let more = rows.len() > limit;
+  rows.truncate(limit);
+  let cursor = if more {rows.last().map_or(since, |(id, _)| *id)} else {latest};
+  Ok(ChangeSet { cursor, reset, more, changes: rows.into_iter().map(|(_, change)| change).collect() })
+}
+
+/// Every tag given by the tag rules with how many of the files `identity` can see have it
+pub fn tags(identity: &access::Identity) -> rusqlite::Result<Vec<TagCount>> {
+  let connection = open()?;
+  let mut statement = connection.prepare("SELECT tag, path FROM tags ORDER BY tag")?;
+  let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+  let mut counts: Vec<TagCount> = Vec::new();
+  for (tag, path) in rows.filter_map(|row| row.ok()) {
+    if !identity.can_see(&path) {
+      continue
+    }
+    match counts.last_mut() {
+      Some(last) if last.tag == tag => last.count += 1,
+      _ => counts.push(TagCount { tag, count: 1 }),
+    }
+  }
+  Ok(counts)
+}
+
+/// Files tagged `tag` that `identity` can see, relative to the media folder
+pub fn tagged(identity: &access::Identity, tag: &str) -> rusqlite::Result<Vec<String>> {
+  let connection = open()?;
+  let mut statement = connection.prepare("SELECT path FROM tags WHERE tag = ?1 ORDER BY path")?;
+  let rows = statement.query_map(params![tag], |row| row.get::<_, String>(0))?;
+  Ok(rows.filter_map(|row| row.ok()).filter(|path| identity.can_see(path)).collect())
+}
+
+/// Every indexed video, relative to the media folder
+pub fn videos() -> rusqlite::Result<Vec<String>> {
+  let connection = open()?;
+  let mut statement = connection.prepare("SELECT path FROM files WHERE kind = 'video' ORDER BY path")?;
+  let rows = statement.query_map([], |row| row.get(0))?;
+  rows.collect()
+}
+
+/// Groups captured images and videos by year, by month when `year` is given
+/// or lists them when both `year` and `month` are given
+pub fn timeline(
+  identity: &access::Identity,
+  year: Option<i64>,
+  month: Option<u32>,
+) -> rusqlite::Result<Timeline> {
+  let (from, to) = match (year, month) {
+    (Some(year), Some(month)) => (
+      metadata::unix_from_civil(year, month, 1),
+      if month >= 12 {
+        metadata::unix_from_civil(year + 1, 1, 1)
+      } else {
+        metadata::unix_from_civil(year, month + 1, 1)
+      },
+    ),
+    (Some(year), None) => (
+      metadata::unix_from_civil(year, 1, 1),
+      metadata::unix_from_civil(year + 1, 1, 1),
+    ),
+    _ => (i64::MIN, i64::MAX),
+  };
+
+  let connection = open()?;
+  let mut statement = connection.prepare(
+    "SELECT path, kind, taken_at FROM files
+    WHERE kind IN ('image', 'video') AND taken_at >= ?1 AND taken_at < ?2
+    ORDER BY taken_at",
+  )?;
+  let items = statement.query_map(params![from, to], |row| {
+    Ok(TimelineItem {
+      path: row.get(0)?,
+      kind: MediaKind::parse(&row.get::<_, String>(1)?),
+      taken_at: row.get(2)?,
+    })
+  })?
+  .filter_map(|item| item.ok())
+  .filter(|item| identity.can_see(&item.path));
+
+  if year.is_some() && month.is_some() {
+    return Ok(Timeline::Items(items.collect()))
+  }
+
+  let mut groups: BTreeMap<u32, usize> = BTreeMap::new();
+  for item in items {
+    let (item_year, item_month, _) = metadata::civil_from_unix(item.taken_at);
+    let key = if year.is_some() {item_month} else {item_year as u32};
+    *groups.entry(key).or_default() += 1;
+  }
+  Ok(Timeline::Groups(
+    groups.into_iter().map(|(key, count)| TimelineGroup { key, count }).collect()
+  ))
+}
+
+/// Max file paths listed per cluster
+const CLUSTER_SAMPLE_LEN: usize = 10;
+const MAX_GEO_CELLS: u32 = 256;
+
+/// `(min_lon, min_lat, max_lon, max_lat)` in degrees
+pub type BoundingBox = (f64, f64, f64, f64);
+
+#[derive(Debug, Serialize)]
+pub struct GeoCluster {
+  /// Average position of the files in the cluster
+  lat: f64,
+  lon: f64,
+  count: usize,
+  /// Some of the files in the cluster
+  paths: Vec<String>,
+}
+
+/// Groups geotagged files inside `bbox` by splitting it into a grid of
+/// `cells` x `cells` and clustering the files that fall in the same cell
+pub fn geo_clusters(
+  identity: &access::Identity,
+  bbox: BoundingBox,
+  cells: u32,
+) -> rusqlite::Result<Vec<GeoCluster>> {
+  let (min_lon, min_lat, max_lon, max_lat) = bbox;
+  let cells = cells.clamp(1, MAX_GEO_CELLS) as f64;
+  let cell_width = (max_lon - min_lon) / cells;
+  let cell_height = (max_lat - min_lat) / cells;
+
+  let connection = open()?;
+  let mut statement = connection.prepare(
+    "SELECT path, lat, lon FROM files
+    WHERE lat BETWEEN ?1 AND ?2 AND lon BETWEEN ?3 AND ?4
+    ORDER BY taken_at DESC",
+  )?;
+  let points = statement.query_map(params![min_lat, max_lat, min_lon, max_lon], |row| {
+    Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?, row.get::<_, f64>(2)?))
+  })?;
+
+  let mut grid: HashMap<(u32, u32), GeoCluster> = HashMap::new();
+  for (path, lat, lon) in points.filter_map(|point| point.ok()) {
+    if !identity.can_see(&path) {
+      continue
+    }
+    let cell = (
+      ((lon - min_lon) / cell_width).clamp(0., cells - 1.) as u32,
+      ((lat - min_lat) / cell_height).clamp(0., cells - 1.) as u32,
+    );
+    let cluster = grid.entry(cell).or_insert(GeoCluster {
+      lat: 0.,
+      lon: 0.,
+      count: 0,
+      paths: Vec::new(),
+    });
+    // Running average of the positions
+    cluster.count += 1;
+    cluster.lat += (lat - cluster.lat) / cluster.count as f64;
+    cluster.lon += (lon - cluster.lon) / cluster.count as f64;
+    if cluster.paths.len() < CLUSTER_SAMPLE_LEN {
+      cluster.paths.push(path);
+    }
+  }
+
+  let mut clusters: Vec<GeoCluster> = grid.into_values().collect();
+  clusters.sort_unstable_by_key(|cluster| std::cmp::Reverse(cluster.count));
+  Ok(clusters)
+}
+
+/// Media folder and mounts with the seconds between their scans
+fn roots() -> Vec<(PathBuf, u64)> {
+  let config = config::get();
+  let mut roots = vec![(PathBuf::from(&config.media_folder), config.scan_interval_secs)];
+  roots.extend(config.mounts.iter().map(|mount| {
+    (PathBuf::from(&mount.path), mount.scan_interval_secs.unwrap_or(config.scan_interval_secs))
+  }));
+  roots
+}
+
+/// Trash folders and folders hidden by mounts, mounts are walked on their own
+fn is_skipped(folder: &Path) -> bool {
+  let is_trash = folder.file_name().is_some_and(|name| name == trash::TRASH_FOLDER);
+  (is_trash && trash::folders().iter().any(|trash| trash == folder)) || file::is_shadowed(folder)
+}
+
+/// Collects the files under `folder` the index would, leaving out sidecars and trash
+pub fn walk(folder: &Path, found: &mut Vec<PathBuf>) {
+  let dir = match std::fs::read_dir(folder) {
+    Ok(dir) => dir,
+    Err(_) => return,
+  };
+  for entry in dir.flatten() {
+    let path = entry.path();
+    match entry.file_type() {
+      Ok(file_type) if file_type.is_dir() && !is_skipped(&path) => walk(&path, found),
+      Ok(file_type) if file_type.is_file() && !sidecar::is_sidecar(&path) => found.push(path),
+      _ => {}
+    }
+  }
+}
+
+fn scanner() -> MutexGuard<'static, ScanStatus> {
+  SCANNER
+  .get_or_init(|| Mutex::new(ScanStatus::default()))
+  .lock()
+  .unwrap_or_else(|err| err.into_inner())
+}
@@ -1,131 +1,2208 @@
-extern crate ffmpeg_next as ffmpeg;
-
 use format as f;
 
-mod file;
-mod math;
-mod video;
-
-use serde::Deserialize;
+use fylvur::{
+  access, audit, batch, cache, capabilities, cast, collisions, config, dash, duplicates, envelope, events,
+  feed, file, highlights, hints, hls, intro, library, manifest, perf, prefer, segment, session, sidecar, spectrogram, storage, storyboard, stream, subtitle,
+  transcode, transfer, trash, userdata, video, waveform,
+};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
 use actix_files as actix_fs;
-use actix_web::{get, web, App, HttpResponse, HttpServer, Responder};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::header;
+use actix_web::{delete, get, patch, post, put, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
 
 use std::path::Path;
 
-include!(concat!(env!("OUT_DIR"), "/config.rs"));
+#[derive(Debug, Deserialize)]
+pub struct ListingRequest {
+  /// `manual` to use the order stored for the folder
+  sort: Option<String>,
+  /// `viewport` to list folders first then videos from the newest, which is also the order
+  /// their thumbnails are prewarmed in
+  prefetch_order: Option<String>,
+}
 
 #[derive(Debug, Deserialize)]
 pub struct ThumbnailRequest {
+  /// In CSS pixels when `?dpr=` or client hints are sent, which round it up to a size bucket
   width: Option<u32>,
+  /// Exact height when padding
+  height: Option<u32>,
   seek: Option<f32>,
+  /// Letterbox color, makes the thumbnail exactly `width` x `height`
+  pad: Option<String>,
+  /// The frame at the video's own size as lossless WebP instead, every other size is ignored
+  #[serde(default)]
+  inspect: bool,
+  /// Draw the codec, size, PTS and picture type over inspected frames
+  #[serde(default)]
+  overlay: bool,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct AtlasRequest {
   page: Option<u32>,
   step: Option<u32>,
+  /// `row-major` (default) or `column-major`
+  #[serde(default)]
+  layout: video::TileLayout,
+  /// `webp` (default) or `jpeg`
+  format: Option<String>,
+  /// JPEG quality from 1 to 100
+  quality: Option<u8>,
+  /// Draw the time of each tile over it
+  #[serde(default)]
+  burn_timestamps: bool,
+  /// `fast`, `balanced` (default) or `quality`, video atlases only
+  #[serde(default)]
+  preset: video::AtlasPreset,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WaveformRequest {
+  /// Slices the audio is split into, a peak or a pixel column each. Pictures guests get are
+  /// at most `guest_max_width` columns
+  samples: Option<usize>,
+  /// `json` (default) for the peaks, `png` or `webp` for a picture of them
+  format: Option<String>,
+  /// Of pictures, in pixels
+  height: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpectrogramRequest {
+  /// Slices the audio is split into, a pixel column each. Guests get at most `guest_max_width`
+  width: Option<usize>,
+  /// Pixels from no frequency at the bottom to half the sample rate at the top
+  height: Option<u32>,
+  /// `png` (default) or `webp`
+  format: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PosterRequest {
+  /// Fraction of the duration below 1, seconds otherwise, like the thumbnail `seek`
+  seek: f32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MarkersRequest {
+  /// `ffmetadata` for a chapters file instead of JSON
+  format: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MarkerRequest {
+  id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StoryboardRequest {
+  #[serde(default)]
+  layout: video::TileLayout,
+  format: Option<String>,
+  quality: Option<u8>,
+  #[serde(default)]
+  burn_timestamps: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpritesRequest {
+  start: Option<u32>,
+  end: Option<u32>,
+  interval: Option<u32>,
+  cols: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SegmentRequest {
+  /// Id the playlist or manifest was given, segments are cached for it
+  playback: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TranscodeRequest {
+  #[serde(default)]
+  codec: transcode::Codec,
+  /// Max height of the video, rounded up to 360, 480, 720, 1080 or 2160. 1080 by default
+  height: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HighlightsRequest {
+  /// Moments returned, 8 by default
+  count: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubtitleSearchRequest {
+  q: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TimelineRequest {
+  year: Option<i64>,
+  month: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangesRequest {
+  /// Cursor of the last response, the whole index is sent without it
+  #[serde(default)]
+  since: i64,
+  /// Entries per response
+  limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GeoRequest {
+  /// `min_lon,min_lat,max_lon,max_lat`
+  bbox: Option<String>,
+  /// Grid size used to cluster points
+  cells: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreRequest {
+  conflict: Option<trash::Conflict>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransferRequest {
+  on_conflict: Option<transfer::OnConflict>,
+  /// Only return what would be done
+  #[serde(default)]
+  dry_run: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginRequest {
+  name: String,
+  password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoginResponse {
+  token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobsResponse {
+  scan: library::ScanStatus,
+  /// Thumbnails left to pre-generate
+  prewarm_queued: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CancelResponse {
+  cancelled: usize,
 }
 
 #[get("/{any:.*}")]
 async fn index() -> impl Responder {
-  actix_fs::NamedFile::open_async(Path::new(PUBLIC_FOLDER).join("index.html")).await
+  actix_fs::NamedFile::open_async(Path::new(&config::get().public_folder).join("index.html")).await
 }
 
 #[get("/api/file/{video_path:.*}")]
 async fn get_folder_info(
   path: web::Path<String>,
+  query: web::Query<ListingRequest>,
+  identity: access::Identity,
+  detail: prefer::Detail,
 ) -> impl Responder {
   let path = &path.into_inner();
-  if let Ok(paths) = file::get_folder_contents(path) {
-    return HttpResponse::Ok().json(paths)
+  if let Err(denied) = identity.check(path) {
+    return HttpResponse::from(denied)
+  }
+  if let Ok(mut paths) = file::get_folder_contents(path, &identity) {
+    let order = sidecar::load_folder_order(&file::get_media_path(path));
+    file::apply_order(&mut paths, &order, query.sort.as_deref() == Some("manual"));
+    if query.prefetch_order.as_deref() == Some("viewport") {
+      file::apply_viewport_order(&mut paths, &order);
+    }
+    if config::get().thumbnail_prewarm {
+      cache::prewarm(
+        paths.iter()
+        .filter(|file| file.file_type() == "video")
+        .map(|file| file.path().to_string())
+      );
+    }
+    return detail.listing(paths, &file::get_media_path(path), &identity).await
   }
   if let Ok(file) = file::FileInfo::from_path(&file::get_media_path(path)) {
-    return HttpResponse::Ok().json(file)
+    return detail.file(file).await
   }
   HttpResponse::NotFound().json(file::FileInfo::default())
 }
 
+#[get("/api/folder-order/{path:.*}")]
+async fn get_folder_order(
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  let path = &path.into_inner();
+  if let Err(denied) = identity.check(path) {
+    return HttpResponse::from(denied)
+  }
+  HttpResponse::Ok().json(sidecar::load_folder_order(&file::get_media_path(path)))
+}
+
+#[put("/api/folder-order/{path:.*}")]
+async fn set_folder_order(
+  path: web::Path<String>,
+  body: web::Json<sidecar::FolderOrder>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.file_management {
+    return HttpResponse::NotFound().finish()
+  }
+  let path = &path.into_inner();
+  if let Err(denied) = identity.check_writable(path) {
+    return HttpResponse::from(denied)
+  }
+  match sidecar::save_folder_order(&file::get_media_path(path), body.into_inner()) {
+    Ok(()) => HttpResponse::NoContent().finish(),
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+      HttpResponse::NotFound().finish()
+    }
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not save folder order - {err:?}"))
+  }
+}
+
 #[get("/api/file-metadata/{path:.*}")]
 async fn get_file_metadata(
   path: web::Path<String>,
+  identity: access::Identity,
 ) -> impl Responder {
   let path = &path.into_inner();
-  HttpResponse::Ok().json(
-    file::FileMetadata::from_path(&file::get_media_path(path))
-  )
+  if let Err(denied) = identity.check(path) {
+    return HttpResponse::from(denied)
+  }
+  let (metadata, warnings) = file::FileMetadata::from_path(&file::get_media_path(path));
+  envelope::ok(metadata, warnings)
 }
 
-#[get("/api/thumbnail/{video_path:.*}")]
-async fn get_video_thumbnail(
+#[patch("/api/file-metadata/{path:.*}")]
+async fn update_file_metadata(
   path: web::Path<String>,
-  query: web::Query<ThumbnailRequest>,
+  body: web::Json<sidecar::SidecarPatch>,
+  identity: access::Identity,
 ) -> impl Responder {
-  use video::SeekTime::*;
+  if !config::get().features.file_management {
+    return HttpResponse::NotFound().finish()
+  }
+  let path = &path.into_inner();
+  if let Err(denied) = identity.check_writable(path) {
+    return HttpResponse::from(denied)
+  }
+  match sidecar::update(&file::get_media_path(path), body.into_inner()) {
+    Ok(sidecar) => HttpResponse::Ok().json(sidecar),
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+      HttpResponse::NotFound().finish()
+    }
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not save metadata - {err:?}"))
+  }
+}
 
-  let video_path = file::get_media_path(&path.into_inner());
-  let video_path = video_path.to_str().unwrap_or_default();
+#[get("/api/markers/{path:.*}")]
+async fn get_markers(
+  path: web::Path<String>,
+  query: web::Query<MarkersRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  let path = &path.into_inner();
+  if let Err(denied) = identity.check(path) {
+    return HttpResponse::from(denied)
+  }
+  let file_path = file::get_media_path(path);
+  if !file_path.is_file() {
+    return HttpResponse::NotFound().finish()
+  }
+  let (sidecar, warning) = sidecar::load_checked(&file_path);
+  match query.format.as_deref() {
+    None => envelope::ok(sidecar.markers, warning.into_iter().collect()),
+    Some("ffmetadata") => {
+      let duration_ms = video::get_duration_from_path(&file_path).unwrap_or_default();
+      let name = file_path.file_stem().unwrap_or_default().to_string_lossy();
+      HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .insert_header(header::ContentDisposition::attachment(f!("{name}.ffmetadata")))
+        .body(sidecar::ffmetadata(&sidecar, duration_ms))
+    }
+    Some(_) => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body("format must be ffmetadata"),
+  }
+}
 
-  let seek = query.seek.unwrap_or(0.);
+#[post("/api/markers/{path:.*}")]
+async fn add_marker(
+  path: web::Path<String>,
+  body: web::Json<sidecar::NewMarker>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.file_management {
+    return HttpResponse::NotFound().finish()
+  }
+  let path = &path.into_inner();
+  if let Err(denied) = identity.check_writable(path) {
+    return HttpResponse::from(denied)
+  }
+  match sidecar::add_marker(&file::get_media_path(path), body.into_inner()) {
+    Ok(marker) => HttpResponse::Created().json(marker),
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+      HttpResponse::NotFound().finish()
+    }
+    Err(err) if err.kind() == std::io::ErrorKind::InvalidInput => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(err.to_string()),
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not save marker - {err:?}"))
+  }
+}
 
-  match video::get_video_thumbnail(
-    &video_path.to_string(),
-    query.width.unwrap_or_default(),
-    if seek < 1. {Percentage(seek)} else {Seconds(seek as u32)},
-  ) {
-    Ok(thumbnail) => HttpResponse::Ok()
-      .content_type("image/webp")
-      .body(web::Bytes::copy_from_slice(&*thumbnail)),
-    Err(err) => HttpResponse::BadRequest()
-      .body(f!("Could not get thumbnail - {err:?}"))
+#[delete("/api/markers/{path:.*}")]
+async fn remove_marker(
+  path: web::Path<String>,
+  query: web::Query<MarkerRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.file_management {
+    return HttpResponse::NotFound().finish()
+  }
+  let path = &path.into_inner();
+  if let Err(denied) = identity.check_writable(path) {
+    return HttpResponse::from(denied)
+  }
+  match sidecar::remove_marker(&file::get_media_path(path), &query.id) {
+    Ok(()) => HttpResponse::NoContent().finish(),
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+      HttpResponse::NotFound().finish()
+    }
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not remove marker - {err:?}"))
   }
 }
 
-#[get("/api/atlas/{video_path:.*}")]
-async fn get_video_atlas(
+/// Frame thumbnails use when they're requested without a seek
+#[put("/api/poster/{path:.*}")]
+async fn set_poster(
   path: web::Path<String>,
-  query: web::Query<AtlasRequest>,
+  body: web::Json<PosterRequest>,
+  identity: access::Identity,
 ) -> impl Responder {
-  let video_path = file::get_media_path(&path.into_inner());
-  let video_path = video_path.to_str().unwrap_or_default();
+  update_poster(&path, Some(body.seek), &identity)
+}
 
-  let page = query.page.unwrap_or(0);
-  let step = match query.step {
-    Some(step) => if step == 0 {1} else {step},
-    None => 1,
+#[delete("/api/poster/{path:.*}")]
+async fn remove_poster(
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  update_poster(&path, None, &identity)
+}
+
+fn update_poster(path: &str, seek: Option<f32>, identity: &access::Identity) -> HttpResponse {
+  if !config::get().features.file_management {
+    return HttpResponse::NotFound().finish()
+  }
+  if let Err(denied) = identity.check_writable(path) {
+    return HttpResponse::from(denied)
+  }
+  match sidecar::set_poster(&file::get_media_path(path), seek) {
+    Ok(sidecar) => HttpResponse::Ok().json(sidecar),
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+      HttpResponse::NotFound().finish()
+    }
+    Err(err) if err.kind() == std::io::ErrorKind::InvalidInput => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(err.to_string()),
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not save poster - {err:?}"))
+  }
+}
+
+/// Image to show as the thumbnail of a file or folder instead of a decoded frame
+#[put("/api/artwork/{path:.*}")]
+async fn set_artwork(
+  path: web::Path<String>,
+  body: web::Bytes,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.uploads {
+    return HttpResponse::NotFound().finish()
+  }
+  let path = &path.into_inner();
+  if let Err(denied) = identity.check_writable(path) {
+    return HttpResponse::from(denied)
+  }
+  match sidecar::save_artwork(&file::get_media_path(path), &body) {
+    Ok(()) => {
+      cache::forget(path);
+      HttpResponse::NoContent().finish()
+    }
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+      HttpResponse::NotFound().finish()
+    }
+    Err(err) if err.kind() == std::io::ErrorKind::InvalidData => HttpResponse::UnsupportedMediaType()
+      .content_type("text/plain")
+      .body(err.to_string()),
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not save artwork - {err:?}"))
+  }
+}
+
+#[delete("/api/artwork/{path:.*}")]
+async fn remove_artwork(
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.uploads {
+    return HttpResponse::NotFound().finish()
+  }
+  let path = &path.into_inner();
+  if let Err(denied) = identity.check_writable(path) {
+    return HttpResponse::from(denied)
+  }
+  match sidecar::remove_artwork(&file::get_media_path(path)) {
+    Ok(()) => {
+      cache::forget(path);
+      HttpResponse::NoContent().finish()
+    }
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+      HttpResponse::NotFound().finish()
+    }
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not remove artwork - {err:?}"))
+  }
+}
+
+#[delete("/api/file/{path:.*}")]
+async fn delete_file(
+  req: HttpRequest,
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.file_management {
+    return HttpResponse::NotFound().finish()
+  }
+  let path = &path.into_inner();
+  if let Err(denied) = identity.check_writable(path) {
+    return HttpResponse::from(denied)
+  }
+  if file::is_root(path) {
+    return HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body("The media folder and mounts themselves can't be deleted")
+  }
+  match trash::delete(path, &identity) {
+    Ok(entry) => {
+      audit::record(audit::Action::Delete, path, &identity, &req);
+      HttpResponse::Ok().json(entry)
+    }
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+      HttpResponse::NotFound().finish()
+    }
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not delete file - {err:?}"))
+  }
+}
+
+/// `POST /api/move?on_conflict=skip|overwrite|rename&dry_run=true` with `{"paths": [...], "to": "folder"}`
+#[post("/api/move")]
+async fn move_files(
+  req: HttpRequest,
+  body: web::Json<transfer::Transfer>,
+  query: web::Query<TransferRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.file_management {
+    return HttpResponse::NotFound().finish()
+  }
+  transfer_files(&req, body.into_inner(), &query, transfer::Mode::Move, &identity).await
+}
+
+#[post("/api/copy")]
+async fn copy_files(
+  req: HttpRequest,
+  body: web::Json<transfer::Transfer>,
+  query: web::Query<TransferRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.file_management {
+    return HttpResponse::NotFound().finish()
+  }
+  transfer_files(&req, body.into_inner(), &query, transfer::Mode::Copy, &identity).await
+}
+
+async fn transfer_files(
+  req: &HttpRequest,
+  body: transfer::Transfer,
+  query: &TransferRequest,
+  mode: transfer::Mode,
+  identity: &access::Identity,
+) -> HttpResponse {
+  if let Err(denied) = identity.check_writable(&body.to) {
+    return HttpResponse::from(denied)
+  }
+  for path in &body.paths {
+    let checked = match mode {
+      transfer::Mode::Move => identity.check_writable(path),
+      transfer::Mode::Copy => identity.check(path),
+    };
+    if let Err(denied) = checked {
+      return HttpResponse::from(denied)
+    }
+  }
+  if body.paths.iter().any(|path| file::is_root(path)) {
+    return HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body("The media folder and mounts themselves can't be moved or copied")
+  }
+
+  let (on_conflict, dry_run, owner) = (query.on_conflict.unwrap_or_default(), query.dry_run, identity.clone());
+  // Copies and moves across devices can take minutes, on the blocking pool they don't hold up other requests
+  let result = web::block(move || {
+    if dry_run {
+      transfer::plan(&body, mode, on_conflict)
+    } else {
+      transfer::run(&body, mode, on_conflict, &owner)
+    }
+  }).await;
+  match result.unwrap_or_else(|err| Err(std::io::Error::other(err.to_string()))) {
+    Ok(operations) => {
+      if !query.dry_run {
+        let action = match mode {
+          transfer::Mode::Move => audit::Action::Move,
+          transfer::Mode::Copy => audit::Action::Copy,
+        };
+        for operation in operations.iter().filter(|operation| !operation.skipped) {
+          audit::record(action, &operation.from, identity, req);
+        }
+      }
+      HttpResponse::Ok().json(operations)
+    }
+    Err(err) => {
+      let mut response = match err.kind() {
+        std::io::ErrorKind::NotFound => HttpResponse::NotFound(),
+        std::io::ErrorKind::InvalidInput => HttpResponse::BadRequest(),
+        std::io::ErrorKind::AlreadyExists => HttpResponse::Conflict(),
+        _ => HttpResponse::InternalServerError(),
+      };
+      let message = match err.kind() {
+        std::io::ErrorKind::AlreadyExists => "Destination exists, use ?on_conflict=skip, overwrite or rename".into(),
+        _ => f!("Could not transfer files - {err}"),
+      };
+      response
+        .content_type("text/plain")
+        .body(message)
+    }
+  }
+}
+
+/// Several moves, copies and deletes in one request, e.g. for multi-select actions
+#[post("/api/batch")]
+async fn run_batch(
+  req: HttpRequest,
+  body: web::Json<batch::Batch>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.file_management {
+    return HttpResponse::NotFound().finish()
+  }
+  if !identity.can_write() {
+    return HttpResponse::from(access::Denied::Forbidden)
+  }
+  for operation in &body.operations {
+    // Copies only read their source
+    let checked = match operation {
+      batch::Operation::Copy { path, .. } => identity.check(path),
+      _ => identity.check_writable(operation.path()),
+    }
+    .and_then(|_| operation.to().map_or(Ok(()), |to| identity.check_writable(to)));
+    if let Err(denied) = checked {
+      return HttpResponse::from(denied)
+    }
+  }
+
+  let (batch, owner) = (body.into_inner(), identity.clone());
+  // Same as `transfer_files`, the operations can copy whole folders
+  let result = web::block(move || batch::run(&batch, &owner).map(|report| (batch, report))).await;
+  match result.unwrap_or_else(|err| Err(std::io::Error::other(err.to_string()))) {
+    Ok((body, report)) => {
+      if !report.rolled_back {
+        for (operation, outcome) in body.operations.iter().zip(&report.results) {
+          let action = match operation {
+            batch::Operation::Move { .. } => audit::Action::Move,
+            batch::Operation::Copy { .. } => audit::Action::Copy,
+            batch::Operation::Delete { .. } => audit::Action::Delete,
+          };
+          if outcome.ok && !outcome.skipped {
+            audit::record(action, &outcome.path, &identity, &req);
+          }
+        }
+      }
+      HttpResponse::Ok().json(report)
+    }
+    Err(err) if err.kind() == std::io::ErrorKind::InvalidInput => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(err.to_string()),
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not run batch - {err:?}"))
+  }
+}
+
+#[get("/api/trash")]
+async fn get_trash(identity: access::Identity) -> impl Responder {
+  if !config::get().features.file_management {
+    return HttpResponse::NotFound().finish()
+  }
+  if !identity.can_write() {
+    return HttpResponse::from(access::Denied::Forbidden)
+  }
+  HttpResponse::Ok().json(trash::list(&identity))
+}
+
+#[post("/api/trash/{id}/restore")]
+async fn restore_trash(
+  req: HttpRequest,
+  id: web::Path<String>,
+  query: web::Query<RestoreRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.file_management {
+    return HttpResponse::NotFound().finish()
+  }
+  if !identity.can_write() {
+    return HttpResponse::from(access::Denied::Forbidden)
+  }
+  let entry = match trash::get(&id) {
+    Ok(entry) => entry,
+    Err(_) => return HttpResponse::NotFound().finish(),
   };
+  if let Err(denied) = identity.check_writable(&entry.path) {
+    return HttpResponse::from(denied)
+  }
+  match trash::restore(&id, query.conflict.unwrap_or_default(), &identity) {
+    Ok(entry) => {
+      audit::record(audit::Action::Restore, &entry.path, &identity, &req);
+      HttpResponse::Ok().json(entry)
+    }
+    Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => HttpResponse::Conflict()
+      .content_type("text/plain")
+      .body(f!("{} exists again, restore with ?conflict=rename or ?conflict=replace", entry.path)),
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not restore file - {err:?}"))
+  }
+}
 
-  match video::get_video_atlas(
-    &video_path.to_string(),
-    page,
-    step,
-  ) {
-    Ok(atlas) => HttpResponse::Ok()
-      .content_type("image/webp")
-      .body(web::Bytes::copy_from_slice(&*atlas)),
-    Err(err) => HttpResponse::BadRequest()
+#[delete("/api/trash/{id}")]
+async fn purge_trash(id: web::Path<String>, identity: access::Identity) -> impl Responder {
+  if !config::get().features.file_management {
+    return HttpResponse::NotFound().finish()
+  }
+  if !identity.is_admin() {
+    return HttpResponse::Forbidden().finish()
+  }
+  if trash::get(&id).is_err() {
+    return HttpResponse::NotFound().finish()
+  }
+  match trash::remove(&id) {
+    Ok(()) => HttpResponse::NoContent().finish(),
+    Err(err) => HttpResponse::InternalServerError()
       .content_type("text/plain")
-      .body(f!("Could not get video atlas - {err:?}"))
+      .body(f!("Could not purge file - {err:?}"))
   }
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-  video::init()
-  .expect("Could not initialize video API");
+#[get("/api/duplicates")]
+async fn get_duplicates(identity: access::Identity) -> impl Responder {
+  if identity.is_guest() {
+    return HttpResponse::from(access::Denied::Forbidden)
+  }
+  match duplicates::groups(&identity) {
+    Ok(groups) => HttpResponse::Ok().json(groups),
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not read duplicates - {err:?}"))
+  }
+}
 
-  let server = HttpServer::new(|| {
-    App::new()
-      .service(get_video_thumbnail)
-      .service(get_folder_info)
-      .service(get_file_metadata)
-      .service(get_video_atlas)
-      .service(actix_fs::Files::new("/file", MEDIA_FOLDER))
-      .service(actix_fs::Files::new("/static", PUBLIC_FOLDER))
-      .service(index)
-  })
-  .bind((HOST, PORT))?
-  .run();
+#[post("/api/duplicates/{id}/resolve")]
+async fn resolve_duplicates(
+  req: HttpRequest,
+  id: web::Path<String>,
+  body: web::Json<duplicates::Resolution>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.file_management {
+    return HttpResponse::NotFound().finish()
+  }
+  if !identity.can_write() {
+    return HttpResponse::from(access::Denied::Forbidden)
+  }
+  match duplicates::resolve(&id, &body, &identity) {
+    Ok(report) => {
+      for entry in &report.trashed {
+        audit::record(audit::Action::Delete, &entry.path, &identity, &req);
+      }
+      HttpResponse::Ok().json(report)
+    }
+    Err(err) => {
+      let mut response = match err.kind() {
+        std::io::ErrorKind::NotFound => HttpResponse::NotFound(),
+        std::io::ErrorKind::InvalidInput => HttpResponse::BadRequest(),
+        std::io::ErrorKind::PermissionDenied => HttpResponse::Forbidden(),
+        std::io::ErrorKind::InvalidData => HttpResponse::Conflict(),
+        _ => HttpResponse::InternalServerError(),
+      };
+      response
+        .content_type("text/plain")
+        .body(f!("Could not resolve duplicates - {err}"))
+    }
+  }
+}
+
+#[get("/api/subtitle/{path:.*}")]
+async fn get_subtitle(
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  let path = &path.into_inner();
+  if let Err(denied) = identity.check(path) {
+    return HttpResponse::from(denied)
+  }
+  match subtitle::to_webvtt(&file::get_media_path(path)) {
+    Ok(vtt) => HttpResponse::Ok()
+      .content_type("text/vtt; charset=utf-8")
+      .body(vtt),
+    Err(err) => HttpResponse::NotFound()
+      .content_type("text/plain")
+      .body(f!("Could not read subtitle - {err:?}"))
+  }
+}
+
+#[get("/api/subtitle-search/{video_path:.*}")]
+async fn search_subtitles(
+  path: web::Path<String>,
+  query: web::Query<SubtitleSearchRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  let path = path.into_inner();
+  if let Err(denied) = identity.check(&path) {
+    return HttpResponse::from(denied)
+  }
+  if query.q.trim().is_empty() {
+    return HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body("Missing search query")
+  }
+  let video_path = file::get_media_path(&path);
+
+  match video::search_subtitles(&video_path, query.q.trim()) {
+    Ok(cues) => HttpResponse::Ok().json(cues),
+    Err(err) => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(f!("Could not search subtitles - {err:?}"))
+  }
+}
+
+#[get("/api/subtitles/{video_path:.*}")]
+async fn get_subtitle_tracks(
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  let path = path.into_inner();
+  if let Err(denied) = identity.check(&path) {
+    return HttpResponse::from(denied)
+  }
+  match video::get_subtitle_tracks(&file::get_media_path(&path)) {
+    Ok(tracks) => HttpResponse::Ok().json(tracks),
+    Err(err) => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(f!("Could not list subtitles - {err:?}"))
+  }
+}
 
-  println!("Listening in http://{}:{}", HOST, PORT);
-  server.await
+#[get("/api/thumbnail/{video_path:.*}")]
+async fn get_video_thumbnail(
+  path: web::Path<String>,
+  query: web::Query<ThumbnailRequest>,
+  hints: hints::ClientHints,
+  identity: access::Identity,
+) -> impl Responder {
+  let path = path.into_inner();
+  if let Err(denied) = identity.check(&path) {
+    return HttpResponse::from(denied)
+  }
+  if file::thumbnail_policy(&path) == config::ThumbnailPolicy::Off {
+    return HttpResponse::NotFound().finish()
+  }
+
+  let media_path = file::get_media_path(&path);
+  // A thumbnail of a partial file would be cached as if it were the real one
+  if !file::is_stable(&media_path) {
+    return HttpResponse::ServiceUnavailable()
+      .insert_header((header::RETRY_AFTER, config::get().stable_after_secs.max(1)))
+      .content_type("text/plain")
+      .body("File is still being written")
+  }
+
+  let defaults = sidecar::thumbnail_defaults_for(&media_path);
+  let seek = query.seek
+  .or_else(|| sidecar::load(&media_path).poster_seek)
+  .or(defaults.seek)
+  .unwrap_or(0.);
+  if query.inspect {
+    return inspect_frame(media_path, seek, query.overlay, &identity).await
+  }
+  let requested = query.width.or(defaults.width);
+  let requested_width = requested.unwrap_or_default();
+  let mut width = hints.thumbnail_width(requested).unwrap_or(requested_width);
+  let guest_max_width = config::get().guest_max_width;
+  if identity.is_guest() && (width == 0 || width > guest_max_width) {
+    width = guest_max_width;
+  }
+  let letterbox = match &query.pad {
+    Some(pad) => {
+      let color = video::parse_color(pad);
+      let height = query.height.filter(|&height| height > 0);
+      let (Some(color), Some(height)) = (color, height) else {
+        return HttpResponse::BadRequest()
+          .content_type("text/plain")
+          .body("pad needs a height and a color like 000000, ffffff80 or transparent")
+      };
+      if requested_width == 0 {
+        return HttpResponse::BadRequest()
+          .content_type("text/plain")
+          .body("pad needs a width")
+      }
+      // Guests get the same shape at their capped width
+      let height = (height as u64 * width as u64 / requested_width as u64).max(1) as u32;
+      Some(video::Letterbox { height, color })
+    }
+    None => None,
+  };
+  let watermark = identity.watermark();
+  let key = cache::ThumbnailKey::new(&path, width, seek, watermark.is_some())
+  .letterboxed(letterbox)
+  .at_quality(defaults.quality);
+
+  match cache::coalesced_thumbnail(key, watermark).await {
+    Ok(thumbnail) => {
+      let mut response = HttpResponse::Ok();
+      hints::ClientHints::advertise(&mut response);
+      response
+      .content_type("image/webp")
+      .body(web::Bytes::copy_from_slice(&thumbnail))
+    }
+    Err(err) if err.is_over_budget() => HttpResponse::ServiceUnavailable()
+      .insert_header((header::RETRY_AFTER, 1))
+      .content_type("text/plain")
+      .body(f!("Could not get thumbnail - {err}")),
+    Err(err) => HttpResponse::BadRequest()
+      .body(f!("Could not get thumbnail - {err:?}"))
+  }
+}
+
+/// Frames at full size are only for diagnosing encodes, guests get thumbnails
+async fn inspect_frame(
+  media_path: std::path::PathBuf,
+  seek: f32,
+  overlay: bool,
+  identity: &access::Identity,
+) -> HttpResponse {
+  if identity.is_guest() {
+    return HttpResponse::from(access::Denied::Forbidden)
+  }
+  let frame = video::unblocked(move || {
+    video::get_inspect_frame(&media_path, seek.into(), overlay).map(|(frame, _)| frame.to_vec())
+  });
+  match frame.await {
+    Ok(frame) => HttpResponse::Ok()
+      .content_type("image/webp")
+      .body(frame),
+    Err(err) if err.is_over_budget() => HttpResponse::ServiceUnavailable()
+      .insert_header((header::RETRY_AFTER, 1))
+      .content_type("text/plain")
+      .body(f!("Could not inspect frame - {err}")),
+    Err(err) => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(f!("Could not inspect frame - {err:?}"))
+  }
+}
+
+#[get("/api/atlas/{video_path:.*}")]
+async fn get_video_atlas(
+  path: web::Path<String>,
+  query: web::Query<AtlasRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.atlas {
+    return HttpResponse::NotFound().finish()
+  }
+  let path = path.into_inner();
+  if let Err(denied) = identity.check(&path) {
+    return HttpResponse::from(denied)
+  }
+  if file::thumbnail_policy(&path) == config::ThumbnailPolicy::Off {
+    return HttpResponse::NotFound().finish()
+  }
+
+  let page = query.page.unwrap_or(0);
+  let step = match query.step {
+    Some(step) => if step == 0 {1} else {step},
+    None => 1,
+  };
+  let Some(format) = video::ImageFormat::parse(query.format.as_deref(), query.quality) else {
+    return HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body("format must be webp or jpeg with a quality from 1 to 100")
+  };
+
+  let key = cache::AtlasKey {
+    path,
+    page,
+    step,
+    layout: query.layout,
+    format,
+    burn_timestamps: query.burn_timestamps,
+    preset: query.preset,
+    watermark: identity.watermark().is_some(),
+  };
+  let watermark = identity.watermark();
+  match video::unblocked(move || cache::atlas(&key, watermark)).await {
+    Ok(atlas) => HttpResponse::Ok()
+      .content_type(format.content_type())
+      .body(atlas),
+    Err(err) if err.is_over_budget() => HttpResponse::ServiceUnavailable()
+      .insert_header((header::RETRY_AFTER, 1))
+      .content_type("text/plain")
+      .body(f!("Could not get video atlas - {err}")),
+    Err(err) => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(f!("Could not get video atlas - {err:?}"))
+  }
+}
+
+/// Atlas page of audio waveforms, one tile per `step` seconds, takes the same options as `/api/atlas`
+#[get("/api/waveform-atlas/{path:.*}")]
+async fn get_waveform_atlas(
+  path: web::Path<String>,
+  query: web::Query<AtlasRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.atlas {
+    return HttpResponse::NotFound().finish()
+  }
+  let path = path.into_inner();
+  if let Err(denied) = identity.check(&path) {
+    return HttpResponse::from(denied)
+  }
+  if file::thumbnail_policy(&path) == config::ThumbnailPolicy::Off {
+    return HttpResponse::NotFound().finish()
+  }
+  let Some(format) = video::ImageFormat::parse(query.format.as_deref(), query.quality) else {
+    return HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body("format must be webp or jpeg with a quality from 1 to 100")
+  };
+
+  let key = cache::AtlasKey {
+    path,
+    page: query.page.unwrap_or(0),
+    step: query.step.unwrap_or(1).max(1),
+    layout: query.layout,
+    format,
+    burn_timestamps: query.burn_timestamps,
+    preset: video::AtlasPreset::default(),
+    watermark: identity.watermark().is_some(),
+  };
+  let watermark = identity.watermark();
+  match video::unblocked(move || cache::waveform_atlas(&key, watermark)).await {
+    Ok(atlas) => HttpResponse::Ok()
+      .content_type(format.content_type())
+      .body(atlas),
+    Err(err) if err.is_over_budget() => HttpResponse::ServiceUnavailable()
+      .insert_header((header::RETRY_AFTER, 1))
+      .content_type("text/plain")
+      .body(f!("Could not get waveform atlas - {err}")),
+    Err(err) => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(f!("Could not get waveform atlas - {err:?}"))
+  }
+}
+
+/// Peaks of the whole audio of a file for a seekable waveform, or a picture of them
+#[get("/api/waveform/{path:.*}")]
+async fn get_waveform(
+  path: web::Path<String>,
+  query: web::Query<WaveformRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  let path = path.into_inner();
+  if let Err(denied) = identity.check(&path) {
+    return HttpResponse::from(denied)
+  }
+  let mut samples = query.samples.unwrap_or(waveform::DEFAULT_SAMPLES);
+  let height = query.height.unwrap_or(waveform::DEFAULT_HEIGHT);
+  if !(1..=waveform::MAX_SAMPLES).contains(&samples) || !(1..=waveform::MAX_HEIGHT).contains(&height) {
+    return HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(f!(
+        "samples must be between 1 and {} and height between 1 and {}",
+        waveform::MAX_SAMPLES,
+        waveform::MAX_HEIGHT,
+      ))
+  }
+  let Some(format) = waveform::Format::parse(query.format.as_deref()) else {
+    return HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body("format must be json, png or webp")
+  };
+  // Pictures have a column per sample, guests get them as wide as their thumbnails at most
+  if identity.is_guest() && format != waveform::Format::Json {
+    samples = samples.min(config::get().guest_max_width as usize);
+  }
+
+  let watermark = identity.watermark();
+  let rendered = video::unblocked(move || {
+    let waveform = waveform::get(&path, samples)?;
+    match format {
+      waveform::Format::Json => Ok((waveform, None)),
+      _ => waveform::render(&waveform, height, format, watermark).map(|image| (waveform, Some(image))),
+    }
+  }).await;
+  match rendered {
+    Ok((waveform, None)) => HttpResponse::Ok().json(&*waveform),
+    Ok((_, Some(image))) => HttpResponse::Ok()
+      .content_type(if format == waveform::Format::Png {"image/png"} else {"image/webp"})
+      .body(image),
+    Err(err) if err.is_over_budget() => HttpResponse::ServiceUnavailable()
+      .insert_header((header::RETRY_AFTER, 1))
+      .content_type("text/plain")
+      .body(f!("Could not get waveform - {err}")),
+    Err(err) => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(f!("Could not get waveform - {err:?}"))
+  }
+}
+
+/// Frequencies in the whole audio of a file over time, to tell lossy transcodes and silence apart
+#[get("/api/spectrogram/{path:.*}")]
+async fn get_spectrogram(
+  path: web::Path<String>,
+  query: web::Query<SpectrogramRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  let path = path.into_inner();
+  if let Err(denied) = identity.check(&path) {
+    return HttpResponse::from(denied)
+  }
+  let mut width = query.width.unwrap_or(spectrogram::DEFAULT_WIDTH);
+  let height = query.height.unwrap_or(spectrogram::DEFAULT_HEIGHT);
+  if !(1..=spectrogram::MAX_WIDTH).contains(&width) || !(1..=spectrogram::MAX_HEIGHT).contains(&height) {
+    return HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(f!(
+        "width must be between 1 and {} and height between 1 and {}",
+        spectrogram::MAX_WIDTH,
+        spectrogram::MAX_HEIGHT,
+      ))
+  }
+  let Some(format) = spectrogram::Format::parse(query.format.as_deref()) else {
+    return HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body("format must be png or webp")
+  };
+  if identity.is_guest() {
+    width = width.min(config::get().guest_max_width as usize);
+  }
+
+  let watermark = identity.watermark();
+  match video::unblocked(move || spectrogram::get(&path, width, height, format, watermark)).await {
+    Ok(image) => HttpResponse::Ok()
+      .content_type(format.mime_type())
+      .body(image.to_vec()),
+    Err(err) if err.is_over_budget() => HttpResponse::ServiceUnavailable()
+      .insert_header((header::RETRY_AFTER, 1))
+      .content_type("text/plain")
+      .body(f!("Could not get spectrogram - {err}")),
+    Err(err) => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(f!("Could not get spectrogram - {err:?}"))
+  }
+}
+
+/// Atlas page URLs, tile cues and geometry for hover previews in a single response
+#[get("/api/storyboard/{video_path:.*}")]
+async fn get_storyboard(
+  path: web::Path<String>,
+  query: web::Query<StoryboardRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.atlas {
+    return HttpResponse::NotFound().finish()
+  }
+  let path = path.into_inner();
+  if let Err(denied) = identity.check(&path) {
+    return HttpResponse::from(denied)
+  }
+  if file::thumbnail_policy(&path) == config::ThumbnailPolicy::Off {
+    return HttpResponse::NotFound().finish()
+  }
+  let Some(format) = video::ImageFormat::parse(query.format.as_deref(), query.quality) else {
+    return HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body("format must be webp or jpeg with a quality from 1 to 100")
+  };
+
+  match storyboard::describe(&path, query.layout, format, query.burn_timestamps) {
+    Ok((storyboard, warnings)) => envelope::ok(storyboard, warnings),
+    Err(err) => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(f!("Could not get storyboard - {err:?}"))
+  }
+}
+
+#[get("/api/sprites/{video_path:.*}")]
+async fn get_video_sprites(
+  path: web::Path<String>,
+  query: web::Query<SpritesRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.atlas {
+    return HttpResponse::NotFound().finish()
+  }
+  let path = path.into_inner();
+  if let Err(denied) = identity.check(&path) {
+    return HttpResponse::from(denied)
+  }
+  if file::thumbnail_policy(&path) == config::ThumbnailPolicy::Off {
+    return HttpResponse::NotFound().finish()
+  }
+  let video_path = file::get_media_path(&path);
+  let query = query.into_inner();
+  let watermark = identity.watermark();
+
+  let sprites = video::unblocked(move || {
+    video::get_video_sprites(
+      &video_path,
+      query.start.unwrap_or(0),
+      query.end,
+      query.interval.unwrap_or(1),
+      query.cols.unwrap_or(10),
+      watermark,
+    )
+    .map(|sprites| sprites.to_vec())
+  });
+  match sprites.await {
+    Ok(sprites) => HttpResponse::Ok()
+      .content_type("image/webp")
+      .body(sprites),
+    Err(err) if err.is_over_budget() => HttpResponse::ServiceUnavailable()
+      .insert_header((header::RETRY_AFTER, 1))
+      .content_type("text/plain")
+      .body(f!("Could not get video sprites - {err}")),
+    Err(err) => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(f!("Could not get video sprites - {err:?}"))
+  }
+}
+
+/// HLS playlist transcoding the video to H.264 and AAC, for browsers that can't play it as is
+#[get("/api/hls/{video_path:.*}/index.m3u8")]
+async fn get_hls_playlist(
+  req: HttpRequest,
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.transcode {
+    return HttpResponse::NotFound().finish()
+  }
+  let path = path.into_inner();
+  if let Err(denied) = identity.check_downloadable(&path) {
+    return HttpResponse::from(denied)
+  }
+  let video_path = file::get_media_path(&path);
+  if !video_path.is_file() {
+    return HttpResponse::NotFound().finish()
+  }
+  match video::unblocked(move || video::get_duration_from_path(&video_path)).await {
+    Ok(duration_ms) => {
+      audit::record(audit::Action::Download, &path, &identity, &req);
+      HttpResponse::Ok()
+      .content_type("application/vnd.apple.mpegurl")
+      .body(hls::playlist(&path, duration_ms))
+    }
+    Err(err) => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(f!("Could not read video duration - {err:?}"))
+  }
+}
+
+#[get("/api/hls/{video_path:.*}/{segment:\\d+}.ts")]
+async fn get_hls_segment(
+  params: web::Path<(String, u32)>,
+  query: web::Query<SegmentRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.transcode {
+    return HttpResponse::NotFound().finish()
+  }
+  let (path, number) = params.into_inner();
+  if let Err(denied) = identity.check_downloadable(&path) {
+    return HttpResponse::from(denied)
+  }
+  let segment = segment::get(path, number, segment::SegmentFormat::MpegTs, query.into_inner().playback).await;
+  segment_response(segment.map(|segment| segment.to_vec()), "video/mp2t")
+}
+
+/// MPEG-DASH manifest with the same segments as HLS in fragmented MP4, for MSE based players
+#[get("/api/dash/{video_path:.*}/manifest.mpd")]
+async fn get_dash_manifest(
+  req: HttpRequest,
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.transcode {
+    return HttpResponse::NotFound().finish()
+  }
+  let path = path.into_inner();
+  if let Err(denied) = identity.check_downloadable(&path) {
+    return HttpResponse::from(denied)
+  }
+  let video_path = file::get_media_path(&path);
+  if !video_path.is_file() {
+    return HttpResponse::NotFound().finish()
+  }
+  let probed = video::unblocked(move || {
+    let has_audio = video::get_stream_codecs(&video_path)?.audio.is_some();
+    Ok((video::get_duration_from_path(&video_path)?, has_audio))
+  }).await;
+  match probed {
+    Ok((duration_ms, has_audio)) => {
+      audit::record(audit::Action::Download, &path, &identity, &req);
+      HttpResponse::Ok()
+      .content_type("application/dash+xml")
+      .body(dash::manifest(&path, duration_ms, has_audio))
+    }
+    Err(err) => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(f!("Could not read video duration - {err:?}"))
+  }
+}
+
+#[get("/api/dash/{video_path:.*}/init.mp4")]
+async fn get_dash_init(
+  path: web::Path<String>,
+  query: web::Query<SegmentRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.transcode {
+    return HttpResponse::NotFound().finish()
+  }
+  let path = path.into_inner();
+  if let Err(denied) = identity.check_downloadable(&path) {
+    return HttpResponse::from(denied)
+  }
+  segment_response(dash::init(path, query.into_inner().playback).await, "video/mp4")
+}
+
+#[get("/api/dash/{video_path:.*}/{segment:\\d+}.m4s")]
+async fn get_dash_segment(
+  params: web::Path<(String, u32)>,
+  query: web::Query<SegmentRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.transcode {
+    return HttpResponse::NotFound().finish()
+  }
+  let (path, number) = params.into_inner();
+  if let Err(denied) = identity.check_downloadable(&path) {
+    return HttpResponse::from(denied)
+  }
+  segment_response(dash::media(path, number, query.into_inner().playback).await, "video/iso.segment")
+}
+
+fn segment_response(segment: Result<Vec<u8>, video::VideoError>, content_type: &str) -> HttpResponse {
+  match segment {
+    Ok(segment) => HttpResponse::Ok()
+      .content_type(content_type)
+      .body(segment),
+    Err(err) if err.is_over_budget() => HttpResponse::ServiceUnavailable()
+      .insert_header((header::RETRY_AFTER, 1))
+      .content_type("text/plain")
+      .body(f!("Could not transcode segment - {err}")),
+    Err(err) => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(f!("Could not transcode segment - {err:?}"))
+  }
+}
+
+/// `GET /api/transcode/{path}?codec=h264&height=720` plays files browsers can't decode as a
+/// fragmented MP4 transcoded on the fly, seeking works as far as it's been transcoded.
+/// Files that already have compatible codecs are only remuxed
+#[get("/api/transcode/{video_path:.*}")]
+async fn get_transcode(
+  req: HttpRequest,
+  path: web::Path<String>,
+  query: web::Query<TranscodeRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.transcode {
+    return HttpResponse::NotFound().finish()
+  }
+  let path = path.into_inner();
+  if let Err(denied) = identity.check_downloadable(&path) {
+    return HttpResponse::from(denied)
+  }
+  serve_transcode(&req, &path, transcode::start(&path, query.codec, query.height, false), &identity).await
+}
+
+/// `GET /api/remux/{path}` plays H.264 and AAC files in containers browsers can't read, like
+/// Matroska, copied to a fragmented MP4 without transcoding. Other files answer 415
+#[get("/api/remux/{video_path:.*}")]
+async fn get_remux(
+  req: HttpRequest,
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !config::get().features.transcode {
+    return HttpResponse::NotFound().finish()
+  }
+  let path = path.into_inner();
+  if let Err(denied) = identity.check_downloadable(&path) {
+    return HttpResponse::from(denied)
+  }
+  let remux = transcode::start(&path, transcode::Codec::H264, None, true);
+  serve_transcode(&req, &path, remux, &identity).await
+}
+
+async fn serve_transcode(
+  req: &HttpRequest,
+  path: &str,
+  transcode: std::io::Result<transcode::Transcode>,
+  identity: &access::Identity,
+) -> HttpResponse {
+  let response = match transcode {
+    Ok(transcode::Transcode::Ready(file_path)) => stream::serve(req, &file_path),
+    Ok(transcode::Transcode::Running(file, viewer)) => {
+      stream::serve_growing(req, file, "video/mp4", viewer.done(), viewer).await
+    }
+    Err(err) => Err(err),
+  };
+  match response {
+    Ok(response) => {
+      audit::record(audit::Action::Download, path, identity, req);
+      response
+    }
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => HttpResponse::NotFound().finish(),
+    Err(err) if err.kind() == std::io::ErrorKind::Unsupported => HttpResponse::UnsupportedMediaType()
+      .content_type("text/plain")
+      .body("The file's codecs need transcoding, use /api/transcode instead"),
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not transcode file - {err:?}"))
+  }
+}
+
+/// Intro and outro the file shares with the other episodes in its folder, for "skip intro" buttons
+#[get("/api/analysis/intro/{path:.*}")]
+async fn get_intro_analysis(
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  let path = path.into_inner();
+  if let Err(denied) = identity.check(&path) {
+    return HttpResponse::from(denied)
+  }
+  if !file::get_media_path(&path).is_file() {
+    return HttpResponse::NotFound().finish()
+  }
+  match video::unblocked(move || intro::detect(&path, &identity)).await {
+    Ok(analysis) => HttpResponse::Ok().json(analysis),
+    Err(err) if err.is_over_budget() => HttpResponse::ServiceUnavailable()
+      .insert_header((header::RETRY_AFTER, 1))
+      .content_type("text/plain")
+      .body(f!("Could not analyze file - {err}")),
+    Err(err) => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(f!("Could not analyze file - {err:?}"))
+  }
+}
+
+/// Moments of the video with the most motion and their thumbnails, a quick summary of long recordings
+#[get("/api/highlights/{path:.*}")]
+async fn get_highlights(
+  path: web::Path<String>,
+  query: web::Query<HighlightsRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  let path = path.into_inner();
+  if let Err(denied) = identity.check(&path) {
+    return HttpResponse::from(denied)
+  }
+  if !file::get_media_path(&path).is_file() {
+    return HttpResponse::NotFound().finish()
+  }
+  let count = query.count.unwrap_or(highlights::DEFAULT_COUNT).clamp(1, highlights::MAX_COUNT);
+  match video::unblocked(move || highlights::find(&path, count)).await {
+    Ok(moments) => HttpResponse::Ok().json(moments),
+    Err(err) if err.is_over_budget() => HttpResponse::ServiceUnavailable()
+      .insert_header((header::RETRY_AFTER, 1))
+      .content_type("text/plain")
+      .body(f!("Could not find highlights - {err}")),
+    Err(err) => HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(f!("Could not find highlights - {err:?}"))
+  }
+}
+
+/// RSS feed of the audio and video files in a folder so podcast apps can subscribe to it
+#[get("/api/feed/{path:.*}")]
+async fn get_feed(
+  req: HttpRequest,
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  let path = &path.into_inner();
+  if let Err(denied) = identity.check_downloadable(path) {
+    return HttpResponse::from(denied)
+  }
+  match feed::rss(&file::get_media_path(path), &base_url(&req), &identity) {
+    Ok(rss) => HttpResponse::Ok()
+      .content_type("application/rss+xml; charset=utf-8")
+      .body(rss),
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+      HttpResponse::NotFound().finish()
+    }
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not read folder - {err:?}"))
+  }
+}
+
+/// Files of a folder with their sizes, hashes and thumbnails for apps keeping an offline copy
+#[get("/api/sync-manifest/{path:.*}")]
+async fn get_sync_manifest(
+  req: HttpRequest,
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  let path = &path.into_inner();
+  if let Err(denied) = identity.check_downloadable(path) {
+    return HttpResponse::from(denied)
+  }
+  match manifest::get(&file::get_media_path(path), &identity) {
+    Ok(manifest) => {
+      let etag = header::EntityTag::new_strong(manifest.version.clone());
+      let fresh = req.headers()
+      .get(header::IF_NONE_MATCH)
+      .and_then(|value| value.to_str().ok())
+      .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag.to_string()));
+      if fresh {
+        return HttpResponse::NotModified().insert_header(header::ETag(etag)).finish()
+      }
+      HttpResponse::Ok().insert_header(header::ETag(etag)).json(manifest)
+    }
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+      HttpResponse::NotFound().finish()
+    }
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not read folder - {err:?}"))
+  }
+}
+
+/// Playback descriptor for Cast receivers, which fetch the media on their own
+#[get("/api/cast/{path:.*}")]
+async fn get_cast_media(
+  req: HttpRequest,
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  let path = &path.into_inner();
+  if let Err(denied) = identity.check_downloadable(path) {
+    return HttpResponse::from(denied)
+  }
+  match cast::describe(path, &base_url(&req)) {
+    Ok((media, warnings)) => envelope::ok(media, warnings),
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+      HttpResponse::NotFound().finish()
+    }
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not describe file - {err:?}"))
+  }
+}
+
+#[get("/api/file-info/{path:.*}")]
+async fn get_download_info(
+  req: HttpRequest,
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  let path = &path.into_inner();
+  if let Err(denied) = identity.check_downloadable(path) {
+    return HttpResponse::from(denied)
+  }
+  match file::DownloadInfo::from_path(path, f!("{}/file/{path}", base_url(&req))) {
+    Ok(info) => HttpResponse::Ok().json(info),
+    Err(_) => HttpResponse::NotFound().finish(),
+  }
+}
+
+/// Scheme and host the request was made to, for URLs fetched by other devices
+fn base_url(req: &HttpRequest) -> String {
+  let info = req.connection_info();
+  f!("{}://{}", info.scheme(), info.host())
+}
+
+#[get("/file/{path:.*}")]
+async fn get_file(
+  req: HttpRequest,
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  let path = &path.into_inner();
+  if let Err(denied) = identity.check_downloadable(path) {
+    return HttpResponse::from(denied)
+  }
+  let file_path = file::get_media_path(path);
+  if file_path.is_dir() {
+    return HttpResponse::NotFound().finish()
+  }
+  if video::is_heif(&file_path) && !accepts_original(&req, &file_path) {
+    return match cache::coalesced_thumbnail(cache::ThumbnailKey::converted(path), None).await {
+      Ok(image) => {
+        audit::record(audit::Action::Download, path, &identity, &req);
+        HttpResponse::Ok()
+        .insert_header((header::VARY, "Accept"))
+        .content_type("image/webp")
+        .body(web::Bytes::copy_from_slice(&image))
+      }
+      Err(err) if err.is_over_budget() => HttpResponse::ServiceUnavailable()
+        .insert_header((header::RETRY_AFTER, 1))
+        .content_type("text/plain")
+        .body(f!("Could not convert image - {err}")),
+      Err(err) => HttpResponse::BadRequest()
+        .content_type("text/plain")
+        .body(f!("Could not convert image - {err:?}"))
+    }
+  }
+  let is_large = std::fs::metadata(&file_path).is_ok_and(|meta| meta.len() >= config::get().file_stream_min_bytes);
+  if is_large {
+    return match stream::serve(&req, &file_path) {
+      Ok(response) => {
+        audit::record(audit::Action::Download, path, &identity, &req);
+        response
+      }
+      Err(_) => HttpResponse::NotFound().finish(),
+    }
+  }
+  match actix_fs::NamedFile::open_async(file_path).await {
+    Ok(file) => {
+      audit::record(audit::Action::Download, path, &identity, &req);
+      file.into_response(&req)
+    }
+    Err(_) => HttpResponse::NotFound().finish(),
+  }
+}
+
+/// Whether the client lists the type of `file_path` in its Accept header. Browsers send `*/*`
+/// for images they can't show too, so only the type itself counts
+fn accepts_original(req: &HttpRequest, file_path: &Path) -> bool {
+  let extension = file_path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+  let mime = actix_fs::file_extension_to_mime(extension);
+  req.headers()
+  .get(header::ACCEPT)
+  .and_then(|value| value.to_str().ok())
+  .is_some_and(|accept| {
+    accept.split(',').any(|item| item.split(';').next().unwrap_or_default().trim() == mime.essence_str())
+  })
+}
+
+/// `GET /api/events?folder=a&folder=b/c&recursive=true` streams changes in the given folders,
+/// the whole library when no folder is given
+#[get("/api/events")]
+async fn get_events(req: HttpRequest, identity: access::Identity) -> impl Responder {
+  let params = web::Query::<Vec<(String, String)>>::from_query(req.query_string())
+  .map(web::Query::into_inner)
+  .unwrap_or_default();
+  let recursive = params.iter().any(|(key, value)| key == "recursive" && value != "false");
+  let mut watches = Vec::new();
+  for (key, folder) in &params {
+    if key != "folder" {
+      continue
+    }
+    let watch = events::Watch::new(folder, recursive);
+    if !watch.folder.is_empty() {
+      if let Err(denied) = identity.check(&watch.folder) {
+        return HttpResponse::from(denied)
+      }
+    }
+    watches.push(watch);
+  }
+  if watches.is_empty() {
+    watches.push(events::Watch::new("", true));
+  }
+  events::subscribe(watches, identity)
+}
+
+#[get("/api/admin/audit")]
+async fn get_audit_log(
+  query: web::Query<audit::AuditQuery>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !identity.is_admin() {
+    return HttpResponse::Forbidden().finish()
+  }
+  match audit::query(&query) {
+    Ok(entries) => HttpResponse::Ok().json(entries),
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not read audit log - {err:?}"))
+  }
+}
+
+/// Files and folders whose hrefs shadow each other once case and Unicode normalization are ignored
+#[get("/api/admin/report/collisions")]
+async fn get_collisions(identity: access::Identity) -> impl Responder {
+  if !identity.is_admin() {
+    return HttpResponse::Forbidden().finish()
+  }
+  match collisions::report() {
+    Ok(collisions) => HttpResponse::Ok().json(collisions),
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not read index - {err:?}"))
+  }
+}
+
+#[get("/api/admin/storage")]
+async fn get_storage(identity: access::Identity) -> impl Responder {
+  if !identity.is_admin() {
+    return HttpResponse::Forbidden().finish()
+  }
+  HttpResponse::Ok().json(storage::volumes())
+}
+
+/// Config in use, reloads included. Tokens and passwords are left out
+#[get("/api/admin/config")]
+async fn get_config(identity: access::Identity) -> impl Responder {
+  if !identity.is_admin() {
+    return HttpResponse::Forbidden().finish()
+  }
+  HttpResponse::Ok().json(config::get())
+}
+
+#[get("/api/admin/stats")]
+async fn get_stats(identity: access::Identity) -> impl Responder {
+  if !identity.is_admin() {
+    return HttpResponse::Forbidden().finish()
+  }
+  HttpResponse::Ok().json(perf::stats())
+}
+
+#[get("/api/admin/thumbnail-defaults/{path:.*}")]
+async fn get_thumbnail_defaults(
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !identity.is_admin() {
+    return HttpResponse::Forbidden().finish()
+  }
+  HttpResponse::Ok().json(sidecar::load_thumbnail_defaults(&file::get_media_path(&path)))
+}
+
+/// Seek, width and quality used for thumbnails of the folder's files when requests don't set them,
+/// stored in the folder so they move with it. An empty object clears them
+#[put("/api/admin/thumbnail-defaults/{path:.*}")]
+async fn set_thumbnail_defaults(
+  path: web::Path<String>,
+  body: web::Json<sidecar::ThumbnailDefaults>,
+  identity: access::Identity,
+) -> impl Responder {
+  if !identity.is_admin() {
+    return HttpResponse::Forbidden().finish()
+  }
+  if let Err(denied) = identity.check_writable(&path) {
+    return HttpResponse::from(denied)
+  }
+  if !body.is_valid() {
+    return HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body("seek must be positive, width above 0 and quality from 1 to 100")
+  }
+  match sidecar::save_thumbnail_defaults(&file::get_media_path(&path), body.into_inner()) {
+    Ok(()) => HttpResponse::NoContent().finish(),
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+      HttpResponse::NotFound().finish()
+    }
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not save thumbnail defaults - {err:?}"))
+  }
+}
+
+/// Clears the thumbnail caches after thumbnail settings changed and regenerates the
+/// default thumbnails in the background, so old and new styles are never mixed
+#[post("/api/admin/cache/regenerate")]
+async fn regenerate_thumbnails(identity: access::Identity) -> impl Responder {
+  if !identity.is_admin() {
+    return HttpResponse::Forbidden().finish()
+  }
+  match library::videos() {
+    Ok(videos) => HttpResponse::Accepted().json(cache::regenerate(videos)),
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not read index - {err:?}"))
+  }
+}
+
+/// Drops every cached thumbnail and atlas without regenerating them, they're made again on demand
+#[post("/api/admin/cache/purge")]
+async fn purge_thumbnails(identity: access::Identity) -> impl Responder {
+  if !identity.is_admin() {
+    return HttpResponse::Forbidden().finish()
+  }
+  HttpResponse::Ok().json(cache::purge())
+}
+
+/// Starts a library scan now instead of waiting for the next one, poll `/api/admin/jobs` to
+/// see when it's done
+#[post("/api/admin/scan")]
+async fn start_scan(identity: access::Identity) -> impl Responder {
+  if !identity.is_admin() {
+    return HttpResponse::Forbidden().finish()
+  }
+  library::request_scan();
+  HttpResponse::Accepted().json(library::scan_status())
+}
+
+/// What the background scanner and thumbnail pre-generation are up to
+#[get("/api/admin/jobs")]
+async fn get_jobs(identity: access::Identity) -> impl Responder {
+  if !identity.is_admin() {
+    return HttpResponse::Forbidden().finish()
+  }
+  HttpResponse::Ok().json(JobsResponse {
+    scan: library::scan_status(),
+    prewarm_queued: cache::prewarm_queued(),
+  })
+}
+
+/// Stops pre-generating thumbnails, e.g. after a regeneration started by mistake
+#[delete("/api/admin/jobs/prewarm")]
+async fn cancel_prewarm(identity: access::Identity) -> impl Responder {
+  if !identity.is_admin() {
+    return HttpResponse::Forbidden().finish()
+  }
+  HttpResponse::Ok().json(CancelResponse { cancelled: cache::cancel_prewarm() })
+}
+
+/// Encoders that work on this machine, probed once at startup
+#[get("/api/capabilities")]
+async fn get_capabilities() -> impl Responder {
+  HttpResponse::Ok().json(capabilities::encoders())
+}
+
+#[get("/api/timeline")]
+async fn get_timeline(
+  query: web::Query<TimelineRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  if query.year.is_some_and(|year| !(1..=9999).contains(&year)) {
+    return HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body("Year must be between 1 and 9999")
+  }
+  if query.month.is_some_and(|month| !(1..=12).contains(&month)) {
+    return HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body("Month must be between 1 and 12")
+  }
+  if query.month.is_some() && query.year.is_none() {
+    return HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body("Month needs a year")
+  }
+  match library::timeline(&identity, query.year, query.month) {
+    Ok(timeline) => HttpResponse::Ok().json(timeline),
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not read timeline - {err:?}"))
+  }
+}
+
+#[get("/api/geo")]
+async fn get_geo_clusters(
+  query: web::Query<GeoRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  let bbox = match &query.bbox {
+    Some(bbox) => {
+      let values: Vec<f64> = bbox.split(',').filter_map(|v| v.trim().parse().ok()).collect();
+      match values[..] {
+        [min_lon, min_lat, max_lon, max_lat] if min_lon <= max_lon && min_lat <= max_lat => {
+          (min_lon, min_lat, max_lon, max_lat)
+        }
+        _ => return HttpResponse::BadRequest()
+          .content_type("text/plain")
+          .body("bbox must be min_lon,min_lat,max_lon,max_lat"),
+      }
+    }
+    None => (-180., -90., 180., 90.),
+  };
+
+  match library::geo_clusters(&identity, bbox, query.cells.unwrap_or(16)) {
+    Ok(clusters) => HttpResponse::Ok().json(clusters),
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not read locations - {err:?}"))
+  }
+}
+
+#[get("/api/tags")]
+async fn get_tags(identity: access::Identity) -> impl Responder {
+  match library::tags(&identity) {
+    Ok(tags) => HttpResponse::Ok().json(tags),
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not read tags - {err:?}"))
+  }
+}
+
+#[get("/api/tags/{tag}")]
+async fn get_tagged(
+  tag: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  match library::tagged(&identity, &tag) {
+    Ok(paths) => HttpResponse::Ok().json(paths),
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not read tagged files - {err:?}"))
+  }
+}
+
+/// Index entries that changed since a cursor so clients can mirror the library without listing
+/// every folder again
+#[get("/api/changes")]
+async fn get_changes(
+  query: web::Query<ChangesRequest>,
+  identity: access::Identity,
+) -> impl Responder {
+  let limit = query.limit.unwrap_or(library::CHANGES_PER_PAGE);
+  if !(1..=library::MAX_CHANGES_PER_PAGE).contains(&limit) {
+    return HttpResponse::BadRequest()
+      .content_type("text/plain")
+      .body(f!("limit must be between 1 and {}", library::MAX_CHANGES_PER_PAGE))
+  }
+  match library::changes(&identity, query.since, limit) {
+    Ok(changes) => HttpResponse::Ok().json(changes),
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not read index changes - {err:?}"))
+  }
+}
+
+#[post("/api/auth/login")]
+async fn login(
+  req: HttpRequest,
+  body: web::Json<LoginRequest>,
+) -> impl Responder {
+  let user = config::get().users.iter().find(|user| {
+    user.name == body.name && user.accepts_password(&body.password)
+  });
+  let user = match user {
+    Some(user) => user,
+    None => return HttpResponse::Unauthorized().finish(),
+  };
+
+  let device = req.headers()
+  .get(header::USER_AGENT)
+  .and_then(|value| value.to_str().ok())
+  .unwrap_or("unknown")
+  .to_string();
+  let ip = req.connection_info().realip_remote_addr().map(String::from);
+  let token = session::create(user, device, ip);
+
+  HttpResponse::Ok()
+    .cookie(
+      Cookie::build(access::TOKEN_COOKIE, token.clone())
+      .path("/")
+      .http_only(true)
+      .same_site(SameSite::Strict)
+      .finish()
+    )
+    .json(LoginResponse { token })
+}
+
+#[get("/api/auth/sessions")]
+async fn get_sessions(identity: access::Identity) -> impl Responder {
+  let user = match identity.user {
+    Some(user) => user,
+    None => return HttpResponse::Unauthorized().finish(),
+  };
+  // Admins can see and revoke every session
+  let owner = if identity.is_admin() {None} else {Some(user.name.as_str())};
+  HttpResponse::Ok().json(session::list(owner, identity.session.as_deref()))
+}
+
+#[delete("/api/auth/sessions/{id}")]
+async fn revoke_session(
+  id: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  let user = match identity.user {
+    Some(user) => user,
+    None => return HttpResponse::Unauthorized().finish(),
+  };
+  let owner = if identity.is_admin() {None} else {Some(user.name.as_str())};
+  if session::revoke(&id, owner) {
+    HttpResponse::NoContent().finish()
+  } else {
+    HttpResponse::NotFound().finish()
+  }
+}
+
+#[get("/api/progress/{path:.*}")]
+async fn get_progress(
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  let path = &path.into_inner();
+  if let Err(denied) = identity.check(path) {
+    return HttpResponse::from(denied)
+  }
+  match userdata::get_progress(&identity, path) {
+    Some(progress) => HttpResponse::Ok().json(progress),
+    None => HttpResponse::NotFound().finish(),
+  }
+}
+
+#[put("/api/progress/{path:.*}")]
+async fn set_progress(
+  path: web::Path<String>,
+  body: web::Json<userdata::Progress>,
+  identity: access::Identity,
+) -> impl Responder {
+  let path = &path.into_inner();
+  if let Err(denied) = identity.check(path) {
+    return HttpResponse::from(denied)
+  }
+  match userdata::set_progress(&identity, path, body.into_inner()) {
+    Ok(()) => HttpResponse::NoContent().finish(),
+    Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+      HttpResponse::from(access::Denied::Unauthorized)
+    }
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not save progress - {err:?}"))
+  }
+}
+
+#[get("/api/continue-watching")]
+async fn get_continue_watching(identity: access::Identity) -> impl Responder {
+  HttpResponse::Ok().json(userdata::continue_watching(&identity))
+}
+
+#[get("/api/favorites")]
+async fn get_favorites(identity: access::Identity) -> impl Responder {
+  HttpResponse::Ok().json(userdata::get_favorites(&identity))
+}
+
+#[put("/api/favorites/{path:.*}")]
+async fn add_favorite(
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  set_favorite(&path.into_inner(), &identity, true)
+}
+
+#[delete("/api/favorites/{path:.*}")]
+async fn remove_favorite(
+  path: web::Path<String>,
+  identity: access::Identity,
+) -> impl Responder {
+  set_favorite(&path.into_inner(), &identity, false)
+}
+
+fn set_favorite(path: &str, identity: &access::Identity, favorite: bool) -> HttpResponse {
+  if let Err(denied) = identity.check(path) {
+    return HttpResponse::from(denied)
+  }
+  match userdata::set_favorite(identity, path, favorite) {
+    Ok(()) => HttpResponse::NoContent().finish(),
+    Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+      HttpResponse::from(access::Denied::Unauthorized)
+    }
+    Err(err) => HttpResponse::InternalServerError()
+      .content_type("text/plain")
+      .body(f!("Could not save favorites - {err:?}"))
+  }
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+  // Read first so a broken config fails before anything starts
+  let args = config::Args::parse();
+  config::init(args.load()?)?;
+  config::watch(args);
+  let config = config::get();
+  video::init()
+  .expect("Could not initialize video API");
+  capabilities::start_probe();
+  library::start_scanner();
+  trash::start_purger();
+  cache::recover();
+  transcode::recover();
+  cache::resume_prewarm();
+  transcode::resume();
+
+  let server = HttpServer::new(move || {
+    App::new()
+      .app_data(web::PayloadConfig::new(sidecar::ARTWORK_MAX_BYTES))
+      .service(get_video_thumbnail)
+      .service(get_folder_info)
+      .service(get_folder_order)
+      .service(set_folder_order)
+      .service(get_file_metadata)
+      .service(update_file_metadata)
+      .service(get_markers)
+      .service(add_marker)
+      .service(remove_marker)
+      .service(set_poster)
+      .service(remove_poster)
+      .service(set_artwork)
+      .service(remove_artwork)
+      .service(delete_file)
+      .service(move_files)
+      .service(copy_files)
+      .service(run_batch)
+      .service(get_trash)
+      .service(restore_trash)
+      .service(purge_trash)
+      .service(get_duplicates)
+      .service(resolve_duplicates)
+      .service(get_subtitle)
+      .service(search_subtitles)
+      .service(get_subtitle_tracks)
+      .service(get_video_atlas)
+      .service(get_waveform_atlas)
+      .service(get_waveform)
+      .service(get_spectrogram)
+      .service(get_storyboard)
+      .service(get_video_sprites)
+      .service(get_hls_playlist)
+      .service(get_hls_segment)
+      .service(get_dash_manifest)
+      .service(get_dash_init)
+      .service(get_dash_segment)
+      .service(get_transcode)
+      .service(get_remux)
+      .service(get_intro_analysis)
+      .service(get_highlights)
+      .service(get_capabilities)
+      .service(get_timeline)
+      .service(get_geo_clusters)
+      .service(get_tags)
+      .service(get_tagged)
+      .service(get_changes)
+      .service(login)
+      .service(get_sessions)
+      .service(revoke_session)
+      .service(get_events)
+      .service(get_audit_log)
+      .service(get_collisions)
+      .service(get_storage)
+      .service(get_stats)
+      .service(get_thumbnail_defaults)
+      .service(set_thumbnail_defaults)
+      .service(get_config)
+      .service(regenerate_thumbnails)
+      .service(purge_thumbnails)
+      .service(start_scan)
+      .service(get_jobs)
+      .service(cancel_prewarm)
+      .service(get_progress)
+      .service(set_progress)
+      .service(get_continue_watching)
+      .service(get_favorites)
+      .service(add_favorite)
+      .service(remove_favorite)
+      .service(get_feed)
+      .service(get_sync_manifest)
+      .service(get_cast_media)
+      .service(get_download_info)
+      .service(get_file)
+      .service(actix_fs::Files::new("/static", &config.public_folder))
+      .service(index)
+  })
+  .on_connect(access::on_connect)
+  .bind((config.host.as_str(), config.port))?;
+  #[cfg(unix)]
+  let server = match &config.admin_socket {
+    Some(socket) => {
+      println!("Admin socket at {socket}");
+      server.listen_uds(bind_admin_socket(Path::new(socket))?)?
+    }
+    None => server,
+  };
+  #[cfg(not(unix))]
+  if config.admin_socket.is_some() {
+    eprintln!("The admin socket is only supported on Unix, ignoring admin_socket");
+  }
+
+  println!("Listening in http://{}:{}", config.host, config.port);
+  let stopped = server.run().await;
+  // Picked up again on the next start
+  session::save();
+  cache::save_queue();
+  if let Some(socket) = &config.admin_socket {
+    let _ = std::fs::remove_file(socket);
+  }
+  stopped
+}
+
+/// Listens on the admin socket at `path` only the server's user can open, a socket left there
+/// by a server that didn't stop cleanly is replaced
+#[cfg(unix)]
+fn bind_admin_socket(path: &Path) -> std::io::Result<std::os::unix::net::UnixListener> {
+  use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+  if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+    std::fs::remove_file(path)?;
+  }
+  let folder = path.parent().filter(|folder| !folder.as_os_str().is_empty()).unwrap_or(Path::new("."));
+  std::fs::create_dir_all(folder)?;
+  // Bound in a folder only the server's user can enter so nobody connects before it's locked down
+  let mut private = path.as_os_str().to_owned();
+  private.push(f!(".{}.bind", std::process::id()));
+  let private = std::path::PathBuf::from(private);
+  std::fs::DirBuilder::new().mode(0o700).create(&private)?;
+  let bound = private.join("admin.sock");
+  let listener = std::os::unix::net::UnixListener::bind(&bound)
+  .and_then(|listener| {
+    std::fs::set_permissions(&bound, std::fs::Permissions::from_mode(0o600))?;
+    std::fs::rename(&bound, path)?;
+    Ok(listener)
+  });
+  let _ = std::fs::remove_file(&bound);
+  std::fs::remove_dir(&private)?;
+  listener
 }
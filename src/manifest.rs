@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::access::Identity;
+use crate::metadata::{self, MediaKind};
+use crate::{book, config, f, file, library, sidecar, util};
+
+/// Folders with more files than this get a partial manifest, it's meant for what fits on a phone
+pub const MAX_FILES: usize = 10_000;
+
+/// What an app needs to keep an offline copy of a folder
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+  folder: String,
+  /// Changes whenever a file is added, removed or modified, the manifest is served with it as
+  /// its `ETag` so checking a copy is still fresh costs an empty 304
+  pub version: String,
+  /// Only the first `MAX_FILES` files by path are listed
+  truncated: bool,
+  files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManifestFile {
+  /// Relative to the media folder, the file is at `/file/{path}`
+  path: String,
+  size: u64,
+  /// Unix seconds
+  mtime: i64,
+  /// Only known for files hashed by the library index
+  #[serde(skip_serializing_if = "Option::is_none")]
+  sha256: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  thumbnail: Option<String>,
+}
+
+/// Manifest of every file under `folder_path` `identity` can see, subfolders included. Mounts
+/// aren't walked into from the media folder, their manifests have to be asked for on their own
+pub fn get(folder_path: &Path, identity: &Identity) -> std::io::Result<Manifest> {
+  if !folder_path.is_dir() {
+    return Err(std::io::ErrorKind::NotFound.into())
+  }
+  let folder = file::get_relative_path(folder_path).unwrap_or_default();
+  let mut found = Vec::new();
+  library::walk(folder_path, &mut found);
+
+  let mut files: Vec<(String, &Path)> = found
+  .iter()
+  .filter_map(|file_path| {
+    let path = file::get_relative_path(file_path).filter(|path| identity.can_see(path))?;
+    Some((path, file_path.as_path()))
+  })
+  .collect();
+  files.sort_unstable();
+  let truncated = files.len() > MAX_FILES;
+  files.truncate(MAX_FILES);
+
+  let mut hasher = Sha256::new();
+  let files: Vec<ManifestFile> = files
+  .into_iter()
+  .filter_map(|(path, file_path)| {
+    let size = std::fs::metadata(file_path).ok()?.len();
+    let mtime = metadata::modified_time(file_path).unwrap_or_default();
+    hasher.update(f!("{path}\0{size}\0{mtime}\n"));
+    let sha256 = library::checksum(&path).unwrap_or_else(|err| {
+      eprintln!("Could not read checksum of {path} - {err:?}");
+      None
+    });
+    let has_thumbnail = matches!(MediaKind::from_path(file_path), MediaKind::Video) ||
+      book::is_book(file_path) ||
+      sidecar::artwork(file_path).is_some();
+    let thumbnail = (has_thumbnail && file::thumbnail_policy(&path) != config::ThumbnailPolicy::Off)
+    .then(|| f!("/api/thumbnail/{}", util::encode_path(&path)));
+    Some(ManifestFile { path, size, mtime, sha256, thumbnail })
+  })
+  .collect();
+
+  let version = hasher.finalize().iter().map(|byte| f!("{byte:02x}")).collect();
+  Ok(Manifest { folder, version, truncated, files })
+}
@@ -58,21 +58,27 @@ pub fn rotate_frame(src_frame: &VideoFrame, dst_frame: &mut VideoFrame, transfor
     a, b, u,
     c, d, v,
     x, y, w,
-  ] = transform;
-
-  let px_area = src_width * src_height;
+  ] = transform.map(|n| n as i64);
+  let px_area = std::cmp::min(src_width * src_height, src_data.len() / PX_BYTES);
   for i in 0..px_area {
     let (p, q) = (
-      (i % src_width) as i32,
-      (i / src_width) as i32,
+      (i % src_width) as i64,
+      (i / src_width) as i64,
     );
 
+    // Matrices come from untrusted files, degenerate ones skip pixels instead of panicking
     let z = u * p + v * q + w;
+    if z == 0 {
+      continue
+    }
     let dp = (a * p + c * q + x) / z;
     let dq = (b * p + d * q + y) / z;
-    let di = (dp + dst_width as i32 * dq) as usize * PX_BYTES;
+    if dp < 0 || dq < 0 || dp >= dst_width as i64 {
+      continue
+    }
+    let di = (dp + dst_width as i64 * dq) as usize * PX_BYTES;
 
-    if di < dst_data.len() {
+    if di + PX_BYTES <= dst_data.len() {
       for color_idx in 0..PX_BYTES {
         dst_data[di + color_idx] = src_data[i * PX_BYTES + color_idx];
       }
@@ -80,10 +86,36 @@ pub fn rotate_frame(src_frame: &VideoFrame, dst_frame: &mut VideoFrame, transfor
   }
 }
 
+/// Transform for `rotate_frame` and rotation in degrees that show an image with EXIF `orientation`
+/// upright, `None` for images already upright and unknown values. Offsets are 1 where they're the
+/// last column or row of the frame, `convert_frame` sets them once the scaled size is known
+pub fn exif_orientation_transform(orientation: u32) -> Option<([i32; 9], i32)> {
+  match orientation {
+    // Mirror
+    2 => Some(([-1, 0, 0, 0, 1, 0, 1, 0, 1], 0)),
+    // Turn 180 degrees
+    3 => Some(([-1, 0, 0, 0, -1, 0, 1, 1, 1], 180)),
+    // Flip upside down
+    4 => Some(([1, 0, 0, 0, -1, 0, 0, 1, 1], 0)),
+    // Mirror along the diagonal from the top left corner
+    5 => Some(([0, 1, 0, 1, 0, 0, 0, 0, 1], 90)),
+    // Turn 90 degrees clockwise
+    6 => Some(([0, 1, 0, -1, 0, 0, 1, 0, 1], 90)),
+    // Mirror along the diagonal from the top right corner
+    7 => Some(([0, -1, 0, -1, 0, 0, 1, 1, 1], -90)),
+    // Turn 90 degrees counterclockwise
+    8 => Some(([0, -1, 0, 1, 0, 0, 0, 1, 1], -90)),
+    _ => None,
+  }
+}
+
 /// Converts display matrix bytes into 3x3 integer matrix `[u8; 36]` => `[i32; 9]`
 /// # Arguments
 /// * `bytes` - Display matrix side data
 pub fn parse_display_matrix(bytes: &[u8]) -> Result<[i32; 9], String> {
+  if bytes.len() < 36 {
+    return Err(f!("Display matrix needs 36 bytes, got {}", bytes.len()))
+  }
   let mut matrix = [0; 9];
   // loop 3x3 matrix
   for i in 0..9 {
@@ -115,6 +147,75 @@ pub fn parse_display_matrix(bytes: &[u8]) -> Result<[i32; 9], String> {
   Ok(matrix)
 }
 
+/// First tile and amount of tiles of atlas page `page` with a tile every `frame_step`
+/// seconds of a `duration_ms` long video
+pub fn atlas_tiles(duration_ms: i64, page: u32, frame_step: u32, tiles_per_page: u32) -> (u32, usize) {
+  let frame_step = std::cmp::max(frame_step, 1);
+  let start = page.saturating_mul(tiles_per_page);
+  let max_frames = std::cmp::min(duration_ms.max(0) / 1000, u32::MAX as i64) as u32 / frame_step;
+  let modulo = max_frames % frame_step;
+  let end = std::cmp::min(
+    page.saturating_add(1).saturating_mul(tiles_per_page),
+    max_frames.saturating_add(frame_step - modulo),
+  );
+  (start, end.saturating_sub(start) as usize)
+}
+
+/// Seek target at `percentage` (0 to 1) of `duration` in `AV_TIME_BASE` units
+pub fn seek_position(percentage: f32, duration: i64) -> i64 {
+  // NaN ends up as 0 after the cast
+  (percentage.clamp(0., 1.) * duration.max(0) as f32) as i64
+}
+
+/// Power of each frequency from 0 to half the sample rate in `window`, Hann windowed.
+/// The window's length has to be a power of two, returns `window.len() / 2` bins
+pub fn power_spectrum(window: &[f32]) -> Vec<f32> {
+  let len = window.len();
+  debug_assert!(len.is_power_of_two());
+  let mut re: Vec<f32> = window
+  .iter()
+  .enumerate()
+  .map(|(i, sample)| {
+    let hann = 0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / len as f32).cos();
+    sample * hann
+  })
+  .collect();
+  let mut im = vec![0f32; len];
+
+  // Iterative radix-2 FFT, inputs in bit reversed order first
+  let mut j = 0;
+  for i in 1..len {
+    let mut bit = len >> 1;
+    while j & bit != 0 {
+      j ^= bit;
+      bit >>= 1;
+    }
+    j |= bit;
+    if i < j {
+      re.swap(i, j);
+    }
+  }
+  let mut size = 2;
+  while size <= len {
+    let angle = -std::f32::consts::TAU / size as f32;
+    for start in (0..len).step_by(size) {
+      for k in 0..size / 2 {
+        let (sin, cos) = (angle * k as f32).sin_cos();
+        let (even, odd) = (start + k, start + k + size / 2);
+        let odd_re = re[odd] * cos - im[odd] * sin;
+        let odd_im = re[odd] * sin + im[odd] * cos;
+        re[odd] = re[even] - odd_re;
+        im[odd] = im[even] - odd_im;
+        re[even] += odd_re;
+        im[even] += odd_im;
+      }
+    }
+    size <<= 1;
+  }
+
+  re.iter().zip(&im).take(len / 2).map(|(re, im)| re * re + im * im).collect()
+}
+
 fn to_fixed_point(x: i32, n: i32) -> i32 {
   ((x as f32) / (1 << n) as f32) as i32
 }
@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use serde::Serialize;
+
+use crate::video;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+  Image,
+  Video,
+  Audio,
+  Other,
+}
+
+impl MediaKind {
+  pub fn from_path(path: &Path) -> Self {
+    let extension = path.extension()
+    .and_then(|ext| ext.to_str())
+    .unwrap_or_default();
+    let mime = actix_files::file_extension_to_mime(extension);
+    match mime.type_().as_str() {
+      "image" => MediaKind::Image,
+      "video" => MediaKind::Video,
+      "audio" => MediaKind::Audio,
+      _ => MediaKind::Other,
+    }
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      MediaKind::Image => "image",
+      MediaKind::Video => "video",
+      MediaKind::Audio => "audio",
+      MediaKind::Other => "other",
+    }
+  }
+
+  pub fn parse(kind: &str) -> Self {
+    match kind {
+      "image" => MediaKind::Image,
+      "video" => MediaKind::Video,
+      "audio" => MediaKind::Audio,
+      _ => MediaKind::Other,
+    }
+  }
+}
+
+/// Camera settings and capture details of a photo, from its EXIF data
+#[derive(Debug, Default, Serialize)]
+pub struct PhotoDetails {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub make: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub model: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub lens: Option<String>,
+  /// Shutter speed in seconds
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub exposure_secs: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub f_number: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub iso: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub focal_length_mm: Option<f64>,
+  /// Unix time in seconds, local to the camera when the photo doesn't say its offset
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub taken_at: Option<i64>,
+  /// `(latitude, longitude)` in degrees
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub location: Option<(f64, f64)>,
+}
+
+/// Song details from the tags of an audio file, for album and artist browsers
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct MusicTags {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub artist: Option<String>,
+  /// Artist the whole album is credited to, differs from `artist` in compilations
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub album_artist: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub album: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub title: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub track: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub year: Option<i32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub genre: Option<String>,
+}
+
+impl MusicTags {
+  /// Picks the song details out of container tags, whose names depend on the format and its
+  /// case on the tagger. `None` when there's none
+  pub fn from_tags(tags: &HashMap<String, String>) -> Option<Self> {
+    let tags: HashMap<String, &str> = tags
+    .iter()
+    .map(|(key, value)| (key.to_lowercase(), value.trim()))
+    .filter(|(_, value)| !value.is_empty())
+    .collect();
+    let text = |keys: &[&str]| keys.iter().find_map(|&key| tags.get(key)).map(|value| value.to_string());
+    let number = |keys: &[&str]| keys.iter().find_map(|&key| leading_number(tags.get(key)?));
+    let music = Self {
+      artist: text(&["artist"]),
+      album_artist: text(&["album_artist", "albumartist"]),
+      album: text(&["album"]),
+      title: text(&["title"]),
+      // Usually `3/12`, the track and how many there are
+      track: number(&["track", "tracknumber"]).and_then(|track| u32::try_from(track).ok()),
+      // Dates like `2001-05-14` start with the year
+      year: number(&["date", "year", "originaldate"]).and_then(|year| i32::try_from(year).ok()),
+      genre: text(&["genre"]),
+    };
+    (music != Self::default()).then_some(music)
+  }
+}
+
+/// Digits `text` starts with
+fn leading_number(text: &str) -> Option<u64> {
+  let end = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+  text[..end].parse().ok()
+}
+
+/// Capture information found in the media itself
+#[derive(Debug, Default)]
+pub struct Probe {
+  /// Unix time in seconds
+  pub taken_at: Option<i64>,
+  /// `(latitude, longitude)` in degrees
+  pub location: Option<(f64, f64)>,
+  /// `(width, height)` as displayed
+  pub dimensions: Option<(u32, u32)>,
+  pub duration_ms: Option<i64>,
+}
+
+/// Reads when and where the media was captured from EXIF for images
+/// and container tags for videos, the capture time falls back to the modification time
+pub fn probe(path: &Path, kind: MediaKind) -> Probe {
+  let mut probe = match kind {
+    MediaKind::Image => probe_exif(path).unwrap_or_default(),
+    MediaKind::Video => match video::get_tags(path) {
+      Ok(tags) => Probe {
+        taken_at: tags.get("creation_time").and_then(|time| parse_datetime(time)),
+        location: tags.get("location")
+          .or_else(|| tags.get("com.apple.quicktime.location.ISO6709"))
+          .and_then(|location| parse_iso6709(location)),
+        ..Probe::default()
+      },
+      Err(_) => Probe::default(),
+    },
+    _ => Probe::default(),
+  };
+  if kind == MediaKind::Video {
+    if let Ok(info) = video::get_info(path) {
+      probe.dimensions = Some((info.width, info.height));
+      probe.duration_ms = Some(info.duration_ms);
+    }
+  }
+  if probe.taken_at.is_none() {
+    probe.taken_at = modified_time(path);
+  }
+  probe
+}
+
+pub fn modified_time(path: &Path) -> Option<i64> {
+  let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+  Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64)
+}
+
+fn probe_exif(path: &Path) -> Option<Probe> {
+  let exif = read_exif(path)?;
+  Some(Probe {
+    taken_at: exif_capture_time(&exif),
+    location: exif_location(&exif),
+    dimensions: exif_dimensions(&exif),
+    duration_ms: None,
+  })
+}
+
+/// Image size as recorded in EXIF, `None` when the image has no EXIF data
+pub fn image_dimensions(path: &Path) -> Option<(u32, u32)> {
+  exif_dimensions(&read_exif(path)?)
+}
+
+fn exif_dimensions(exif: &exif::Exif) -> Option<(u32, u32)> {
+  let dimension = |tag: exif::Tag, fallback: exif::Tag| {
+    exif.get_field(tag, exif::In::PRIMARY)
+    .or_else(|| exif.get_field(fallback, exif::In::PRIMARY))?
+    .value
+    .get_uint(0)
+  };
+  Some((
+    dimension(exif::Tag::PixelXDimension, exif::Tag::ImageWidth)?,
+    dimension(exif::Tag::PixelYDimension, exif::Tag::ImageLength)?,
+  ))
+}
+
+/// Artist, album, title and the rest of the song details of an audio file, `None` when it isn't tagged
+pub fn music_tags(path: &Path) -> Option<MusicTags> {
+  MusicTags::from_tags(&video::get_audio_tags(path).ok()?)
+}
+
+/// Camera, exposure, capture time and location of a photo, `None` when it has no EXIF data
+pub fn photo_details(path: &Path) -> Option<PhotoDetails> {
+  let exif = read_exif(path)?;
+  let text = |tag: exif::Tag| match exif.get_field(tag, exif::In::PRIMARY)?.value {
+    exif::Value::Ascii(ref values) => {
+      let text = String::from_utf8_lossy(values.first()?);
+      let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+      (!text.is_empty()).then(|| text.to_string())
+    }
+    _ => None,
+  };
+  let number = |tag: exif::Tag| match exif.get_field(tag, exif::In::PRIMARY)?.value {
+    exif::Value::Rational(ref values) => values.first().map(|value| value.to_f64()).filter(|value| value.is_finite()),
+    _ => None,
+  };
+  Some(PhotoDetails {
+    make: text(exif::Tag::Make),
+    model: text(exif::Tag::Model),
+    lens: text(exif::Tag::LensModel),
+    exposure_secs: number(exif::Tag::ExposureTime),
+    f_number: number(exif::Tag::FNumber),
+    iso: exif.get_field(exif::Tag::PhotographicSensitivity, exif::In::PRIMARY)
+      .and_then(|field| field.value.get_uint(0)),
+    focal_length_mm: number(exif::Tag::FocalLength),
+    taken_at: exif_capture_time(&exif),
+    location: exif_location(&exif),
+  })
+}
+
+/// EXIF orientation of an image from 1 to 8, `None` when the image has no EXIF data or doesn't say
+pub fn image_orientation(path: &Path) -> Option<u32> {
+  let exif = read_exif(path)?;
+  exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?.value.get_uint(0)
+}
+
+fn read_exif(path: &Path) -> Option<exif::Exif> {
+  let file = std::fs::File::open(path).ok()?;
+  exif::Reader::new()
+  .read_from_container(&mut std::io::BufReader::new(file))
+  .ok()
+}
+
+fn exif_capture_time(exif: &exif::Exif) -> Option<i64> {
+  let field = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)
+  .or_else(|| exif.get_field(exif::Tag::DateTime, exif::In::PRIMARY))?;
+
+  match field.value {
+    exif::Value::Ascii(ref values) => {
+      let time = exif::DateTime::from_ascii(values.first()?).ok()?;
+      let seconds = unix_from_civil(
+        time.year as i64,
+        time.month as u32,
+        time.day as u32,
+      ) + time.hour as i64 * 3600 + time.minute as i64 * 60 + time.second as i64;
+      // Without an offset the time is local to the camera, keep it as is
+      Some(seconds - time.offset.unwrap_or(0) as i64 * 60)
+    }
+    _ => None,
+  }
+}
+
+fn exif_location(exif: &exif::Exif) -> Option<(f64, f64)> {
+  let coordinate = |tag: exif::Tag, ref_tag: exif::Tag, negative: u8| {
+    let degrees = match exif.get_field(tag, exif::In::PRIMARY)?.value {
+      exif::Value::Rational(ref dms) if dms.len() >= 3 => {
+        dms[0].to_f64() + dms[1].to_f64() / 60. + dms[2].to_f64() / 3600.
+      }
+      _ => return None,
+    };
+    // Reference is "N"/"S" for latitude and "E"/"W" for longitude
+    let is_negative = match exif.get_field(ref_tag, exif::In::PRIMARY).map(|field| &field.value) {
+      Some(exif::Value::Ascii(values)) => {
+        values.first().and_then(|value| value.first()) == Some(&negative)
+      }
+      _ => false,
+    };
+    Some(if is_negative {-degrees} else {degrees})
+  };
+
+  let latitude = coordinate(exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef, b'S')?;
+  let longitude = coordinate(exif::Tag::GPSLongitude, exif::Tag::GPSLongitudeRef, b'W')?;
+  if !latitude.is_finite() || !longitude.is_finite() {
+    return None
+  }
+  Some((latitude, longitude))
+}
+
+/// Parses the latitude and longitude of an ISO 6709 location like `+37.7749-122.4194+010.000/`
+pub fn parse_iso6709(location: &str) -> Option<(f64, f64)> {
+  let mut numbers = Vec::new();
+  let mut start = None;
+  for (i, c) in location.char_indices() {
+    if c == '+' || c == '-' || c == '/' {
+      if let Some(start) = start {
+        numbers.push(location[start..i].parse::<f64>().ok()?);
+      }
+      start = if c == '/' {None} else {Some(i)};
+    }
+  }
+  if let Some(start) = start {
+    numbers.push(location[start..].parse::<f64>().ok()?);
+  }
+  match numbers[..] {
+    [latitude, longitude, ..] if latitude.abs() <= 90. && longitude.abs() <= 180. => {
+      Some((latitude, longitude))
+    }
+    _ => None,
+  }
+}
+
+/// Parses `YYYY-MM-DD[T ]HH:MM:SS` (ISO 8601, as found in container tags)
+/// and `YYYY:MM:DD HH:MM:SS` (EXIF) into unix time in seconds. Time zones are ignored,
+/// out of range fields make it `None` since they come from untrusted files
+pub fn parse_datetime(time: &str) -> Option<i64> {
+  let digits: Vec<i64> = time
+  .split(|c: char| !c.is_ascii_digit())
+  .filter(|part| !part.is_empty())
+  .take(6)
+  .map(|part| part.parse().ok())
+  .collect::<Option<_>>()?;
+  if digits.len() < 3 {
+    return None
+  }
+  let part = |i: usize| digits.get(i).copied().unwrap_or(0);
+  let limits = [1..=9999, 1..=12, 1..=31, 0..=23, 0..=59, 0..=60];
+  if !limits.iter().enumerate().all(|(i, limit)| limit.contains(&part(i))) {
+    return None
+  }
+  Some(
+    unix_from_civil(part(0), part(1) as u32, part(2) as u32) +
+    part(3) * 3600 + part(4) * 60 + part(5)
+  )
+}
+
+/// Converts unix time in seconds to `(year, month, day)`
+///
+/// *Note: This and `unix_from_civil` are translated from [date algorithms](http://howardhinnant.github.io/date_algorithms.html)*
+pub fn civil_from_unix(seconds: i64) -> (i64, u32, u32) {
+  let days = seconds.div_euclid(86400) + 719468;
+  let era = days.div_euclid(146097);
+  let day_of_era = days - era * 146097;
+  let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let mp = (5 * day_of_year + 2) / 153;
+  let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+  let month = (if mp < 10 {mp + 3} else {mp - 9}) as u32;
+  let year = year_of_era + era * 400 + if month <= 2 {1} else {0};
+  (year, month, day)
+}
+
+/// Midnight of `year-month-day` as unix time in seconds
+pub fn unix_from_civil(year: i64, month: u32, day: u32) -> i64 {
+  let year = if month <= 2 {year - 1} else {year};
+  let era = year.div_euclid(400);
+  let year_of_era = year - era * 400;
+  let month = month as i64;
+  let day_of_year = (153 * (if month > 2 {month - 3} else {month + 9}) + 2) / 5 + day as i64 - 1;
+  let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+  (era * 146097 + day_of_era - 719468) * 86400
+}
@@ -0,0 +1,90 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use serde::Serialize;
+
+/// Steps of the frame pipeline that are timed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+  Open,
+  Decode,
+  Scale,
+  FixImgData,
+  Rotate,
+  Compose,
+  Encode,
+}
+
+const STAGES: [Stage; 7] = [
+  Stage::Open,
+  Stage::Decode,
+  Stage::Scale,
+  Stage::FixImgData,
+  Stage::Rotate,
+  Stage::Compose,
+  Stage::Encode,
+];
+
+struct Counter {
+  count: AtomicU64,
+  total_ns: AtomicU64,
+  max_ns: AtomicU64,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const COUNTER: Counter = Counter {
+  count: AtomicU64::new(0),
+  total_ns: AtomicU64::new(0),
+  max_ns: AtomicU64::new(0),
+};
+
+static COUNTERS: [Counter; STAGES.len()] = [COUNTER; STAGES.len()];
+
+#[derive(Debug, Serialize)]
+pub struct StageStats {
+  stage: Stage,
+  count: u64,
+  total_ms: f64,
+  avg_ms: f64,
+  max_ms: f64,
+}
+
+/// Records the time since it was created under `stage` when dropped
+pub struct Timer {
+  stage: Stage,
+  start: Instant,
+}
+
+impl Drop for Timer {
+  fn drop(&mut self) {
+    record(self.stage, self.start.elapsed().as_nanos() as u64);
+  }
+}
+
+pub fn time(stage: Stage) -> Timer {
+  Timer { stage, start: Instant::now() }
+}
+
+pub fn record(stage: Stage, elapsed_ns: u64) {
+  let counter = &COUNTERS[stage as usize];
+  counter.count.fetch_add(1, Ordering::Relaxed);
+  counter.total_ns.fetch_add(elapsed_ns, Ordering::Relaxed);
+  counter.max_ns.fetch_max(elapsed_ns, Ordering::Relaxed);
+}
+
+/// Totals since the server started
+pub fn stats() -> Vec<StageStats> {
+  STAGES.iter().map(|&stage| {
+    let counter = &COUNTERS[stage as usize];
+    let count = counter.count.load(Ordering::Relaxed);
+    let total_ms = counter.total_ns.load(Ordering::Relaxed) as f64 / 1e6;
+    StageStats {
+      stage,
+      count,
+      total_ms,
+      avg_ms: if count == 0 {0.} else {total_ms / count as f64},
+      max_ms: counter.max_ns.load(Ordering::Relaxed) as f64 / 1e6,
+    }
+  }).collect()
+}
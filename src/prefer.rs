@@ -0,0 +1,137 @@
+use std::future::{ready, Ready};
+use std::path::Path;
+
+use actix_web::error::BlockingError;
+use actix_web::{dev::Payload, http::header, web, FromRequest, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::{access, description, f};
+use crate::envelope::{Envelope, Warning};
+use crate::file::FileInfo;
+
+/// How much detail a client wants in file listings, read from `?detail=minimal|enriched`
+/// or from `Prefer: return=minimal` / `Prefer: return=enriched`. The query wins over the header
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Detail {
+  /// Only what's needed to navigate, meant for scripts
+  Minimal,
+  #[default]
+  Standard,
+  /// Also reads every file for its size, duration, dimensions and ThumbHash placeholder
+  Enriched,
+}
+
+#[derive(Deserialize)]
+struct DetailQuery {
+  detail: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Shaped<'a> {
+  Minimal(crate::file::MinimalFileInfo<'a>),
+  Full(&'a FileInfo),
+}
+
+impl Detail {
+  fn parse(value: &str) -> Option<Self> {
+    match value.trim() {
+      "minimal" => Some(Self::Minimal),
+      "standard" | "representation" => Some(Self::Standard),
+      "enriched" => Some(Self::Enriched),
+      _ => None,
+    }
+  }
+
+  /// Responds with `files` and the folder's `description`, which scripts asking for minimal listings
+  /// don't get, nor anyone who can't see the description file
+  pub async fn listing(self, files: Vec<FileInfo>, folder: &Path, identity: &access::Identity) -> HttpResponse {
+    let mut warnings: Vec<Warning> = files.iter().filter_map(FileInfo::warning).collect();
+    let description = match self {
+      Self::Minimal => None,
+      _ => description::read(folder, identity).unwrap_or_else(|warning| {
+        warnings.push(warning);
+        None
+      }),
+    };
+    let files = match self.enrich(files).await {
+      Ok((files, enriched)) => {
+        warnings.extend(enriched);
+        files
+      }
+      Err(err) => return enrich_failed(err),
+    };
+    let shaped: Vec<Shaped> = files.iter().map(|file| self.shape(file)).collect();
+    Envelope::new(shaped, warnings)
+    .with_description(description)
+    .respond(self.response())
+  }
+
+  pub async fn file(self, file: FileInfo) -> HttpResponse {
+    match self.enrich(vec![file]).await {
+      Ok((files, warnings)) => Envelope::new(self.shape(&files[0]), warnings).respond(self.response()),
+      Err(err) => enrich_failed(err),
+    }
+  }
+
+  /// Enriches `files` on the blocking thread pool when asked to, reading them and waiting for
+  /// frame memory to make their placeholders would hold up the async workers
+  async fn enrich(self, mut files: Vec<FileInfo>) -> Result<(Vec<FileInfo>, Vec<Warning>), BlockingError> {
+    if self != Self::Enriched {
+      return Ok((files, Vec::new()))
+    }
+    web::block(move || {
+      let warnings = files.iter_mut().flat_map(FileInfo::enrich).collect();
+      (files, warnings)
+    }).await
+  }
+
+  fn shape(self, file: &FileInfo) -> Shaped<'_> {
+    match self {
+      Self::Minimal => Shaped::Minimal(file.minimal()),
+      _ => Shaped::Full(file),
+    }
+  }
+
+  fn response(self) -> actix_web::HttpResponseBuilder {
+    let mut response = HttpResponse::Ok();
+    response.insert_header((header::VARY, "Prefer"));
+    let applied = match self {
+      Self::Minimal => Some("return=minimal"),
+      Self::Standard => None,
+      Self::Enriched => Some("return=enriched"),
+    };
+    if let Some(applied) = applied {
+      response.insert_header(("Preference-Applied", applied));
+    }
+    response
+  }
+}
+
+fn enrich_failed(err: BlockingError) -> HttpResponse {
+  HttpResponse::InternalServerError()
+    .content_type("text/plain")
+    .body(f!("Could not read files - {err:?}"))
+}
+
+impl FromRequest for Detail {
+  type Error = actix_web::Error;
+  type Future = Ready<Result<Self, Self::Error>>;
+
+  fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+    let from_query = web::Query::<DetailQuery>::from_query(req.query_string())
+    .ok()
+    .and_then(|query| query.detail.as_deref().and_then(Self::parse));
+
+    let from_header = || {
+      req.headers()
+      .get_all("Prefer")
+      .filter_map(|value| value.to_str().ok())
+      .flat_map(|value| value.split(','))
+      .filter_map(|preference| preference.trim().strip_prefix("return="))
+      .find_map(Self::parse)
+    };
+
+    ready(Ok(from_query.or_else(from_header).unwrap_or_default()))
+  }
+}
@@ -0,0 +1,186 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Camera RAW photos with JPEG previews made by the camera, all TIFF inside but RAF
+const RAW_EXTENSIONS: [&str; 7] = ["arw", "cr2", "dng", "nef", "orf", "raf", "rw2"];
+/// Fujifilm RAF files start with this and say where their preview is right after it
+const RAF_MAGIC: &[u8] = b"FUJIFILMCCD-RAW";
+const RAF_PREVIEW_OFFSET: usize = 84;
+/// IFDs followed in a file, more than any camera writes
+const MAX_IFDS: usize = 32;
+const MAX_IFD_ENTRIES: u16 = 1024;
+/// JPEG segments skipped looking for the one with the picture size
+const MAX_JPEG_SEGMENTS: usize = 64;
+
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014A;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+/// Panasonic RW2 keeps its preview inline as undefined data
+const TAG_RW2_JPEG: u16 = 0x002E;
+
+/// Whether `path` is a camera RAW photo by its extension
+pub fn is_raw(path: &Path) -> bool {
+  path.extension()
+  .and_then(|ext| ext.to_str())
+  .is_some_and(|ext| RAW_EXTENSIONS.iter().any(|raw| ext.eq_ignore_ascii_case(raw)))
+}
+
+/// Offset and length of the largest JPEG preview inside the RAW photo at `path`. Sensor data stored
+/// as lossless JPEG is left out since it's not a picture, fails with `NotFound` when there's no preview
+pub fn preview(path: &Path) -> io::Result<(u64, u64)> {
+  let mut file = File::open(path)?;
+  let mut header = Vec::new();
+  (&mut file).take(RAF_PREVIEW_OFFSET as u64 + 8).read_to_end(&mut header)?;
+  let candidates = if header.starts_with(RAF_MAGIC) {
+    let field = |at: usize| header.get(at..at + 4).map(|bytes| read_u32(bytes, false) as u64);
+    match (field(RAF_PREVIEW_OFFSET), field(RAF_PREVIEW_OFFSET + 4)) {
+      (Some(offset), Some(len)) => vec![(offset, len)],
+      _ => Vec::new(),
+    }
+  } else {
+    tiff_candidates(&mut file, &header)?
+  };
+
+  candidates
+  .into_iter()
+  .filter(|&(_, len)| len > 0)
+  .filter_map(|(offset, len)| Some((jpeg_pixels(&mut file, offset)?, offset, len)))
+  .max_by_key(|&(pixels, ..)| pixels)
+  .map(|(_, offset, len)| (offset, len))
+  .ok_or_else(|| io::ErrorKind::NotFound.into())
+}
+
+/// Ranges in any IFD of a TIFF file that could be a JPEG
+fn tiff_candidates(file: &mut File, header: &[u8]) -> io::Result<Vec<(u64, u64)>> {
+  let little_endian = match header.get(..2) {
+    Some(b"II") => true,
+    Some(b"MM") => false,
+    _ => return Err(io::ErrorKind::InvalidData.into()),
+  };
+  let first_ifd = header.get(4..8).ok_or(io::ErrorKind::InvalidData)?;
+
+  let mut candidates = Vec::new();
+  let mut pending = vec![read_u32(first_ifd, little_endian) as u64];
+  let mut visited = Vec::new();
+  while let Some(ifd) = pending.pop() {
+    if ifd == 0 || visited.contains(&ifd) || visited.len() == MAX_IFDS {
+      continue
+    }
+    visited.push(ifd);
+    let Ok((entries, next_ifd)) = read_ifd(file, ifd, little_endian) else { continue };
+
+    let value = |tag: u16| entries.iter().find(|entry| entry.tag == tag);
+    if let (Some(offset), Some(len)) = (value(TAG_JPEG_OFFSET), value(TAG_JPEG_LENGTH)) {
+      candidates.push((offset.value as u64, len.value as u64));
+    }
+    // Full size previews are often the image of the IFD itself, a single strip of JPEG
+    if let (Some(offset), Some(len)) = (value(TAG_STRIP_OFFSETS), value(TAG_STRIP_BYTE_COUNTS)) {
+      if offset.count == 1 && len.count == 1 {
+        candidates.push((offset.value as u64, len.value as u64));
+      }
+    }
+    if let Some(jpeg) = value(TAG_RW2_JPEG) {
+      candidates.push((jpeg.value as u64, jpeg.count as u64));
+    }
+    if let Some(sub_ifds) = value(TAG_SUB_IFDS) {
+      pending.extend(read_offsets(file, sub_ifds, little_endian));
+    }
+    pending.push(next_ifd);
+  }
+  Ok(candidates)
+}
+
+struct IfdEntry {
+  tag: u16,
+  count: u32,
+  /// The value itself when it fits in 4 bytes, its offset otherwise
+  value: u32,
+}
+
+/// Entries of the IFD at `offset` and the offset of the next one, 0 for none
+fn read_ifd(file: &mut File, offset: u64, little_endian: bool) -> io::Result<(Vec<IfdEntry>, u64)> {
+  let mut count = [0; 2];
+  file.seek(SeekFrom::Start(offset))?;
+  file.read_exact(&mut count)?;
+  let count = read_u16(&count, little_endian).min(MAX_IFD_ENTRIES) as usize;
+  let mut bytes = vec![0; count * 12 + 4];
+  file.read_exact(&mut bytes)?;
+
+  let entries = bytes[..count * 12]
+  .chunks_exact(12)
+  .map(|entry| {
+    let kind = read_u16(&entry[2..4], little_endian);
+    // Shorts are left aligned in the value field
+    let value = if kind == 3 {
+      read_u16(&entry[8..10], little_endian) as u32
+    } else {
+      read_u32(&entry[8..12], little_endian)
+    };
+    IfdEntry {
+      tag: read_u16(&entry[..2], little_endian),
+      count: read_u32(&entry[4..8], little_endian),
+      value,
+    }
+  })
+  .collect();
+  Ok((entries, read_u32(&bytes[count * 12..], little_endian) as u64))
+}
+
+/// Offsets of a long entry, a single one is stored in the entry itself
+fn read_offsets(file: &mut File, entry: &IfdEntry, little_endian: bool) -> Vec<u64> {
+  if entry.count == 1 {
+    return vec![entry.value as u64]
+  }
+  let mut bytes = vec![0; entry.count.min(MAX_IFDS as u32) as usize * 4];
+  let read = file
+  .seek(SeekFrom::Start(entry.value as u64))
+  .and_then(|_| file.read_exact(&mut bytes));
+  if read.is_err() {
+    return Vec::new()
+  }
+  bytes.chunks_exact(4).map(|offset| read_u32(offset, little_endian) as u64).collect()
+}
+
+/// Width times height of the JPEG at `offset`, `None` if it isn't one or it's lossless
+/// or arithmetic coded, which is how sensor data is stored and nothing shows
+fn jpeg_pixels(file: &mut File, offset: u64) -> Option<u64> {
+  let mut marker = [0; 4];
+  file.seek(SeekFrom::Start(offset)).ok()?;
+  file.read_exact(&mut marker[..2]).ok()?;
+  if marker[..2] != [0xFF, 0xD8] {
+    return None
+  }
+  let mut position = offset + 2;
+  for _ in 0..MAX_JPEG_SEGMENTS {
+    file.seek(SeekFrom::Start(position)).ok()?;
+    file.read_exact(&mut marker).ok()?;
+    if marker[0] != 0xFF {
+      return None
+    }
+    match marker[1] {
+      // Baseline, extended and progressive
+      0xC0..=0xC2 => {
+        let mut frame = [0; 5];
+        file.read_exact(&mut frame).ok()?;
+        let (height, width) = (read_u16(&frame[1..3], false), read_u16(&frame[3..5], false));
+        return Some(height as u64 * width as u64)
+      }
+      0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF | 0xD9 | 0xDA => return None,
+      _ => position += 2 + read_u16(&marker[2..], false) as u64,
+    }
+  }
+  None
+}
+
+fn read_u16(bytes: &[u8], little_endian: bool) -> u16 {
+  let bytes = [bytes[0], bytes[1]];
+  if little_endian {u16::from_le_bytes(bytes)} else {u16::from_be_bytes(bytes)}
+}
+
+fn read_u32(bytes: &[u8], little_endian: bool) -> u32 {
+  let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+  if little_endian {u32::from_le_bytes(bytes)} else {u32::from_be_bytes(bytes)}
+}
@@ -0,0 +1,118 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+use rand::{distributions::Alphanumeric, Rng};
+
+use crate::{file, video};
+
+pub use video::SegmentFormat;
+
+/// Length of every segment but the last, in seconds
+pub const SEGMENT_SECS: u32 = 6;
+/// Playbacks that haven't fetched anything for this long are dropped along with their segments
+const PLAYBACK_IDLE: Duration = Duration::from_secs(10 * 60);
+/// Segments kept per playback, enough to seek back a little without transcoding again
+const SEGMENTS_PER_PLAYBACK: usize = 10;
+const ID_LEN: usize = 16;
+
+/// Playbacks indexed by the id given out with their playlist or manifest
+static PLAYBACKS: OnceLock<Mutex<HashMap<String, Playback>>> = OnceLock::new();
+
+/// A file being played through HLS or DASH and the segments transcoded for it
+struct Playback {
+  path: String,
+  /// Oldest first
+  segments: VecDeque<(SegmentFormat, u32, Arc<Vec<u8>>)>,
+  last_used: Instant,
+}
+
+/// Starts a playback of `path` and returns its id, segments fetched with it are cached for a while
+pub fn start(path: &str) -> String {
+  let id = random_id();
+  let mut playbacks = playbacks();
+  playbacks.retain(|_, playback| playback.last_used.elapsed() < PLAYBACK_IDLE);
+  playbacks.insert(id.clone(), Playback {
+    path: path.to_string(),
+    segments: VecDeque::new(),
+    last_used: Instant::now(),
+  });
+  id
+}
+
+/// Number of segments a video lasting `duration_ms` is split into
+pub fn count(duration_ms: i64) -> u32 {
+  (seconds(duration_ms) / SEGMENT_SECS as f64).ceil().max(1.) as u32
+}
+
+/// Length of segment `index` in seconds, only the last one is shorter than `SEGMENT_SECS`
+pub fn length(duration_ms: i64, index: u32) -> f64 {
+  (seconds(duration_ms) - (index * SEGMENT_SECS) as f64).clamp(0., SEGMENT_SECS as f64)
+}
+
+/// Segment `index` of `path` as `format`, from the cache of `playback` or transcoded.
+/// Segments are only cached for playbacks of the same file
+pub async fn get(
+  path: String,
+  index: u32,
+  format: SegmentFormat,
+  playback: Option<String>,
+) -> Result<Arc<Vec<u8>>, video::VideoError> {
+  let playback = playback.filter(|id| playbacks().get(id).is_some_and(|playback| playback.path == path));
+  if let Some(segment) = playback.as_deref().and_then(|id| cached(id, format, index)) {
+    return Ok(segment)
+  }
+
+  let video_path = file::get_media_path(&path);
+  let start = index.saturating_mul(SEGMENT_SECS);
+  let segment = Arc::new(
+    video::unblocked(move || video::transcode_segment(&video_path, start, SEGMENT_SECS, format)).await?,
+  );
+  if let Some(id) = playback {
+    store(&id, format, index, segment.clone());
+  }
+  Ok(segment)
+}
+
+fn seconds(duration_ms: i64) -> f64 {
+  duration_ms.max(0) as f64 / 1000.
+}
+
+fn cached(id: &str, format: SegmentFormat, index: u32) -> Option<Arc<Vec<u8>>> {
+  let mut playbacks = playbacks();
+  let playback = playbacks.get_mut(id)?;
+  playback.last_used = Instant::now();
+  playback
+  .segments
+  .iter()
+  .find(|(cached_format, cached, _)| *cached_format == format && *cached == index)
+  .map(|(_, _, segment)| segment.clone())
+}
+
+fn store(id: &str, format: SegmentFormat, index: u32, segment: Arc<Vec<u8>>) {
+  let mut playbacks = playbacks();
+  let Some(playback) = playbacks.get_mut(id) else { return };
+  playback.last_used = Instant::now();
+  if playback.segments.iter().any(|(cached_format, cached, _)| *cached_format == format && *cached == index) {
+    return
+  }
+  playback.segments.push_back((format, index, segment));
+  while playback.segments.len() > SEGMENTS_PER_PLAYBACK {
+    playback.segments.pop_front();
+  }
+}
+
+fn playbacks() -> MutexGuard<'static, HashMap<String, Playback>> {
+  PLAYBACKS
+  .get_or_init(|| Mutex::new(HashMap::new()))
+  .lock()
+  .unwrap_or_else(|err| err.into_inner())
+}
+
+fn random_id() -> String {
+  rand::thread_rng()
+  .sample_iter(&Alphanumeric)
+  .take(ID_LEN)
+  .map(char::from)
+  .collect()
+}
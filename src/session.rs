@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::{access, config, events, util};
+
+const TOKEN_LEN: usize = 48;
+const ID_LEN: usize = 12;
+
+/// Active sessions indexed by their token, loaded from `sessions.json` in the data folder
+static SESSIONS: OnceLock<Mutex<HashMap<String, Session>>> = OnceLock::new();
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+  /// Public identifier used to list and revoke the session, the token is never exposed
+  pub id: String,
+  pub user: &'static str,
+  /// User agent of the device that logged in
+  pub device: String,
+  pub ip: Option<String>,
+  /// Unix time in seconds
+  pub created: u64,
+  /// Unix time in seconds
+  pub last_seen: u64,
+  /// Whether this is the session making the request
+  pub current: bool,
+}
+
+/// A session as saved to disk, so logins survive restarts
+#[derive(Serialize, Deserialize)]
+struct StoredSession {
+  token: String,
+  id: String,
+  user: String,
+  device: String,
+  ip: Option<String>,
+  created: u64,
+  last_seen: u64,
+}
+
+/// Starts a new session for `user`, returns the session token
+pub fn create(user: &'static access::User, device: String, ip: Option<String>) -> String {
+  let token = random_string(TOKEN_LEN);
+  let now = util::now();
+  let mut sessions = sessions();
+  sessions.insert(token.clone(), Session {
+    id: random_string(ID_LEN),
+    user: &user.name,
+    device,
+    ip,
+    created: now,
+    last_seen: now,
+    current: false,
+  });
+  store(&sessions);
+  token
+}
+
+/// Finds the user and session id for `token`, refreshing its last seen time
+pub fn resolve(token: &str, ip: Option<String>) -> Option<(&'static access::User, String)> {
+  let mut sessions = sessions();
+  let session = sessions.get_mut(token)?;
+  let user = config::get().users.iter().find(|user| user.name == session.user)?;
+  session.last_seen = util::now();
+  if ip.is_some() {
+    session.ip = ip;
+  }
+  Some((user, session.id.clone()))
+}
+
+/// Lists sessions for `user` or every session if `None`, newest first
+pub fn list(user: Option<&str>, current: Option<&str>) -> Vec<Session> {
+  let mut list: Vec<Session> = sessions()
+  .values()
+  .filter(|session| user.is_none_or(|user| session.user == user))
+  .map(|session| Session {
+    current: current == Some(session.id.as_str()),
+    ..session.clone()
+  })
+  .collect();
+  list.sort_unstable_by_key(|session| std::cmp::Reverse(session.created));
+  list
+}
+
+/// Revokes the session with `id`, restricted to sessions of `user` unless `None`.
+/// Returns whether a session was revoked
+pub fn revoke(id: &str, user: Option<&str>) -> bool {
+  let mut sessions = sessions();
+  let len = sessions.len();
+  sessions.retain(|_, session| {
+    session.id != id || user.is_some_and(|user| session.user != user)
+  });
+  if sessions.len() == len {
+    return false
+  }
+  store(&sessions);
+  events::close_session(id);
+  true
+}
+
+/// Writes every session to disk, last seen times are otherwise only saved along with logins and revocations
+pub fn save() {
+  store(&sessions());
+}
+
+fn store(sessions: &HashMap<String, Session>) {
+  let stored: Vec<StoredSession> = sessions
+  .iter()
+  .map(|(token, session)| StoredSession {
+    token: token.clone(),
+    id: session.id.clone(),
+    user: session.user.to_string(),
+    device: session.device.clone(),
+    ip: session.ip.clone(),
+    created: session.created,
+    last_seen: session.last_seen,
+  })
+  .collect();
+  if let Err(err) = write_private(&sessions_path(), &serde_json::to_vec(&stored).unwrap_or_default()) {
+    eprintln!("Could not save sessions - {err:?}");
+  }
+}
+
+/// Sessions saved by a previous run, the ones of users no longer in the config are dropped
+fn load() -> HashMap<String, Session> {
+  let Ok(bytes) = std::fs::read(sessions_path()) else { return HashMap::new() };
+  let stored: Vec<StoredSession> = match serde_json::from_slice(&bytes) {
+    Ok(stored) => stored,
+    Err(err) => {
+      eprintln!("Could not read saved sessions - {err:?}");
+      return HashMap::new()
+    }
+  };
+  let users = &config::get().users;
+  stored
+  .into_iter()
+  .filter_map(|stored| {
+    let user = users.iter().find(|user| user.name == stored.user)?;
+    Some((stored.token, Session {
+      id: stored.id,
+      user: &user.name,
+      device: stored.device,
+      ip: stored.ip,
+      created: stored.created,
+      last_seen: stored.last_seen,
+      current: false,
+    }))
+  })
+  .collect()
+}
+
+/// Writes `bytes` to `path` readable only by the owner, the file holds session tokens
+fn write_private(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+  let mut options = std::fs::OpenOptions::new();
+  options.write(true).create(true).truncate(true);
+  #[cfg(unix)]
+  std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+  options.open(path)?.write_all(bytes)
+}
+
+fn sessions_path() -> PathBuf {
+  PathBuf::from(&config::get().data_folder).join("sessions.json")
+}
+
+fn sessions() -> MutexGuard<'static, HashMap<String, Session>> {
+  SESSIONS
+  .get_or_init(|| Mutex::new(load()))
+  .lock()
+  .unwrap_or_else(|err| err.into_inner())
+}
+
+fn random_string(len: usize) -> String {
+  rand::thread_rng()
+  .sample_iter(&Alphanumeric)
+  .take(len)
+  .map(char::from)
+  .collect()
+}
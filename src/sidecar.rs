@@ -0,0 +1,363 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::envelope::Warning;
+use crate::f;
+
+const SIDECAR_SUFFIX: &str = ".fylvur.json";
+const ARTWORK_SUFFIX: &str = ".fylvur.artwork";
+/// Uploaded artwork is a single image, anything larger is likely not meant as one
+pub const ARTWORK_MAX_BYTES: usize = 16 * 1024 * 1024;
+const MARKER_ID_LEN: usize = 8;
+/// Folder settings kept inside the folder, named so it's hidden like the sidecars
+const FOLDER_SIDECAR: &str = ".fylvur.json";
+
+/// User curated metadata stored next to a media file as `.<file name>.fylvur.json`
+/// so it survives moving the library to another server
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Sidecar {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub title: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  /// Sorted by start
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub markers: Vec<Marker>,
+  /// Thumbnail seek used when none is requested, a fraction of the duration below 1 or seconds
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub poster_seek: Option<f32>,
+}
+
+/// Named moment of a video, a chapter when it has an end
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Marker {
+  pub id: String,
+  pub name: String,
+  pub start_ms: i64,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub end_ms: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewMarker {
+  name: String,
+  start_ms: i64,
+  end_ms: Option<i64>,
+}
+
+/// Fields to update, missing fields are left untouched and empty strings clear them
+#[derive(Debug, Deserialize)]
+pub struct SidecarPatch {
+  title: Option<String>,
+  description: Option<String>,
+}
+
+/// Entries shown first in a folder and the order of the rest when sorting manually,
+/// both by file name
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FolderOrder {
+  #[serde(default)]
+  pub pinned: Vec<String>,
+  #[serde(default)]
+  pub order: Vec<String>,
+}
+
+impl FolderOrder {
+  fn is_empty(&self) -> bool {
+    self.pinned.is_empty() && self.order.is_empty()
+  }
+}
+
+/// Thumbnail settings of the files in a folder, used when a request doesn't set them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ThumbnailDefaults {
+  /// A fraction of the duration below 1 or seconds, a file's own poster seek comes first
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub seek: Option<f32>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub width: Option<u32>,
+  /// WebP quality from 1 to 100
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub quality: Option<u8>,
+}
+
+impl ThumbnailDefaults {
+  fn is_empty(&self) -> bool {
+    *self == Self::default()
+  }
+
+  pub fn is_valid(&self) -> bool {
+    self.seek.is_none_or(|seek| seek.is_finite() && seek >= 0.) &&
+    self.width.is_none_or(|width| width > 0) &&
+    self.quality.is_none_or(|quality| (1..=100).contains(&quality))
+  }
+}
+
+/// Everything stored in `FOLDER_SIDECAR`
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct FolderSidecar {
+  #[serde(flatten)]
+  order: FolderOrder,
+  #[serde(default, skip_serializing_if = "ThumbnailDefaults::is_empty")]
+  thumbnails: ThumbnailDefaults,
+}
+
+fn load_folder(folder: &Path) -> FolderSidecar {
+  std::fs::read(folder.join(FOLDER_SIDECAR))
+  .ok()
+  .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+  .unwrap_or_default()
+}
+
+/// Changes the folder sidecar with `update`, the file is removed once there's nothing left in it
+fn update_folder(folder: &Path, update: impl FnOnce(&mut FolderSidecar)) -> std::io::Result<()> {
+  if !folder.is_dir() {
+    return Err(std::io::ErrorKind::NotFound.into())
+  }
+  let mut sidecar = load_folder(folder);
+  update(&mut sidecar);
+  let path = folder.join(FOLDER_SIDECAR);
+  if sidecar.order.is_empty() && sidecar.thumbnails.is_empty() {
+    return match std::fs::remove_file(path) {
+      Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+      _ => Ok(()),
+    }
+  }
+  std::fs::write(path, serde_json::to_vec_pretty(&sidecar)?)
+}
+
+pub fn load_folder_order(folder: &Path) -> FolderOrder {
+  load_folder(folder).order
+}
+
+pub fn save_folder_order(folder: &Path, order: FolderOrder) -> std::io::Result<()> {
+  update_folder(folder, |sidecar| sidecar.order = order)
+}
+
+pub fn load_thumbnail_defaults(folder: &Path) -> ThumbnailDefaults {
+  load_folder(folder).thumbnails
+}
+
+pub fn save_thumbnail_defaults(folder: &Path, defaults: ThumbnailDefaults) -> std::io::Result<()> {
+  update_folder(folder, |sidecar| sidecar.thumbnails = defaults)
+}
+
+/// Thumbnail defaults of the folder `path` is in
+pub fn thumbnail_defaults_for(path: &Path) -> ThumbnailDefaults {
+  path.parent().map(load_thumbnail_defaults).unwrap_or_default()
+}
+
+pub fn sidecar_path(path: &Path) -> Option<PathBuf> {
+  hidden_path(path, SIDECAR_SUFFIX)
+}
+
+/// Image shown instead of a decoded frame for `path`, stored next to it as `.<file name>.fylvur.artwork`
+pub fn artwork_path(path: &Path) -> Option<PathBuf> {
+  hidden_path(path, ARTWORK_SUFFIX)
+}
+
+fn hidden_path(path: &Path, suffix: &str) -> Option<PathBuf> {
+  let mut name = OsString::from(".");
+  name.push(path.file_name()?);
+  name.push(suffix);
+  Some(path.with_file_name(name))
+}
+
+pub fn is_sidecar(path: &Path) -> bool {
+  path.file_name()
+  .map(|name| name.to_string_lossy())
+  .is_some_and(|name| {
+    name.starts_with('.') && (name.ends_with(SIDECAR_SUFFIX) || name.ends_with(ARTWORK_SUFFIX))
+  })
+}
+
+/// Uploaded artwork of `path`, if it has any
+pub fn artwork(path: &Path) -> Option<PathBuf> {
+  artwork_path(path).filter(|artwork| artwork.is_file())
+}
+
+/// Stores `image` as the artwork of the file or folder at `path`, only PNG, JPEG and WebP are accepted
+pub fn save_artwork(path: &Path, image: &[u8]) -> std::io::Result<()> {
+  if !path.exists() {
+    return Err(std::io::ErrorKind::NotFound.into())
+  }
+  let is_image = image.starts_with(b"\x89PNG\r\n\x1a\n")
+    || image.starts_with(&[0xFF, 0xD8, 0xFF])
+    || (image.starts_with(b"RIFF") && image.get(8..12) == Some(b"WEBP"));
+  if !is_image {
+    let message = "Artwork has to be a PNG, JPEG or WebP image";
+    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message))
+  }
+  let artwork_path = artwork_path(path).ok_or(std::io::ErrorKind::InvalidInput)?;
+  std::fs::write(artwork_path, image)
+}
+
+pub fn remove_artwork(path: &Path) -> std::io::Result<()> {
+  let artwork_path = artwork_path(path).ok_or(std::io::ErrorKind::InvalidInput)?;
+  std::fs::remove_file(artwork_path)
+}
+
+/// Reads the sidecar for `path`, missing or invalid sidecars are treated as empty
+pub fn load(path: &Path) -> Sidecar {
+  load_checked(path).0
+}
+
+/// Like `load` along with a warning when the sidecar exists but had to be ignored
+pub fn load_checked(path: &Path) -> (Sidecar, Option<Warning>) {
+  let bytes = match sidecar_path(path).map(std::fs::read) {
+    Some(Ok(bytes)) => bytes,
+    Some(Err(err)) if err.kind() != std::io::ErrorKind::NotFound => {
+      let warning = Warning::new("sidecar_unreadable", f!("Could not read sidecar - {err}"));
+      return (Sidecar::default(), Some(warning))
+    }
+    _ => return (Sidecar::default(), None),
+  };
+  match serde_json::from_slice(&bytes) {
+    Ok(sidecar) => (sidecar, None),
+    Err(err) => {
+      let warning = Warning::new("sidecar_invalid", f!("Sidecar was ignored, it's not valid - {err}"));
+      (Sidecar::default(), Some(warning))
+    }
+  }
+}
+
+/// Moves the sidecar and artwork of `from` so they belong to `to`, files without them are left alone
+pub fn rename(from: &Path, to: &Path) -> std::io::Result<()> {
+  for suffix in [SIDECAR_SUFFIX, ARTWORK_SUFFIX] {
+    let (Some(from), Some(to)) = (hidden_path(from, suffix), hidden_path(to, suffix)) else { return Ok(()) };
+    match std::fs::rename(from, to) {
+      Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+      _ => (),
+    }
+  }
+  Ok(())
+}
+
+/// Copies the sidecar and artwork of `from` for a copy of it at `to`
+pub fn copy(from: &Path, to: &Path) -> std::io::Result<()> {
+  for suffix in [SIDECAR_SUFFIX, ARTWORK_SUFFIX] {
+    let (Some(from), Some(to)) = (hidden_path(from, suffix), hidden_path(to, suffix)) else { return Ok(()) };
+    match std::fs::copy(from, to) {
+      Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+      _ => (),
+    }
+  }
+  Ok(())
+}
+
+/// Removes the sidecar and artwork of `path`, files without them are left alone
+pub fn remove(path: &Path) -> std::io::Result<()> {
+  for suffix in [SIDECAR_SUFFIX, ARTWORK_SUFFIX] {
+    let Some(hidden) = hidden_path(path, suffix) else { return Ok(()) };
+    match std::fs::remove_file(hidden) {
+      Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+      _ => (),
+    }
+  }
+  Ok(())
+}
+
+pub fn update(path: &Path, patch: SidecarPatch) -> std::io::Result<Sidecar> {
+  if !path.exists() {
+    return Err(std::io::ErrorKind::NotFound.into())
+  }
+  let mut sidecar = load(path);
+
+  if let Some(title) = patch.title {
+    sidecar.title = if title.is_empty() {None} else {Some(title)};
+  }
+  if let Some(description) = patch.description {
+    sidecar.description = if description.is_empty() {None} else {Some(description)};
+  }
+
+  save(path, &sidecar)?;
+  Ok(sidecar)
+}
+
+/// Stores the poster seek of a video, `None` goes back to the first frame
+pub fn set_poster(path: &Path, seek: Option<f32>) -> std::io::Result<Sidecar> {
+  if !path.is_file() {
+    return Err(std::io::ErrorKind::NotFound.into())
+  }
+  if seek.is_some_and(|seek| !seek.is_finite() || seek < 0.) {
+    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Seek has to be a positive number"))
+  }
+  let mut sidecar = load(path);
+  sidecar.poster_seek = seek;
+  save(path, &sidecar)?;
+  Ok(sidecar)
+}
+
+pub fn add_marker(path: &Path, marker: NewMarker) -> std::io::Result<Marker> {
+  if !path.is_file() {
+    return Err(std::io::ErrorKind::NotFound.into())
+  }
+  if marker.start_ms < 0 || marker.end_ms.is_some_and(|end_ms| end_ms <= marker.start_ms) {
+    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Markers have to end after they start"))
+  }
+  let marker = Marker {
+    id: rand::thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(MARKER_ID_LEN)
+    .map(char::from)
+    .collect(),
+    name: marker.name,
+    start_ms: marker.start_ms,
+    end_ms: marker.end_ms,
+  };
+  let mut sidecar = load(path);
+  let index = sidecar.markers.partition_point(|other| other.start_ms <= marker.start_ms);
+  sidecar.markers.insert(index, marker.clone());
+  save(path, &sidecar)?;
+  Ok(marker)
+}
+
+pub fn remove_marker(path: &Path, id: &str) -> std::io::Result<()> {
+  let mut sidecar = load(path);
+  let count = sidecar.markers.len();
+  sidecar.markers.retain(|marker| marker.id != id);
+  if sidecar.markers.len() == count {
+    return Err(std::io::ErrorKind::NotFound.into())
+  }
+  save(path, &sidecar)
+}
+
+/// Markers as an FFMETADATA file `ffmpeg -i video -i chapters -map_chapters 1` can embed,
+/// markers without an end last until the next one
+pub fn ffmetadata(sidecar: &Sidecar, duration_ms: i64) -> String {
+  let mut metadata = String::from(";FFMETADATA1\n");
+  if let Some(title) = &sidecar.title {
+    metadata.push_str(&f!("title={}\n", escape_ffmetadata(title)));
+  }
+  for (i, marker) in sidecar.markers.iter().enumerate() {
+    let end_ms = marker.end_ms.unwrap_or_else(|| {
+      sidecar.markers.get(i + 1).map_or(duration_ms, |next| next.start_ms)
+    });
+    metadata.push_str(&f!(
+      "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+      marker.start_ms,
+      end_ms.max(marker.start_ms),
+      escape_ffmetadata(&marker.name),
+    ));
+  }
+  metadata
+}
+
+fn escape_ffmetadata(value: &str) -> String {
+  let mut escaped = String::with_capacity(value.len());
+  for c in value.chars() {
+    if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+      escaped.push('\\');
+    }
+    escaped.push(c);
+  }
+  escaped
+}
+
+fn save(path: &Path, sidecar: &Sidecar) -> std::io::Result<()> {
+  let sidecar_path = sidecar_path(path).ok_or(std::io::ErrorKind::InvalidInput)?;
+  std::fs::write(sidecar_path, serde_json::to_vec_pretty(sidecar)?)
+}
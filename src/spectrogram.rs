@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::SystemTime;
+
+use crate::video::{self, VideoError};
+use crate::{f, file};
+
+/// Audio is resampled to this before its spectrum is taken, high enough to show the cutoff lossy
+/// encoders leave around 16 to 20 kHz
+const RATE: u32 = 44100;
+/// Samples per FFT, giving `WINDOW / 2` bins about 43 Hz wide
+const WINDOW: usize = 1024;
+/// Of rendered spectrograms, a column per slice of the audio
+pub const DEFAULT_WIDTH: usize = 800;
+pub const MAX_WIDTH: usize = 4096;
+pub const DEFAULT_HEIGHT: u32 = 256;
+/// Taller pictures would only repeat bins
+pub const MAX_HEIGHT: u32 = (WINDOW / 2) as u32;
+/// Spectrograms kept in memory, the whole lot is dropped past this
+const CACHED_SPECTROGRAMS: usize = 32;
+
+/// Encoded spectrograms by file, width, height, format and whether they're watermarked, with the
+/// modification time they were made for
+type Spectrograms = HashMap<(PathBuf, usize, u32, Format, bool), (SystemTime, Arc<Vec<u8>>)>;
+
+static SPECTROGRAMS: OnceLock<Mutex<Spectrograms>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+  Png,
+  Webp,
+}
+
+impl Format {
+  /// Format for the `format` query parameter, `None` if it's not one
+  pub fn parse(format: Option<&str>) -> Option<Self> {
+    match format.map(|format| format.to_lowercase()).as_deref() {
+      None | Some("png") => Some(Self::Png),
+      Some("webp") => Some(Self::Webp),
+      Some(_) => None,
+    }
+  }
+
+  pub fn mime_type(self) -> &'static str {
+    match self {
+      Self::Png => "image/png",
+      Self::Webp => "image/webp",
+    }
+  }
+}
+
+/// Picture of the frequencies in the audio of the file at `path` over time, `width` columns
+/// wide and `height` pixels tall. The whole file is decoded so it's kept for as long as the
+/// file doesn't change
+pub fn get(
+  path: &str,
+  width: usize,
+  height: u32,
+  format: Format,
+  watermark: Option<&video::Watermark>,
+) -> Result<Arc<Vec<u8>>, VideoError> {
+  let file_path = file::get_media_path(path);
+  let modified = std::fs::metadata(&file_path)
+  .and_then(|meta| meta.modified())
+  .map_err(|err| (f!("Could not read {file_path:?}"), err))?;
+  let key = (file_path, width, height, format, watermark.is_some());
+  if let Some((cached, spectrogram)) = cached_spectrograms().get(&key) {
+    if *cached == modified {
+      return Ok(spectrogram.clone())
+    }
+  }
+
+  let (_, spectra) = video::get_spectrum(Path::new(&key.0), width, WINDOW, RATE)?;
+  let mut frame = video::draw_spectrogram(&spectra, WINDOW, height)?;
+  if let Some(watermark) = watermark {
+    watermark.apply(&mut frame);
+  }
+  let spectrogram = Arc::new(match format {
+    Format::Png => video::encode_png(&frame)?,
+    Format::Webp => {
+      video::fix_img_data(&mut frame);
+      video::encode_webp_lossless(&frame).to_vec()
+    }
+  });
+  let mut cache = cached_spectrograms();
+  if cache.len() >= CACHED_SPECTROGRAMS {
+    cache.clear();
+  }
+  cache.insert(key, (modified, spectrogram.clone()));
+  Ok(spectrogram)
+}
+
+fn cached_spectrograms() -> MutexGuard<'static, Spectrograms> {
+  SPECTROGRAMS
+  .get_or_init(|| Mutex::new(HashMap::new()))
+  .lock()
+  .unwrap_or_else(|err| err.into_inner())
+}
@@ -0,0 +1,74 @@
+use std::io;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{config, f};
+
+#[derive(Debug, Serialize)]
+pub struct Volume {
+  /// What the volume is used for, `media`, `data` (index, user data and caches) or a mount name
+  name: &'static str,
+  path: &'static str,
+  total_bytes: u64,
+  /// Space usable by the server, may be lower than the free space on volumes with reserved blocks
+  available_bytes: u64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  warning: Option<String>,
+}
+
+/// Space left in the media folder, mount and data folder volumes
+pub fn volumes() -> Vec<Volume> {
+  let config = config::get();
+  [("media", config.media_folder.as_str()), ("data", config.data_folder.as_str())]
+  .into_iter()
+  .chain(config.mounts.iter().map(|mount| (mount.name.as_str(), mount.path.as_str())))
+  .map(|(name, path)| match disk_space(Path::new(path)) {
+    Ok((total_bytes, available_bytes)) => Volume {
+      name,
+      path,
+      total_bytes,
+      available_bytes,
+      warning: low_space_warning(total_bytes, available_bytes),
+    },
+    Err(err) => Volume {
+      name,
+      path,
+      total_bytes: 0,
+      available_bytes: 0,
+      warning: Some(f!("Could not read disk space - {err}")),
+    },
+  })
+  .collect()
+}
+
+fn low_space_warning(total_bytes: u64, available_bytes: u64) -> Option<String> {
+  let percent = if total_bytes == 0 {0.} else {available_bytes as f64 * 100. / total_bytes as f64};
+  let config = config::get();
+  if available_bytes < config.storage_warn_free_bytes || percent < config.storage_warn_free_percent {
+    return Some(f!("Low disk space, {} MiB ({percent:.1}%) left", available_bytes / 1024 / 1024))
+  }
+  None
+}
+
+/// Returns `(total, available)` bytes of the volume containing `path`
+#[cfg(unix)]
+fn disk_space(path: &Path) -> io::Result<(u64, u64)> {
+  use std::ffi::CString;
+  use std::os::unix::ffi::OsStrExt;
+
+  // The data folder may not exist until something is written to it
+  let path = path.ancestors().find(|path| path.exists()).unwrap_or(Path::new("."));
+  let path = CString::new(path.as_os_str().as_bytes())?;
+  let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+  if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+    return Err(io::Error::last_os_error())
+  }
+  let block_size = stat.f_frsize as u64;
+  Ok((stat.f_blocks as u64 * block_size, stat.f_bavail as u64 * block_size))
+}
+
+#[cfg(not(unix))]
+fn disk_space(_: &Path) -> io::Result<(u64, u64)> {
+  Err(io::ErrorKind::Unsupported.into())
+}
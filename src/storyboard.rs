@@ -0,0 +1,81 @@
+use serde::Serialize;
+
+use crate::envelope::Warning;
+use crate::video::{self, ImageFormat, TileLayout, VideoError};
+use crate::{f, file, util};
+
+/// Everything a player needs for hover previews
+#[derive(Debug, Serialize)]
+pub struct Storyboard {
+  pub duration_ms: i64,
+  pub tile_width: u32,
+  pub tile_height: u32,
+  pub layout: TileLayout,
+  /// URL of every atlas page, cues refer to them by index
+  pub pages: Vec<String>,
+  pub cues: Vec<Cue>,
+}
+
+/// A second of the video and the tile showing it
+#[derive(Debug, Serialize)]
+pub struct Cue {
+  pub start_ms: i64,
+  pub end_ms: i64,
+  pub page: u32,
+  pub x: u32,
+  pub y: u32,
+  /// WebVTT thumbnail cue text, the page URL with a media fragment for the tile
+  pub text: String,
+}
+
+/// Storyboard of the video at `path` with atlas pages in `layout` order and `format`
+pub fn describe(
+  path: &str,
+  layout: TileLayout,
+  format: ImageFormat,
+  burn_timestamps: bool,
+) -> Result<(Storyboard, Vec<Warning>), VideoError> {
+  let (duration_ms, warning) = video::get_checked_duration(&file::get_media_path(path))?;
+  let tiles = video::atlas_layout(duration_ms, layout);
+  let page_count = tiles.last().map_or(0, |tile| tile.page + 1);
+  let pages: Vec<String> = (0..page_count)
+  .map(|page| page_url(path, page, layout, format, burn_timestamps))
+  .collect();
+
+  let (tile_width, tile_height) = (video::ATLAS_TILE_WIDTH as u32, video::ATLAS_TILE_HEIGHT as u32);
+  let cues = tiles.into_iter().map(|tile| {
+    let start_ms = tile.seconds as i64 * 1000;
+    Cue {
+      start_ms,
+      end_ms: std::cmp::min(start_ms + 1000, duration_ms).max(start_ms),
+      text: f!("{}#xywh={},{},{tile_width},{tile_height}", pages[tile.page as usize], tile.x, tile.y),
+      page: tile.page,
+      x: tile.x,
+      y: tile.y,
+    }
+  }).collect();
+
+  let storyboard = Storyboard {
+    duration_ms,
+    tile_width,
+    tile_height,
+    layout,
+    pages,
+    cues,
+  };
+  Ok((storyboard, warning.into_iter().collect()))
+}
+
+fn page_url(path: &str, page: u32, layout: TileLayout, format: ImageFormat, burn_timestamps: bool) -> String {
+  let mut url = f!("/api/atlas/{}?page={page}", util::encode_path(path));
+  if layout == TileLayout::ColumnMajor {
+    url.push_str("&layout=column-major");
+  }
+  if let ImageFormat::Jpeg { quality } = format {
+    url.push_str(&f!("&format=jpeg&quality={quality}"));
+  }
+  if burn_timestamps {
+    url.push_str("&burn_timestamps=true");
+  }
+  url
+}
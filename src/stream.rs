@@ -0,0 +1,281 @@
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use actix_files::HttpRange;
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::http::header;
+use actix_web::rt::task::{spawn_blocking, JoinHandle};
+use actix_web::web::Bytes;
+use actix_web::http::StatusCode;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+
+use crate::{config, f};
+
+/// How often a file still being written is checked for more bytes
+const GROWING_POLL: Duration = Duration::from_millis(250);
+/// How long a range past the end of a file still being written waits for it
+const GROWING_WAIT: Duration = Duration::from_secs(30);
+
+/// Serves `path` reading `file_chunk_bytes` at a time, large chunks keep the amount of
+/// reads (and the CPU spent on them) low when downloading big originals.
+/// Honors a single byte range, further ranges are ignored like `NamedFile` does. Ranges with an
+/// `If-Range` that doesn't match the file anymore get the whole file so resumed downloads
+/// never mix two versions of it
+pub fn serve(req: &HttpRequest, path: &Path) -> io::Result<HttpResponse> {
+  let file = File::open(path)?;
+  let metadata = file.metadata()?;
+  let size = metadata.len();
+  let extension = path.extension()
+  .and_then(|ext| ext.to_str())
+  .unwrap_or_default();
+  let modified = metadata.modified().ok();
+  let etag = modified.map(|modified| entity_tag(size, modified));
+
+  let mut response = HttpResponse::Ok();
+  response
+  .content_type(actix_files::file_extension_to_mime(extension))
+  .insert_header((header::ACCEPT_RANGES, "bytes"));
+  if let Some(modified) = modified {
+    response.insert_header(header::LastModified(modified.into()));
+  }
+  if let Some(etag) = &etag {
+    response.insert_header(header::ETag(etag.clone()));
+  }
+  if !is_modified(req, etag.as_ref(), modified) {
+    return Ok(response.status(StatusCode::NOT_MODIFIED).finish())
+  }
+
+  let range = req.headers()
+  .get(header::RANGE)
+  .and_then(|range| range.to_str().ok())
+  .filter(|_| range_applies(req, etag.as_ref(), modified));
+  let (offset, length) = match range.map(|range| HttpRange::parse(range, size)) {
+    Some(Ok(ranges)) => match ranges.first() {
+      Some(range) => {
+        response
+        .status(StatusCode::PARTIAL_CONTENT)
+        .insert_header((
+          header::CONTENT_RANGE,
+          f!("bytes {}-{}/{size}", range.start, range.start + range.length - 1),
+        ));
+        (range.start, range.length)
+      }
+      None => (0, size),
+    },
+    Some(Err(_)) => {
+      return Ok(
+        HttpResponse::RangeNotSatisfiable()
+        .insert_header((header::CONTENT_RANGE, f!("bytes */{size}")))
+        .finish()
+      )
+    }
+    None => (0, size),
+  };
+
+  Ok(response.body(FileBody {
+    offset,
+    remaining: length,
+    growing: None,
+    state: ReadState::Idle(Some(file)),
+    _guard: None,
+  }))
+}
+
+/// Validator that changes whenever the file does, made from its size and modification time
+pub fn entity_tag(size: u64, modified: SystemTime) -> header::EntityTag {
+  let modified = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+  header::EntityTag::new_strong(f!("{size:x}-{:x}-{:x}", modified.as_secs(), modified.subsec_nanos()))
+}
+
+/// HTTP dates only have whole seconds
+fn unix_secs(time: SystemTime) -> u64 {
+  time.duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or_default()
+}
+
+/// Whether the copy the client has is outdated according to `If-None-Match`, or
+/// `If-Modified-Since` when there's no `If-None-Match`
+fn is_modified(req: &HttpRequest, etag: Option<&header::EntityTag>, modified: Option<SystemTime>) -> bool {
+  match req.get_header::<header::IfNoneMatch>() {
+    Some(header::IfNoneMatch::Any) => return false,
+    Some(header::IfNoneMatch::Items(tags)) => {
+      return !etag.is_some_and(|etag| tags.iter().any(|tag| tag.weak_eq(etag)))
+    }
+    None if req.headers().contains_key(header::IF_NONE_MATCH) => return true,
+    None => {}
+  }
+  match (req.get_header::<header::IfModifiedSince>(), modified) {
+    (Some(header::IfModifiedSince(since)), Some(modified)) => unix_secs(modified) > unix_secs(since.into()),
+    _ => true,
+  }
+}
+
+/// Whether the `Range` of the request can be served, its `If-Range` has to match the file as it is now
+fn range_applies(req: &HttpRequest, etag: Option<&header::EntityTag>, modified: Option<SystemTime>) -> bool {
+  if !req.headers().contains_key(header::IF_RANGE) {
+    return true
+  }
+  match req.get_header::<header::IfRange>() {
+    Some(header::IfRange::EntityTag(tag)) => etag.is_some_and(|etag| tag.strong_eq(etag)),
+    Some(header::IfRange::Date(date)) => {
+      modified.is_some_and(|modified| unix_secs(modified) == unix_secs(date.into()))
+    }
+    None => false,
+  }
+}
+
+/// Serves `file` while something is still appending to it, until `done` is set. The whole file
+/// (or a range from its start) is sent as it grows. Other ranges wait for their first byte to be
+/// written and get what's there so far with an unknown total size, players ask for more as they need it.
+/// `guard` is dropped once the response is sent or the client goes away
+pub async fn serve_growing(
+  req: &HttpRequest,
+  file: File,
+  content_type: &str,
+  done: Arc<AtomicBool>,
+  guard: impl Send + 'static,
+) -> io::Result<HttpResponse> {
+  let range = req.headers()
+  .get(header::RANGE)
+  .and_then(|range| range.to_str().ok())
+  // Ranges from the end can't be known yet
+  .filter(|range| !range.trim_start_matches("bytes=").starts_with('-'))
+  .and_then(|range| HttpRange::parse(range, u64::MAX).ok())
+  .and_then(|ranges| ranges.first().copied())
+  .filter(|range| range.start > 0);
+
+  let mut response = HttpResponse::Ok();
+  response
+  .content_type(content_type)
+  .insert_header((header::ACCEPT_RANGES, "bytes"));
+  let Some(range) = range else {
+    return Ok(response.body(FileBody {
+      offset: 0,
+      remaining: u64::MAX,
+      growing: Some(done),
+      state: ReadState::Idle(Some(file)),
+      _guard: Some(Box::new(guard)),
+    }))
+  };
+
+  let waited = Instant::now();
+  let written = loop {
+    // Checked first so bytes written right before it was set aren't missed
+    let finished = done.load(Ordering::Acquire);
+    let written = file.metadata()?.len();
+    if written > range.start || finished || waited.elapsed() >= GROWING_WAIT {
+      break written
+    }
+    actix_web::rt::time::sleep(GROWING_POLL).await;
+  };
+  if written <= range.start {
+    return Ok(
+      HttpResponse::RangeNotSatisfiable()
+      .insert_header((header::CONTENT_RANGE, f!("bytes */{written}")))
+      .finish()
+    )
+  }
+  let length = range.length.min(written - range.start);
+  response
+  .status(StatusCode::PARTIAL_CONTENT)
+  .insert_header((header::CONTENT_RANGE, f!("bytes {}-{}/*", range.start, range.start + length - 1)));
+  Ok(response.body(FileBody {
+    offset: range.start,
+    remaining: length,
+    growing: None,
+    state: ReadState::Idle(Some(file)),
+    _guard: Some(Box::new(guard)),
+  }))
+}
+
+/// File contents read in a blocking thread one chunk at a time
+struct FileBody {
+  offset: u64,
+  remaining: u64,
+  /// Set for files still being written, reads wait for more bytes until it's true
+  growing: Option<Arc<AtomicBool>>,
+  state: ReadState,
+  /// Kept for as long as the body is being sent
+  _guard: Option<Box<dyn Send>>,
+}
+
+enum ReadState {
+  Idle(Option<File>),
+  Reading(JoinHandle<io::Result<(File, Bytes)>>),
+}
+
+impl MessageBody for FileBody {
+  type Error = io::Error;
+
+  fn size(&self) -> BodySize {
+    match self.growing {
+      Some(_) => BodySize::Stream,
+      None => BodySize::Sized(self.remaining),
+    }
+  }
+
+  fn poll_next(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+    let body = self.get_mut();
+    loop {
+      match &mut body.state {
+        ReadState::Idle(file) => {
+          if body.remaining == 0 {
+            return Poll::Ready(None)
+          }
+          let mut file = match file.take() {
+            Some(file) => file,
+            None => return Poll::Ready(None),
+          };
+          let offset = body.offset;
+          let max_bytes = body.remaining.min(config::get().file_chunk_bytes as u64);
+          let growing = body.growing.clone();
+          body.state = ReadState::Reading(spawn_blocking(move || {
+            let mut chunk = Vec::with_capacity(max_bytes as usize);
+            file.seek(SeekFrom::Start(offset))?;
+            loop {
+              let finished = growing.as_ref().is_none_or(|done| done.load(Ordering::Acquire));
+              file.by_ref().take(max_bytes).read_to_end(&mut chunk)?;
+              if !chunk.is_empty() || finished {
+                break
+              }
+              std::thread::sleep(GROWING_POLL);
+            }
+            if chunk.is_empty() && growing.is_none() {
+              // File was truncated while being sent
+              return Err(io::ErrorKind::UnexpectedEof.into())
+            }
+            Ok((file, Bytes::from(chunk)))
+          }));
+        }
+        ReadState::Reading(handle) => {
+          let result = match Pin::new(handle).poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+          };
+          body.state = ReadState::Idle(None);
+          return Poll::Ready(Some(match result {
+            // A growing file was finished and everything in it sent
+            Ok(Ok((_, chunk))) if chunk.is_empty() => return Poll::Ready(None),
+            Ok(Ok((file, chunk))) => {
+              body.offset += chunk.len() as u64;
+              body.remaining -= chunk.len() as u64;
+              body.state = ReadState::Idle(Some(file));
+              Ok(chunk)
+            }
+            Ok(Err(err)) => Err(err),
+            Err(err) => Err(io::Error::other(err)),
+          }))
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,150 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{collate, f, file, util};
+
+const SUBTITLE_EXTENSIONS: [&str; 4] = ["srt", "vtt", "ass", "ssa"];
+
+/// External subtitle file sitting next to a video, e.g. `movie.en.srt` for `movie.mkv`
+#[derive(Debug, Serialize)]
+pub struct SubtitleFile {
+  name: String,
+  /// Whatever is between the video name and the extension, usually a language code
+  language: Option<String>,
+  format: String,
+  /// Endpoint serving the subtitle converted to WebVTT
+  href: String,
+}
+
+pub fn find_sidecars(video_path: &Path) -> Vec<SubtitleFile> {
+  let (folder, stem) = match (video_path.parent(), video_path.file_stem()) {
+    (Some(folder), Some(stem)) => (folder, stem.to_string_lossy()),
+    _ => return Vec::new(),
+  };
+  let dir = match std::fs::read_dir(folder) {
+    Ok(dir) => dir,
+    Err(_) => return Vec::new(),
+  };
+
+  let mut subtitles: Vec<SubtitleFile> = dir.filter_map(|entry| {
+    let path = entry.ok()?.path();
+    let name = path.file_name()?.to_str()?.to_string();
+    let format = path.extension()?.to_str()?.to_lowercase();
+    if !SUBTITLE_EXTENSIONS.contains(&format.as_str()) {
+      return None
+    }
+    let middle = name
+    .strip_prefix(stem.as_ref())?
+    .strip_prefix('.')?
+    .strip_suffix(path.extension()?.to_str()?)?
+    .trim_matches('.');
+    Some(SubtitleFile {
+      language: if middle.is_empty() {None} else {Some(middle.to_string())},
+      href: f!("/api/subtitle/{}", util::encode_path(&file::get_relative_path(&path)?)),
+      name,
+      format,
+    })
+  }).collect();
+
+  subtitles.sort_unstable_by(|a, b| collate::compare(&a.name, &b.name));
+  subtitles
+}
+
+/// Reads a subtitle file and converts it to WebVTT, anything that isn't a subtitle is refused
+/// so this can't be used to read arbitrary files as text
+pub fn to_webvtt(path: &Path) -> std::io::Result<String> {
+  let format = path.extension()
+  .and_then(|ext| ext.to_str())
+  .unwrap_or_default()
+  .to_lowercase();
+  if !SUBTITLE_EXTENSIONS.contains(&format.as_str()) {
+    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Not a subtitle file"))
+  }
+  let bytes = std::fs::read(path)?;
+  let content = String::from_utf8_lossy(&bytes);
+  let content = content.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+
+  Ok(match format.as_str() {
+    "vtt" => content,
+    "ass" | "ssa" => ass_to_webvtt(&content),
+    _ => srt_to_webvtt(&content),
+  })
+}
+
+fn srt_to_webvtt(content: &str) -> String {
+  let mut vtt = String::from("WEBVTT\n\n");
+  for line in content.lines() {
+    // SRT uses a comma as decimal separator in timestamps
+    if line.contains("-->") {
+      vtt.push_str(&line.replace(',', "."));
+    } else {
+      vtt.push_str(line);
+    }
+    vtt.push('\n');
+  }
+  vtt
+}
+
+fn ass_to_webvtt(content: &str) -> String {
+  let mut vtt = String::from("WEBVTT\n\n");
+  let mut in_events = false;
+  // Default column order of the [Events] section
+  let mut columns: Vec<String> = [
+    "layer", "start", "end", "style", "name",
+    "marginl", "marginr", "marginv", "effect", "text",
+  ].iter().map(|c| c.to_string()).collect();
+
+  for line in content.lines() {
+    let line = line.trim();
+    if line.starts_with('[') {
+      in_events = line.eq_ignore_ascii_case("[events]");
+      continue
+    }
+    if !in_events {
+      continue
+    }
+    if let Some(format) = line.strip_prefix("Format:") {
+      columns = format.split(',').map(|c| c.trim().to_lowercase()).collect();
+    } else if let Some(dialogue) = line.strip_prefix("Dialogue:") {
+      // Text is the last column and may contain commas itself
+      let values: Vec<&str> = dialogue.splitn(columns.len(), ',').collect();
+      let column = |name: &str| {
+        columns.iter().position(|c| c == name).and_then(|i| values.get(i)).map(|v| v.trim())
+      };
+      if let (Some(start), Some(end), Some(text)) = (
+        column("start").and_then(ass_timestamp),
+        column("end").and_then(ass_timestamp),
+        column("text"),
+      ) {
+        vtt.push_str(&f!("{start} --> {end}\n{}\n\n", ass_text(text)));
+      }
+    }
+  }
+  vtt
+}
+
+/// Converts `H:MM:SS.cc` to `HH:MM:SS.mmm`
+fn ass_timestamp(time: &str) -> Option<String> {
+  let mut parts = time.split(':');
+  let hours: u32 = parts.next()?.parse().ok()?;
+  let minutes: u32 = parts.next()?.parse().ok()?;
+  let seconds: f64 = parts.next()?.parse().ok()?;
+  let millis = (seconds.fract() * 1000.).round() as u32;
+  Some(f!("{hours:02}:{minutes:02}:{:02}.{millis:03}", seconds.trunc() as u32))
+}
+
+/// Strips override tags like `{\i1}` and converts ASS escapes
+pub fn ass_text(text: &str) -> String {
+  let mut plain = String::with_capacity(text.len());
+  let mut in_tag = false;
+  for c in text.chars() {
+    match c {
+      '{' => in_tag = true,
+      '}' if in_tag => in_tag = false,
+      _ if !in_tag => plain.push(c),
+      _ => {}
+    }
+  }
+  plain.replace("\\N", "\n").replace("\\n", "\n").replace("\\h", " ")
+}
@@ -0,0 +1,142 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::MediaKind;
+use crate::{access, f};
+
+/// Tag given to every indexed file matching `path` and all of `when`, rules are applied
+/// to the whole index on every scan so changing them retags files that didn't change
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TagRule {
+  pub tag: String,
+  /// Pattern relative to the media folder like the paths of access rules, every file when unset
+  pub path: Option<String>,
+  /// Conditions like `height>=2160` or `kind=video` on what the scan found out about the file
+  #[serde(default)]
+  pub when: Vec<String>,
+}
+
+/// What the scan found out about a file that rules can test
+#[derive(Debug)]
+pub struct Facts<'a> {
+  /// Relative to the media folder
+  pub path: &'a str,
+  pub kind: MediaKind,
+  pub size: u64,
+  pub width: Option<u32>,
+  pub height: Option<u32>,
+  pub duration_ms: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+  Kind,
+  Extension,
+  /// Bytes
+  Size,
+  Width,
+  Height,
+  /// Seconds
+  Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+  Equal,
+  NotEqual,
+  Less,
+  LessOrEqual,
+  Greater,
+  GreaterOrEqual,
+}
+
+#[derive(Debug)]
+struct Condition<'a> {
+  field: Field,
+  operator: Operator,
+  value: &'a str,
+}
+
+impl TagRule {
+  /// Catches conditions that can't be parsed when the config is loaded instead of on every scan
+  pub fn check(&self) -> Result<(), String> {
+    if self.tag.trim().is_empty() {
+      return Err("Tag can't be empty".into())
+    }
+    self.when.iter().try_for_each(|condition| parse(condition).map(|_| ()))
+  }
+
+  /// Whether the file `facts` describe gets this rule's tag. Files missing a field the
+  /// conditions test, like the height of a song, don't
+  pub fn matches(&self, facts: &Facts) -> bool {
+    self.path.as_ref().is_none_or(|pattern| access::matches_pattern(pattern, Path::new(facts.path))) &&
+    self.when.iter().all(|condition| parse(condition).is_ok_and(|condition| condition.holds(facts)))
+  }
+}
+
+impl Condition<'_> {
+  fn holds(&self, facts: &Facts) -> bool {
+    let text = match self.field {
+      Field::Kind => Some(facts.kind.as_str()),
+      Field::Extension => Path::new(facts.path).extension().and_then(|ext| ext.to_str()),
+      _ => None,
+    };
+    if let Some(text) = text {
+      let equal = text.eq_ignore_ascii_case(self.value);
+      return if self.operator == Operator::Equal {equal} else {!equal}
+    }
+
+    let number = match self.field {
+      Field::Size => Some(facts.size as f64),
+      Field::Width => facts.width.map(f64::from),
+      Field::Height => facts.height.map(f64::from),
+      Field::Duration => facts.duration_ms.map(|duration_ms| duration_ms as f64 / 1000.),
+      Field::Kind | Field::Extension => None,
+    };
+    let (Some(number), Ok(value)) = (number, self.value.parse::<f64>()) else { return false };
+    match self.operator {
+      Operator::Equal => number == value,
+      Operator::NotEqual => number != value,
+      Operator::Less => number < value,
+      Operator::LessOrEqual => number <= value,
+      Operator::Greater => number > value,
+      Operator::GreaterOrEqual => number >= value,
+    }
+  }
+}
+
+/// Splits `field operator value` where the operator is one of `=`, `!=`, `<`, `<=`, `>` or `>=`
+fn parse(condition: &str) -> Result<Condition<'_>, String> {
+  let at = condition
+  .find(['=', '!', '<', '>'])
+  .ok_or_else(|| f!("{condition:?} has no operator"))?;
+  let (field, rest) = condition.split_at(at);
+  let (operator, len) = match (rest.as_bytes()[0], rest.as_bytes().get(1)) {
+    (b'!', Some(b'=')) => (Operator::NotEqual, 2),
+    (b'<', Some(b'=')) => (Operator::LessOrEqual, 2),
+    (b'>', Some(b'=')) => (Operator::GreaterOrEqual, 2),
+    (b'<', _) => (Operator::Less, 1),
+    (b'>', _) => (Operator::Greater, 1),
+    (b'=', _) => (Operator::Equal, 1),
+    _ => return Err(f!("{condition:?} has an unknown operator")),
+  };
+  let field = match field.trim() {
+    "kind" => Field::Kind,
+    "extension" => Field::Extension,
+    "size" => Field::Size,
+    "width" => Field::Width,
+    "height" => Field::Height,
+    "duration" => Field::Duration,
+    field => return Err(f!("Unknown field {field:?} in {condition:?}")),
+  };
+  let value = rest[len..].trim();
+  let is_text = matches!(field, Field::Kind | Field::Extension);
+  if is_text && !matches!(operator, Operator::Equal | Operator::NotEqual) {
+    return Err(f!("{condition:?} can only compare with = or !="))
+  }
+  if !is_text && value.parse::<f64>().is_err() {
+    return Err(f!("{condition:?} has to compare with a number"))
+  }
+  Ok(Condition { field, operator, value })
+}
@@ -0,0 +1,182 @@
+use std::f64::consts::PI;
+
+/// ThumbHash only looks at images up to this size, bigger ones are scaled down first
+const MAX_SIZE: usize = 100;
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Base64 ThumbHash of a WebP image, `None` when it can't be decoded
+pub fn from_webp(bytes: &[u8]) -> Option<String> {
+  let image = webp::Decoder::new(bytes).decode()?;
+  let (width, height) = (image.width() as usize, image.height() as usize);
+  if width == 0 || height == 0 {
+    return None
+  }
+  // Decoded as RGB or RGBA depending on whether the image has alpha
+  let rgba: Vec<u8> = if image.len() == width * height * 4 {
+    image.to_vec()
+  } else {
+    image.chunks_exact(3).flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255]).collect()
+  };
+  let (width, height, rgba) = fit(width, height, &rgba);
+  Some(base64(&encode(width, height, &rgba)))
+}
+
+/// Scales an RGBA image down to fit in `MAX_SIZE` by averaging the pixels each one covers
+fn fit(width: usize, height: usize, rgba: &[u8]) -> (usize, usize, Vec<u8>) {
+  let scale = width.max(height) as f32 / MAX_SIZE as f32;
+  if scale <= 1. {
+    return (width, height, rgba.to_vec())
+  }
+  let w = ((width as f32 / scale).round() as usize).max(1);
+  let h = ((height as f32 / scale).round() as usize).max(1);
+  let mut scaled = Vec::with_capacity(w * h * 4);
+  for y in 0..h {
+    let (top, bottom) = (y * height / h, ((y + 1) * height / h).max(y * height / h + 1));
+    for x in 0..w {
+      let (left, right) = (x * width / w, ((x + 1) * width / w).max(x * width / w + 1));
+      let mut sum = [0u32; 4];
+      for sy in top..bottom {
+        for sx in left..right {
+          let i = (sx + sy * width) * 4;
+          for (total, channel) in sum.iter_mut().zip(&rgba[i..i + 4]) {
+            *total += *channel as u32;
+          }
+        }
+      }
+      let count = ((bottom - top) * (right - left)) as u32;
+      scaled.extend(sum.map(|total| (total / count) as u8));
+    }
+  }
+  (w, h, scaled)
+}
+
+/// Encodes an RGBA image of at most 100x100 as a ThumbHash, a tiny placeholder clients
+/// decode to a blurred preview. Same output as the reference implementation
+pub fn encode(width: usize, height: usize, rgba: &[u8]) -> Vec<u8> {
+  let pixels = width * height;
+  let (mut avg_r, mut avg_g, mut avg_b, mut avg_a) = (0., 0., 0., 0.);
+  for pixel in rgba.chunks_exact(4).take(pixels) {
+    let alpha = pixel[3] as f64 / 255.;
+    avg_r += alpha / 255. * pixel[0] as f64;
+    avg_g += alpha / 255. * pixel[1] as f64;
+    avg_b += alpha / 255. * pixel[2] as f64;
+    avg_a += alpha;
+  }
+  if avg_a > 0. {
+    avg_r /= avg_a;
+    avg_g /= avg_a;
+    avg_b /= avg_a;
+  }
+
+  let has_alpha = avg_a < pixels as f64;
+  let l_limit = if has_alpha {5.} else {7.};
+  let longest = width.max(height) as f64;
+  let lx = ((l_limit * width as f64 / longest).round() as usize).max(1);
+  let ly = ((l_limit * height as f64 / longest).round() as usize).max(1);
+
+  // Luminance, yellow-blue, red-green and alpha
+  let mut l = Vec::with_capacity(pixels);
+  let mut p = Vec::with_capacity(pixels);
+  let mut q = Vec::with_capacity(pixels);
+  let mut a = Vec::with_capacity(pixels);
+  for pixel in rgba.chunks_exact(4).take(pixels) {
+    let alpha = pixel[3] as f64 / 255.;
+    let r = avg_r * (1. - alpha) + alpha / 255. * pixel[0] as f64;
+    let g = avg_g * (1. - alpha) + alpha / 255. * pixel[1] as f64;
+    let b = avg_b * (1. - alpha) + alpha / 255. * pixel[2] as f64;
+    l.push((r + g + b) / 3.);
+    p.push((r + g) / 2. - b);
+    q.push(r - g);
+    a.push(alpha);
+  }
+
+  let encode_channel = |channel: &[f64], nx: usize, ny: usize| {
+    let (mut dc, mut ac, mut scale) = (0., Vec::new(), 0f64);
+    let mut fx = vec![0.; width];
+    for cy in 0..ny {
+      let mut cx = 0;
+      while cx * ny < nx * (ny - cy) {
+        for (x, f) in fx.iter_mut().enumerate() {
+          *f = (PI / width as f64 * cx as f64 * (x as f64 + 0.5)).cos();
+        }
+        let mut f = 0.;
+        for y in 0..height {
+          let fy = (PI / height as f64 * cy as f64 * (y as f64 + 0.5)).cos();
+          for x in 0..width {
+            f += channel[x + y * width] * fx[x] * fy;
+          }
+        }
+        f /= pixels as f64;
+        if cx > 0 || cy > 0 {
+          ac.push(f);
+          scale = scale.max(f.abs());
+        } else {
+          dc = f;
+        }
+        cx += 1;
+      }
+    }
+    if scale > 0. {
+      for f in &mut ac {
+        *f = 0.5 + 0.5 / scale * *f;
+      }
+    }
+    (dc, ac, scale)
+  };
+
+  let (l_dc, l_ac, l_scale) = encode_channel(&l, lx.max(3), ly.max(3));
+  let (p_dc, p_ac, p_scale) = encode_channel(&p, 3, 3);
+  let (q_dc, q_ac, q_scale) = encode_channel(&q, 3, 3);
+  let alpha = if has_alpha {Some(encode_channel(&a, 5, 5))} else {None};
+
+  let is_landscape = width > height;
+  let header24 = (63. * l_dc).round() as u32
+    | ((31.5 + 31.5 * p_dc).round() as u32) << 6
+    | ((31.5 + 31.5 * q_dc).round() as u32) << 12
+    | ((31. * l_scale).round() as u32) << 18
+    | (has_alpha as u32) << 23;
+  let header16 = (if is_landscape {ly} else {lx}) as u32
+    | ((63. * p_scale).round() as u32) << 3
+    | ((63. * q_scale).round() as u32) << 9
+    | (is_landscape as u32) << 15;
+  let mut hash = vec![
+    header24 as u8,
+    (header24 >> 8) as u8,
+    (header24 >> 16) as u8,
+    header16 as u8,
+    (header16 >> 8) as u8,
+  ];
+  if let Some((a_dc, _, a_scale)) = &alpha {
+    hash.push((15. * a_dc).round() as u8 | ((15. * a_scale).round() as u8) << 4);
+  }
+
+  let mut acs = vec![l_ac, p_ac, q_ac];
+  acs.extend(alpha.map(|(_, a_ac, _)| a_ac));
+  for (i, f) in acs.iter().flatten().enumerate() {
+    let nibble = (15. * f).round() as u8;
+    if i % 2 == 0 {
+      hash.push(nibble);
+    } else if let Some(last) = hash.last_mut() {
+      *last |= nibble << 4;
+    }
+  }
+  hash
+}
+
+fn base64(bytes: &[u8]) -> String {
+  let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+  for chunk in bytes.chunks(3) {
+    let n = (chunk[0] as u32) << 16
+      | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+      | *chunk.get(2).unwrap_or(&0) as u32;
+    for i in 0..4 {
+      if i <= chunk.len() {
+        encoded.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char);
+      } else {
+        encoded.push('=');
+      }
+    }
+  }
+  encoded
+}
+
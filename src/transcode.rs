@@ -0,0 +1,339 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{cache, config, f, file, video};
+
+/// Height of transcodes when the request doesn't pick one
+const DEFAULT_MAX_HEIGHT: u32 = 1080;
+/// Heights transcodes are made at, requested heights are rounded up so clients share them
+const HEIGHTS: [u32; 5] = [360, 480, 720, 1080, 2160];
+/// Transcodes made at once, the rest wait in the queue
+const WORKERS: usize = 2;
+/// How long a transcode nobody is streaming keeps going. Players often drop a response and ask
+/// for a range of the same file right after
+const ABANDONED_AFTER: Duration = Duration::from_secs(30);
+
+static JOBS: OnceLock<(Mutex<Jobs>, Condvar)> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+  #[default]
+  H264,
+}
+
+/// A transcode of a file, done or still being written
+pub enum Transcode {
+  /// Finished and kept in the data folder, the file won't change anymore
+  Ready(PathBuf),
+  /// Being written or waiting for its turn, the file keeps growing until it's done
+  Running(File, Viewer),
+}
+
+/// Someone streaming a running transcode, it's stopped a while after the last viewer is dropped
+pub struct Viewer(Arc<Job>);
+
+impl Viewer {
+  /// Joins `job` unless it was already stopped
+  fn join(job: &Arc<Job>) -> Option<Self> {
+    let mut watch = job.watch();
+    if watch.cancelled {
+      return None
+    }
+    watch.viewers += 1;
+    watch.left_at = None;
+    Some(Self(job.clone()))
+  }
+
+  /// Set once the transcode is finished, or failed
+  pub fn done(&self) -> Arc<AtomicBool> {
+    self.0.done.clone()
+  }
+}
+
+impl Drop for Viewer {
+  fn drop(&mut self) {
+    let mut watch = self.0.watch();
+    watch.viewers -= 1;
+    if watch.viewers == 0 {
+      watch.left_at = Some(Instant::now());
+    }
+  }
+}
+
+#[derive(Default)]
+struct Jobs {
+  /// Transcodes queued or being written, indexed by the file they end up in
+  by_target: HashMap<PathBuf, Arc<Job>>,
+  /// Transcodes waiting for a worker, oldest first
+  queue: VecDeque<Arc<Job>>,
+}
+
+/// Transcode left to make, saved so it's picked up again after a restart
+#[derive(Serialize, Deserialize)]
+struct Pending {
+  path: String,
+  codec: Codec,
+  max_height: u32,
+}
+
+struct Job {
+  path: String,
+  codec: Codec,
+  source: PathBuf,
+  max_height: u32,
+  target: PathBuf,
+  /// Unique to the job, a new one may start writing while a stopped one is cleaned up
+  partial: PathBuf,
+  done: Arc<AtomicBool>,
+  watch: Mutex<Watch>,
+}
+
+#[derive(Default)]
+struct Watch {
+  viewers: usize,
+  /// When the last viewer left, `None` while someone is streaming or nobody ever did
+  left_at: Option<Instant>,
+  cancelled: bool,
+}
+
+impl Job {
+  fn watch(&self) -> MutexGuard<'_, Watch> {
+    self.watch.lock().unwrap_or_else(|err| err.into_inner())
+  }
+
+  /// Stops the job if nobody streamed it for a while, it's not joined anymore once it's stopped
+  fn is_abandoned(&self) -> bool {
+    let mut watch = self.watch();
+    if watch.left_at.is_some_and(|left_at| left_at.elapsed() >= ABANDONED_AFTER) {
+      watch.cancelled = true;
+    }
+    watch.cancelled
+  }
+}
+
+/// Transcode of `path` to `codec` at most `max_height` pixels tall, queued in the background
+/// if there's none yet. Heights are rounded up to the closest of `HEIGHTS`. Files browsers can
+/// already decode are only remuxed, with `remux_only` other files fail with `Unsupported` instead
+/// of being transcoded. Transcodes are kept until evicted, files that change get a new one
+pub fn start(path: &str, codec: Codec, max_height: Option<u32>, remux_only: bool) -> io::Result<Transcode> {
+  let source = file::get_media_path(path);
+  let max_height = rendition_height(max_height);
+  let folder = folder();
+  let name = file_name(&source, codec, max_height)?;
+  if remux_only && !can_remux(&source, max_height) {
+    return Err(io::ErrorKind::Unsupported.into())
+  }
+  let target = folder.join(&name);
+
+  let (jobs, wake) = queue();
+  let mut jobs = jobs.lock().unwrap_or_else(|err| err.into_inner());
+  if let Some(viewer) = jobs.by_target.get(&target).and_then(Viewer::join) {
+    return Ok(Transcode::Running(File::open(&viewer.0.partial)?, viewer))
+  }
+  if target.is_file() && !is_intact(&target) {
+    eprintln!("Dropping damaged transcode {target:?}");
+    std::fs::remove_file(&target)?;
+  }
+  if target.is_file() {
+    // Marks it as recently used
+    if let Ok(file) = File::options().write(true).open(&target) {
+      let _ = file.set_modified(SystemTime::now());
+    }
+    return Ok(Transcode::Ready(target))
+  }
+
+  let job = add(&mut jobs, Pending { path: path.to_string(), codec, max_height }, &name, 1)?;
+  wake.notify_one();
+  store_queue(&jobs);
+  let viewer = Viewer(job);
+  Ok(Transcode::Running(File::open(&viewer.0.partial)?, viewer))
+}
+
+/// Queues the transcodes left to make when the server last stopped, nobody is streaming them
+/// so they're made to the end
+pub fn resume() {
+  let pending: Vec<Pending> = match std::fs::read(queue_path()) {
+    Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+      eprintln!("Could not read the transcode queue - {err:?}");
+      Vec::new()
+    }),
+    Err(_) => return,
+  };
+  let (jobs, wake) = queue();
+  let mut jobs = jobs.lock().unwrap_or_else(|err| err.into_inner());
+  for pending in pending {
+    let source = file::get_media_path(&pending.path);
+    // Removed since the server stopped
+    let Ok(name) = file_name(&source, pending.codec, pending.max_height) else { continue };
+    let target = folder().join(&name);
+    if jobs.by_target.contains_key(&target) || target.is_file() {
+      continue
+    }
+    let path = pending.path.clone();
+    match add(&mut jobs, pending, &name, 0) {
+      Ok(_) => println!("Resuming transcode of {path}"),
+      Err(err) => eprintln!("Could not resume transcode of {path} - {err:?}"),
+    }
+  }
+  wake.notify_all();
+  store_queue(&jobs);
+}
+
+/// Queues a transcode into `name` in the transcodes folder, `viewers` are already streaming it
+fn add(jobs: &mut Jobs, pending: Pending, name: &str, viewers: usize) -> io::Result<Arc<Job>> {
+  static PARTIAL_FILES: AtomicUsize = AtomicUsize::new(0);
+  let folder = folder();
+  std::fs::create_dir_all(&folder)?;
+  let id = PARTIAL_FILES.fetch_add(1, Ordering::Relaxed);
+  let partial = folder.join(f!("{name}.{id}{}", cache::PARTIAL_SUFFIX));
+  File::create(&partial)?;
+  let job = Arc::new(Job {
+    source: file::get_media_path(&pending.path),
+    path: pending.path,
+    codec: pending.codec,
+    max_height: pending.max_height,
+    target: folder.join(name),
+    partial,
+    done: Arc::new(AtomicBool::new(false)),
+    watch: Mutex::new(Watch { viewers, ..Watch::default() }),
+  });
+  jobs.by_target.insert(job.target.clone(), job.clone());
+  jobs.queue.push_back(job.clone());
+  Ok(job)
+}
+
+/// Writes the transcodes queued or being written to the data folder, stopped ones are left out
+fn store_queue(jobs: &Jobs) {
+  let pending: Vec<Pending> = jobs
+  .by_target
+  .values()
+  .filter(|job| !job.watch().cancelled)
+  .map(|job| Pending { path: job.path.clone(), codec: job.codec, max_height: job.max_height })
+  .collect();
+  let path = queue_path();
+  let saved = serde_json::to_vec(&pending)
+  .map_err(io::Error::from)
+  .and_then(|bytes| cache::write_atomic(&path, &bytes));
+  if let Err(err) = saved {
+    eprintln!("Could not save the transcode queue - {err:?}");
+  }
+}
+
+fn queue_path() -> PathBuf {
+  PathBuf::from(&config::get().data_folder).join("transcode-queue.json")
+}
+
+/// Smallest of `HEIGHTS` at least `height` tall or the tallest one, `DEFAULT_MAX_HEIGHT` without any
+fn rendition_height(height: Option<u32>) -> u32 {
+  let Some(height) = height.filter(|&height| height > 0) else { return DEFAULT_MAX_HEIGHT };
+  HEIGHTS
+  .into_iter()
+  .find(|&bucket| bucket >= height)
+  .unwrap_or(HEIGHTS[HEIGHTS.len() - 1])
+}
+
+/// Takes jobs from the queue one at a time, `WORKERS` of these run at once
+fn work() {
+  let (jobs, wake) = queue();
+  loop {
+    let job = {
+      let mut jobs = jobs.lock().unwrap_or_else(|err| err.into_inner());
+      loop {
+        match jobs.queue.pop_front() {
+          Some(job) => break job,
+          None => jobs = wake.wait(jobs).unwrap_or_else(|err| err.into_inner()),
+        }
+      }
+    };
+    run(&job);
+  }
+}
+
+fn run(job: &Arc<Job>) {
+  let Job { source, partial, target, .. } = job.as_ref();
+  let cancelled = || job.is_abandoned();
+  let result = if can_remux(source, job.max_height) {
+    video::remux_file(source, partial, &cancelled)
+  } else {
+    video::transcode_file(source, partial, job.max_height, &cancelled)
+  };
+  // Held while renaming so nobody opens the partial file once it's gone
+  let mut jobs = queue().0.lock().unwrap_or_else(|err| err.into_inner());
+  match result {
+    Ok(()) => match cache::persist(partial, target) {
+      Ok(()) => {
+        cache::evict(&folder(), config::get().transcode_cache_max_bytes);
+      }
+      Err(err) => eprintln!("Could not keep transcode of {source:?} - {err:?}"),
+    },
+    Err(err) => {
+      if job.watch().cancelled {
+        println!("Stopped transcoding {source:?}, nobody was watching");
+      } else {
+        eprintln!("Could not transcode {source:?} - {err:?}");
+      }
+      let _ = std::fs::remove_file(partial);
+    }
+  }
+  // A new job for the same file may have taken its place after this one was stopped
+  if jobs.by_target.get(target).is_some_and(|current| Arc::ptr_eq(current, job)) {
+    jobs.by_target.remove(target);
+    store_queue(&jobs);
+  }
+  job.done.store(true, Ordering::Release);
+}
+
+/// Whether the MP4 at `path` starts with its `ftyp` box, transcodes are only renamed to their
+/// final name once complete so anything else was damaged on disk
+fn is_intact(path: &Path) -> bool {
+  let mut header = [0; 8];
+  File::open(path).and_then(|mut file| file.read_exact(&mut header)).is_ok() && &header[4..] == b"ftyp"
+}
+
+/// Removes transcodes a crash left half written, only meant to be called when the server starts
+pub fn recover() {
+  cache::remove_partials(&folder());
+}
+
+fn can_remux(source: &Path, max_height: u32) -> bool {
+  video::can_remux(source, max_height).unwrap_or_else(|err| {
+    eprintln!("Could not check codecs of {source:?} - {err:?}");
+    false
+  })
+}
+
+/// Named after the source's path, size and modification time along with the transcode settings
+fn file_name(source: &Path, codec: Codec, max_height: u32) -> io::Result<String> {
+  let meta = std::fs::metadata(source)?;
+  if !meta.is_file() {
+    return Err(io::ErrorKind::NotFound.into())
+  }
+  let modified = meta.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+  let mut hasher = Sha256::new();
+  hasher.update(f!("{source:?}\n{modified}\n{}\n{codec:?}\n{max_height}", meta.len()));
+  let hash: String = hasher.finalize().iter().map(|byte| f!("{byte:02x}")).collect();
+  Ok(f!("{hash}.mp4"))
+}
+
+fn folder() -> PathBuf {
+  Path::new(&config::get().data_folder).join("transcodes")
+}
+
+/// Jobs and the condition workers wait on for more, the workers are started along with it
+fn queue() -> &'static (Mutex<Jobs>, Condvar) {
+  JOBS.get_or_init(|| {
+    for _ in 0..WORKERS {
+      std::thread::spawn(work);
+    }
+    (Mutex::default(), Condvar::new())
+  })
+}
@@ -0,0 +1,185 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{access, events, f, file, library, sidecar, trash};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+  Move,
+  Copy,
+}
+
+/// What to do when something already has the name an entry would get
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+  /// Nothing is moved or copied if any entry conflicts
+  #[default]
+  Fail,
+  /// Leave the entry where it is
+  Skip,
+  /// Move what's there to the trash first
+  Overwrite,
+  /// Use `name (1).ext` instead
+  Rename,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Transfer {
+  /// Files and folders relative to the media folder
+  pub paths: Vec<String>,
+  /// Folder they go into
+  pub to: String,
+}
+
+/// A single entry of a transfer, planned or done
+#[derive(Debug, Clone, Serialize)]
+pub struct Operation {
+  pub from: String,
+  pub to: String,
+  /// `to` was taken and is left alone
+  pub skipped: bool,
+  /// What was at `to` is moved to the trash first
+  pub replaces: bool,
+}
+
+/// What moving or copying `transfer` would do, without touching anything. Fails with
+/// `AlreadyExists` on conflicts when `on_conflict` is `Fail`, with `NotFound` for missing
+/// entries and with `InvalidInput` for folders put inside themselves
+pub fn plan(transfer: &Transfer, mode: Mode, on_conflict: OnConflict) -> io::Result<Vec<Operation>> {
+  let folder = transfer.to.trim_matches('/');
+  if !file::get_media_path(folder).is_dir() {
+    return Err(io::ErrorKind::NotFound.into())
+  }
+
+  let mut targets: HashSet<PathBuf> = HashSet::new();
+  let mut operations = Vec::with_capacity(transfer.paths.len());
+  for path in &transfer.paths {
+    let path = path.trim_matches('/');
+    let name = Path::new(path).file_name().ok_or(io::ErrorKind::InvalidInput)?;
+    let source = file::get_media_path(path);
+    std::fs::symlink_metadata(&source)?;
+    if folder == path || folder.starts_with(&f!("{path}/")) {
+      return Err(io::ErrorKind::InvalidInput.into())
+    }
+
+    let mut target = file::get_media_path(folder).join(name);
+    let is_taken = |target: &Path| std::fs::symlink_metadata(target).is_ok() || targets.contains(target);
+    let mut operation = Operation {
+      from: path.to_string(),
+      to: String::new(),
+      skipped: false,
+      replaces: false,
+    };
+    if is_taken(&target) {
+      match on_conflict {
+        OnConflict::Fail => return Err(io::ErrorKind::AlreadyExists.into()),
+        OnConflict::Skip => operation.skipped = true,
+        // Trashing the entry itself, a folder it's in or another entry of the transfer would lose it
+        OnConflict::Overwrite if source.starts_with(&target) || targets.contains(&target) => {
+          operation.skipped = true
+        }
+        OnConflict::Overwrite => operation.replaces = true,
+        // Moving to where it already is
+        OnConflict::Rename if target == source && mode == Mode::Move => operation.skipped = true,
+        OnConflict::Rename => target = file::free_path(&target, |path| targets.contains(path)),
+      }
+    }
+    operation.to = file::get_relative_path_lossy(&target);
+    if !operation.skipped {
+      targets.insert(target);
+    }
+    operations.push(operation);
+  }
+  Ok(operations)
+}
+
+/// Plans `transfer` and carries it out, stops at the first entry that fails.
+/// Returns the operations done, skipped ones included
+pub fn run(
+  transfer: &Transfer,
+  mode: Mode,
+  on_conflict: OnConflict,
+  identity: &access::Identity,
+) -> io::Result<Vec<Operation>> {
+  let operations = plan(transfer, mode, on_conflict)?;
+  for operation in operations.iter().filter(|operation| !operation.skipped) {
+    if operation.replaces {
+      trash::delete(&operation.to, identity)?;
+    }
+    match mode {
+      Mode::Move => rename(&operation.from, &operation.to)?,
+      Mode::Copy => {
+        let (from, to) = (file::get_media_path(&operation.from), file::get_media_path(&operation.to));
+        copy_all(&from, &to)?;
+        sidecar::copy(&from, &to)?;
+        events::publish(events::Event { kind: events::EventKind::Added, path: operation.to.clone() });
+      }
+    }
+  }
+  Ok(operations)
+}
+
+/// Puts an entry moved by `run` back where it was
+pub fn move_back(operation: &Operation) -> io::Result<()> {
+  if std::fs::symlink_metadata(file::get_media_path(&operation.from)).is_ok() {
+    return Err(io::ErrorKind::AlreadyExists.into())
+  }
+  rename(&operation.to, &operation.from)
+}
+
+/// Moves an entry and its sidecars, both paths relative to the media folder
+fn rename(from: &str, to: &str) -> io::Result<()> {
+  let (from_path, to_path) = (file::get_media_path(from), file::get_media_path(to));
+  match std::fs::rename(&from_path, &to_path) {
+    Err(err) if err.kind() == io::ErrorKind::CrossesDevices => move_across(&from_path, &to_path)?,
+    result => {
+      result?;
+      sidecar::rename(&from_path, &to_path)?;
+    }
+  }
+  if let Err(err) = library::forget(from) {
+    eprintln!("Could not remove {from} from the index - {err:?}");
+  }
+  events::publish(events::Event { kind: events::EventKind::Removed, path: from.to_string() });
+  events::publish(events::Event { kind: events::EventKind::Added, path: to.to_string() });
+  Ok(())
+}
+
+/// Moves an entry and its sidecars to another filesystem, where they can't just be renamed.
+/// The original is only removed once everything was copied
+fn move_across(from: &Path, to: &Path) -> io::Result<()> {
+  if let Err(err) = copy_all(from, to).and_then(|_| sidecar::copy(from, to)) {
+    // Partial copies would block retrying the move
+    let _ = remove_all(to);
+    let _ = sidecar::remove(to);
+    return Err(err)
+  }
+  remove_all(from)?;
+  sidecar::remove(from)
+}
+
+fn remove_all(path: &Path) -> io::Result<()> {
+  if std::fs::symlink_metadata(path)?.is_dir() {
+    std::fs::remove_dir_all(path)
+  } else {
+    std::fs::remove_file(path)
+  }
+}
+
+/// Copies a file or a whole folder, sidecars inside folders are copied along with everything else
+fn copy_all(from: &Path, to: &Path) -> io::Result<()> {
+  if !std::fs::symlink_metadata(from)?.is_dir() {
+    return std::fs::copy(from, to).map(|_| ())
+  }
+  std::fs::create_dir(to)?;
+  for entry in std::fs::read_dir(from)? {
+    let entry = entry?;
+    copy_all(&entry.path(), &to.join(entry.file_name()))?;
+  }
+  Ok(())
+}
@@ -0,0 +1,242 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::{access, config, events, f, file, library, sidecar, util};
+
+/// Deleted files are kept in this folder of the media folder or mount they were in,
+/// being on the same volume deleting and restoring them are only renames
+pub const TRASH_FOLDER: &str = ".fylvur-trash";
+const ID_LEN: usize = 16;
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashEntry {
+  pub id: String,
+  /// Where the file was, relative to the media folder
+  pub path: String,
+  pub is_folder: bool,
+  /// Unix time in seconds
+  pub deleted_at: u64,
+  pub deleted_by: Option<String>,
+}
+
+/// What to do when restoring to a path that was taken in the meantime
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Conflict {
+  #[default]
+  Fail,
+  /// Restore next to it as `name (1).ext`
+  Rename,
+  /// Move what's there to the trash and restore in its place
+  Replace,
+}
+
+/// Trash of the media folder or mount `path` is in
+pub fn folder(path: &str) -> PathBuf {
+  file::get_root_path(path).join(TRASH_FOLDER)
+}
+
+/// Trash of the media folder and of every mount
+pub fn folders() -> Vec<PathBuf> {
+  let config = config::get();
+  std::iter::once(&config.media_folder)
+  .chain(config.mounts.iter().map(|mount| &mount.path))
+  .map(|root| Path::new(root).join(TRASH_FOLDER))
+  .collect()
+}
+
+/// Whether `path` (relative to the media folder) is a trash or something in it
+pub fn contains(path: &Path) -> bool {
+  let mut components = path.components().map(|component| component.as_os_str());
+  match components.next() {
+    Some(first) if first == TRASH_FOLDER => true,
+    Some(first) => {
+      config::get().mounts.iter().any(|mount| first == mount.name.as_str()) &&
+      components.next().is_some_and(|second| second == TRASH_FOLDER)
+    }
+    None => false,
+  }
+}
+
+/// Moves `path` along with its sidecar to the trash
+pub fn delete(path: &str, identity: &access::Identity) -> io::Result<TrashEntry> {
+  let path = path.trim_matches('/');
+  if file::is_root(path) {
+    return Err(io::ErrorKind::InvalidInput.into())
+  }
+  if file::is_read_only(path) {
+    return Err(io::ErrorKind::PermissionDenied.into())
+  }
+  let file_path = file::get_media_path(path);
+  let name = file_path.file_name().ok_or(io::ErrorKind::InvalidInput)?;
+  let metadata = std::fs::symlink_metadata(&file_path)?;
+
+  let entry = TrashEntry {
+    id: random_id(),
+    path: path.to_string(),
+    is_folder: metadata.is_dir(),
+    deleted_at: util::now(),
+    deleted_by: identity.user.map(|user| user.name.to_string()),
+  };
+  let trash = folder(path);
+  let item = item_path(&trash, &entry.id);
+  std::fs::create_dir_all(&item)?;
+  // Written first so a crash can't leave an item without the path to restore it to,
+  // entries whose item is missing are ignored
+  std::fs::write(entry_path(&trash, &entry.id), serde_json::to_vec_pretty(&entry)?)?;
+  if let Err(err) = std::fs::rename(&file_path, item.join(name)) {
+    let _ = std::fs::remove_file(entry_path(&trash, &entry.id));
+    let _ = std::fs::remove_dir(&item);
+    return Err(err)
+  }
+  sidecar::rename(&file_path, &item.join(name))?;
+
+  if let Err(err) = library::forget(&entry.path) {
+    eprintln!("Could not remove {} from the index - {err:?}", entry.path);
+  }
+  events::publish(events::Event { kind: events::EventKind::Removed, path: entry.path.clone() });
+  Ok(entry)
+}
+
+/// Trashed items `identity` could see before they were deleted, newest first
+pub fn list(identity: &access::Identity) -> Vec<TrashEntry> {
+  let mut entries: Vec<TrashEntry> = entries()
+  .into_iter()
+  .filter(|entry| identity.can_see(&entry.path))
+  .collect();
+  entries.sort_unstable_by_key(|entry| std::cmp::Reverse(entry.deleted_at));
+  entries
+}
+
+pub fn get(id: &str) -> io::Result<TrashEntry> {
+  if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+    return Err(io::ErrorKind::NotFound.into())
+  }
+  let trash = find(id)?;
+  let entry: TrashEntry = serde_json::from_slice(&std::fs::read(entry_path(&trash, id))?)?;
+  if !item_path(&trash, id).exists() {
+    return Err(io::ErrorKind::NotFound.into())
+  }
+  Ok(entry)
+}
+
+/// Moves an item back where it was, returns the entry with the path it was restored to
+pub fn restore(id: &str, conflict: Conflict, identity: &access::Identity) -> io::Result<TrashEntry> {
+  let mut entry = get(id)?;
+  let item = item_path(&find(id)?, id);
+  let name = Path::new(&entry.path).file_name().ok_or(io::ErrorKind::InvalidData)?.to_owned();
+
+  let mut target = file::get_media_path(&entry.path);
+  if std::fs::symlink_metadata(&target).is_ok() {
+    match conflict {
+      Conflict::Fail => return Err(io::ErrorKind::AlreadyExists.into()),
+      Conflict::Rename => {
+        target = file::free_path(&target, |_| false);
+        let name = target.file_name().unwrap_or_default().to_string_lossy();
+        entry.path = match entry.path.rsplit_once('/') {
+          Some((parent, _)) => f!("{parent}/{name}"),
+          None => name.to_string(),
+        };
+      }
+      Conflict::Replace => {
+        delete(&entry.path, identity)?;
+      }
+    }
+  }
+
+  if let Some(parent) = target.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+  std::fs::rename(item.join(&name), &target)?;
+  sidecar::rename(&item.join(&name), &target)?;
+  remove(id)?;
+
+  events::publish(events::Event { kind: events::EventKind::Added, path: entry.path.clone() });
+  Ok(entry)
+}
+
+/// Deletes an item for good
+pub fn remove(id: &str) -> io::Result<()> {
+  let trash = find(id)?;
+  match std::fs::remove_dir_all(item_path(&trash, id)) {
+    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+    _ => {}
+  }
+  std::fs::remove_file(entry_path(&trash, id))
+}
+
+/// Deletes items that have been in the trash for longer than `max_age`
+pub fn purge(max_age: Duration) -> usize {
+  let deadline = util::now().saturating_sub(max_age.as_secs());
+  entries()
+  .into_iter()
+  .filter(|entry| entry.deleted_at <= deadline)
+  .filter(|entry| match remove(&entry.id) {
+    Ok(()) => true,
+    Err(err) => {
+      eprintln!("Could not purge {} from the trash - {err:?}", entry.path);
+      false
+    }
+  })
+  .count()
+}
+
+/// Purges items older than `trash_retention_days` every hour, 0 keeps them until removed by hand
+pub fn start_purger() {
+  let retention_days = config::get().trash_retention_days;
+  if retention_days == 0 {
+    return
+  }
+  std::thread::spawn(move || loop {
+    let purged = purge(Duration::from_secs(retention_days * 24 * 3600));
+    if purged > 0 {
+      println!("Purged {purged} items from the trash");
+    }
+    std::thread::sleep(PURGE_INTERVAL);
+  });
+}
+
+fn entries() -> Vec<TrashEntry> {
+  folders()
+  .iter()
+  .filter_map(|trash| std::fs::read_dir(trash).ok())
+  .flat_map(|dir| dir.flatten())
+  .filter_map(|dir_entry| {
+    let path = dir_entry.path();
+    if path.extension()? != "json" {
+      return None
+    }
+    get(path.file_stem()?.to_str()?).ok()
+  })
+  .collect()
+}
+
+/// Trash holding the item with `id`
+fn find(id: &str) -> io::Result<PathBuf> {
+  folders()
+  .into_iter()
+  .find(|trash| entry_path(trash, id).exists())
+  .ok_or_else(|| io::ErrorKind::NotFound.into())
+}
+
+/// Folder holding the trashed file and its sidecar
+fn item_path(trash: &Path, id: &str) -> PathBuf {
+  trash.join(id)
+}
+
+fn entry_path(trash: &Path, id: &str) -> PathBuf {
+  trash.join(f!("{id}.json"))
+}
+
+fn random_id() -> String {
+  rand::thread_rng()
+  .sample_iter(&Alphanumeric)
+  .take(ID_LEN)
+  .map(char::from)
+  .collect()
+}
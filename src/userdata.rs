@@ -0,0 +1,147 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{access, config, f, file, util};
+
+/// Owner of the data stored before there were users, unauthenticated requests can read it
+/// but not change it
+pub const DEFAULT_USER: &str = "default";
+/// Videos watched past this fraction are considered finished
+const FINISHED_RATIO: f64 = 0.95;
+
+static STORE: OnceLock<Mutex<HashMap<String, UserData>>> = OnceLock::new();
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UserData {
+  #[serde(default)]
+  progress: HashMap<String, Progress>,
+  #[serde(default)]
+  favorites: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Progress {
+  pub position_ms: i64,
+  pub duration_ms: i64,
+  /// Unix time in seconds
+  #[serde(default)]
+  pub updated: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProgressEntry {
+  pub path: String,
+  #[serde(flatten)]
+  pub progress: Progress,
+}
+
+pub fn get_progress(identity: &access::Identity, path: &str) -> Option<Progress> {
+  with_user(identity, |data| data.progress.get(path).cloned())
+}
+
+pub fn set_progress(
+  identity: &access::Identity,
+  path: &str,
+  mut progress: Progress,
+) -> std::io::Result<()> {
+  progress.updated = util::now();
+  with_user_mut(identity, |data| {
+    data.progress.insert(path.to_string(), progress);
+  })
+}
+
+/// Started but unfinished videos, most recently watched first
+pub fn continue_watching(identity: &access::Identity) -> Vec<ProgressEntry> {
+  let mut entries: Vec<ProgressEntry> = with_user(identity, |data| {
+    data.progress.iter()
+    .filter(|(path, progress)| {
+      progress.position_ms > 0 &&
+      (progress.position_ms as f64) < progress.duration_ms as f64 * FINISHED_RATIO &&
+      identity.can_see(path) &&
+      file::get_media_path(path).exists()
+    })
+    .map(|(path, progress)| ProgressEntry {
+      path: path.clone(),
+      progress: progress.clone(),
+    })
+    .collect()
+  });
+  entries.sort_unstable_by_key(|entry| std::cmp::Reverse(entry.progress.updated));
+  entries
+}
+
+pub fn get_favorites(identity: &access::Identity) -> Vec<String> {
+  with_user(identity, |data| {
+    data.favorites.iter()
+    // Deleted files come back along with their progress and favorites if restored
+    .filter(|path| identity.can_see(path) && file::get_media_path(path).exists())
+    .cloned()
+    .collect()
+  })
+}
+
+pub fn set_favorite(
+  identity: &access::Identity,
+  path: &str,
+  favorite: bool,
+) -> std::io::Result<()> {
+  with_user_mut(identity, |data| {
+    if favorite {
+      data.favorites.insert(path.to_string());
+    } else {
+      data.favorites.remove(path);
+    }
+  })
+}
+
+fn with_user<T>(identity: &access::Identity, f: impl FnOnce(&UserData) -> T) -> T {
+  let name = user_name(identity);
+  let mut store = store();
+  f(store.entry(name.to_string()).or_insert_with(|| load(name)))
+}
+
+/// Applies `f` to the user's data and saves it to disk. Fails with `PermissionDenied` for
+/// unauthenticated requests, there's nobody to keep their data for
+fn with_user_mut(
+  identity: &access::Identity,
+  f: impl FnOnce(&mut UserData),
+) -> std::io::Result<()> {
+  let Some(user) = identity.user else {
+    return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Only signed in users have their own data"))
+  };
+  let name = user.name.as_str();
+  let mut store = store();
+  let data = store.entry(name.to_string()).or_insert_with(|| load(name));
+  f(data);
+
+  let path = data_path(name);
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+  std::fs::write(path, serde_json::to_vec(data)?)
+}
+
+fn user_name(identity: &access::Identity) -> &'static str {
+  identity.user.map_or(DEFAULT_USER, |user| user.name.as_str())
+}
+
+fn load(name: &str) -> UserData {
+  std::fs::read(data_path(name))
+  .ok()
+  .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+  .unwrap_or_default()
+}
+
+fn data_path(name: &str) -> PathBuf {
+  PathBuf::from(&config::get().data_folder).join("users").join(f!("{name}.json"))
+}
+
+fn store() -> MutexGuard<'static, HashMap<String, UserData>> {
+  STORE
+  .get_or_init(|| Mutex::new(HashMap::new()))
+  .lock()
+  .unwrap_or_else(|err| err.into_inner())
+}
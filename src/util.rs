@@ -0,0 +1,20 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::f;
+
+/// Unix time in seconds
+pub fn now() -> u64 {
+  SystemTime::now()
+  .duration_since(UNIX_EPOCH)
+  .map(|d| d.as_secs())
+  .unwrap_or_default()
+}
+
+/// Percent-encodes everything in `path` but its slashes and unreserved characters, so media
+/// paths with spaces, `#` or `?` can be put in URLs
+pub fn encode_path(path: &str) -> String {
+  path.bytes().map(|byte| match byte {
+    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => (byte as char).to_string(),
+    byte => f!("%{byte:02X}"),
+  }).collect()
+}
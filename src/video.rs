@@ -1,7 +1,17 @@
 extern crate ffmpeg_next as ffmpeg;
 
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::{c_int, c_void, CStr, CString};
 use std::fmt::Display;
 use std::fmt::Debug;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime};
 
 use ffmpeg::Rescale;
 use ffmpeg::rescale;
@@ -11,94 +21,662 @@ use ffmpeg::format;
 use ffmpeg::format::context::Input as AVFormatContext;
 use ffmpeg::packet::side_data;
 use ffmpeg::media::Type;
+use ffmpeg::channel_layout::ChannelLayout;
+use ffmpeg::software::resampling::context::Context as ResamplingCtx;
 use ffmpeg::software::scaling::{context::Context as ScalingCtx, flag::Flags};
+use ffmpeg::subtitle::Rect;
+use ffmpeg::util::frame::audio::Audio as AudioFrame;
 use ffmpeg::util::frame::video::Video as VideoFrame;
 use webp::Encoder;
 use webp::WebPMemory;
+use serde::{Deserialize, Serialize};
 
-use crate::{f, math};
+use crate::envelope::Warning;
+use crate::{book, capabilities, config, f, math, metadata, perf, raw, subtitle};
 
 const FFMPEG_RETRY_ERR: ffmpeg::Error = ffmpeg::Error::Other { errno: ffmpeg::error::EAGAIN };
 const MAX_ATLAS_TILE_WIDTH: usize = 10;
 const MAX_ATLAS_TILE_HEIGHT: usize = 10;
-const ATLAS_TILE_WIDTH: usize = 80;
-const ATLAS_TILE_HEIGHT: usize = 45;
+pub const ATLAS_TILE_WIDTH: usize = 80;
+pub const ATLAS_TILE_HEIGHT: usize = 45;
 const MAX_ATLAS_TILES: u32 = MAX_ATLAS_TILE_WIDTH as u32 * MAX_ATLAS_TILE_HEIGHT as u32;
+const DEFAULT_JPEG_QUALITY: u8 = 75;
+/// Bounds the amount of frames decoded for a single sprite sheet
+const MAX_SPRITE_TILES: usize = 400;
+/// Outputs this wide or smaller (atlas and sprite tiles) are scaled with the cheaper
+/// area averaging, SINC is only worth it for thumbnails people look at up close
+const FAST_SCALE_MAX_WIDTH: u32 = 160;
+/// Corrupt packets in a row after which a file is considered unreadable
+const MAX_SKIPPED_PACKETS: usize = 1000;
+/// Tiles are decoded into frames and then copied into the sheet, so they take twice their size
+const TILE_BYTES: usize = ATLAS_TILE_WIDTH * ATLAS_TILE_HEIGHT * 4 * 2;
+
+/// Seeks closer than this to each other reuse the same decoded frame, in `AV_TIME_BASE` units
+const DECODED_SEEK_WINDOW: i64 = 1_000_000;
+/// Bytes of decoded frames kept for nearby seeks, the least recently used are dropped past this
+const DECODED_CACHE_BYTES: usize = 64 * 1024 * 1024;
+/// H.264 encoders HLS segments can use, in order of preference. VAAPI is left out since it
+/// only takes frames already uploaded to the GPU
+const H264_ENCODERS: [&str; 4] = ["libx264", "h264_nvenc", "h264_qsv", "h264_videotoolbox"];
+/// Taller videos are scaled down when transcoded
+const HLS_MAX_HEIGHT: u32 = 1080;
+const HLS_AUDIO_RATE: u32 = 48000;
+const HLS_AUDIO_BIT_RATE: usize = 160_000;
+const HLS_AUDIO_FORMAT: format::Sample = format::Sample::F32(format::sample::Type::Planar);
+const WAVEFORM_BACKGROUND: [u8; 4] = [24, 24, 24, 255];
+const WAVEFORM_COLOR: [u8; 4] = [220, 220, 220, 255];
+/// Quietest level spectrograms tell apart from silence, in dB below a full scale sine
+const SPECTROGRAM_FLOOR_DB: f32 = 100.;
+/// Colors spectrogram levels fade through from the floor up to full scale
+const SPECTROGRAM_RAMP: [[u8; 3]; 5] = [[0, 0, 0], [40, 10, 110], [180, 30, 90], [250, 140, 20], [255, 255, 210]];
+/// Extensions of HEIF images, AVIF is HEIF with AV1 inside
+const HEIF_EXTENSIONS: [&str; 3] = ["heic", "heif", "avif"];
+/// Thumbnails and atlases, from 0 to 100
+pub const WEBP_QUALITY: f32 = 50.;
+/// Frames are scaled down to this width before being compared for motion
+const MOTION_WIDTH: u32 = 64;
+/// `AV_CODEC_HW_CONFIG_METHOD_HW_DEVICE_CTX`, decoders taking a device context list it in their
+/// hardware configs. It's in an anonymous enum the bindings don't name
+const HW_CONFIG_DEVICE_CTX: c_int = 0x01;
+
+/// Hardware devices opened so far by API and device name, `None` when opening failed so it isn't
+/// tried again for every frame
+static HW_DEVICES: Mutex<Vec<HwDeviceEntry>> = Mutex::new(Vec::new());
+static DECODED_FRAMES: Mutex<VecDeque<(DecodedKey, Arc<DecodedFrame>)>> = Mutex::new(VecDeque::new());
+/// Decoding jobs running on the blocking pool or waiting for a thread there
+static DECODE_QUEUE: AtomicUsize = AtomicUsize::new(0);
+static FRAME_MEMORY: Mutex<FrameUsage> = Mutex::new(FrameUsage { bytes: 0, interactive: 0 });
+static FRAME_MEMORY_FREED: Condvar = Condvar::new();
+
+thread_local! {
+  /// Set while running work that should only use the decoder when nothing else does
+  static BACKGROUND: Cell<bool> = const { Cell::new(false) };
+}
 
 pub fn init() -> Result<(), ffmpeg::Error> {
   ffmpeg::init()
 }
 
-/// Returns 10x10 webp atlas with an 80x45 tile for every second of the video
+/// Returns 10x10 atlas with an 80x45 tile for every second of the video
 /// 
 /// # Arguments
 /// * `video_path` - Path to the video where the atlas will be made from
 /// * `progress_secs` - Atlas page will contain the frame at this second
+/// * `layout` - Order of the tiles in the page
+/// * `format` - Encoding of the returned image
+/// * `burn_timestamps` - Draw the time of each tile in its bottom left corner
+/// * `preset` - Speed and looks of the tiles
+#[allow(clippy::too_many_arguments)]
 pub fn get_video_atlas(
-  video_path: &String,
+  video_path: &Path,
   page_i: u32,
   frame_step: u32,
-) -> Result<WebPMemory, VideoError> {
-  let mut av_format_ctx = match format::input(video_path) {
-    Ok(av_format_ctx) => av_format_ctx,
-    Err(err) => return Err((f!("Could not open file \"{video_path}\""), err).into())
-  };
+  layout: TileLayout,
+  format: ImageFormat,
+  burn_timestamps: bool,
+  preset: AtlasPreset,
+  watermark: Option<&Watermark>,
+) -> Result<Vec<u8>, VideoError> {
+  let mut av_format_ctx = open_input(video_path)?;
 
-  let tile_index_start = page_i * MAX_ATLAS_TILES;
-  let tile_index_end = std::cmp::min(
-    (page_i + 1) * MAX_ATLAS_TILES, {
-      let max_frames = get_duration(&av_format_ctx) as u32 / 1000 / frame_step;
-      let modulo = max_frames % frame_step;
-      max_frames + (frame_step - modulo)
-    },
+  let frame_step = std::cmp::max(frame_step, 1);
+  let (tile_index_start, tile_count) = math::atlas_tiles(
+    get_duration(&av_format_ctx),
+    page_i,
+    frame_step,
+    MAX_ATLAS_TILES,
   );
-  let tile_count = std::cmp::max(
-    0,
-    tile_index_end as i32 - tile_index_start as i32
-  ) as usize;
 
   if tile_count == 0 {
-    return Ok(encode_webp_from_frame(&VideoFrame::new(
+    return format.encode(&VideoFrame::new(
       ffmpeg::format::Pixel::RGBA,
       ATLAS_TILE_WIDTH as u32,
       ATLAS_TILE_HEIGHT as u32,
-    )))
+    ))
   }
 
-  let mut out_frame = VideoFrame::new(
-    format::Pixel::RGBA,
-    ATLAS_TILE_WIDTH as u32 * std::cmp::min(
-      tile_count as u32,
-      MAX_ATLAS_TILE_WIDTH as u32,
-    ),
-    ATLAS_TILE_HEIGHT as u32 * std::cmp::min(
-      (tile_count as u32 / MAX_ATLAS_TILE_WIDTH as u32) + 1,
-      MAX_ATLAS_TILE_HEIGHT as u32,
-    ),
-  );
+  let _memory = FrameMemory::reserve(tile_count * TILE_BYTES)?;
+  let frames = get_frame(
+    &mut av_format_ctx,
+    ATLAS_TILE_WIDTH as u32,
+    SeekTime::Seconds(tile_index_start),
+    tile_count,
+    frame_step,
+    Some(ATLAS_TILE_HEIGHT as u32),
+    preset,
+  )?;
+  let grid = TileGrid::new(frames.len(), MAX_ATLAS_TILE_WIDTH, layout);
+  let mut out_frame = compose_tiles(&frames, &grid);
+  if burn_timestamps {
+    for index in 0..frames.len() {
+      let (tile_x, tile_y) = grid.position(index);
+      let x = tile_x * ATLAS_TILE_WIDTH;
+      let bottom = (tile_y + 1) * ATLAS_TILE_HEIGHT;
+      burn_timestamp(&mut out_frame, x, bottom, tile_index_start + index as u32 * frame_step);
+    }
+  }
+  if let Some(watermark) = watermark {
+    watermark.apply(&mut out_frame);
+  }
+  format.encode_with(&out_frame, preset)
+}
 
-  let out_width = out_frame.width();
-  let out_data = out_frame.data_mut(0);
+/// Returns webp sprite sheet with an 80x45 tile every `interval` seconds
+/// between `start` and `end`
+///
+/// # Arguments
+/// * `video_path` - Path to the video where the sprites will be made from
+/// * `start` - Second of the first tile
+/// * `end` - Last second that can have a tile, defaults to the end of the video
+/// * `interval` - Seconds between tiles
+/// * `columns` - Tiles per row
+pub fn get_video_sprites(
+  video_path: &Path,
+  start: u32,
+  end: Option<u32>,
+  interval: u32,
+  columns: u32,
+  watermark: Option<&Watermark>,
+) -> Result<WebPMemory, VideoError> {
+  let mut av_format_ctx = open_input(video_path)?;
 
-  let mut thumb_pos = 0;
+  let duration_secs = (get_duration(&av_format_ctx) / 1000) as u32;
+  let end = std::cmp::min(end.unwrap_or(duration_secs), duration_secs);
+  let interval = std::cmp::max(interval, 1);
+
+  if start > end {
+    return Ok(encode_webp_from_frame(&VideoFrame::new(
+      format::Pixel::RGBA,
+      ATLAS_TILE_WIDTH as u32,
+      ATLAS_TILE_HEIGHT as u32,
+    )))
+  }
 
+  let tile_count = std::cmp::min(
+    ((end - start) / interval + 1) as usize,
+    MAX_SPRITE_TILES,
+  );
+  let _memory = FrameMemory::reserve(tile_count * TILE_BYTES)?;
   let frames = get_frame(
     &mut av_format_ctx,
     ATLAS_TILE_WIDTH as u32,
-    SeekTime::Seconds(tile_index_start),
+    SeekTime::Seconds(start),
     tile_count,
-    frame_step,
+    interval,
     Some(ATLAS_TILE_HEIGHT as u32),
+    AtlasPreset::Balanced,
+  )?;
+  let grid = TileGrid::new(frames.len(), columns as usize, TileLayout::RowMajor);
+  let mut out_frame = compose_tiles(&frames, &grid);
+  if let Some(watermark) = watermark {
+    watermark.apply(&mut out_frame);
+  }
+  Ok(encode_webp_from_frame(&out_frame))
+}
+
+/// How much the picture changes at up to `samples` points spread evenly over the video, as the
+/// mean difference between two consecutive frames from 0 to 1. Returns the second of each point
+/// and its score. Points are taken at the keyframe before each sample so close samples can share
+/// one, it's only scored once
+pub fn get_motion_scores(video_path: &Path, samples: u32) -> Result<Vec<(u32, f32)>, VideoError> {
+  let mut av_format_ctx = open_input(video_path)?;
+  let duration_secs = (get_duration(&av_format_ctx) / 1000) as u32;
+  let video_stream = av_format_ctx
+  .streams()
+  .best(Type::Video)
+  .ok_or(ffmpeg::Error::StreamNotFound)?;
+  let video_stream_index = video_stream.index();
+  let time_base = video_stream.time_base();
+  let mut decoder = open_video_decoder(&video_stream, false, AtlasPreset::Fast)?;
+  let mut scaler: Option<ScalingCtx> = None;
+
+  let step = (duration_secs / samples.max(1)).max(1);
+  let mut scores: Vec<(u32, f32)> = Vec::new();
+  for seconds in (0..duration_secs.max(1)).step_by(step as usize).take(samples as usize) {
+    seek_seconds(&mut av_format_ctx, seconds)?;
+    decoder.flush();
+    let mut frames: Vec<(Option<i64>, VideoFrame)> = Vec::with_capacity(2);
+    for (stream_index, packet) in read_packets(&mut av_format_ctx) {
+      if stream_index != video_stream_index {
+        continue
+      }
+      if let Err(err) = decoder.send_packet(&packet) {
+        if err != FFMPEG_RETRY_ERR {
+          return Err(("Error sending packet", err).into())
+        }
+      }
+      let mut decoded = VideoFrame::empty();
+      while frames.len() < 2 && decoder.receive_frame(&mut decoded).is_ok() {
+        let scaler = match scaler {
+          Some(ref mut scaler) => scaler,
+          None => {
+            let height = (MOTION_WIDTH * decoded.height() / decoded.width().max(1)).max(1);
+            scaler.insert(ScalingCtx::get(
+              decoded.format(),
+              decoded.width(),
+              decoded.height(),
+              format::Pixel::GRAY8,
+              MOTION_WIDTH,
+              height,
+              Flags::AREA,
+            )?)
+          }
+        };
+        let mut gray = VideoFrame::empty();
+        scaler.run(&decoded, &mut gray)?;
+        frames.push((decoded.timestamp(), gray));
+      }
+      if frames.len() == 2 {
+        break
+      }
+    }
+
+    let [(timestamp, first), (_, second)] = &frames[..] else { continue };
+    let seconds = timestamp.map_or(seconds, |timestamp| timestamp.rescale(time_base, (1, 1)).max(0) as u32);
+    if scores.last().is_none_or(|&(last, _)| last != seconds) {
+      scores.push((seconds, frame_difference(first, second)));
+    }
+  }
+  Ok(scores)
+}
+
+/// Mean difference between the pixels of two gray frames of the same size, from 0 to 1
+fn frame_difference(a: &VideoFrame, b: &VideoFrame) -> f32 {
+  let (width, height) = (a.width() as usize, a.height() as usize);
+  let mut total = 0u64;
+  for y in 0..height {
+    let row_a = &a.data(0)[y * a.stride(0)..][..width];
+    let row_b = &b.data(0)[y * b.stride(0)..][..width];
+    total += row_a.iter().zip(row_b).map(|(a, b)| a.abs_diff(*b) as u64).sum::<u64>();
+  }
+  total as f32 / (width * height).max(1) as f32 / 255.
+}
+
+/// Returns an atlas page laid out like `get_video_atlas` where each 80x45 tile draws the waveform
+/// of the `frame_step` seconds of audio from its time on, for scrubbing through long audio
+///
+/// # Arguments
+/// * `video_path` - Path to the file with the audio
+/// * `page_i` - Atlas page, tiles start where they would in the video atlas
+/// * `frame_step` - Seconds of audio per tile
+/// * `layout` - Order of the tiles in the page
+/// * `format` - Encoding of the returned image
+/// * `burn_timestamps` - Draw the time of each tile in its bottom left corner
+/// * `watermark` - Image blended over the bottom right corner of the page
+pub fn get_waveform_atlas(
+  video_path: &Path,
+  page_i: u32,
+  frame_step: u32,
+  layout: TileLayout,
+  format: ImageFormat,
+  burn_timestamps: bool,
+  watermark: Option<&Watermark>,
+) -> Result<Vec<u8>, VideoError> {
+  let mut av_format_ctx = open_input(video_path)?;
+
+  let frame_step = std::cmp::max(frame_step, 1);
+  let (tile_index_start, tile_count) = math::atlas_tiles(
+    get_duration(&av_format_ctx),
+    page_i,
+    frame_step,
+    MAX_ATLAS_TILES,
+  );
+
+  if tile_count == 0 {
+    return format.encode(&VideoFrame::new(
+      ffmpeg::format::Pixel::RGBA,
+      ATLAS_TILE_WIDTH as u32,
+      ATLAS_TILE_HEIGHT as u32,
+    ))
+  }
+
+  let _memory = FrameMemory::reserve(tile_count * TILE_BYTES)?;
+  let start_secs = tile_index_start as f64;
+  let end_secs = start_secs + (tile_count as u32 * frame_step) as f64;
+  let peaks = audio_peaks(&mut av_format_ctx, start_secs, end_secs, tile_count * ATLAS_TILE_WIDTH)?;
+
+  let grid = TileGrid::new(tile_count, MAX_ATLAS_TILE_WIDTH, layout);
+  let mut out_frame = VideoFrame::new(
+    format::Pixel::RGBA,
+    (ATLAS_TILE_WIDTH * grid.columns) as u32,
+    (ATLAS_TILE_HEIGHT * grid.rows) as u32,
+  );
+  let frame_width = out_frame.width() as usize;
+  let data = out_frame.data_mut(0);
+  for pixel in data.chunks_exact_mut(4) {
+    pixel.copy_from_slice(&WAVEFORM_BACKGROUND);
+  }
+  for (column, &peak) in peaks.iter().enumerate() {
+    let (tile_x, tile_y) = grid.position(column / ATLAS_TILE_WIDTH);
+    let x = tile_x * ATLAS_TILE_WIDTH + column % ATLAS_TILE_WIDTH;
+    draw_peak(data, frame_width, x, tile_y * ATLAS_TILE_HEIGHT, ATLAS_TILE_HEIGHT, peak);
+  }
+  if burn_timestamps {
+    for index in 0..tile_count {
+      let (tile_x, tile_y) = grid.position(index);
+      let x = tile_x * ATLAS_TILE_WIDTH;
+      let bottom = (tile_y + 1) * ATLAS_TILE_HEIGHT;
+      burn_timestamp(&mut out_frame, x, bottom, tile_index_start + index as u32 * frame_step);
+    }
+  }
+  if let Some(watermark) = watermark {
+    watermark.apply(&mut out_frame);
+  }
+  format.encode(&out_frame)
+}
+
+/// Lowest and highest sample of the best audio stream of `audio_path` mixed down to mono, from -1
+/// to 1, for each of `samples` equal slices of the whole file. Also returns the duration in ms
+pub fn get_waveform(audio_path: &Path, samples: usize) -> Result<(i64, Vec<[f32; 2]>), VideoError> {
+  let mut av_format_ctx = open_input(audio_path)?;
+  let duration_ms = get_duration(&av_format_ctx);
+  if duration_ms <= 0 {
+    return Err(VideoError::from(("Could not find how long the audio lasts", audio_path)))
+  }
+  let peaks = audio_peaks(&mut av_format_ctx, 0., duration_ms as f64 / 1000., samples)?;
+  Ok((duration_ms, peaks))
+}
+
+/// Draws `peaks` a column each over a `height` pixels tall frame the way waveform atlas tiles are,
+/// its rows are left at the frame's stride
+pub fn draw_waveform(peaks: &[[f32; 2]], height: u32) -> Result<VideoFrame, VideoError> {
+  let width = peaks.len().max(1);
+  let _memory = FrameMemory::reserve(width * height as usize * 4)?;
+  let mut frame = VideoFrame::new(format::Pixel::RGBA, width as u32, height);
+  let frame_width = frame.stride(0) / 4;
+  let data = frame.data_mut(0);
+  for pixel in data.chunks_exact_mut(4) {
+    pixel.copy_from_slice(&WAVEFORM_BACKGROUND);
+  }
+  for (x, &peak) in peaks.iter().enumerate() {
+    draw_peak(data, frame_width, x, 0, height as usize, peak);
+  }
+  Ok(frame)
+}
+
+/// Draws the column `x` of a waveform `height` pixels tall whose top row is `top`. Samples go up
+/// and down from the middle row, which is drawn even for silence
+fn draw_peak(data: &mut [u8], frame_width: usize, x: usize, top: usize, height: usize, [low, high]: [f32; 2]) {
+  let half_height = (height / 2) as f32;
+  let middle = top + height / 2;
+  let first = middle - (high.clamp(0., 1.) * half_height) as usize;
+  let last = (middle + (-low.clamp(-1., 0.) * half_height) as usize).min(top + height - 1);
+  for y in first..=last {
+    let di = (x + y * frame_width) * 4;
+    data[di..di + 4].copy_from_slice(&WAVEFORM_COLOR);
+  }
+}
+
+/// Power of the frequencies from 0 to half of `rate` in the first `window` samples of each of
+/// `columns` equal slices of the whole best audio stream of `audio_path`, mixed down to mono and
+/// resampled to `rate`. Slices shorter than `window` are padded with silence and slices without
+/// audio stay at 0. `window` has to be a power of two. Also returns the duration in ms
+pub fn get_spectrum(
+  audio_path: &Path,
+  columns: usize,
+  window: usize,
+  rate: u32,
+) -> Result<(i64, Vec<Vec<f32>>), VideoError> {
+  let mut av_format_ctx = open_input(audio_path)?;
+  let duration_ms = get_duration(&av_format_ctx);
+  if duration_ms <= 0 {
+    return Err(VideoError::from(("Could not find how long the audio lasts", audio_path)))
+  }
+  let _memory = FrameMemory::reserve(columns * window / 2 * std::mem::size_of::<f32>())?;
+  let mut spectra = vec![vec![0f32; window / 2]; columns];
+  let duration_secs = duration_ms as f64 / 1000.;
+  let column_secs = duration_secs / columns.max(1) as f64;
+  let mut column_i = 0;
+  let mut stretch = Vec::with_capacity(window);
+  for_each_mono_frame(&mut av_format_ctx, Some(rate), 0., duration_secs, |frame_secs, samples, rate| {
+    for (sample_i, &sample) in samples.iter().enumerate() {
+      let column = ((frame_secs + sample_i as f64 / rate).max(0.) / column_secs) as usize;
+      if column >= columns {
+        break
+      }
+      if column != column_i {
+        finish_stretch(&mut spectra[column_i], &mut stretch, window);
+        column_i = column;
+      }
+      if stretch.len() < window {
+        stretch.push(sample);
+      }
+    }
+  })?;
+  if let Some(spectrum) = spectra.get_mut(column_i) {
+    finish_stretch(spectrum, &mut stretch, window);
+  }
+  Ok((duration_ms, spectra))
+}
+
+/// Turns the samples gathered for a spectrogram column into its spectrum and empties them
+fn finish_stretch(spectrum: &mut Vec<f32>, stretch: &mut Vec<f32>, window: usize) {
+  if stretch.is_empty() {
+    return
+  }
+  stretch.resize(window, 0.);
+  *spectrum = math::power_spectrum(stretch);
+  stretch.clear();
+}
+
+/// Draws `spectra` a column each over a `height` pixels tall frame with the lowest frequency at
+/// the bottom, colored by how loud they are next to a full scale sine. `window` is the amount of
+/// samples each spectrum was taken from, its rows are left at the frame's stride
+pub fn draw_spectrogram(spectra: &[Vec<f32>], window: usize, height: u32) -> Result<VideoFrame, VideoError> {
+  let width = spectra.len().max(1);
+  let _memory = FrameMemory::reserve(width * height as usize * 4)?;
+  let mut frame = VideoFrame::new(format::Pixel::RGBA, width as u32, height);
+  let frame_width = frame.stride(0) / 4;
+  let data = frame.data_mut(0);
+  // A Hann windowed sine at full scale peaks at a quarter of the window
+  let full_scale = (window as f32 / 4.).powi(2);
+  for (x, spectrum) in spectra.iter().enumerate() {
+    for y in 0..height as usize {
+      // Rows can span several bins, the loudest one shows
+      let bins = spectrum.len();
+      let first = ((height as usize - 1 - y) * bins / height as usize).min(bins);
+      let last = ((height as usize - y) * bins / height as usize).clamp(first + 1, bins.max(1));
+      let power = spectrum
+      .get(first..last)
+      .unwrap_or_default()
+      .iter()
+      .fold(0f32, |loudest, &power| loudest.max(power));
+      let level = 1. + 10. * (power / full_scale).max(f32::MIN_POSITIVE).log10() / SPECTROGRAM_FLOOR_DB;
+      let di = (x + y * frame_width) * 4;
+      data[di..di + 3].copy_from_slice(&spectrogram_color(level));
+      data[di + 3] = 255;
+    }
+  }
+  Ok(frame)
+}
+
+/// Color of a spectrogram `level` from 0 (the floor) to 1 (full scale) along `SPECTROGRAM_RAMP`
+fn spectrogram_color(level: f32) -> [u8; 3] {
+  let position = level.clamp(0., 1.) * (SPECTROGRAM_RAMP.len() - 1) as f32;
+  let from = (position as usize).min(SPECTROGRAM_RAMP.len() - 2);
+  let blend = position - from as f32;
+  let [a, b] = [SPECTROGRAM_RAMP[from], SPECTROGRAM_RAMP[from + 1]];
+  std::array::from_fn(|i| (a[i] as f32 + (b[i] as f32 - a[i] as f32) * blend).round() as u8)
+}
+
+/// Lowest and highest sample of the best audio stream mixed down to mono, for each of `columns`
+/// equal slices of the time from `start_secs` to `end_secs`. Slices without audio stay at 0
+fn audio_peaks(
+  av_format_ctx: &mut AVFormatContext,
+  start_secs: f64,
+  end_secs: f64,
+  columns: usize,
+) -> Result<Vec<[f32; 2]>, VideoError> {
+  let mut peaks = vec![[0f32; 2]; columns];
+  let column_secs = (end_secs - start_secs) / columns.max(1) as f64;
+  for_each_mono_frame(av_format_ctx, None, start_secs, end_secs, |frame_secs, samples, rate| {
+    for (sample_i, &sample) in samples.iter().enumerate() {
+      let seconds = frame_secs + sample_i as f64 / rate;
+      if seconds < start_secs {
+        continue
+      }
+      let Some(peak) = peaks.get_mut(((seconds - start_secs) / column_secs) as usize) else { break };
+      peak[0] = peak[0].min(sample);
+      peak[1] = peak[1].max(sample);
+    }
+  })?;
+  Ok(peaks)
+}
+
+/// Samples of the best audio stream of `video_path` mixed down to mono and resampled to `rate`,
+/// from `start_secs` on for at most `duration_secs`
+pub fn decode_audio(video_path: &Path, start_secs: f64, duration_secs: f64, rate: u32) -> Result<Vec<f32>, VideoError> {
+  let mut av_format_ctx = open_input(video_path)?;
+  let max_samples = (duration_secs.max(0.) * rate as f64) as usize;
+  let _memory = FrameMemory::reserve(max_samples * std::mem::size_of::<f32>())?;
+  let mut audio = Vec::with_capacity(max_samples);
+  let end_secs = start_secs + duration_secs;
+  for_each_mono_frame(&mut av_format_ctx, Some(rate), start_secs, end_secs, |frame_secs, samples, rate| {
+    let skipped = ((start_secs - frame_secs) * rate).max(0.) as usize;
+    let wanted = max_samples - audio.len();
+    audio.extend(samples.iter().skip(skipped).take(wanted));
+  })?;
+  Ok(audio)
+}
+
+/// Decodes the best audio stream from around `start_secs` until `end_secs`, handing each frame
+/// mixed down to mono to `on_frame` along with the second it starts at and its sample rate.
+/// Frames keep the stream's rate without `rate`
+fn for_each_mono_frame(
+  av_format_ctx: &mut AVFormatContext,
+  rate: Option<u32>,
+  start_secs: f64,
+  end_secs: f64,
+  mut on_frame: impl FnMut(f64, &[f32], f64),
+) -> Result<(), VideoError> {
+  let stream = av_format_ctx.streams().best(Type::Audio).ok_or(ffmpeg::Error::StreamNotFound)?;
+  let stream_index = stream.index();
+  let time_base = f64::from(stream.time_base());
+  // Unknown start times are AV_NOPTS_VALUE, a huge negative number
+  let offset = stream.start_time().max(0) as f64 * time_base;
+  let mut decoder = CodecCtx::from_parameters(stream.parameters())?.decoder().audio()?;
+  let layout = match decoder.channel_layout() {
+    layout if layout.is_empty() => ChannelLayout::default(decoder.channels() as i32),
+    layout => layout,
+  };
+  let rate = rate.unwrap_or(decoder.rate()).max(1);
+  let mut resampler = ResamplingCtx::get(
+    decoder.format(),
+    layout,
+    decoder.rate(),
+    format::Sample::F32(format::sample::Type::Packed),
+    ChannelLayout::MONO,
+    rate,
   )?;
-  for frame in frames {
+  seek_seconds(av_format_ctx, start_secs.max(0.) as u32)?;
+
+  let mut decoded = AudioFrame::empty();
+  let mut mono = AudioFrame::empty();
+  for (index, packet) in read_packets(av_format_ctx) {
+    if index != stream_index {
+      continue
+    }
+    if let Err(err) = decoder.send_packet(&packet) {
+      if err != FFMPEG_RETRY_ERR {
+        return Err(("Error sending packet", err).into())
+      }
+    }
+    while decoder.receive_frame(&mut decoded).is_ok() {
+      let Some(timestamp) = decoded.timestamp() else { continue };
+      let frame_secs = timestamp as f64 * time_base - offset;
+      if frame_secs >= end_secs {
+        return Ok(())
+      }
+      resampler.run(&decoded, &mut mono)?;
+      on_frame(frame_secs, &mono.plane::<f32>(0)[..mono.samples()], f64::from(rate));
+    }
+  }
+  Ok(())
+}
+
+/// Where the frame at `seconds` is drawn in the atlas
+#[derive(Debug, Serialize)]
+pub struct AtlasTile {
+  pub page: u32,
+  pub seconds: u32,
+  /// Pixel offset of the tile's top left corner in the page
+  pub x: u32,
+  pub y: u32,
+}
+
+/// Every tile of the atlas pages of a video `duration_ms` long, laid out as
+/// `get_video_atlas` does with a step of one second
+pub fn atlas_layout(duration_ms: i64, layout: TileLayout) -> Vec<AtlasTile> {
+  let mut tiles = Vec::new();
+  for page in 0.. {
+    let (start, count) = math::atlas_tiles(duration_ms, page, 1, MAX_ATLAS_TILES);
+    if count == 0 {
+      break
+    }
+    let grid = TileGrid::new(count, MAX_ATLAS_TILE_WIDTH, layout);
+    tiles.extend((0..count).map(|index| {
+      let (column, row) = grid.position(index);
+      AtlasTile {
+        page,
+        seconds: start + index as u32,
+        x: (column * ATLAS_TILE_WIDTH) as u32,
+        y: (row * ATLAS_TILE_HEIGHT) as u32,
+      }
+    }));
+  }
+  tiles
+}
+
+/// Position of the tiles of an atlas or sprite sheet
+struct TileGrid {
+  columns: usize,
+  rows: usize,
+  layout: TileLayout,
+}
+
+impl TileGrid {
+  /// Grid for `count` tiles at most `columns` wide
+  fn new(count: usize, columns: usize, layout: TileLayout) -> Self {
+    let count = std::cmp::max(1, count);
+    let columns = columns.clamp(1, count);
+    let rows = (count + columns - 1) / columns;
+    // Filling whole columns may leave the last ones empty, those are dropped
+    let columns = match layout {
+      TileLayout::RowMajor => columns,
+      TileLayout::ColumnMajor => (count + rows - 1) / rows,
+    };
+    Self { columns, rows, layout }
+  }
+
+  /// Column and row of the tile at `index`
+  fn position(&self, index: usize) -> (usize, usize) {
+    match self.layout {
+      TileLayout::RowMajor => (index % self.columns, index / self.columns),
+      TileLayout::ColumnMajor => (index / self.rows, index % self.rows),
+    }
+  }
+}
+
+/// Lays out `frames` in a grid of 80x45 tiles, frames smaller than a tile are centered in it
+fn compose_tiles(frames: &[VideoFrame], grid: &TileGrid) -> VideoFrame {
+  let _timer = perf::time(perf::Stage::Compose);
+  let mut out_frame = VideoFrame::new(
+    format::Pixel::RGBA,
+    (ATLAS_TILE_WIDTH * grid.columns) as u32,
+    (ATLAS_TILE_HEIGHT * grid.rows) as u32,
+  );
+
+  let out_width = out_frame.width();
+  let out_data = out_frame.data_mut(0);
+
+  for (thumb_pos, frame) in frames.iter().enumerate() {
     let frame_width = frame.width() as usize;
     let frame_height = frame.height() as usize;
     // Center image in tile when width/height is too small
     let blank_width_offset = (ATLAS_TILE_WIDTH - frame_width) / 2;
     let blank_height_offset = (ATLAS_TILE_HEIGHT - frame_height) / 2;
     let frame_area = frame_width * frame_height;
-    let tile_x = thumb_pos % MAX_ATLAS_TILE_WIDTH;
-    let tile_y = thumb_pos / MAX_ATLAS_TILE_HEIGHT;
+    let (tile_x, tile_y) = grid.position(thumb_pos);
     let tile_x_offset = tile_x * ATLAS_TILE_WIDTH + blank_width_offset;
     let tile_y_offset = tile_y * ATLAS_TILE_HEIGHT + blank_height_offset;
 
@@ -119,9 +697,8 @@ pub fn get_video_atlas(
         out_data[di + color_i] = frame_data[i * 4 + color_i];
       }
     }
-    thumb_pos += 1;
   }
-  Ok(encode_webp_from_frame(&out_frame))
+  out_frame
 }
 
 /// Returns webp image for the `video_path` at `frame_time` second
@@ -130,6 +707,8 @@ pub fn get_video_atlas(
 /// * `video_path` - Path to the video where the frame will be taken from
 /// * `frame_width` - Width of the returned frame, pass 0 to use the video's width
 /// * `frame_time` - Video time where the frame will come from, in seconds
+/// * `watermark` - Image blended over the bottom right corner of the frame
+/// * `quality` - WebP quality from 0 to 100, `WEBP_QUALITY` by default
 /// 
 /// # Examples
 /// Saving webp file to disk
@@ -137,7 +716,9 @@ pub fn get_video_atlas(
 /// let thumbnail = video::get_frame(
 /// String::from("/path/to/video/file"),
 /// 0, // Use the video's width
-/// 60 // Take frame at the 60 seconds mark,
+/// 60, // Take frame at the 60 seconds mark
+/// None, // No watermark
+/// video::WEBP_QUALITY,
 /// ).expect("Could not get thumbnail");
 /// 
 /// let output_path = PathBuf::from(format!("./thumbnail.webp"));
@@ -145,33 +726,251 @@ pub fn get_video_atlas(
 /// std::fs::write(&output_path, &*thumbnail).expect("Could not save thumbnail");
 /// ```
 pub fn get_video_thumbnail(
-  video_path: &String,
+  video_path: &Path,
   thumbnail_width: u32,
   time_position: SeekTime,
+  watermark: Option<&Watermark>,
+  quality: f32,
+) -> Result<WebPMemory, VideoError> {
+  let mut av_format_ctx = open_input(video_path)?;
+  let _memory = FrameMemory::reserve(estimate_frame_bytes(&av_format_ctx, thumbnail_width))?;
+  let decoded = get_decoded_frame(video_path, &mut av_format_ctx, time_position)?;
+  let mut frame = decoded.convert(thumbnail_width, None)?;
+  if let Some(watermark) = watermark {
+    watermark.apply(&mut frame);
+  }
+  Ok(encode_webp_with_quality(&frame, quality))
+}
+
+/// Thumbnail of a still image like JPEG, PNG or WebP. It's `thumbnail_width` wide or as wide as the
+/// image when that's narrower, images aren't scaled up unless they're letterboxed
+pub fn get_image_thumbnail(
+  image_path: &Path,
+  thumbnail_width: u32,
+  letterbox: Option<Letterbox>,
+  watermark: Option<&Watermark>,
+  quality: f32,
 ) -> Result<WebPMemory, VideoError> {
-  let mut av_format_ctx = match format::input(video_path) {
-    Ok(av_format_ctx) => av_format_ctx,
-    Err(err) => return Err((f!("Could not open file \"{video_path}\""), err).into())
+  let canvas_bytes = letterbox.map_or(0, |letterbox| thumbnail_width as usize * letterbox.height as usize * 4);
+  let (decoded, _memory) = decode_image(image_path, thumbnail_width, canvas_bytes)?;
+  let image_width = if decoded.rotation.abs() == 90 {
+    decoded.frame.height()
+  } else {
+    decoded.frame.width()
   };
-  let frame = get_frame(
-    &mut av_format_ctx,
-    thumbnail_width,
-    time_position,
-    1,
-    1,
-    None,
-  )?;
-  Ok(encode_webp_from_frame(&frame[0]))
+  let mut frame = match letterbox {
+    Some(letterbox) => {
+      let frame = decoded.convert(thumbnail_width, Some(letterbox.height))?;
+      letterbox.apply(&frame, thumbnail_width)
+    }
+    None => decoded.convert(thumbnail_width.min(image_width), None)?,
+  };
+  if let Some(watermark) = watermark {
+    watermark.apply(&mut frame);
+  }
+  Ok(encode_webp_with_quality(&frame, quality))
+}
+
+/// Whether `path` is a HEIF image, iPhone photos (HEIC) and AVIF included
+pub fn is_heif(path: &Path) -> bool {
+  path.extension()
+  .and_then(|ext| ext.to_str())
+  .is_some_and(|ext| HEIF_EXTENSIONS.iter().any(|heif| ext.eq_ignore_ascii_case(heif)))
+}
+
+/// Single frame of a still image turned upright, the embedded preview for camera RAW photos
+/// and the cover for books.
+/// `extra_bytes` are reserved along with what scaling it to `frame_width` takes
+fn decode_image(
+  image_path: &Path,
+  frame_width: u32,
+  extra_bytes: usize,
+) -> Result<(DecodedFrame, FrameMemory), VideoError> {
+  #[cfg(feature = "heif")]
+  if is_heif(image_path) {
+    return decode_heif(image_path, frame_width, extra_bytes)
+  }
+  let mut av_format_ctx = if raw::is_raw(image_path) {
+    // Sensor data needs demosaicing, the preview the camera made is what people saw anyway
+    let (start, len) = raw::preview(image_path)
+    .map_err(|err| VideoError::from((f!("Could not find a preview in {image_path:?}"), err)))?;
+    open_input_range(image_path, start, Some(len))?
+  } else if book::is_book(image_path) {
+    let cover = book::cover(image_path)
+    .map_err(|err| VideoError::from((f!("Could not find a cover in {image_path:?}"), err)))?;
+    open_input_bytes(image_path, cover)?
+  } else {
+    open_input(image_path)?
+  };
+  let memory = FrameMemory::reserve(estimate_frame_bytes(&av_format_ctx, frame_width) + extra_bytes)?;
+  // Nothing to seek in a single frame
+  let mut decoded = decode_first(&mut av_format_ctx, false)?;
+  // Photos say how they're turned in EXIF instead of a display matrix
+  if decoded.matrix.is_none() {
+    if let Some((matrix, rotation)) = metadata::image_orientation(image_path)
+    .and_then(math::exif_orientation_transform) {
+      decoded.matrix = Some(matrix);
+      decoded.rotation = rotation;
+    }
+  }
+  Ok((decoded, memory))
+}
+
+/// Whether `audio_path` has cover art embedded, like an ID3 APIC frame or a FLAC picture block.
+/// ffmpeg shows it as a video stream of a single frame, so `get_image_thumbnail` decodes it
+pub fn has_attached_picture(audio_path: &Path) -> bool {
+  open_input(audio_path).is_ok_and(|av_format_ctx| {
+    av_format_ctx
+    .streams()
+    .any(|stream| stream.disposition().contains(format::stream::Disposition::ATTACHED_PIC))
+  })
+}
+
+/// HEIC and AVIF photos are usually a grid of tiles ffmpeg doesn't put together, libheif does
+/// and turns them upright as well, their EXIF orientation only repeats that
+#[cfg(feature = "heif")]
+fn decode_heif(
+  image_path: &Path,
+  frame_width: u32,
+  extra_bytes: usize,
+) -> Result<(DecodedFrame, FrameMemory), VideoError> {
+  use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+  // libheif only takes UTF-8 paths, others would open a different file
+  let path = image_path.to_str().ok_or_else(|| {
+    let message = f!("Could not open file {image_path:?}, libheif needs UTF-8 paths");
+    VideoError::from((message, std::io::ErrorKind::InvalidInput))
+  })?;
+  let context = {
+    let _timer = perf::time(perf::Stage::Open);
+    HeifContext::read_from_file(path)
+    .map_err(|err| VideoError::from((f!("Could not open file {image_path:?}"), err)))?
+  };
+  let handle = context
+  .primary_image_handle()
+  .map_err(|err| VideoError::from(("Could not find primary image", err)))?;
+  let size = (handle.width() as usize, handle.height() as usize);
+  // The whole image is decoded before it's scaled
+  let memory = FrameMemory::reserve(size.0 * size.1 * 4 + scaled_frame_bytes(size, frame_width) + extra_bytes)?;
+
+  let _timer = perf::time(perf::Stage::Decode);
+  let image = LibHeif::new()
+  .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+  .map_err(|err| VideoError::from(("Could not decode image", err)))?;
+  let planes = image.planes();
+  let plane = planes.interleaved.ok_or(ffmpeg::Error::InvalidData)?;
+  let mut frame = VideoFrame::new(format::Pixel::RGBA, plane.width, plane.height);
+  let row_bytes = plane.width as usize * 4;
+  let stride = frame.stride(0);
+  for (row, source) in plane.data.chunks(plane.stride).take(plane.height as usize).enumerate() {
+    frame.data_mut(0)[row * stride..row * stride + row_bytes].copy_from_slice(&source[..row_bytes]);
+  }
+  Ok((DecodedFrame { frame, matrix: None, rotation: 0 }, memory))
+}
+
+/// Thumbnail of exactly `thumbnail_width` x `letterbox.height`, the frame is fit
+/// inside and centered over the letterbox color
+pub fn get_letterboxed_thumbnail(
+  video_path: &Path,
+  thumbnail_width: u32,
+  letterbox: Letterbox,
+  time_position: SeekTime,
+  watermark: Option<&Watermark>,
+  quality: f32,
+) -> Result<WebPMemory, VideoError> {
+  let mut av_format_ctx = open_input(video_path)?;
+  let canvas_bytes = thumbnail_width as usize * letterbox.height as usize * 4;
+  let _memory = FrameMemory::reserve(estimate_frame_bytes(&av_format_ctx, thumbnail_width) + canvas_bytes)?;
+  let decoded = get_decoded_frame(video_path, &mut av_format_ctx, time_position)?;
+  let frame = decoded.convert(thumbnail_width, Some(letterbox.height))?;
+  let mut frame = letterbox.apply(&frame, thumbnail_width);
+  if let Some(watermark) = watermark {
+    watermark.apply(&mut frame);
+  }
+  Ok(encode_webp_with_quality(&frame, quality))
+}
+
+/// What a decoded frame is made of, burned over inspected frames
+#[derive(Debug, Clone, Serialize)]
+pub struct FrameInfo {
+  pub codec: String,
+  /// Size the frame was decoded at, before the display matrix turns it
+  pub width: u32,
+  pub height: u32,
+  /// In the stream's time base
+  pub pts: Option<i64>,
+  pub seconds: Option<f64>,
+  /// `I`, `P`, `B` and so on, `?` when the decoder doesn't say
+  pub pict_type: char,
+}
+
+impl FrameInfo {
+  fn overlay_text(&self) -> String {
+    let pts = self.pts.map_or(String::from("-"), |pts| pts.to_string());
+    let seconds = self.seconds.map_or(String::from("-"), |seconds| f!("{seconds:.3}S"));
+    f!("{} {}X{} PTS {pts} {seconds} {}", self.codec, self.width, self.height, self.pict_type)
+  }
+}
+
+/// Frame at `time_position` at the video's own size as lossless WebP, so the artifacts seen are
+/// the video's and not the thumbnail's. With `overlay` its `FrameInfo` is burned in the top left corner
+pub fn get_inspect_frame(
+  video_path: &Path,
+  time_position: SeekTime,
+  overlay: bool,
+) -> Result<(WebPMemory, FrameInfo), VideoError> {
+  let mut av_format_ctx = open_input(video_path)?;
+  let _memory = FrameMemory::reserve(estimate_frame_bytes(&av_format_ctx, 0))?;
+  let (codec, time_base) = av_format_ctx
+  .streams()
+  .best(Type::Video)
+  .map(|stream| (stream.parameters().id().name().to_string(), f64::from(stream.time_base())))
+  .ok_or(ffmpeg::Error::StreamNotFound)?;
+  let decoded = get_decoded_frame(video_path, &mut av_format_ctx, time_position)?;
+  let pts = decoded.frame.pts();
+  let info = FrameInfo {
+    codec,
+    width: decoded.frame.width(),
+    height: decoded.frame.height(),
+    pts,
+    seconds: pts.map(|pts| pts as f64 * time_base),
+    pict_type: unsafe { ffmpeg::ffi::av_get_picture_type_char(decoded.frame.kind().into()) } as u8 as char,
+  };
+
+  let mut frame = decoded.convert(0, None)?;
+  if overlay {
+    // Readable at a glance on anything up to 4K
+    let scale = (frame.width() as usize / 480).max(1);
+    burn_text(&mut frame, 0, (GLYPH_HEIGHT + 2) * scale, &info.overlay_text(), scale);
+  }
+  Ok((encode_webp_lossless(&frame), info))
 }
 
 pub fn get_frame(
-  mut av_format_ctx: &mut AVFormatContext,
+  av_format_ctx: &mut AVFormatContext,
   frame_width: u32,
   frame_time: SeekTime,
   frame_count: usize,
   fps: u32,
   max_height: Option<u32>,
+  preset: AtlasPreset,
+) -> Result<Vec<VideoFrame>, VideoError> {
+  with_decode_fallback(av_format_ctx, |av_format_ctx, hardware| {
+    decode_frames(av_format_ctx, (frame_width, max_height), frame_time, frame_count, fps, preset, hardware)
+  })
+}
+
+/// `get_frame` with `size` as the frame width and max height
+fn decode_frames(
+  mut av_format_ctx: &mut AVFormatContext,
+  size: (u32, Option<u32>),
+  frame_time: SeekTime,
+  frame_count: usize,
+  fps: u32,
+  preset: AtlasPreset,
+  hardware: bool,
 ) -> Result<Vec<VideoFrame>, VideoError> {
+  let (frame_width, max_height) = size;
   seek(&mut av_format_ctx, &frame_time)?;
 
   let video_stream = av_format_ctx
@@ -179,11 +978,10 @@ pub fn get_frame(
   .best(Type::Video)
   .ok_or(ffmpeg::Error::StreamNotFound)?;
   let video_stream_index = video_stream.index();
+  let time_base = video_stream.time_base();
 
-  // Find decoder
-  let context_decoder = CodecCtx::from_parameters(video_stream.parameters())?;
   // Used to decode the packets and be able to receive frames
-  let mut decoder = context_decoder.decoder().video()?;
+  let mut decoder = open_video_decoder(&video_stream, hardware, preset)?;
 
   let frame_width = if frame_width == 0 {
     decoder.width()
@@ -200,20 +998,18 @@ pub fn get_frame(
     None => 0
   };
 
-  // Allows to perform image rescaling and pixel format conversion
-  let mut scaler = get_scaler(
-    &decoder,
-    frame_width,
-    rotation,
-    max_height,
-  )?;
+  // Allows to perform image rescaling and pixel format conversion. Made from the first frame
+  // since frames downloaded from the GPU aren't in the format the stream says
+  let mut scaler: Option<ScalingCtx> = None;
   let mut frames = Vec::new();
   let mut seconds: u32 = frame_time.into();
 
   while frames.len() < frame_count {
-    for (stream, packet) in av_format_ctx.packets() {
+    let decoded_count = frames.len();
+    let target = (seconds as i64).rescale((1, 1), time_base);
+    for (stream_index, packet) in read_packets(av_format_ctx) {
       // Only send packet for video streams
-      if stream.index() == video_stream_index {
+      if stream_index == video_stream_index {
         // Decode into a video frame
         if let Err(err) = decoder.send_packet(&packet) {
           if err != FFMPEG_RETRY_ERR {
@@ -221,9 +1017,21 @@ pub fn get_frame(
           }
         }
         // Receive the video frame and do format/scale/rotation transformations
-        match decode_frame(&mut decoder, matrix, rotation, &mut scaler) {
-          Ok(frame) => {
-            frames.push(frame);
+        match receive_frame(&mut decoder) {
+          // Frames between the keyframe the seek landed on and the wanted second
+          Ok(decoded) if preset.exact_seek() && decoded.timestamp().is_some_and(|time| time < target) => {}
+          Ok(decoded) => {
+            let scaler = match &mut scaler {
+              Some(scaler) => scaler,
+              None => scaler.insert(get_scaler(
+                (decoded.format(), decoded.width(), decoded.height()),
+                frame_width,
+                rotation,
+                max_height,
+                preset,
+              )?),
+            };
+            frames.push(convert_frame(&decoded, matrix, rotation, scaler)?);
             break
           }
           Err(err) => {
@@ -234,6 +1042,10 @@ pub fn get_frame(
         }
       }
     }
+    // Ran out of packets, seeking any further won't produce more frames
+    if frames.len() == decoded_count {
+      break
+    }
     seconds += fps;
     seek_seconds(&mut av_format_ctx, seconds)?;
   }
@@ -245,6 +1057,28 @@ pub fn get_frame(
   Ok(frames)
 }
 
+/// Runs `decode` on the GPU when hardware decoding is configured and the device can decode the
+/// video, and again on the CPU if that fails. `decode` is told which one to use
+fn with_decode_fallback<T>(
+  av_format_ctx: &mut AVFormatContext,
+  decode: impl Fn(&mut AVFormatContext, bool) -> Result<T, VideoError>,
+) -> Result<T, VideoError> {
+  let hardware = av_format_ctx
+  .streams()
+  .best(Type::Video)
+  .is_some_and(|stream| hw_decoder(stream.parameters().id()).is_some());
+  if !hardware {
+    return decode(av_format_ctx, false)
+  }
+  decode(av_format_ctx, true).or_else(|err| {
+    if err.is_over_budget() {
+      return Err(err)
+    }
+    eprintln!("Hardware decoding failed, decoding on the CPU instead - {err:?}");
+    decode(av_format_ctx, false)
+  })
+}
+
 fn get_display_matrix_values(stream: &ffmpeg::Stream) -> Result<[i32; 9], String> {
   // Find rotation in video metadata
   let side_data = stream.side_data().find(|tag| {
@@ -254,15 +1088,18 @@ fn get_display_matrix_values(stream: &ffmpeg::Stream) -> Result<[i32; 9], String
   math::parse_display_matrix(side_data.data())
 }
 
+/// Scaler from `source` (pixel format, width and height) to RGBA at `frame_width`
 fn get_scaler(
-  decoder: &decoder::Video,
+  source: (format::Pixel, u32, u32),
   frame_width: u32,
   rotation: i32,
-  max_height: Option<u32>
+  max_height: Option<u32>,
+  preset: AtlasPreset,
 ) -> Result<ScalingCtx, ffmpeg::Error> {
-  let (scaler_dst_w, scaler_dst_h) = if frame_width != decoder.width() &&
+  let (src_format, src_width, src_height) = source;
+  let (scaler_dst_w, scaler_dst_h) = if frame_width != src_width &&
   rotation.abs() == 90 {
-    let mut width = frame_width * decoder.width() / decoder.height() + 1;
+    let mut width = frame_width * src_width / src_height + 1;
     let mut height = frame_width;
     if let Some(max_height) = max_height {
       if height > max_height {
@@ -273,7 +1110,7 @@ fn get_scaler(
     (width, height)
   } else {
     let mut width = frame_width;
-    let mut height = frame_width * decoder.height() / decoder.width();
+    let mut height = frame_width * src_height / src_width;
     if let Some(max_height) = max_height {
       if height > max_height {
         width = max_height * width / height;
@@ -284,28 +1121,57 @@ fn get_scaler(
   };
 
   ScalingCtx::get(
-    decoder.format(),
-    decoder.width(),
-    decoder.height(),
+    src_format,
+    src_width,
+    src_height,
     format::Pixel::RGBA,
     scaler_dst_w,
     scaler_dst_h,
-    Flags::SINC,
+    get_scaler_flags(source, scaler_dst_w, scaler_dst_h, preset),
   )
 }
 
-fn decode_frame(
-  decoder: &mut decoder::Video,
+fn get_scaler_flags(source: (format::Pixel, u32, u32), dst_width: u32, dst_height: u32, preset: AtlasPreset) -> Flags {
+  let (src_format, src_width, src_height) = source;
+  // Same size means only the pixel format changes, there's nothing to resample
+  if dst_width == src_width && dst_height == src_height {
+    return match src_format {
+      format::Pixel::YUV420P | format::Pixel::YUVJ420P | format::Pixel::NV12 => Flags::POINT,
+      _ => Flags::POINT | Flags::ACCURATE_RND,
+    }
+  }
+  match preset {
+    AtlasPreset::Fast => return Flags::FAST_BILINEAR,
+    AtlasPreset::Quality => return Flags::SINC | Flags::ACCURATE_RND,
+    AtlasPreset::Balanced => {}
+  }
+  if dst_width <= FAST_SCALE_MAX_WIDTH {
+    return Flags::AREA
+  }
+  Flags::SINC
+}
+
+/// Next frame out of the decoder, copied to memory if it was decoded on the GPU
+fn receive_frame(decoder: &mut decoder::Video) -> Result<VideoFrame, ffmpeg::Error> {
+  let mut decoded = VideoFrame::empty();
+  let _timer = perf::time(perf::Stage::Decode);
+  decoder.receive_frame(&mut decoded)?;
+  download_frame(decoded)
+}
+
+/// Scales a decoded frame to RGBA and rotates it as its display matrix says
+fn convert_frame(
+  decoded: &VideoFrame,
   matrix: Option<[i32; 9]>,
   rotation: i32,
   scaler: &mut ScalingCtx,
 ) -> Result<VideoFrame, ffmpeg::Error> {
-  let mut decoded = VideoFrame::empty();
-  decoder.receive_frame(&mut decoded)?;
-
   let mut src_frame = VideoFrame::empty();
-  // Convert to RGBA pixel format and resize
-  scaler.run(&decoded, &mut src_frame)?;
+  {
+    let _timer = perf::time(perf::Stage::Scale);
+    // Convert to RGBA pixel format and resize
+    scaler.run(decoded, &mut src_frame)?;
+  }
   // Running the scaler can break images depending on the output size
   fix_img_data(&mut src_frame);
 
@@ -333,6 +1199,7 @@ fn decode_frame(
       dst_height,
     );
 
+    let _timer = perf::time(perf::Stage::Rotate);
     math::rotate_frame(
       &src_frame,
       &mut dst_frame,
@@ -344,25 +1211,415 @@ fn decode_frame(
   return Ok(src_frame)
 }
 
-fn encode_webp_from_frame(frame: &VideoFrame) -> WebPMemory {
-  let encoder = Encoder::from_rgba(
-    frame.data(0),
-    frame.width(),
-    frame.height(),
-  );
-  let webp = encoder.encode(50.);
-  webp
+/// Decoder for `stream` set up for `preset`, decoding on the GPU if `hardware` is set and
+/// `hw_decoder` finds a device for it
+fn open_video_decoder(
+  stream: &ffmpeg::Stream,
+  hardware: bool,
+  preset: AtlasPreset,
+) -> Result<decoder::Video, ffmpeg::Error> {
+  let mut context = CodecCtx::from_parameters(stream.parameters())?;
+  preset.configure_decoder(&mut context);
+  let Some((device, pixel_format, codec)) = hardware.then(|| hw_decoder(context.id())).flatten() else {
+    return context.decoder().video()
+  };
+  unsafe {
+    let context = context.as_mut_ptr();
+    (*context).hw_device_ctx = ffmpeg::ffi::av_buffer_ref(device.0);
+    // Read back by `get_hw_format`
+    (*context).opaque = pixel_format as isize as *mut c_void;
+    (*context).get_format = Some(get_hw_format);
+  }
+  context.decoder().open_as(codec)?.video()
 }
 
-fn fix_img_data(frame: &mut VideoFrame) {
-  let stride = frame.stride(0);
-  let width: usize = frame.width() as usize;
-  let height: usize = frame.height() as usize;
-  let data = frame.data_mut(0);
-  let byte_width = width * 4;
-  let mut buffer = Vec::with_capacity(data.len());
-
-  for line in 0..height {
+/// Device, pixel format and decoder to decode `codec` on the GPU with, `None` when hardware decoding
+/// is off, the device can't be opened or it can't decode the codec. QSV only decodes with its own
+/// decoders, e.g. `h264_qsv`, the other APIs with FFmpeg's usual decoder
+fn hw_decoder(codec: ffmpeg::codec::Id) -> Option<(HwDevice, ffmpeg::ffi::AVPixelFormat, ffmpeg::Codec)> {
+  use ffmpeg::ffi::AVHWDeviceType::*;
+  let config = config::get();
+  let device_type = match config.hardware_decode {
+    config::HardwareDecode::None => return None,
+    config::HardwareDecode::Vaapi => AV_HWDEVICE_TYPE_VAAPI,
+    config::HardwareDecode::Nvdec => AV_HWDEVICE_TYPE_CUDA,
+    config::HardwareDecode::Qsv => AV_HWDEVICE_TYPE_QSV,
+  };
+  let codec = match config.hardware_decode {
+    config::HardwareDecode::Qsv => decoder::find_by_name(&f!("{}_qsv", codec.name()))?,
+    _ => decoder::find(codec)?,
+  };
+  let pixel_format = (0..)
+  .map_while(|index| unsafe { ffmpeg::ffi::avcodec_get_hw_config(codec.as_ptr(), index).as_ref() })
+  .find(|hw_config| hw_config.methods & HW_CONFIG_DEVICE_CTX != 0 && hw_config.device_type == device_type)?
+  .pix_fmt;
+  let device = hw_device(config.hardware_decode, device_type, config.hardware_decode_device.as_deref())?;
+  Some((device, pixel_format, codec))
+}
+
+/// Opens the device the first time it's asked for, later calls get the same one
+fn hw_device(
+  kind: config::HardwareDecode,
+  device_type: ffmpeg::ffi::AVHWDeviceType,
+  device: Option<&str>,
+) -> Option<HwDevice> {
+  let key = (kind, device.map(String::from));
+  let mut devices = HW_DEVICES.lock().unwrap_or_else(|err| err.into_inner());
+  if let Some((_, opened)) = devices.iter().find(|(opened, _)| *opened == key) {
+    return *opened
+  }
+
+  let name = device.and_then(|device| CString::new(device).ok());
+  let mut context = ptr::null_mut();
+  let created = unsafe {
+    ffmpeg::ffi::av_hwdevice_ctx_create(
+      &mut context,
+      device_type,
+      name.as_ref().map_or(ptr::null(), |name| name.as_ptr()),
+      ptr::null_mut(),
+      0,
+    )
+  };
+  let opened = if created < 0 {
+    eprintln!("Could not open {kind:?} device, decoding on the CPU - {:?}", ffmpeg::Error::from(created));
+    None
+  } else {
+    Some(HwDevice(context))
+  };
+  devices.push((key, opened));
+  opened
+}
+
+/// Picks the GPU pixel format `open_video_decoder` asked for, or lets FFmpeg pick a software one
+/// when the decoder can't output it, which decodes on the CPU
+unsafe extern "C" fn get_hw_format(
+  context: *mut ffmpeg::ffi::AVCodecContext,
+  formats: *const ffmpeg::ffi::AVPixelFormat,
+) -> ffmpeg::ffi::AVPixelFormat {
+  let wanted = (*context).opaque as isize;
+  let mut format = formats;
+  while *format != ffmpeg::ffi::AVPixelFormat::AV_PIX_FMT_NONE {
+    if *format as isize == wanted {
+      return *format
+    }
+    format = format.add(1);
+  }
+  ffmpeg::ffi::avcodec_default_get_format(context, formats)
+}
+
+/// Copies a frame decoded on the GPU to memory, frames decoded on the CPU are returned as they are
+fn download_frame(frame: VideoFrame) -> Result<VideoFrame, ffmpeg::Error> {
+  if unsafe { (*frame.as_ptr()).hw_frames_ctx.is_null() } {
+    return Ok(frame)
+  }
+  let mut downloaded = VideoFrame::empty();
+  unsafe {
+    let transferred = ffmpeg::ffi::av_hwframe_transfer_data(downloaded.as_mut_ptr(), frame.as_ptr(), 0);
+    if transferred < 0 {
+      return Err(ffmpeg::Error::from(transferred))
+    }
+    ffmpeg::ffi::av_frame_copy_props(downloaded.as_mut_ptr(), frame.as_ptr());
+  }
+  Ok(downloaded)
+}
+
+/// Hardware device context, reference counted by FFmpeg and safe to share between decoders on any
+/// thread. There's one per configured device and they're never freed
+#[derive(Clone, Copy)]
+struct HwDevice(*mut ffmpeg::ffi::AVBufferRef);
+
+type HwDeviceEntry = ((config::HardwareDecode, Option<String>), Option<HwDevice>);
+
+unsafe impl Send for HwDevice {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DecodedKey {
+  path: PathBuf,
+  modified: Option<SystemTime>,
+  /// Seek target divided by `DECODED_SEEK_WINDOW`
+  window: i64,
+}
+
+/// Frame as it came out of the decoder, before scaling and rotation
+struct DecodedFrame {
+  frame: VideoFrame,
+  matrix: Option<[i32; 9]>,
+  rotation: i32,
+}
+
+impl DecodedFrame {
+  fn bytes(&self) -> usize {
+    (0..self.frame.planes()).map(|plane| self.frame.data(plane).len()).sum()
+  }
+
+  /// RGBA frame `frame_width` wide, 0 keeps the video's width
+  fn convert(&self, frame_width: u32, max_height: Option<u32>) -> Result<VideoFrame, VideoError> {
+    let source = (self.frame.format(), self.frame.width(), self.frame.height());
+    let frame_width = if frame_width == 0 {
+      source.1
+    } else {
+      frame_width
+    };
+    let mut scaler = get_scaler(source, frame_width, self.rotation, max_height, AtlasPreset::Balanced)?;
+    Ok(convert_frame(&self.frame, self.matrix, self.rotation, &mut scaler)?)
+  }
+}
+
+/// First frame decoded at `frame_time`. Frames are kept for a while so thumbnails of nearby
+/// seeks, e.g. while scrubbing or at other widths, only have to be scaled and encoded again
+fn get_decoded_frame(
+  video_path: &Path,
+  av_format_ctx: &mut AVFormatContext,
+  frame_time: SeekTime,
+) -> Result<Arc<DecodedFrame>, VideoError> {
+  let target = match frame_time {
+    SeekTime::Seconds(seconds) => seconds.rescale((1, 1), rescale::TIME_BASE),
+    SeekTime::Percentage(percentage) => math::seek_position(percentage, av_format_ctx.duration()),
+  };
+  let key = DecodedKey {
+    path: video_path.to_path_buf(),
+    modified: std::fs::metadata(video_path).and_then(|meta| meta.modified()).ok(),
+    window: target / DECODED_SEEK_WINDOW,
+  };
+  {
+    let mut frames = DECODED_FRAMES.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some(index) = frames.iter().position(|(cached, _)| *cached == key) {
+      let entry = frames.remove(index).unwrap();
+      let decoded = entry.1.clone();
+      frames.push_back(entry);
+      return Ok(decoded)
+    }
+  }
+
+  let decoded = Arc::new(decode_at(av_format_ctx, &frame_time)?);
+  let mut frames = DECODED_FRAMES.lock().unwrap_or_else(|err| err.into_inner());
+  frames.push_back((key, decoded.clone()));
+  let mut bytes: usize = frames.iter().map(|(_, frame)| frame.bytes()).sum();
+  while bytes > DECODED_CACHE_BYTES && frames.len() > 1 {
+    if let Some((_, oldest)) = frames.pop_front() {
+      bytes -= oldest.bytes();
+    }
+  }
+  Ok(decoded)
+}
+
+/// Seeks to `frame_time` and decodes the first frame found from there
+fn decode_at(av_format_ctx: &mut AVFormatContext, frame_time: &SeekTime) -> Result<DecodedFrame, VideoError> {
+  with_decode_fallback(av_format_ctx, |av_format_ctx, hardware| {
+    seek(av_format_ctx, frame_time)?;
+    decode_first(av_format_ctx, hardware)
+  })
+}
+
+/// Decodes the first frame from where `av_format_ctx` is
+fn decode_first(av_format_ctx: &mut AVFormatContext, hardware: bool) -> Result<DecodedFrame, VideoError> {
+  let video_stream = av_format_ctx
+  .streams()
+  .best(Type::Video)
+  .ok_or(ffmpeg::Error::StreamNotFound)?;
+  let video_stream_index = video_stream.index();
+  let matrix = get_display_matrix_values(&video_stream).ok();
+  let rotation = matrix
+  .and_then(|transform| math::av_display_rotation_get(&transform))
+  .unwrap_or_default() as i32;
+  let mut decoder = open_video_decoder(&video_stream, hardware, AtlasPreset::Balanced)?;
+
+  for (stream_index, packet) in read_packets(av_format_ctx) {
+    if stream_index != video_stream_index {
+      continue
+    }
+    if let Err(err) = decoder.send_packet(&packet) {
+      if err != FFMPEG_RETRY_ERR {
+        return Err(("Error sending packet", err).into())
+      }
+    }
+    match receive_frame(&mut decoder) {
+      Ok(frame) => return Ok(DecodedFrame { frame, matrix, rotation }),
+      Err(err) if err != FFMPEG_RETRY_ERR => return Err(("Error receiving frame", err).into()),
+      Err(_) => {}
+    }
+  }
+  // Decoders may hold on to the last frames until they're told there's nothing else coming,
+  // which is all there is in still images
+  decoder.send_eof()?;
+  receive_frame(&mut decoder)
+  .map(|frame| DecodedFrame { frame, matrix, rotation })
+  .map_err(|err| ("Error receiving frame", err).into())
+}
+
+/// Encodes an RGBA frame with packed rows
+pub fn encode_webp_from_frame(frame: &VideoFrame) -> WebPMemory {
+  encode_webp_with_quality(frame, WEBP_QUALITY)
+}
+
+/// Same as `encode_webp_from_frame` without losing any detail
+pub fn encode_webp_lossless(frame: &VideoFrame) -> WebPMemory {
+  let _timer = perf::time(perf::Stage::Encode);
+  Encoder::from_rgba(frame.data(0), frame.width(), frame.height()).encode_lossless()
+}
+
+/// Encodes an RGBA frame as PNG. Unlike the WebP and JPEG encoders FFmpeg's reads rows at the
+/// frame's stride, so they don't have to be packed
+pub fn encode_png(frame: &VideoFrame) -> Result<Vec<u8>, VideoError> {
+  let _timer = perf::time(perf::Stage::Encode);
+  let codec = ffmpeg::encoder::find(ffmpeg::codec::Id::PNG).ok_or(ffmpeg::Error::EncoderNotFound)?;
+  let mut encoder = CodecCtx::new().encoder().video()?;
+  encoder.set_width(frame.width());
+  encoder.set_height(frame.height());
+  encoder.set_format(format::Pixel::RGBA);
+  encoder.set_time_base((1, 1));
+  let mut encoder = encoder.open_as(codec)?;
+  encoder.send_frame(frame)?;
+  encoder.send_eof()?;
+  let mut packet = ffmpeg::Packet::empty();
+  encoder.receive_packet(&mut packet)?;
+  Ok(packet.data().unwrap_or_default().to_vec())
+}
+
+/// Same as `encode_webp_from_frame` at `quality`, from 0 to 100
+pub fn encode_webp_with_quality(frame: &VideoFrame, quality: f32) -> WebPMemory {
+  let _timer = perf::time(perf::Stage::Encode);
+  let encoder = Encoder::from_rgba(
+    frame.data(0),
+    frame.width(),
+    frame.height(),
+  );
+  let webp = encoder.encode(quality);
+  webp
+}
+
+/// Same as `encode_webp_from_frame` with `method` trading size for speed, from 0 (fastest) to 6
+fn encode_webp_with_method(frame: &VideoFrame, method: c_int) -> Result<WebPMemory, VideoError> {
+  let _timer = perf::time(perf::Stage::Encode);
+  let mut config = libwebp_sys::WebPConfig::new()
+  .map_err(|_| VideoError::from(("Could not configure WebP encoder", method)))?;
+  config.quality = WEBP_QUALITY;
+  config.method = method;
+  Encoder::from_rgba(frame.data(0), frame.width(), frame.height())
+  .encode_advanced(&config)
+  .map_err(|err| VideoError::from(("Could not encode WebP", err)))
+}
+
+/// Speed and looks of atlas tiles in one setting, at 80x45 most clients won't tell `fast` apart
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AtlasPreset {
+  /// Bilinear scaling, multithreaded decoding without deblocking and the fastest WebP encoding
+  Fast,
+  /// Area averaging and the keyframe each seek lands on
+  #[default]
+  Balanced,
+  /// Sinc scaling, frames decoded up to the exact second of each tile and the smallest WebP
+  Quality,
+}
+
+impl AtlasPreset {
+  /// Sets up a decoder before it's opened
+  fn configure_decoder(self, context: &mut CodecCtx) {
+    if self != Self::Fast {
+      return
+    }
+    context.set_threading(ffmpeg::threading::Config::kind(ffmpeg::threading::Type::Slice));
+    unsafe {
+      (*context.as_mut_ptr()).skip_loop_filter = ffmpeg::ffi::AVDiscard::AVDISCARD_ALL;
+    }
+  }
+
+  /// Whether frames before the wanted second are skipped instead of using the keyframe before it
+  fn exact_seek(self) -> bool {
+    self == Self::Quality
+  }
+
+  fn webp_method(self) -> c_int {
+    match self {
+      Self::Fast => 0,
+      Self::Balanced => 4,
+      Self::Quality => 6,
+    }
+  }
+}
+
+/// Order tiles are placed in on an atlas page
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TileLayout {
+  /// Left to right, then top to bottom
+  #[default]
+  RowMajor,
+  /// Top to bottom, then left to right
+  ColumnMajor,
+}
+
+/// Encoding of generated images
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+  Webp,
+  /// Smaller than webp for photographic images that don't need alpha, `quality` goes from 1 to 100
+  Jpeg { quality: u8 },
+}
+
+impl ImageFormat {
+  /// Format for the `format` and `quality` query parameters, `None` if either is invalid
+  pub fn parse(format: Option<&str>, quality: Option<u8>) -> Option<Self> {
+    match format.map(|format| format.to_lowercase()).as_deref() {
+      None | Some("webp") => Some(Self::Webp),
+      Some("jpeg" | "jpg") => {
+        let quality = quality.unwrap_or(DEFAULT_JPEG_QUALITY);
+        (1..=100).contains(&quality).then_some(Self::Jpeg { quality })
+      }
+      Some(_) => None,
+    }
+  }
+
+  pub fn content_type(&self) -> &'static str {
+    match self {
+      Self::Webp => "image/webp",
+      Self::Jpeg { .. } => "image/jpeg",
+    }
+  }
+
+  /// Same as `encode` with the WebP effort of `preset`
+  pub fn encode_with(&self, frame: &VideoFrame, preset: AtlasPreset) -> Result<Vec<u8>, VideoError> {
+    match self {
+      Self::Webp => Ok(encode_webp_with_method(frame, preset.webp_method())?.to_vec()),
+      Self::Jpeg { .. } => self.encode(frame),
+    }
+  }
+
+  /// Encodes an RGBA frame with packed rows, alpha is dropped for JPEG
+  pub fn encode(&self, frame: &VideoFrame) -> Result<Vec<u8>, VideoError> {
+    match self {
+      Self::Webp => Ok(encode_webp_from_frame(frame).to_vec()),
+      Self::Jpeg { quality } => {
+        let _timer = perf::time(perf::Stage::Encode);
+        let mut jpeg = Vec::new();
+        let (Ok(width), Ok(height)) = (u16::try_from(frame.width()), u16::try_from(frame.height())) else {
+          return Err(VideoError::from(("Image is too large for JPEG", (frame.width(), frame.height()))))
+        };
+        jpeg_encoder::Encoder::new(&mut jpeg, *quality)
+        .encode(frame.data(0), width, height, jpeg_encoder::ColorType::Rgba)
+        .map_err(|err| VideoError::from(("Could not encode JPEG", err)))?;
+        Ok(jpeg)
+      }
+    }
+  }
+}
+
+/// Packs the rows of an RGBA frame the scaler left padded to its stride
+pub fn fix_img_data(frame: &mut VideoFrame) {
+  let _timer = perf::time(perf::Stage::FixImgData);
+  let stride = frame.stride(0);
+  let width: usize = frame.width() as usize;
+  let height: usize = frame.height() as usize;
+  let byte_width = width * 4;
+  // Rows are already packed, nothing to move
+  if stride == byte_width {
+    return
+  }
+  let data = frame.data_mut(0);
+  let mut buffer = Vec::with_capacity(data.len());
+
+  for line in 0..height {
     let begin = line * stride;
     let end = begin + byte_width;
     buffer.extend_from_slice(&data[begin..end]);
@@ -381,8 +1638,7 @@ fn seek(
   match seek_time {
     SeekTime::Seconds(seconds) => seek_seconds(&mut video_stream, *seconds),
     SeekTime::Percentage(percentage) => {
-      let duration = video_stream.duration();
-      let position = (percentage * duration as f32) as i64;
+      let position = math::seek_position(*percentage, video_stream.duration());
       video_stream.seek(position, ..position)
     }
   }
@@ -393,26 +1649,1338 @@ fn seek_seconds(video_stream: &mut AVFormatContext, seconds: u32) -> Result<(),
   video_stream.seek(position, ..position)
 }
 
-pub fn get_duration_from_path(video_path: &String) -> Result<i64, VideoError> {
-  let av_format_ctx = match format::input(video_path) {
-    Ok(av_format_ctx) => av_format_ctx,
-    Err(err) => return Err((f!("Could not open file \"{video_path}\""), err).into())
+/// Opens `video_path` for demuxing. ffmpeg reads it through `RangedReader` instead of its file
+/// protocol, which also lets paths that aren't UTF-8 be opened everywhere
+fn open_input(video_path: &Path) -> Result<InputFile, VideoError> {
+  open_input_range(video_path, 0, None)
+}
+
+/// Opens `len` bytes of the file from `start` as if they were all of it, the rest of the file
+/// when `len` is `None`
+fn open_input_range(video_path: &Path, start: u64, len: Option<u64>) -> Result<InputFile, VideoError> {
+  let _timer = perf::time(perf::Stage::Open);
+  let open_err = |err: io::Error| VideoError::from((f!("Could not open file {video_path:?}"), err));
+  let file = open_shared(video_path).map_err(open_err)?;
+  let rest = file.metadata().map_err(open_err)?.len().saturating_sub(start);
+  let size = len.map_or(rest, |len| len.min(rest));
+  // Only names the input in ffmpeg's logs and hints the format from the extension
+  let name = CString::new(video_path.to_string_lossy().as_bytes()).unwrap_or_default();
+  let read_bytes = config::get().media_read_bytes.clamp(4096, i32::MAX as usize);
+
+  let opened = with_retry(|| {
+    let file = file.try_clone().map_err(|err| ffmpeg::Error::from(io_errno(&err)))?;
+    InputFile::open(&name, RangedReader { source: Source::File(file), start, size, position: 0 }, read_bytes)
+  });
+  match opened {
+    Ok(input) => Ok(input),
+    Err(err) => Err((f!("Could not open file {video_path:?}"), err).into())
+  }
+}
+
+/// Opens `path` for reading without keeping it from being renamed or deleted while ffmpeg reads it,
+/// which is what Windows does by default with open files
+fn open_shared(path: &Path) -> io::Result<File> {
+  let mut options = File::options();
+  options.read(true);
+  #[cfg(windows)]
+  {
+    use std::os::windows::fs::OpenOptionsExt;
+    // FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE
+    options.share_mode(0x1 | 0x2 | 0x4);
+  }
+  options.open(path)
+}
+
+/// Opens `bytes` taken out of the file at `path`, e.g. an image inside an archive
+fn open_input_bytes(path: &Path, bytes: Vec<u8>) -> Result<InputFile, VideoError> {
+  let _timer = perf::time(perf::Stage::Open);
+  let name = CString::new(path.to_string_lossy().as_bytes()).unwrap_or_default();
+  let read_bytes = config::get().media_read_bytes.clamp(4096, i32::MAX as usize);
+  let size = bytes.len() as u64;
+  let reader = RangedReader { source: Source::Memory(bytes), start: 0, size, position: 0 };
+  InputFile::open(&name, reader, read_bytes)
+  .map_err(|err| (f!("Could not open image in {path:?}"), err).into())
+}
+
+/// Input ffmpeg reads through. Every read is a positioned read of up to `media_read_bytes`
+/// and seeking only moves `position`, the size is taken once when opening. On network mounts
+/// this saves the round trips of ffmpeg's small reads, stats and seeks
+struct RangedReader {
+  source: Source,
+  /// Where the input starts in the source, `position` and `size` are relative to it
+  start: u64,
+  size: u64,
+  position: u64,
+}
+
+/// What `RangedReader` reads from, a file or bytes taken out of one, like a book cover
+enum Source {
+  File(File),
+  Memory(Vec<u8>),
+}
+
+impl RangedReader {
+  fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+    let left = self.size.saturating_sub(self.position);
+    let buffer = &mut buffer[..left.min(buffer.len() as u64) as usize];
+    if buffer.is_empty() {
+      return Ok(0)
+    }
+    loop {
+      match self.read_at(buffer, self.start + self.position) {
+        Ok(read) => {
+          self.position += read as u64;
+          return Ok(read)
+        }
+        Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+        Err(err) => return Err(err),
+      }
+    }
+  }
+
+  fn read_at(&self, buffer: &mut [u8], offset: u64) -> io::Result<usize> {
+    match &self.source {
+      #[cfg(unix)]
+      Source::File(file) => std::os::unix::fs::FileExt::read_at(file, buffer, offset),
+      #[cfg(not(unix))]
+      Source::File(file) => std::os::windows::fs::FileExt::seek_read(file, buffer, offset),
+      Source::Memory(bytes) => {
+        let from = usize::try_from(offset).unwrap_or(usize::MAX).min(bytes.len());
+        let read = buffer.len().min(bytes.len() - from);
+        buffer[..read].copy_from_slice(&bytes[from..from + read]);
+        Ok(read)
+      }
+    }
+  }
+}
+
+unsafe extern "C" fn read_ranged(opaque: *mut c_void, buffer: *mut u8, len: c_int) -> c_int {
+  let reader = &mut *(opaque as *mut RangedReader);
+  let buffer = std::slice::from_raw_parts_mut(buffer, len.max(0) as usize);
+  match reader.read(buffer) {
+    Ok(0) => ffmpeg::ffi::AVERROR_EOF,
+    Ok(read) => read as c_int,
+    Err(err) => ffmpeg::ffi::AVERROR(io_errno(&err)),
+  }
+}
+
+unsafe extern "C" fn seek_ranged(opaque: *mut c_void, offset: i64, whence: c_int) -> i64 {
+  let reader = &mut *(opaque as *mut RangedReader);
+  let position = match whence & !(ffmpeg::ffi::AVSEEK_FORCE as c_int) {
+    whence if whence == ffmpeg::ffi::AVSEEK_SIZE as c_int => return reader.size as i64,
+    libc::SEEK_SET => offset,
+    libc::SEEK_CUR => reader.position as i64 + offset,
+    libc::SEEK_END => reader.size as i64 + offset,
+    _ => -1,
+  };
+  if position < 0 {
+    return ffmpeg::ffi::AVERROR(ffmpeg::error::EINVAL) as i64
+  }
+  reader.position = position as u64;
+  position
+}
+
+/// errno for `err`, EIO when it has none so it's retried like other read errors
+fn io_errno(err: &io::Error) -> c_int {
+  err.raw_os_error().unwrap_or(ffmpeg::error::EIO)
+}
+
+/// IO context handed to ffmpeg, it owns the reader behind it
+struct RangedIo {
+  context: *mut ffmpeg::ffi::AVIOContext,
+  reader: *mut RangedReader,
+}
+
+impl RangedIo {
+  fn new(reader: RangedReader, read_bytes: usize) -> Result<Self, ffmpeg::Error> {
+    let no_memory = ffmpeg::Error::Other { errno: ffmpeg::error::ENOMEM };
+    unsafe {
+      let buffer = ffmpeg::ffi::av_malloc(read_bytes) as *mut u8;
+      if buffer.is_null() {
+        return Err(no_memory)
+      }
+      let reader = Box::into_raw(Box::new(reader));
+      let context = ffmpeg::ffi::avio_alloc_context(
+        buffer,
+        read_bytes as c_int,
+        0,
+        reader as *mut c_void,
+        Some(read_ranged),
+        None,
+        Some(seek_ranged),
+      );
+      if context.is_null() {
+        ffmpeg::ffi::av_free(buffer as *mut c_void);
+        drop(Box::from_raw(reader));
+        return Err(no_memory)
+      }
+      Ok(Self { context, reader })
+    }
+  }
+}
+
+impl Drop for RangedIo {
+  fn drop(&mut self) {
+    unsafe {
+      // ffmpeg may have replaced the buffer, free the one it holds now
+      ffmpeg::ffi::av_freep(&mut (*self.context).buffer as *mut *mut u8 as *mut c_void);
+      ffmpeg::ffi::avio_context_free(&mut self.context);
+      drop(Box::from_raw(self.reader));
+    }
+  }
+}
+
+/// Demuxer reading through a `RangedIo`, derefs to ffmpeg-next's input
+struct InputFile {
+  // Declared first so the format context is closed before the IO it reads from is freed
+  input: AVFormatContext,
+  _io: RangedIo,
+}
+
+impl InputFile {
+  fn open(name: &CStr, reader: RangedReader, read_bytes: usize) -> Result<Self, ffmpeg::Error> {
+    let io = RangedIo::new(reader, read_bytes)?;
+    unsafe {
+      let mut context = ffmpeg::ffi::avformat_alloc_context();
+      if context.is_null() {
+        return Err(ffmpeg::Error::Other { errno: ffmpeg::error::ENOMEM })
+      }
+      (*context).pb = io.context;
+      // Keeps ffmpeg from closing the IO context, `RangedIo` frees it
+      (*context).flags |= ffmpeg::ffi::AVFMT_FLAG_CUSTOM_IO as c_int;
+      // Frees the context on failure
+      let opened = ffmpeg::ffi::avformat_open_input(&mut context, name.as_ptr(), ptr::null_mut(), ptr::null_mut());
+      if opened < 0 {
+        return Err(ffmpeg::Error::from(opened))
+      }
+      let mut input = AVFormatContext::wrap(context);
+      let found = ffmpeg::ffi::avformat_find_stream_info(input.as_mut_ptr(), ptr::null_mut());
+      if found < 0 {
+        return Err(ffmpeg::Error::from(found))
+      }
+      Ok(Self { input, _io: io })
+    }
+  }
+}
+
+impl std::ops::Deref for InputFile {
+  type Target = AVFormatContext;
+
+  fn deref(&self) -> &Self::Target {
+    &self.input
+  }
+}
+
+impl std::ops::DerefMut for InputFile {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.input
+  }
+}
+
+/// Bytes of an RGBA frame scaled to `frame_width` (0 for the video's width)
+fn estimate_frame_bytes(av_format_ctx: &AVFormatContext, frame_width: u32) -> usize {
+  let (width, height) = av_format_ctx
+  .streams()
+  .best(Type::Video)
+  .and_then(|stream| CodecCtx::from_parameters(stream.parameters()).ok())
+  .and_then(|context| context.decoder().video().ok())
+  .map(|decoder| (decoder.width() as usize, decoder.height() as usize))
+  .unwrap_or_default();
+  scaled_frame_bytes((width, height), frame_width)
+}
+
+/// Bytes of an RGBA frame of `size` scaled down to `frame_width`, 0 keeps its width
+fn scaled_frame_bytes((width, height): (usize, usize), frame_width: u32) -> usize {
+  let frame_width = frame_width as usize;
+  if frame_width == 0 || width == 0 || frame_width >= width {
+    return width * height * 4
+  }
+  frame_width * (frame_width * height / width) * 4
+}
+
+struct FrameUsage {
+  /// Approximate bytes used by the RGBA frames of the requests being served
+  bytes: usize,
+  /// Interactive requests decoding or waiting to, background work waits for them
+  interactive: usize,
+}
+
+/// Runs `work` at idle priority, e.g. pre-generating thumbnails. Its frames are only
+/// decoded once no interactive request is decoding or waiting to
+pub fn in_background<T>(work: impl FnOnce() -> T) -> T {
+  BACKGROUND.with(|background| background.set(true));
+  let result = work();
+  BACKGROUND.with(|background| background.set(false));
+  result
+}
+
+/// Runs `work` on actix's blocking thread pool so decoding doesn't hold up the async workers.
+/// Fails right away as over budget when `decode_queue_size` jobs are already queued or running
+pub async fn unblocked<T: Send + 'static>(
+  work: impl FnOnce() -> Result<T, VideoError> + Send + 'static,
+) -> Result<T, VideoError> {
+  let queued = DECODE_QUEUE.fetch_update(Ordering::AcqRel, Ordering::Acquire, |queued| {
+    let size = config::get().decode_queue_size;
+    (size == 0 || queued < size).then_some(queued + 1)
+  });
+  if let Err(queued) = queued {
+    return Err(VideoError {
+      message: f!("Video Error: Decode queue is full, {queued} jobs waiting or running"),
+      over_budget: true,
+    })
+  }
+  // Counted until the job is done even if the request goes away before
+  let job = DecodeJob;
+  actix_web::web::block(move || {
+    let _job = job;
+    work()
+  })
+  .await
+  .map_err(|err| VideoError { message: f!("Video Error: {err}"), over_budget: false })?
+}
+
+/// Place taken in the decode queue, given back when dropped
+struct DecodeJob;
+
+impl Drop for DecodeJob {
+  fn drop(&mut self) {
+    DECODE_QUEUE.fetch_sub(1, Ordering::AcqRel);
+  }
+}
+
+/// Frame memory taken by a request, given back when dropped
+struct FrameMemory {
+  bytes: usize,
+  interactive: bool,
+}
+
+impl FrameMemory {
+  /// Waits up to `frame_memory_wait_ms` for `bytes` to fit in `frame_memory_budget`.
+  /// Requests bigger than the whole budget are let through once nothing else is running.
+  /// Background work waits as long as it takes instead. It blocks, requests have to get here
+  /// through `unblocked` or `web::block` so the wait doesn't hold up an async worker
+  fn reserve(bytes: usize) -> Result<Self, VideoError> {
+    let budget = config::get().frame_memory_budget;
+    let bytes = if budget == 0 {0} else {bytes.min(budget)};
+    let fits = |usage: &FrameUsage| budget == 0 || usage.bytes + bytes <= budget;
+    let usage = FRAME_MEMORY.lock().unwrap_or_else(|err| err.into_inner());
+
+    if BACKGROUND.with(Cell::get) {
+      let mut usage = FRAME_MEMORY_FREED
+      .wait_while(usage, |usage| usage.interactive > 0 || !fits(usage))
+      .unwrap_or_else(|err| err.into_inner());
+      usage.bytes += bytes;
+      return Ok(Self { bytes, interactive: false })
+    }
+
+    let mut usage = usage;
+    usage.interactive += 1;
+    let (mut usage, wait) = FRAME_MEMORY_FREED
+    .wait_timeout_while(
+      usage,
+      Duration::from_millis(config::get().frame_memory_wait_ms),
+      |usage| !fits(usage),
+    )
+    .unwrap_or_else(|err| err.into_inner());
+    if wait.timed_out() && !fits(&usage) {
+      usage.interactive -= 1;
+      // Background work may be waiting on this request only
+      FRAME_MEMORY_FREED.notify_all();
+      return Err(VideoError {
+        message: f!("Video Error: Frame memory budget exceeded, {} MiB in use", usage.bytes / 1024 / 1024),
+        over_budget: true,
+      })
+    }
+    usage.bytes += bytes;
+    Ok(Self { bytes, interactive: true })
+  }
+}
+
+impl Drop for FrameMemory {
+  fn drop(&mut self) {
+    let mut usage = FRAME_MEMORY.lock().unwrap_or_else(|err| err.into_inner());
+    usage.bytes = usage.bytes.saturating_sub(self.bytes);
+    if self.interactive {
+      usage.interactive = usage.interactive.saturating_sub(1);
+    }
+    FRAME_MEMORY_FREED.notify_all();
+  }
+}
+
+/// IO errors network mounts (NFS/SMB) return now and then, unlike decode errors
+/// reading again has a good chance of working
+fn is_transient(err: &ffmpeg::Error) -> bool {
+  use ffmpeg::error::{EAGAIN, ECONNRESET, EINTR, EIO, ETIMEDOUT};
+  matches!(
+    err,
+    ffmpeg::Error::Other { errno } if [EIO, EAGAIN, EINTR, ETIMEDOUT, ECONNRESET].contains(errno)
+  )
+}
+
+/// Runs `read` again on transient errors, waiting `io_retry_backoff_ms`
+/// and doubling the wait after every attempt
+fn with_retry<T>(mut read: impl FnMut() -> Result<T, ffmpeg::Error>) -> Result<T, ffmpeg::Error> {
+  let config = config::get();
+  let mut attempt = 0;
+  loop {
+    match read() {
+      Err(err) if is_transient(&err) && attempt < config.io_retry_attempts => {
+        std::thread::sleep(Duration::from_millis(config.io_retry_backoff_ms << attempt.min(16)));
+        attempt += 1;
+      }
+      result => return result,
+    }
+  }
+}
+
+/// Like `Input::packets` but retries transient read errors and gives up on files that
+/// keep failing instead of looping forever. Yields the stream index along with each packet
+fn read_packets(av_format_ctx: &mut AVFormatContext) -> impl Iterator<Item = (usize, ffmpeg::Packet)> + '_ {
+  let mut skipped = 0;
+  std::iter::from_fn(move || loop {
+    let mut packet = ffmpeg::Packet::empty();
+    match with_retry(|| packet.read(av_format_ctx)) {
+      Ok(()) => {
+        skipped = 0;
+        return Some((packet.stream(), packet))
+      }
+      Err(ffmpeg::Error::Eof) => return None,
+      Err(err) if is_transient(&err) => {
+        eprintln!("Giving up reading packets after {} retries - {err:?}", config::get().io_retry_attempts);
+        return None
+      }
+      // Corrupt packets are skipped
+      Err(_) => {
+        skipped += 1;
+        if skipped >= MAX_SKIPPED_PACKETS {
+          return None
+        }
+      }
+    }
+  })
+}
+
+/// Container of transcoded segments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SegmentFormat {
+  /// MPEG-TS for HLS
+  MpegTs,
+  /// Fragmented MP4 for DASH, the `moov` before the first `moof` is the initialization segment
+  Fmp4,
+  /// Fragmented MP4 of a whole file, playable from the start while it's still being written
+  Progressive,
+}
+
+impl SegmentFormat {
+  fn muxer(self) -> &'static str {
+    match self {
+      Self::MpegTs => "mpegts",
+      Self::Fmp4 | Self::Progressive => "mp4",
+    }
+  }
+
+  fn options(self) -> ffmpeg::Dictionary<'static> {
+    let mut options = ffmpeg::Dictionary::new();
+    match self {
+      Self::MpegTs => (),
+      // frag_discont keeps the source timestamps in each fragment instead of starting from 0
+      Self::Fmp4 => options.set("movflags", "frag_keyframe+empty_moov+default_base_moof+frag_discont"),
+      Self::Progressive => options.set("movflags", "frag_keyframe+empty_moov+default_base_moof"),
+    }
+    options
+  }
+}
+
+/// Transcodes `duration_secs` of `video_path` from `start_secs` on into a `format` segment with
+/// H.264 video and AAC audio, for HLS and DASH. Every segment starts on a keyframe and keeps the
+/// source timestamps so segments transcoded separately play back to back. Rotation metadata isn't kept
+pub fn transcode_segment(
+  video_path: &Path,
+  start_secs: u32,
+  duration_secs: u32,
+  format: SegmentFormat,
+) -> Result<Vec<u8>, VideoError> {
+  let output_path = segment_file(format)?;
+  // Removed however transcoding ends
+  let _output_file = TempFile(output_path.clone());
+  transcode(video_path, &output_path, start_secs, Some(duration_secs), format, HLS_MAX_HEIGHT, &|| false)?;
+  std::fs::read(&output_path).map_err(|err| (f!("Could not read segment {output_path:?}"), err).into())
+}
+
+/// Transcodes the whole of `video_path` to `output_path` as a progressive MP4 with H.264 video at
+/// most `max_height` pixels tall and AAC audio. The file can be played while it's being written,
+/// transcoding stops with an error once `cancelled` returns true
+pub fn transcode_file(
+  video_path: &Path,
+  output_path: &Path,
+  max_height: u32,
+  cancelled: &dyn Fn() -> bool,
+) -> Result<(), VideoError> {
+  transcode(video_path, output_path, 0, None, SegmentFormat::Progressive, max_height, cancelled)
+}
+
+/// Whether browsers can play `video_path` once its streams are copied to MP4 as they are:
+/// 8-bit 4:2:0 H.264 video no taller than `max_height`, and AAC or MP3 audio if there's any
+pub fn can_remux(video_path: &Path, max_height: u32) -> Result<bool, VideoError> {
+  let av_format_ctx = open_input(video_path)?;
+  let Some(video_stream) = av_format_ctx
+  .streams()
+  .best(Type::Video)
+  .filter(|stream| !stream.disposition().contains(format::stream::Disposition::ATTACHED_PIC))
+  else {
+    return Ok(false)
+  };
+  if video_stream.parameters().id() != ffmpeg::codec::Id::H264 {
+    return Ok(false)
+  }
+  let video_decoder = CodecCtx::from_parameters(video_stream.parameters())?.decoder().video()?;
+  if video_decoder.format() != format::Pixel::YUV420P || video_decoder.height() > max_height {
+    return Ok(false)
+  }
+  Ok(av_format_ctx.streams().best(Type::Audio).is_none_or(|stream| {
+    matches!(stream.parameters().id(), ffmpeg::codec::Id::AAC | ffmpeg::codec::Id::MP3)
+  }))
+}
+
+/// Copies the video and audio of `video_path` to `output_path` as a progressive MP4 without
+/// encoding them again, far faster than `transcode_file` for files `can_remux` accepts.
+/// Rotation metadata isn't kept. Stops with an error once `cancelled` returns true
+pub fn remux_file(video_path: &Path, output_path: &Path, cancelled: &dyn Fn() -> bool) -> Result<(), VideoError> {
+  let mut av_format_ctx = open_input(video_path)?;
+  let mut output = format::output_as(output_path, SegmentFormat::Progressive.muxer())?;
+
+  // Input index and time base of each stream copied, in the order they're added to the output
+  let mut copied = Vec::with_capacity(2);
+  for kind in [Type::Video, Type::Audio] {
+    let Some(stream) = av_format_ctx
+    .streams()
+    .best(kind)
+    .filter(|stream| !stream.disposition().contains(format::stream::Disposition::ATTACHED_PIC))
+    else {
+      continue
+    };
+    let mut out_stream = output.add_stream(ffmpeg::encoder::find(ffmpeg::codec::Id::None))?;
+    out_stream.set_parameters(stream.parameters());
+    // Tags are container specific, e.g. Matroska's don't mean anything in MP4
+    unsafe {
+      (*out_stream.parameters().as_mut_ptr()).codec_tag = 0;
+    }
+    copied.push((stream.index(), stream.time_base()));
+  }
+  if copied.is_empty() {
+    return Err(ffmpeg::Error::StreamNotFound.into())
+  }
+
+  output.write_header_with(SegmentFormat::Progressive.options())?;
+  let out_time_bases: Vec<ffmpeg::Rational> = output.streams().map(|stream| stream.time_base()).collect();
+  for (stream_index, mut packet) in read_packets(&mut av_format_ctx) {
+    if cancelled() {
+      return Err(("Remux was cancelled", ffmpeg::Error::Exit).into())
+    }
+    let Some(out_index) = copied.iter().position(|&(index, _)| index == stream_index) else { continue };
+    packet.rescale_ts(copied[out_index].1, out_time_bases[out_index]);
+    packet.set_position(-1);
+    packet.set_stream(out_index);
+    packet.write_interleaved(&mut output)?;
+  }
+  output.write_trailer()?;
+  Ok(())
+}
+
+/// Transcodes `video_path` from `start_secs` on to `output_path`, until the end of the file
+/// without `duration_secs` or until `cancelled` returns true
+fn transcode(
+  video_path: &Path,
+  output_path: &Path,
+  start_secs: u32,
+  duration_secs: Option<u32>,
+  format: SegmentFormat,
+  max_height: u32,
+  cancelled: &dyn Fn() -> bool,
+) -> Result<(), VideoError> {
+  let mut av_format_ctx = open_input(video_path)?;
+  let _memory = FrameMemory::reserve(estimate_frame_bytes(&av_format_ctx, 0) * 2)?;
+  seek_seconds(&mut av_format_ctx, start_secs)?;
+
+  let video_stream = av_format_ctx
+  .streams()
+  .best(Type::Video)
+  .filter(|stream| !stream.disposition().contains(format::stream::Disposition::ATTACHED_PIC))
+  .ok_or(ffmpeg::Error::StreamNotFound)?;
+  let video_index = video_stream.index();
+  let video_time_base = video_stream.time_base();
+  let frame_rate = video_stream.avg_frame_rate();
+  let mut video_decoder = CodecCtx::from_parameters(video_stream.parameters())?.decoder().video()?;
+  let video_range = segment_range(&video_stream, start_secs, duration_secs);
+  // Audio may end before the video or lag behind it a little, it's not waited for past this
+  let read_until = video_range.end.saturating_add((1. / f64::from(video_time_base)) as i64);
+
+  let audio = match av_format_ctx.streams().best(Type::Audio) {
+    Some(stream) => {
+      let decoder = CodecCtx::from_parameters(stream.parameters())?.decoder().audio()?;
+      Some((stream.index(), stream.time_base(), segment_range(&stream, start_secs, duration_secs), decoder))
+    }
+    None => None,
+  };
+
+  let mut output = format::output_as(output_path, format.muxer())?;
+  // MP4 keeps codec headers in the container instead of in the stream
+  let global_header = output.format().flags().contains(format::Flags::GLOBAL_HEADER);
+
+  let (codec, pixel_format, options) = h264_encoder()?;
+  let (width, height) = segment_size(video_decoder.width(), video_decoder.height(), max_height);
+  let mut video_encoder = CodecCtx::new().encoder().video()?;
+  video_encoder.set_width(width);
+  video_encoder.set_height(height);
+  video_encoder.set_format(pixel_format);
+  video_encoder.set_time_base(video_time_base);
+  if global_header {
+    video_encoder.set_flags(ffmpeg::codec::Flags::GLOBAL_HEADER);
+  }
+  if frame_rate.numerator() > 0 {
+    video_encoder.set_frame_rate(Some(frame_rate));
+  }
+  let mut video_encoder = video_encoder.open_as_with(codec, options)?;
+  output.add_stream(codec)?.set_parameters(&video_encoder);
+  let mut scaler = ScalingCtx::get(
+    video_decoder.format(),
+    video_decoder.width(),
+    video_decoder.height(),
+    pixel_format,
+    width,
+    height,
+    Flags::BILINEAR,
+  )?;
+
+  let mut audio = match audio {
+    Some((index, time_base, range, decoder)) => {
+      let codec = ffmpeg::encoder::find(ffmpeg::codec::Id::AAC).ok_or(ffmpeg::Error::EncoderNotFound)?;
+      let mut encoder = CodecCtx::new().encoder().audio()?;
+      encoder.set_rate(HLS_AUDIO_RATE as i32);
+      encoder.set_channel_layout(ChannelLayout::STEREO);
+      encoder.set_channels(2);
+      encoder.set_format(HLS_AUDIO_FORMAT);
+      encoder.set_bit_rate(HLS_AUDIO_BIT_RATE);
+      encoder.set_time_base((1, HLS_AUDIO_RATE as i32));
+      if global_header {
+        encoder.set_flags(ffmpeg::codec::Flags::GLOBAL_HEADER);
+      }
+      let encoder = encoder.open_as(codec)?;
+      output.add_stream(codec)?.set_parameters(&encoder);
+      let layout = match decoder.channel_layout() {
+        layout if layout.is_empty() => ChannelLayout::default(decoder.channels() as i32),
+        layout => layout,
+      };
+      let resampler = ResamplingCtx::get(
+        decoder.format(),
+        layout,
+        decoder.rate(),
+        HLS_AUDIO_FORMAT,
+        ChannelLayout::STEREO,
+        HLS_AUDIO_RATE,
+      )?;
+      Some(SegmentAudio {
+        index,
+        time_base,
+        range,
+        decoder,
+        resampler,
+        encoder,
+        samples: [Vec::new(), Vec::new()],
+        next_pts: None,
+        done: false,
+      })
+    }
+    None => None,
+  };
+
+  output.write_header_with(format.options())?;
+  let video_out = (0, output.stream(0).ok_or(ffmpeg::Error::StreamNotFound)?.time_base());
+  let audio_out = output.stream(1).map(|stream| (1, stream.time_base()));
+
+  let mut decoded = VideoFrame::empty();
+  let mut video_done = false;
+  let mut encoded_frames = 0;
+  let mut encode_video = |decoder: &mut decoder::Video, output: &mut format::context::Output| -> Result<bool, ffmpeg::Error> {
+    while decoder.receive_frame(&mut decoded).is_ok() {
+      let Some(timestamp) = decoded.timestamp() else { continue };
+      if timestamp < video_range.start {
+        continue
+      }
+      if timestamp >= video_range.end {
+        return Ok(true)
+      }
+      let mut scaled = VideoFrame::empty();
+      scaler.run(&decoded, &mut scaled)?;
+      scaled.set_pts(Some(timestamp));
+      video_encoder.send_frame(&scaled)?;
+      write_packets(&mut video_encoder, video_time_base, video_out, output)?;
+      encoded_frames += 1;
+    }
+    Ok(false)
+  };
+
+  for (stream_index, packet) in read_packets(&mut av_format_ctx) {
+    if cancelled() {
+      return Err(("Transcode was cancelled", ffmpeg::Error::Exit).into())
+    }
+    if stream_index == video_index && !video_done {
+      if let Err(err) = video_decoder.send_packet(&packet) {
+        if err != FFMPEG_RETRY_ERR {
+          return Err(("Error sending packet", err).into())
+        }
+      }
+      video_done = encode_video(&mut video_decoder, &mut output)?;
+    } else if let (Some(audio), Some(audio_out)) = (audio.as_mut(), audio_out) {
+      if stream_index == audio.index && !audio.done {
+        if let Err(err) = audio.decoder.send_packet(&packet) {
+          if err != FFMPEG_RETRY_ERR {
+            return Err(("Error sending packet", err).into())
+          }
+        }
+        audio.encode(audio_out, &mut output)?;
+      }
+    }
+    if video_done && audio.as_ref().is_none_or(|audio| audio.done) {
+      break
+    }
+    if video_done && stream_index == video_index && packet.pts().is_some_and(|pts| pts > read_until) {
+      break
+    }
+  }
+  if !video_done {
+    // Reached the end of the file, frames held back by the decoder still belong to the segment
+    video_decoder.send_eof()?;
+    encode_video(&mut video_decoder, &mut output)?;
+  }
+  if encoded_frames == 0 {
+    return Err(("Transcode has no frames", ffmpeg::Error::Eof).into())
+  }
+
+  video_encoder.send_eof()?;
+  write_packets(&mut video_encoder, video_time_base, video_out, &mut output)?;
+  if let (Some(audio), Some(audio_out)) = (audio.as_mut(), audio_out) {
+    audio.finish(audio_out, &mut output)?;
+  }
+  output.write_trailer()?;
+  Ok(())
+}
+
+/// Audio side of a segment being transcoded, resampled to stereo and re-chunked into the
+/// frame size AAC expects
+struct SegmentAudio {
+  index: usize,
+  time_base: ffmpeg::Rational,
+  range: std::ops::Range<i64>,
+  decoder: decoder::Audio,
+  resampler: ResamplingCtx,
+  encoder: ffmpeg::encoder::audio::Encoder,
+  /// Resampled samples of each channel waiting for a full frame
+  samples: [Vec<f32>; 2],
+  /// Timestamp of the next encoded frame in samples, set by the first frame in range
+  next_pts: Option<i64>,
+  done: bool,
+}
+
+impl SegmentAudio {
+  fn encode(&mut self, out: (usize, ffmpeg::Rational), output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+    let mut decoded = AudioFrame::empty();
+    while !self.done && self.decoder.receive_frame(&mut decoded).is_ok() {
+      let Some(timestamp) = decoded.timestamp() else { continue };
+      if timestamp < self.range.start {
+        continue
+      }
+      if timestamp >= self.range.end {
+        self.done = true;
+        break
+      }
+      if self.next_pts.is_none() {
+        let seconds = timestamp as f64 * f64::from(self.time_base);
+        self.next_pts = Some((seconds * HLS_AUDIO_RATE as f64) as i64);
+      }
+      let mut resampled = AudioFrame::empty();
+      self.resampler.run(&decoded, &mut resampled)?;
+      for (channel, samples) in self.samples.iter_mut().enumerate() {
+        samples.extend_from_slice(&resampled.plane::<f32>(channel)[..resampled.samples()]);
+      }
+      self.send_frames(false, out, output)?;
+    }
+    Ok(())
+  }
+
+  fn finish(&mut self, out: (usize, ffmpeg::Rational), output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+    self.send_frames(true, out, output)?;
+    self.encoder.send_eof()?;
+    write_packets(&mut self.encoder, (1, HLS_AUDIO_RATE as i32).into(), out, output)
+  }
+
+  /// Encodes every full frame of buffered samples, and the last partial one with `last`
+  fn send_frames(&mut self, last: bool, out: (usize, ffmpeg::Rational), output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+    let frame_size = match self.encoder.frame_size() {
+      0 => 1024,
+      size => size as usize,
+    };
+    while self.samples[0].len() >= frame_size || (last && !self.samples[0].is_empty()) {
+      let len = self.samples[0].len().min(frame_size);
+      let mut frame = AudioFrame::new(HLS_AUDIO_FORMAT, len, ChannelLayout::STEREO);
+      for (channel, samples) in self.samples.iter_mut().enumerate() {
+        frame.plane_mut::<f32>(channel)[..len].copy_from_slice(&samples[..len]);
+        samples.drain(..len);
+      }
+      let pts = self.next_pts.unwrap_or_default();
+      frame.set_rate(HLS_AUDIO_RATE);
+      frame.set_pts(Some(pts));
+      self.next_pts = Some(pts + len as i64);
+      self.encoder.send_frame(&frame)?;
+      write_packets(&mut self.encoder, (1, HLS_AUDIO_RATE as i32).into(), out, output)?;
+    }
+    Ok(())
+  }
+}
+
+/// Writes the packets `encoder` has ready to the output stream `out`
+fn write_packets(
+  encoder: &mut ffmpeg::encoder::Encoder,
+  time_base: ffmpeg::Rational,
+  (index, out_time_base): (usize, ffmpeg::Rational),
+  output: &mut format::context::Output,
+) -> Result<(), ffmpeg::Error> {
+  let mut packet = ffmpeg::Packet::empty();
+  while encoder.receive_packet(&mut packet).is_ok() {
+    packet.set_stream(index);
+    packet.rescale_ts(time_base, out_time_base);
+    packet.write_interleaved(output)?;
+  }
+  Ok(())
+}
+
+/// Timestamps of `stream` from `start_secs` to `duration_secs` later, or to the end without it
+fn segment_range(stream: &ffmpeg::Stream, start_secs: u32, duration_secs: Option<u32>) -> std::ops::Range<i64> {
+  let time_base = f64::from(stream.time_base());
+  // Unknown start times are AV_NOPTS_VALUE, a huge negative number
+  let offset = stream.start_time().max(0);
+  let to_timestamp = |seconds: u32| offset + (seconds as f64 / time_base) as i64;
+  let end = duration_secs.map_or(i64::MAX, |duration_secs| to_timestamp(start_secs + duration_secs));
+  to_timestamp(start_secs)..end
+}
+
+/// Size of transcoded video, scaled down to `max_height` and rounded to even numbers for 4:2:0
+fn segment_size(width: u32, height: u32, max_height: u32) -> (u32, u32) {
+  let (width, height) = if height > max_height {
+    (width * max_height / height, max_height)
+  } else {
+    (width, height)
+  };
+  ((width & !1).max(2), (height & !1).max(2))
+}
+
+/// First H.264 encoder that works here along with the pixel format and options to use it with
+fn h264_encoder() -> Result<(ffmpeg::Codec, format::Pixel, ffmpeg::Dictionary<'static>), ffmpeg::Error> {
+  let name = H264_ENCODERS
+  .into_iter()
+  .find(|name| capabilities::is_available(name))
+  .ok_or(ffmpeg::Error::EncoderNotFound)?;
+  let codec = ffmpeg::encoder::find_by_name(name).ok_or(ffmpeg::Error::EncoderNotFound)?;
+  let pixel_format = capabilities::software_format(codec).ok_or(ffmpeg::Error::EncoderNotFound)?;
+  let mut options = ffmpeg::Dictionary::new();
+  if name == "libx264" {
+    options.set("preset", "veryfast");
+  }
+  Ok((codec, pixel_format, options))
+}
+
+/// New file in the data folder for a segment being written
+fn segment_file(format: SegmentFormat) -> Result<PathBuf, VideoError> {
+  static SEGMENT_FILES: AtomicUsize = AtomicUsize::new(0);
+  let folder = Path::new(&config::get().data_folder).join("segments");
+  std::fs::create_dir_all(&folder).map_err(|err| VideoError::from((f!("Could not create {folder:?}"), err)))?;
+  let id = SEGMENT_FILES.fetch_add(1, Ordering::Relaxed);
+  Ok(folder.join(f!("{}-{id}.{}", std::process::id(), format.muxer())))
+}
+
+/// Removes the file when dropped
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+  fn drop(&mut self) {
+    let _ = std::fs::remove_file(&self.0);
+  }
+}
+
+/// Lists the embedded subtitle streams without decoding any of them
+pub fn get_subtitle_tracks(video_path: &Path) -> Result<Vec<SubtitleTrack>, VideoError> {
+  let av_format_ctx = open_input(video_path)?;
+  Ok(
+    av_format_ctx
+    .streams()
+    .filter(|stream| stream.parameters().medium() == Type::Subtitle)
+    .map(|stream| {
+      let codec = stream.parameters().id();
+      let metadata = stream.metadata();
+      let disposition = stream.disposition();
+      SubtitleTrack {
+        index: stream.index(),
+        language: metadata.get("language").map(String::from),
+        codec: codec.name().to_string(),
+        title: metadata.get("title").map(String::from),
+        bitmap: matches!(
+          codec,
+          ffmpeg::codec::Id::HDMV_PGS_SUBTITLE
+          | ffmpeg::codec::Id::DVD_SUBTITLE
+          | ffmpeg::codec::Id::DVB_SUBTITLE
+          | ffmpeg::codec::Id::XSUB
+        ),
+        default: disposition.contains(format::stream::Disposition::DEFAULT),
+        forced: disposition.contains(format::stream::Disposition::FORCED),
+      }
+    })
+    .collect()
+  )
+}
+
+/// Decodes every embedded text subtitle stream and returns the cues containing `query`
+/// (case insensitive). Bitmap subtitles can't be searched and are skipped
+pub fn search_subtitles(video_path: &Path, query: &str) -> Result<Vec<SubtitleCue>, VideoError> {
+  let mut av_format_ctx = open_input(video_path)?;
+
+  let mut decoders = HashMap::new();
+  for stream in av_format_ctx.streams() {
+    if stream.parameters().medium() != Type::Subtitle {
+      continue
+    }
+    let decoder = CodecCtx::from_parameters(stream.parameters())?
+    .decoder()
+    .subtitle()?;
+    let language = stream.metadata().get("language").map(String::from);
+    decoders.insert(stream.index(), (decoder, stream.time_base(), language));
+  }
+
+  let query = query.to_lowercase();
+  let mut cues = Vec::new();
+  if decoders.is_empty() {
+    return Ok(cues)
+  }
+
+  for (stream_index, packet) in read_packets(&mut av_format_ctx) {
+    let (decoder, time_base, language) = match decoders.get_mut(&stream_index) {
+      Some(decoder) => decoder,
+      None => continue,
+    };
+    let mut subtitle = ffmpeg::Subtitle::new();
+    // Corrupt packets are skipped rather than failing the whole search
+    if !decoder.decode(&packet, &mut subtitle).unwrap_or(false) {
+      continue
+    }
+
+    let start_ms = packet.pts().unwrap_or_default().rescale(*time_base, (1, 1000));
+    let end_ms = start_ms + packet.duration().rescale(*time_base, (1, 1000));
+    for rect in subtitle.rects() {
+      let text = match rect {
+        Rect::Text(text) => text.get().to_string(),
+        // ASS rects are dialogue lines without the "Dialogue:" prefix and timestamps,
+        // text comes after the first 8 fields
+        Rect::Ass(ass) => subtitle::ass_text(ass.get().splitn(9, ',').last().unwrap_or_default()),
+        _ => continue,
+      };
+      if text.to_lowercase().contains(&query) {
+        cues.push(SubtitleCue {
+          stream: stream_index,
+          language: language.clone(),
+          start_ms,
+          end_ms,
+          text,
+        });
+      }
+    }
+  }
+
+  Ok(cues)
+}
+
+/// Returns the container metadata tags (title, creation_time, location...)
+pub fn get_tags(video_path: &Path) -> Result<HashMap<String, String>, VideoError> {
+  let av_format_ctx = open_input(video_path)?;
+  Ok(
+    av_format_ctx
+    .metadata()
+    .iter()
+    .map(|(key, value)| (key.to_string(), value.to_string()))
+    .collect()
+  )
+}
+
+/// Same as `get_tags` along with the tags of the best audio stream the container lacks, Ogg
+/// files keep them there
+pub fn get_audio_tags(audio_path: &Path) -> Result<HashMap<String, String>, VideoError> {
+  let av_format_ctx = open_input(audio_path)?;
+  let mut tags: HashMap<String, String> = av_format_ctx
+  .metadata()
+  .iter()
+  .map(|(key, value)| (key.to_string(), value.to_string()))
+  .collect();
+  if let Some(stream) = av_format_ctx.streams().best(Type::Audio) {
+    for (key, value) in stream.metadata().iter() {
+      tags.entry(key.to_string()).or_insert_with(|| value.to_string());
+    }
+  }
+  Ok(tags)
+}
+
+/// Duration and display size of a video
+#[derive(Debug, Serialize)]
+pub struct VideoInfo {
+  pub duration_ms: i64,
+  pub width: u32,
+  pub height: u32,
+  /// Metadata that couldn't be trusted while reading the rest
+  #[serde(skip)]
+  pub warnings: Vec<Warning>,
+}
+
+/// Reads the duration and display size (rotation applied) without decoding any frame
+pub fn get_info(video_path: &Path) -> Result<VideoInfo, VideoError> {
+  let av_format_ctx = open_input(video_path)?;
+  let video_stream = av_format_ctx
+  .streams()
+  .best(Type::Video)
+  .ok_or(ffmpeg::Error::StreamNotFound)?;
+  let decoder = CodecCtx::from_parameters(video_stream.parameters())?
+  .decoder()
+  .video()?;
+
+  let mut warnings: Vec<Warning> = duration_warning(&av_format_ctx).into_iter().collect();
+  let rotation = match get_rotation(&video_stream) {
+    Ok(rotation) => rotation,
+    Err(err) => {
+      warnings.push(Warning::new("rotation_invalid", f!("{err}, served unrotated")));
+      0
+    }
+  };
+  let (width, height) = if rotation.abs() % 180 == 90 {
+    (decoder.height(), decoder.width())
+  } else {
+    (decoder.width(), decoder.height())
+  };
+
+  Ok(VideoInfo { duration_ms: get_duration(&av_format_ctx), width, height, warnings })
+}
+
+/// Rotation in degrees, 0 when the stream has no display matrix
+fn get_rotation(stream: &ffmpeg::Stream) -> Result<i32, String> {
+  let has_matrix = stream.side_data().any(|tag| tag.kind() == side_data::Type::DisplayMatrix);
+  if !has_matrix {
+    return Ok(0)
+  }
+  let transform = get_display_matrix_values(stream)?;
+  math::av_display_rotation_get(&transform)
+  .map(|rotation| rotation as i32)
+  .ok_or_else(|| "Display matrix has no rotation".into())
+}
+
+/// Durations that are missing or only guessed from the bitrate, which is usual for
+/// MPEG-TS and raw streams and can be off by minutes
+fn duration_warning(av_format_ctx: &AVFormatContext) -> Option<Warning> {
+  use ffmpeg::ffi::AVDurationEstimationMethod;
+
+  if av_format_ctx.duration() < 0 {
+    return Some(Warning::new("duration_unknown", "Duration is unknown"))
+  }
+  let method = unsafe { (*av_format_ctx.as_ptr()).duration_estimation_method };
+  (method == AVDurationEstimationMethod::AVFMT_DURATION_FROM_BITRATE).then(|| {
+    Warning::new("duration_estimated", "Duration was estimated from the bitrate")
+  })
+}
+
+/// Codec names of the main video and audio streams, as FFmpeg calls them (`h264`, `aac`...)
+#[derive(Debug, Default)]
+pub struct StreamCodecs {
+  pub video: Option<String>,
+  pub audio: Option<String>,
+}
+
+pub fn get_stream_codecs(video_path: &Path) -> Result<StreamCodecs, VideoError> {
+  let av_format_ctx = open_input(video_path)?;
+  let codec_name = |kind: Type| {
+    av_format_ctx
+    .streams()
+    .best(kind)
+    // Cover art of audio files shows up as a single frame video stream
+    .filter(|stream| !stream.disposition().contains(format::stream::Disposition::ATTACHED_PIC))
+    .map(|stream| stream.parameters().id().name().to_string())
   };
+  Ok(StreamCodecs { video: codec_name(Type::Video), audio: codec_name(Type::Audio) })
+}
+
+pub fn get_duration_from_path(video_path: &Path) -> Result<i64, VideoError> {
+  let av_format_ctx = open_input(video_path)?;
 
   Ok(get_duration(&av_format_ctx))
 }
 
+/// Like `get_duration_from_path` along with a warning when the duration can't be trusted
+pub fn get_checked_duration(video_path: &Path) -> Result<(i64, Option<Warning>), VideoError> {
+  let av_format_ctx = open_input(video_path)?;
+
+  Ok((get_duration(&av_format_ctx), duration_warning(&av_format_ctx)))
+}
+
 pub fn get_duration(av_format_ctx: &AVFormatContext) -> i64 {
   let time_base = ffmpeg::rescale::TIME_BASE.0 as f32 / ffmpeg::rescale::TIME_BASE.1 as f32;
-  (av_format_ctx.duration() as f32 * time_base * 1000.) as i64
+  // Unknown durations are AV_NOPTS_VALUE, a huge negative number
+  (av_format_ctx.duration().max(0) as f32 * time_base * 1000.) as i64
 }
 
-#[derive(Debug)]
+/// Fills the space around frames that don't fill the requested height
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Letterbox {
+  pub height: u32,
+  /// RGBA
+  pub color: [u8; 4],
+}
+
+impl Letterbox {
+  /// Returns a `width` x `self.height` frame with `frame` centered in it,
+  /// cropping whatever doesn't fit
+  fn apply(&self, frame: &VideoFrame, width: u32) -> VideoFrame {
+    let _timer = perf::time(perf::Stage::Compose);
+    let mut out_frame = VideoFrame::new(format::Pixel::RGBA, width, self.height);
+    let stride = out_frame.stride(0);
+    let width = width as usize;
+    let height = self.height as usize;
+    let frame_width = frame.width() as usize;
+    let copy_width = frame_width.min(width);
+    let copy_height = (frame.height() as usize).min(height);
+    let x_offset = (width - copy_width) / 2;
+    let y_offset = (height - copy_height) / 2;
+
+    let out_data = out_frame.data_mut(0);
+    for row in out_data.chunks_exact_mut(stride).take(height) {
+      for pixel in row[..width * 4].chunks_exact_mut(4) {
+        pixel.copy_from_slice(&self.color);
+      }
+    }
+    let frame_data = frame.data(0);
+    for y in 0..copy_height {
+      let src = y * frame_width * 4;
+      let dst = (y + y_offset) * stride + x_offset * 4;
+      out_data[dst..dst + copy_width * 4].copy_from_slice(&frame_data[src..src + copy_width * 4]);
+    }
+    // Rows were written at the frame's stride, the encoder wants them packed
+    fix_img_data(&mut out_frame);
+    out_frame
+  }
+}
+
+/// Parses `rrggbb` or `rrggbbaa` hex colors with an optional `#`,
+/// or one of `black`, `white` and `transparent`
+pub fn parse_color(color: &str) -> Option<[u8; 4]> {
+  match color {
+    "black" => return Some([0, 0, 0, 255]),
+    "white" => return Some([255, 255, 255, 255]),
+    "transparent" => return Some([0, 0, 0, 0]),
+    _ => {}
+  }
+  let hex = color.strip_prefix('#').unwrap_or(color);
+  if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+    return None
+  }
+  let mut rgba = [255; 4];
+  for (channel, value) in rgba.iter_mut().zip(hex.as_bytes().chunks(2)) {
+    *channel = u8::from_str_radix(std::str::from_utf8(value).ok()?, 16).ok()?;
+  }
+  Some(rgba)
+}
+
+/// 3x5 glyphs for `0` to `9` and `:`, a row per byte with the leftmost pixel in the third bit
+const GLYPHS: [[u8; 5]; 11] = [
+  [0b111, 0b101, 0b101, 0b101, 0b111],
+  [0b010, 0b110, 0b010, 0b010, 0b111],
+  [0b111, 0b001, 0b111, 0b100, 0b111],
+  [0b111, 0b001, 0b111, 0b001, 0b111],
+  [0b101, 0b101, 0b111, 0b001, 0b001],
+  [0b111, 0b100, 0b111, 0b001, 0b111],
+  [0b111, 0b100, 0b111, 0b101, 0b111],
+  [0b111, 0b001, 0b010, 0b010, 0b010],
+  [0b111, 0b101, 0b111, 0b101, 0b111],
+  [0b111, 0b101, 0b111, 0b001, 0b111],
+  [0b000, 0b010, 0b000, 0b010, 0b000],
+];
+/// 3x5 glyphs for `A` to `Z`, same layout as `GLYPHS`
+const LETTERS: [[u8; 5]; 26] = [
+  [0b010, 0b101, 0b111, 0b101, 0b101],
+  [0b110, 0b101, 0b110, 0b101, 0b110],
+  [0b011, 0b100, 0b100, 0b100, 0b011],
+  [0b110, 0b101, 0b101, 0b101, 0b110],
+  [0b111, 0b100, 0b110, 0b100, 0b111],
+  [0b111, 0b100, 0b110, 0b100, 0b100],
+  [0b011, 0b100, 0b101, 0b101, 0b011],
+  [0b101, 0b101, 0b111, 0b101, 0b101],
+  [0b111, 0b010, 0b010, 0b010, 0b111],
+  [0b001, 0b001, 0b001, 0b101, 0b010],
+  [0b101, 0b101, 0b110, 0b101, 0b101],
+  [0b100, 0b100, 0b100, 0b100, 0b111],
+  [0b101, 0b111, 0b111, 0b101, 0b101],
+  [0b110, 0b101, 0b101, 0b101, 0b101],
+  [0b010, 0b101, 0b101, 0b101, 0b010],
+  [0b110, 0b101, 0b110, 0b100, 0b100],
+  [0b010, 0b101, 0b101, 0b110, 0b011],
+  [0b110, 0b101, 0b110, 0b101, 0b101],
+  [0b011, 0b100, 0b010, 0b001, 0b110],
+  [0b111, 0b010, 0b010, 0b010, 0b010],
+  [0b101, 0b101, 0b101, 0b101, 0b011],
+  [0b101, 0b101, 0b101, 0b010, 0b010],
+  [0b101, 0b101, 0b111, 0b111, 0b101],
+  [0b101, 0b101, 0b010, 0b101, 0b101],
+  [0b101, 0b101, 0b010, 0b010, 0b010],
+  [0b111, 0b001, 0b010, 0b100, 0b111],
+];
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+
+/// Glyph of an ASCII character, lowercase is drawn as uppercase and anything unknown as `?`
+fn glyph(c: u8) -> [u8; 5] {
+  match c.to_ascii_uppercase() {
+    c @ b'0'..=b':' => GLYPHS[(c - b'0') as usize],
+    c @ b'A'..=b'Z' => LETTERS[(c - b'A') as usize],
+    b' ' => [0; 5],
+    b'.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+    b'-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+    b'_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+    b'/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+    _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+  }
+}
+
+/// Draws `seconds` as HH:MM:SS in white over a darkened box whose bottom left corner is at
+/// `x`, `bottom` of an RGBA frame with packed rows, text past the right edge is cropped
+fn burn_timestamp(frame: &mut VideoFrame, x: usize, bottom: usize, seconds: u32) {
+  let text = f!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60);
+  burn_text(frame, x, bottom, &text, 1);
+}
+
+/// Draws `text` like `burn_timestamp` with every glyph pixel made `scale` pixels wide
+fn burn_text(frame: &mut VideoFrame, x: usize, bottom: usize, text: &str, scale: usize) {
+  let scale = scale.max(1);
+  let frame_width = frame.width() as usize;
+  let box_width = (text.len() * (GLYPH_WIDTH + 1) + 1) * scale;
+  let box_height = (GLYPH_HEIGHT + 2) * scale;
+  let right = std::cmp::min(x + box_width, frame_width);
+  let bottom = std::cmp::min(bottom, frame.height() as usize);
+  let top = bottom.saturating_sub(box_height);
+  let data = frame.data_mut(0);
+
+  for y in top..bottom {
+    for px in x..right {
+      let di = (px + y * frame_width) * 4;
+      for color_i in 0..3 {
+        data[di + color_i] /= 3;
+      }
+      data[di + 3] = 255;
+    }
+  }
+  for (char_i, glyph) in text.bytes().map(glyph).enumerate() {
+    let glyph_x = x + (1 + char_i * (GLYPH_WIDTH + 1)) * scale;
+    for (row, bits) in glyph.iter().enumerate() {
+      for column in 0..GLYPH_WIDTH {
+        if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+          continue
+        }
+        for py in (top + (1 + row) * scale..).take(scale).filter(|&py| py < bottom) {
+          for px in (glyph_x + column * scale..).take(scale).filter(|&px| px < right) {
+            let di = (px + py * frame_width) * 4;
+            data[di..di + 4].fill(255);
+          }
+        }
+      }
+    }
+  }
+}
+
+/// RGBA image blended over generated frames
+pub struct Watermark {
+  width: usize,
+  height: usize,
+  data: Vec<u8>,
+}
+
+impl Watermark {
+  pub fn from_webp(bytes: &[u8]) -> Option<Self> {
+    let image = webp::Decoder::new(bytes).decode()?;
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    let data = if image.is_alpha() {
+      image.to_vec()
+    } else {
+      image.chunks_exact(3).flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255]).collect()
+    };
+    Some(Self { width, height, data })
+  }
+
+  /// Blends the watermark over the bottom right corner of `frame`,
+  /// anything that doesn't fit in the frame is cropped
+  pub fn apply(&self, frame: &mut VideoFrame) {
+    let frame_width = frame.width() as usize;
+    let frame_height = frame.height() as usize;
+    let width = std::cmp::min(self.width, frame_width);
+    let height = std::cmp::min(self.height, frame_height);
+    let x_offset = frame_width - width;
+    let y_offset = frame_height - height;
+    let frame_data = frame.data_mut(0);
+
+    for y in 0..height {
+      for x in 0..width {
+        let si = (x + y * self.width) * 4;
+        let di = (x_offset + x + (y_offset + y) * frame_width) * 4;
+        let alpha = self.data[si + 3] as u32;
+        for color_i in 0..3 {
+          let src = self.data[si + color_i] as u32;
+          let dst = frame_data[di + color_i] as u32;
+          frame_data[di + color_i] = ((src * alpha + dst * (255 - alpha)) / 255) as u8;
+        }
+      }
+    }
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubtitleTrack {
+  /// Stream index, the same `stream` subtitle search cues carry
+  index: usize,
+  language: Option<String>,
+  codec: String,
+  title: Option<String>,
+  /// Pictures rather than text, these can't be searched
+  bitmap: bool,
+  default: bool,
+  forced: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubtitleCue {
+  stream: usize,
+  language: Option<String>,
+  start_ms: i64,
+  end_ms: i64,
+  text: String,
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum SeekTime {
   Seconds(u32),
   Percentage(f32),
 }
 
+impl From<f32> for SeekTime {
+  /// Seeks below 1 are a fraction of the duration, seconds otherwise
+  fn from(seek: f32) -> Self {
+    if seek < 1. {
+      Self::Percentage(seek)
+    } else {
+      Self::Seconds(seek as u32)
+    }
+  }
+}
+
 impl Into<u32> for SeekTime {
   fn into(self) -> u32 {
     use SeekTime::*;
@@ -423,9 +2991,17 @@ impl Into<u32> for SeekTime {
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct VideoError {
   message: String,
+  /// Too many frames are being decoded already, the request can be tried again later
+  over_budget: bool,
+}
+
+impl VideoError {
+  pub fn is_over_budget(&self) -> bool {
+    self.over_budget
+  }
 }
 
 impl Display for VideoError {
@@ -436,12 +3012,12 @@ impl Display for VideoError {
 
 impl<T: Display, E: Debug> From<(T, E)> for VideoError {
   fn from((message, err): (T, E)) -> Self {
-    Self { message: f!("Video Error: {message}\n\n{err:?}") }
+    Self { message: f!("Video Error: {message}\n\n{err:?}"), over_budget: false }
   }
 }
 
 impl From<ffmpeg::Error> for VideoError {
   fn from(err: ffmpeg::Error) -> Self {
-    Self { message: f!("Video Error: {err:?}") }
+    Self { message: f!("Video Error: {err:?}"), over_budget: false }
   }
 }
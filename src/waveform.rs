@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::SystemTime;
+
+use serde::Serialize;
+
+use crate::video::{self, VideoError};
+use crate::{f, file};
+
+pub const DEFAULT_SAMPLES: usize = 800;
+pub const MAX_SAMPLES: usize = 8192;
+/// Of rendered waveforms, in pixels
+pub const DEFAULT_HEIGHT: u32 = 64;
+pub const MAX_HEIGHT: u32 = 1024;
+/// Waveforms kept in memory, the whole lot is dropped past this
+const CACHED_WAVEFORMS: usize = 64;
+
+/// Waveforms by file and sample count, with the modification time they were made for
+type Waveforms = HashMap<(PathBuf, usize), (SystemTime, Arc<Waveform>)>;
+
+static WAVEFORMS: OnceLock<Mutex<Waveforms>> = OnceLock::new();
+
+#[derive(Debug, Serialize)]
+pub struct Waveform {
+  pub duration_ms: i64,
+  /// Lowest and highest sample of each equal slice of the audio, from -1 to 1
+  pub peaks: Vec<[f32; 2]>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  Json,
+  Png,
+  Webp,
+}
+
+impl Format {
+  /// Format for the `format` query parameter, `None` if it's not one
+  pub fn parse(format: Option<&str>) -> Option<Self> {
+    match format.map(|format| format.to_lowercase()).as_deref() {
+      None | Some("json") => Some(Self::Json),
+      Some("png") => Some(Self::Png),
+      Some("webp") => Some(Self::Webp),
+      Some(_) => None,
+    }
+  }
+}
+
+/// Waveform of the audio of the file at `path` split in `samples` slices. The whole file is
+/// decoded so it's kept for as long as the file doesn't change
+pub fn get(path: &str, samples: usize) -> Result<Arc<Waveform>, VideoError> {
+  let file_path = file::get_media_path(path);
+  let modified = std::fs::metadata(&file_path)
+  .and_then(|meta| meta.modified())
+  .map_err(|err| (f!("Could not read {file_path:?}"), err))?;
+  let key = (file_path, samples);
+  if let Some((cached, waveform)) = cached_waveforms().get(&key) {
+    if *cached == modified {
+      return Ok(waveform.clone())
+    }
+  }
+
+  let (duration_ms, peaks) = video::get_waveform(Path::new(&key.0), samples)?;
+  let waveform = Arc::new(Waveform { duration_ms, peaks });
+  let mut cache = cached_waveforms();
+  if cache.len() >= CACHED_WAVEFORMS {
+    cache.clear();
+  }
+  cache.insert(key, (modified, waveform.clone()));
+  Ok(waveform)
+}
+
+/// Picture of `waveform` as `format`, a pixel column per slice and `height` pixels tall
+pub fn render(
+  waveform: &Waveform,
+  height: u32,
+  format: Format,
+  watermark: Option<&video::Watermark>,
+) -> Result<Vec<u8>, VideoError> {
+  let mut frame = video::draw_waveform(&waveform.peaks, height)?;
+  if let Some(watermark) = watermark {
+    watermark.apply(&mut frame);
+  }
+  match format {
+    Format::Png | Format::Json => video::encode_png(&frame),
+    Format::Webp => {
+      video::fix_img_data(&mut frame);
+      Ok(video::encode_webp_lossless(&frame).to_vec())
+    }
+  }
+}
+
+fn cached_waveforms() -> MutexGuard<'static, Waveforms> {
+  WAVEFORMS
+  .get_or_init(|| Mutex::new(HashMap::new()))
+  .lock()
+  .unwrap_or_else(|err| err.into_inner())
+}
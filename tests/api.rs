@@ -0,0 +1,592 @@
+mod common;
+
+use actix_web::body::to_bytes;
+use actix_web::http::{header, StatusCode};
+use actix_web::test::TestRequest;
+use actix_web::{FromRequest, HttpResponse};
+
+use fylvur::{access, cache, cast, dash, envelope, events, feed, file, hints, manifest, prefer, session, sidecar, stream, subtitle, thumbhash, trash};
+
+fn numbered_file() -> std::path::PathBuf {
+  common::init_config();
+  let path = common::temp_dir().join("numbers.bin");
+  std::fs::write(&path, (0..=255u8).collect::<Vec<_>>()).unwrap();
+  path
+}
+
+#[actix_web::test]
+async fn stream_whole_file() {
+  let path = numbered_file();
+  let req = TestRequest::default().to_http_request();
+  let response = stream::serve(&req, &path).unwrap();
+  assert_eq!(response.status(), StatusCode::OK);
+  assert_eq!(response.headers().get(header::ACCEPT_RANGES).unwrap(), "bytes");
+  let body = to_bytes(response.into_body()).await.unwrap();
+  assert_eq!(body.len(), 256);
+}
+
+#[actix_web::test]
+async fn stream_range() {
+  let path = numbered_file();
+  let req = TestRequest::default()
+  .insert_header((header::RANGE, "bytes=10-19"))
+  .to_http_request();
+  let response = stream::serve(&req, &path).unwrap();
+  assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+  assert_eq!(response.headers().get(header::CONTENT_RANGE).unwrap(), "bytes 10-19/256");
+  let body = to_bytes(response.into_body()).await.unwrap();
+  assert_eq!(&body[..], &(10..20u8).collect::<Vec<_>>()[..]);
+}
+
+#[actix_web::test]
+async fn stream_suffix_range() {
+  let path = numbered_file();
+  let req = TestRequest::default()
+  .insert_header((header::RANGE, "bytes=-6"))
+  .to_http_request();
+  let response = stream::serve(&req, &path).unwrap();
+  assert_eq!(response.headers().get(header::CONTENT_RANGE).unwrap(), "bytes 250-255/256");
+  let body = to_bytes(response.into_body()).await.unwrap();
+  assert_eq!(&body[..], &[250, 251, 252, 253, 254, 255]);
+}
+
+#[actix_web::test]
+async fn stream_unsatisfiable_range() {
+  let path = numbered_file();
+  let req = TestRequest::default()
+  .insert_header((header::RANGE, "bytes=1000-2000"))
+  .to_http_request();
+  let response = stream::serve(&req, &path).unwrap();
+  assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+  assert_eq!(response.headers().get(header::CONTENT_RANGE).unwrap(), "bytes */256");
+}
+
+#[actix_web::test]
+async fn stream_validators() {
+  // Not shared with other tests, rewriting it would change its validators
+  let path = numbered_file().with_file_name("validated.bin");
+  std::fs::write(&path, (0..=255u8).collect::<Vec<_>>()).unwrap();
+  let response = stream::serve(&TestRequest::default().to_http_request(), &path).unwrap();
+  let etag = response.headers().get(header::ETAG).unwrap().clone();
+  assert!(response.headers().contains_key(header::LAST_MODIFIED));
+
+  let req = TestRequest::default()
+  .insert_header((header::IF_NONE_MATCH, etag.clone()))
+  .to_http_request();
+  assert_eq!(stream::serve(&req, &path).unwrap().status(), StatusCode::NOT_MODIFIED);
+
+  let req = TestRequest::default()
+  .insert_header((header::RANGE, "bytes=10-19"))
+  .insert_header((header::IF_RANGE, etag))
+  .to_http_request();
+  assert_eq!(stream::serve(&req, &path).unwrap().status(), StatusCode::PARTIAL_CONTENT);
+}
+
+#[actix_web::test]
+async fn stream_changed_file_is_sent_whole() {
+  let path = numbered_file();
+  let req = TestRequest::default()
+  .insert_header((header::RANGE, "bytes=10-19"))
+  .insert_header((header::IF_RANGE, "\"an-older-version\""))
+  .to_http_request();
+  let response = stream::serve(&req, &path).unwrap();
+  assert_eq!(response.status(), StatusCode::OK);
+  assert!(!response.headers().contains_key(header::CONTENT_RANGE));
+  let body = to_bytes(response.into_body()).await.unwrap();
+  assert_eq!(body.len(), 256);
+}
+
+#[actix_web::test]
+async fn download_info_resumes_with_its_etag() {
+  let path = numbered_file().with_file_name("resumed.bin");
+  std::fs::write(&path, (0..=255u8).collect::<Vec<_>>()).unwrap();
+  let relative = file::get_relative_path(&path).unwrap();
+  let info = file::DownloadInfo::from_path(&relative, format!("http://nas:8080/file/{relative}")).unwrap();
+  let info = serde_json::to_value(info).unwrap();
+  assert_eq!(info["size"], 256);
+  let req = TestRequest::default()
+  .insert_header((header::RANGE, "bytes=100-"))
+  .insert_header((header::IF_RANGE, info["etag"].as_str().unwrap()))
+  .to_http_request();
+  let response = stream::serve(&req, &path).unwrap();
+  assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+  assert_eq!(response.headers().get(header::CONTENT_RANGE).unwrap(), "bytes 100-255/256");
+}
+
+#[actix_web::test]
+async fn detail_from_prefer_header() {
+  let req = TestRequest::default()
+  .insert_header(("Prefer", "respond-async, return=minimal"))
+  .to_http_request();
+  let detail = prefer::Detail::extract(&req).await.unwrap();
+  assert_eq!(detail, prefer::Detail::Minimal);
+}
+
+#[actix_web::test]
+async fn detail_query_wins_over_header() {
+  let req = TestRequest::with_uri("/api/file/?detail=enriched")
+  .insert_header(("Prefer", "return=minimal"))
+  .to_http_request();
+  let detail = prefer::Detail::extract(&req).await.unwrap();
+  assert_eq!(detail, prefer::Detail::Enriched);
+
+  let req = TestRequest::default().to_http_request();
+  let detail = prefer::Detail::extract(&req).await.unwrap();
+  assert_eq!(detail, prefer::Detail::Standard);
+}
+
+#[actix_web::test]
+async fn thumbnail_width_from_client_hints() {
+  let req = TestRequest::with_uri("/api/thumbnail/a.mp4?width=200&dpr=2")
+  .insert_header(("Sec-CH-DPR", "3"))
+  .to_http_request();
+  let hints = hints::ClientHints::extract(&req).await.unwrap();
+  assert_eq!(hints.thumbnail_width(Some(200)), Some(480));
+
+  let req = TestRequest::default()
+  .insert_header(("Sec-CH-Viewport-Width", "300"))
+  .insert_header(("Sec-CH-DPR", "1.5"))
+  .to_http_request();
+  let hints = hints::ClientHints::extract(&req).await.unwrap();
+  assert_eq!(hints.thumbnail_width(None), Some(480));
+
+  // Without hints the requested width is kept as is
+  let req = TestRequest::default().to_http_request();
+  let hints = hints::ClientHints::extract(&req).await.unwrap();
+  assert_eq!(hints.thumbnail_width(Some(200)), None);
+  assert_eq!(hints::bucket(10_000), 2560);
+}
+
+#[actix_web::test]
+async fn minimal_listing() {
+  let file = file::FileInfo::from_path(&numbered_file()).unwrap();
+  let response = prefer::Detail::Minimal.listing(vec![file], &common::temp_dir(), &access::Identity::default()).await;
+  assert_eq!(response.headers().get("Preference-Applied").unwrap(), "return=minimal");
+  let body = to_bytes(response.into_body()).await.unwrap();
+  let listing: serde_json::Value = serde_json::from_slice(&body).unwrap();
+  let mut keys: Vec<&str> = listing["data"][0].as_object().unwrap().keys().map(String::as_str).collect();
+  keys.sort_unstable();
+  assert_eq!(keys, ["href", "is_folder", "name"]);
+}
+
+#[test]
+fn viewport_order() {
+  common::init_config();
+  let folder = common::temp_dir().join("viewport");
+  std::fs::create_dir_all(folder.join("season")).unwrap();
+  std::fs::write(folder.join("notes.txt"), b"notes").unwrap();
+  std::fs::write(folder.join("clip.mp4"), b"not a video").unwrap();
+  let mut files: Vec<file::FileInfo> = ["notes.txt", "clip.mp4", "season"]
+  .into_iter()
+  .map(|name| file::FileInfo::from_path(&folder.join(name)).unwrap())
+  .collect();
+  file::apply_viewport_order(&mut files, &sidecar::FolderOrder::default());
+  let names: Vec<serde_json::Value> = files.iter().map(|file| serde_json::to_value(file).unwrap()["name"].clone()).collect();
+  assert_eq!(names, ["season", "clip.mp4", "notes.txt"]);
+}
+
+#[test]
+fn pinned_and_manual_order() {
+  common::init_config();
+  let folder = common::temp_dir().join("curated");
+  std::fs::create_dir_all(&folder).unwrap();
+  let names = ["a.txt", "b.txt", "c.txt", "start here.txt"];
+  for name in names {
+    std::fs::write(folder.join(name), name).unwrap();
+  }
+  let order = sidecar::FolderOrder {
+    pinned: vec!["start here.txt".to_string()],
+    order: vec!["c.txt".to_string(), "a.txt".to_string()],
+  };
+  sidecar::save_folder_order(&folder, order).unwrap();
+  let order = sidecar::load_folder_order(&folder);
+
+  let sorted = |manual: bool| {
+    let mut files: Vec<file::FileInfo> = names
+    .into_iter()
+    .map(|name| file::FileInfo::from_path(&folder.join(name)).unwrap())
+    .collect();
+    file::apply_order(&mut files, &order, manual);
+    files.iter().map(|file| serde_json::to_value(file).unwrap()["name"].clone()).collect::<Vec<_>>()
+  };
+  assert_eq!(sorted(false), ["start here.txt", "a.txt", "b.txt", "c.txt"]);
+  assert_eq!(sorted(true), ["start here.txt", "c.txt", "a.txt", "b.txt"]);
+}
+
+#[test]
+fn atomic_writes_leave_no_partial_files() {
+  let folder = common::temp_dir().join("atomic");
+  std::fs::create_dir_all(&folder).unwrap();
+  let path = folder.join("entry.webp");
+  cache::write_atomic(&path, b"first").unwrap();
+  cache::write_atomic(&path, b"second").unwrap();
+  assert_eq!(std::fs::read(&path).unwrap(), b"second");
+  assert_eq!(std::fs::read_dir(&folder).unwrap().count(), 1);
+}
+
+#[test]
+fn only_subtitles_convert_to_webvtt() {
+  let folder = common::temp_dir().join("subtitles");
+  std::fs::create_dir_all(&folder).unwrap();
+  let srt = folder.join("movie.en.srt");
+  std::fs::write(&srt, "1\r\n00:00:01,000 --> 00:00:02,000\r\nHello\r\n").unwrap();
+  assert!(subtitle::to_webvtt(&srt).unwrap().contains("00:00:01.000 --> 00:00:02.000"));
+  let secret = folder.join("secret.txt");
+  std::fs::write(&secret, "password").unwrap();
+  assert!(subtitle::to_webvtt(&secret).is_err());
+}
+
+#[test]
+fn subtitle_links_are_encoded() {
+  common::init_config();
+  let folder = common::temp_dir().join("series");
+  std::fs::create_dir_all(&folder).unwrap();
+  std::fs::write(folder.join("Movie #2.en.srt"), "1\n00:00:01,000 --> 00:00:02,000\nHello\n").unwrap();
+  let subtitles = serde_json::to_value(subtitle::find_sidecars(&folder.join("Movie #2.mkv"))).unwrap();
+  assert_eq!(subtitles[0]["language"], "en");
+  assert_eq!(subtitles[0]["href"], "/api/subtitle/series/Movie%20%232.en.srt");
+}
+
+#[test]
+fn thumbhash_matches_reference() {
+  // Busy pattern so no coefficient sits right between two steps, `alpha` makes it translucent
+  let pattern = |width: u32, height: u32, alpha: bool| -> Vec<u8> {
+    (0..height).flat_map(|y| (0..width).flat_map(move |x| [
+      ((x * 37 + y * 11) % 256) as u8,
+      ((x * x + y * 53) % 256) as u8,
+      ((x * y * 7 + 90) % 256) as u8,
+      if alpha {((x * 29 + y * 17) % 256) as u8} else {255},
+    ])).collect()
+  };
+  // Hashes from the reference JavaScript implementation
+  assert_eq!(
+    thumbhash::encode(16, 8, &pattern(16, 8, false)),
+    [158, 7, 10, 20, 132, 69, 7, 151, 102, 103, 183, 85, 167, 85, 2, 56, 247, 97, 233],
+  );
+  assert_eq!(
+    thumbhash::encode(8, 12, &pattern(8, 12, true)),
+    [225, 55, 134, 19, 6, 24, 39, 103, 72, 74, 10, 5, 150, 129, 112, 151, 71, 118, 7, 150, 102, 108, 105],
+  );
+}
+
+#[test]
+fn thumbhash_from_big_webp() {
+  let rgba = [10, 20, 30, 255].repeat(400 * 200);
+  let webp = webp::Encoder::from_rgba(&rgba, 400, 200).encode_lossless();
+  let hash = thumbhash::from_webp(&webp).unwrap();
+  assert!(hash.len().is_multiple_of(4) && hash.len() > 20);
+  assert!(thumbhash::from_webp(b"not a webp").is_none());
+}
+
+#[test]
+fn cast_urls_are_encoded() {
+  common::init_config();
+  let folder = common::temp_dir().join("cast");
+  std::fs::create_dir_all(&folder).unwrap();
+  std::fs::write(folder.join("take #1?.png"), b"not really a png").unwrap();
+  let (media, _) = cast::describe("cast/take #1?.png", "http://nas:8080").unwrap();
+  let media = serde_json::to_value(media).unwrap();
+  assert_eq!(media["contentUrl"], "http://nas:8080/file/cast/take%20%231%3F.png");
+  assert_eq!(media["metadata"]["images"][0]["url"], media["contentUrl"]);
+}
+
+#[actix_web::test]
+async fn description_needs_its_file_visible() {
+  common::init_config();
+  let folder = common::temp_dir().join("members");
+  std::fs::create_dir_all(&folder).unwrap();
+  std::fs::write(folder.join("README.md"), "# Members only").unwrap();
+  let description = |identity: access::Identity| {
+    let folder = folder.clone();
+    async move {
+      let response = prefer::Detail::Standard.listing(Vec::new(), &folder, &identity).await;
+      let body = to_bytes(response.into_body()).await.unwrap();
+      serde_json::from_slice::<serde_json::Value>(&body).unwrap()["description"].clone()
+    }
+  };
+  assert!(description(access::Identity::default()).await.is_null());
+  let local = access::Identity { local: true, ..access::Identity::default() };
+  assert_eq!(description(local).await["text"], "# Members only");
+}
+
+#[actix_web::test]
+async fn invalid_sidecar_is_a_warning() {
+  let path = common::temp_dir().join("described.bin");
+  std::fs::write(&path, b"not a video").unwrap();
+  std::fs::write(sidecar::sidecar_path(&path).unwrap(), b"{ not json").unwrap();
+  let (metadata, warnings) = file::FileMetadata::from_path(&path);
+  let response = envelope::ok(metadata, warnings);
+  assert_eq!(response.status(), StatusCode::OK);
+  let body = to_bytes(response.into_body()).await.unwrap();
+  let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+  assert_eq!(body["data"]["duration_ms"], 0);
+  assert_eq!(body["warnings"][0]["code"], "sidecar_invalid");
+}
+
+#[actix_web::test]
+async fn markers_export_as_chapters() {
+  let path = common::temp_dir().join("recording.bin");
+  std::fs::write(&path, b"not a video").unwrap();
+  let add = |marker: serde_json::Value| sidecar::add_marker(&path, serde_json::from_value(marker).unwrap());
+  let goal = add(serde_json::json!({"name": "Goal=1", "start_ms": 5000})).unwrap();
+  add(serde_json::json!({"name": "Intro", "start_ms": 0, "end_ms": 2000})).unwrap();
+  assert!(add(serde_json::json!({"name": "Backwards", "start_ms": 10, "end_ms": 5})).is_err());
+
+  let chapters = sidecar::ffmetadata(&sidecar::load(&path), 9000);
+  assert!(chapters.starts_with(";FFMETADATA1\n"));
+  assert!(chapters.contains("START=0\nEND=2000\ntitle=Intro\n"));
+  assert!(chapters.contains("START=5000\nEND=9000\ntitle=Goal\\=1\n"));
+
+  sidecar::remove_marker(&path, &goal.id).unwrap();
+  assert_eq!(sidecar::load(&path).markers.len(), 1);
+}
+
+#[actix_web::test]
+async fn artwork_must_be_an_image() {
+  let path = common::temp_dir().join("covered.bin");
+  std::fs::write(&path, b"not a video").unwrap();
+  let err = sidecar::save_artwork(&path, b"not an image").unwrap_err();
+  assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+  assert!(sidecar::artwork(&path).is_none());
+
+  sidecar::save_artwork(&path, b"\x89PNG\r\n\x1a\nrest of the image").unwrap();
+  let artwork = sidecar::artwork(&path).expect("Artwork was not saved");
+  assert!(sidecar::is_sidecar(&artwork));
+}
+
+#[actix_web::test]
+async fn poster_seek_is_stored() {
+  let path = common::temp_dir().join("postered.bin");
+  std::fs::write(&path, b"not a video").unwrap();
+  assert!(sidecar::set_poster(&path, Some(-1.)).is_err());
+  sidecar::set_poster(&path, Some(12.5)).unwrap();
+  assert_eq!(sidecar::load(&path).poster_seek, Some(12.5));
+  sidecar::set_poster(&path, None).unwrap();
+  assert_eq!(sidecar::load(&path).poster_seek, None);
+}
+
+#[actix_web::test]
+async fn anonymous_requests_cannot_write() {
+  common::init_config();
+  let anonymous = access::Identity::extract(&TestRequest::default().to_http_request()).await.unwrap();
+  assert!(anonymous.check("clip.mp4").is_ok());
+  assert_eq!(anonymous.check_writable("clip.mp4"), Err(access::Denied::Forbidden));
+  let local = access::Identity { local: true, ..access::Identity::default() };
+  assert!(local.check_writable("clip.mp4").is_ok());
+}
+
+#[actix_web::test]
+async fn no_token_cannot_delete_or_move() {
+  common::init_config();
+  let req = TestRequest::delete().uri("/api/file/movies/clip.mp4").to_http_request();
+  let anonymous = access::Identity::extract(&req).await.unwrap();
+  assert!(!anonymous.can_write());
+  let deleted = anonymous.check_writable("movies/clip.mp4");
+  assert_eq!(HttpResponse::from(deleted.unwrap_err()).status(), StatusCode::FORBIDDEN);
+  let req = TestRequest::post().uri("/api/move").to_http_request();
+  let anonymous = access::Identity::extract(&req).await.unwrap();
+  let moved = anonymous.check_writable("movies/clip.mp4").and(anonymous.check_writable("archive"));
+  assert_eq!(HttpResponse::from(moved.unwrap_err()).status(), StatusCode::FORBIDDEN);
+}
+
+#[test]
+fn roots_are_never_deleted() {
+  common::init_config();
+  let archive = file::get_media_path("archive");
+  std::fs::write(archive.join("kept.txt"), b"kept").unwrap();
+  for root in ["", "/", "archive", "/archive/"] {
+    assert!(file::is_root(root), "{root}");
+  }
+  assert!(!file::is_root("archive/kept.txt"));
+
+  let local = access::Identity { local: true, ..access::Identity::default() };
+  let err = trash::delete("archive", &local).unwrap_err();
+  assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+  assert!(archive.join("kept.txt").exists());
+}
+
+#[test]
+fn metadata_edits_need_a_writer() {
+  common::init_config();
+  let path = common::temp_dir().join("edited.bin");
+  std::fs::write(&path, b"not a video").unwrap();
+  let relative = file::get_relative_path(&path).unwrap();
+  let user = |roles: &[&str]| access::Identity {
+    user: Some(Box::leak(Box::new(access::User {
+      name: "someone".to_string(),
+      token: "token".to_string(),
+      password: None,
+      roles: roles.iter().map(|role| role.to_string()).collect(),
+    }))),
+    ..access::Identity::default()
+  };
+  assert_eq!(access::Identity::default().check_writable(&relative), Err(access::Denied::Forbidden));
+  assert_eq!(user(&[access::GUEST_ROLE]).check_writable(&relative), Err(access::Denied::Forbidden));
+  assert!(user(&[]).check_writable(&relative).is_ok());
+
+  let patch = serde_json::from_str(r#"{"title": "Edited"}"#).unwrap();
+  sidecar::update(&path, patch).unwrap();
+  assert_eq!(sidecar::load(&path).title.as_deref(), Some("Edited"));
+}
+
+#[test]
+fn guests_cannot_download() {
+  common::init_config();
+  let guest = access::Identity {
+    user: Some(Box::leak(Box::new(access::User {
+      name: "guest".to_string(),
+      token: "guest-token".to_string(),
+      password: None,
+      roles: vec![access::GUEST_ROLE.to_string()],
+    }))),
+    ..access::Identity::default()
+  };
+  assert!(guest.check("clip.mp4").is_ok());
+  assert_eq!(guest.check_downloadable("clip.mp4"), Err(access::Denied::Forbidden));
+  assert_eq!(guest.check_downloadable("../clip.mp4"), Err(access::Denied::NotFound));
+  assert!(access::Identity::default().check_downloadable("clip.mp4").is_ok());
+}
+
+#[test]
+fn sidecars_are_never_served() {
+  common::init_config();
+  let admin = access::Identity { local: true, ..access::Identity::default() };
+  assert!(admin.check("members/README.md").is_ok());
+  for sidecar in ["members/.README.md.fylvur.json", "members/.README.md.fylvur.artwork", "members/.fylvur.json"] {
+    assert_eq!(admin.check(sidecar), Err(access::Denied::NotFound), "{sidecar}");
+  }
+}
+
+#[actix_web::test]
+async fn revoked_sessions_stop_getting_events() {
+  common::init_config();
+  let user = Box::leak(Box::new(access::User {
+    name: "phone owner".to_string(),
+    token: "owner-token".to_string(),
+    password: None,
+    roles: Vec::new(),
+  }));
+  session::create(user, "Lost phone".to_string(), None);
+  let id = session::list(Some("phone owner"), None)[0].id.clone();
+  let identity = access::Identity { user: Some(user), session: Some(id.clone()), local: false };
+  let stream = events::subscribe(vec![events::Watch::new("", true)], identity);
+
+  assert!(session::revoke(&id, None));
+  let body = actix_web::rt::time::timeout(std::time::Duration::from_secs(5), to_bytes(stream.into_body())).await;
+  assert!(body.expect("the stream is still open").unwrap().is_empty());
+}
+
+#[cfg(unix)]
+#[actix_web::test]
+async fn admin_socket_needs_no_token() {
+  use std::io::{Read, Write};
+  use actix_web::{web, App, HttpServer};
+
+  async fn whoami(identity: access::Identity) -> HttpResponse {
+    HttpResponse::Ok().body(if identity.is_admin() {"admin"} else {"anonymous"})
+  }
+  let anonymous = access::Identity::extract(&TestRequest::default().to_http_request()).await.unwrap();
+  assert!(!anonymous.is_admin());
+
+  let path = common::temp_dir().join("admin.sock");
+  let _ = std::fs::remove_file(&path);
+  let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+  let server = HttpServer::new(|| App::new().route("/", web::get().to(whoami)))
+  .workers(1)
+  .on_connect(access::on_connect)
+  .listen_uds(listener)
+  .unwrap()
+  .run();
+  let handle = server.handle();
+  actix_web::rt::spawn(server);
+  let response = actix_web::rt::task::spawn_blocking(move || {
+    let mut stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+  }).await.unwrap();
+  handle.stop(false).await;
+  assert!(response.ends_with("admin"), "{response}");
+}
+
+#[test]
+fn feed_lists_newest_media_first() {
+  common::init_config();
+  let folder = common::temp_dir().join("podcast");
+  std::fs::create_dir_all(&folder).unwrap();
+  for (name, age_secs) in [("old & gold.mp3", 60), ("new.mp4", 0), ("notes.txt", 0)] {
+    let path = folder.join(name);
+    std::fs::write(&path, b"episode").unwrap();
+    let modified = std::time::SystemTime::now() - std::time::Duration::from_secs(age_secs);
+    std::fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+  }
+
+  let rss = feed::rss(&folder, "http://nas:8080", &access::Identity::default()).unwrap();
+  assert_eq!(rss.matches("<item>").count(), 2, "{rss}");
+  let new = rss.find("<title>new</title>").expect("new.mp4 is missing");
+  let old = rss.find("<title>old &amp; gold</title>").expect("old & gold.mp3 is missing");
+  assert!(new < old, "{rss}");
+  assert!(rss.contains(r#"length="7" type="audio/mpeg""#), "{rss}");
+  assert!(rss.contains("<guid isPermaLink=\"false\">podcast/new.mp4</guid>"), "{rss}");
+  assert!(rss.contains("url=\"http://nas:8080/file/podcast/old%20%26%20gold.mp3\""), "{rss}");
+  assert!(feed::rss(&folder.join("new.mp4"), "http://nas:8080", &access::Identity::default()).is_err());
+}
+
+#[test]
+fn sync_manifest_changes_with_its_files() {
+  common::init_config();
+  let folder = common::temp_dir().join("offline");
+  std::fs::create_dir_all(folder.join("notes")).unwrap();
+  std::fs::write(folder.join("clip #1.mp4"), b"video").unwrap();
+  std::fs::write(folder.join("notes/todo.txt"), b"todo").unwrap();
+
+  let manifest = |folder: &std::path::Path| {
+    let manifest = manifest::get(folder, &access::Identity::default()).unwrap();
+    (manifest.version.clone(), serde_json::to_value(&manifest).unwrap())
+  };
+  let (version, json) = manifest(&folder);
+  let files = json["files"].as_array().unwrap();
+  assert_eq!(files.len(), 2, "{json}");
+  assert_eq!(json["folder"], "offline");
+  assert_eq!(files[0]["path"], "offline/clip #1.mp4");
+  assert_eq!(files[0]["size"], 5);
+  assert_eq!(files[0]["thumbnail"], "/api/thumbnail/offline/clip%20%231.mp4");
+  assert_eq!(files[1]["path"], "offline/notes/todo.txt");
+  assert!(files[1].get("thumbnail").is_none(), "{json}");
+  assert_eq!(manifest(&folder).0, version);
+
+  std::fs::write(folder.join("notes/todo.txt"), b"done!").unwrap();
+  assert_ne!(manifest(&folder).0, version);
+  assert!(manifest::get(&folder.join("clip #1.mp4"), &access::Identity::default()).is_err());
+}
+
+#[test]
+fn mounts_without_prewarm_skip_thumbnails() {
+  common::init_config();
+  for mount in ["archive", "inbox"] {
+    let path = file::get_media_path(mount).join("clip.mp4");
+    std::fs::write(&path, b"video").unwrap();
+    let modified = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+    std::fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+    assert!(file::is_stable(&path));
+  }
+
+  cache::prewarm(["archive/clip.mp4".to_string(), "inbox/clip.mp4".to_string()]);
+  assert_eq!(cache::prewarm_queued(), 0);
+  let href = |path: &str| {
+    let info = file::FileInfo::from_path(&file::get_media_path(path)).unwrap();
+    serde_json::to_value(&info).unwrap()["api_href"].clone()
+  };
+  assert_eq!(href("archive/clip.mp4"), "/file/archive/clip.mp4");
+  assert_eq!(href("inbox/clip.mp4"), "/api/thumbnail/inbox/clip.mp4");
+}
+
+#[test]
+fn dash_manifest_only_lists_audio_it_has() {
+  let with_audio = dash::manifest("show.mkv", 12_000, true);
+  assert!(with_audio.contains(r#"profiles="urn:mpeg:dash:profile:isoff-live:2011""#), "{with_audio}");
+  assert!(with_audio.contains(r#"codecs="avc1.640028,mp4a.40.2""#), "{with_audio}");
+  let silent = dash::manifest("silent.mkv", 12_000, false);
+  assert!(silent.contains(r#"codecs="avc1.640028""#), "{silent}");
+}
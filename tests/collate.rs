@@ -0,0 +1,25 @@
+mod common;
+
+use std::cmp::Ordering;
+
+use fylvur::collate;
+
+#[test]
+fn accents_and_case_sort_with_their_letter() {
+  common::init_config();
+  let mut names = vec!["Zebra", "été", "apple", "Éclair", "banana"];
+  names.sort_by(|a, b| collate::compare(a, b));
+  assert_eq!(names, ["apple", "banana", "Éclair", "été", "Zebra"]);
+}
+
+#[test]
+fn numbers_sort_by_value() {
+  common::init_config();
+  assert_eq!(collate::compare("Episode 2.mkv", "Episode 10.mkv"), Ordering::Less);
+}
+
+#[test]
+fn bidi_marks_are_ignored() {
+  common::init_config();
+  assert_eq!(collate::compare("\u{200F}שלום", "שלום"), Ordering::Equal);
+}
@@ -0,0 +1,274 @@
+//! Fixture media generated with the `ffmpeg` command line tool.
+//! Tests needing a fixture are skipped when `ffmpeg` isn't available.
+
+#![allow(dead_code)]
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Once;
+
+static INIT: Once = Once::new();
+static CONFIG: Once = Once::new();
+
+pub fn setup() {
+  init_config();
+  INIT.call_once(|| fylvur::video::init().expect("Could not initialize video API"));
+}
+
+/// Folder for this test run's files, removed by the OS along with the rest of the temp dir
+pub fn temp_dir() -> PathBuf {
+  let dir = std::env::temp_dir().join(format!("fylvur-tests-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).expect("Could not create temp dir");
+  dir
+}
+
+/// Config with `temp_dir` as the media folder so fixtures have real paths, and the index,
+/// caches and logs in a folder next to it. `members/README.md` needs the "member" role,
+/// `archive` is a mount without thumbnails and `inbox` one that only makes them on demand.
+/// Has to be called before anything reads the config, which otherwise looks for ./fylvur-cfg.toml
+pub fn init_config() {
+  CONFIG.call_once(|| {
+    let media = temp_dir();
+    let data = media.with_extension("data");
+    let archive = media.with_extension("archive");
+    let inbox = media.with_extension("inbox");
+    for mount in [&archive, &inbox] {
+      std::fs::create_dir_all(mount).expect("Could not create mount");
+    }
+    let config = toml::from_str(&format!(
+      "public_folder = {public:?}\nmedia_folder = {media:?}\nhost = \"127.0.0.1\"\nport = 0\n\
+      data_folder = {data:?}\naudit_log = {audit:?}\n\
+      [[access]]\npath = \"members/README.md\"\nrole = \"member\"\n\
+      [[mounts]]\nname = \"archive\"\npath = {archive:?}\nthumbnails = \"off\"\n\
+      [[mounts]]\nname = \"inbox\"\npath = {inbox:?}\nthumbnails = \"on_demand\"\n",
+      public = media.join("public"),
+      data = data,
+      audit = data.join("audit.log"),
+    ))
+    .expect("Invalid test config");
+    fylvur::config::init(config).expect("Could not set the test config");
+  });
+}
+
+/// Runs `ffmpeg` with `args` writing to `name` in the temp dir, `None` if ffmpeg
+/// is missing or can't produce this fixture (e.g. too old for some option)
+pub fn ffmpeg(name: &str, args: &[&str]) -> Option<PathBuf> {
+  setup();
+  let output = temp_dir().join(name);
+  if output.exists() {
+    return Some(output)
+  }
+  let status = Command::new("ffmpeg")
+  .args(["-hide_banner", "-loglevel", "error", "-y"])
+  .args(args)
+  .arg(&output)
+  .status();
+  match status {
+    Ok(status) if status.success() => Some(output),
+    _ => {
+      eprintln!("Skipping, could not generate {name} with ffmpeg");
+      None
+    }
+  }
+}
+
+/// 2 seconds of solid red 64x36 video
+pub fn solid_red() -> Option<PathBuf> {
+  ffmpeg("red.mkv", &[
+    "-f", "lavfi", "-i", "color=c=red:s=64x36:d=2:r=10",
+    "-c:v", "mpeg4", "-q:v", "2",
+  ])
+}
+
+/// 64x36 clip with a display matrix rotating it 90 degrees, like phone recordings
+pub fn rotated() -> Option<PathBuf> {
+  ffmpeg("rotated.mp4", &[
+    "-display_rotation", "90",
+    "-f", "lavfi", "-i", "testsrc=s=64x36:d=2:r=10",
+    "-c:v", "mpeg4",
+  ])
+}
+
+/// 10 bit BT.2020 PQ video
+pub fn hdr() -> Option<PathBuf> {
+  ffmpeg("hdr.mkv", &[
+    "-f", "lavfi", "-i", "testsrc=s=64x36:d=2:r=10",
+    "-pix_fmt", "yuv420p10le",
+    "-color_primaries", "bt2020", "-color_trc", "smpte2084", "-colorspace", "bt2020nc",
+    "-c:v", "ffv1",
+  ])
+}
+
+/// Two video streams, an audio stream and a text subtitle containing "hello there"
+pub fn multi_stream() -> Option<PathBuf> {
+  let subtitle = temp_dir().join("multi.srt");
+  std::fs::write(&subtitle, "1\n00:00:00,500 --> 00:00:01,500\nhello there\n").ok()?;
+  ffmpeg("multi.mkv", &[
+    "-f", "lavfi", "-i", "testsrc=s=64x36:d=2:r=10",
+    "-f", "lavfi", "-i", "color=c=blue:s=32x18:d=2:r=10",
+    "-f", "lavfi", "-i", "sine=d=2",
+    "-i", subtitle.to_str()?,
+    "-map", "0", "-map", "1", "-map", "2", "-map", "3",
+    "-c:v", "mpeg4", "-c:a", "pcm_s16le", "-c:s", "srt",
+  ])
+}
+
+/// Single 64x36 image of solid `color`, the extension of `name` picks the format.
+/// AVIF needs an ffmpeg built with libaom
+pub fn image(name: &str, color: &str) -> Option<PathBuf> {
+  let source = format!("color=c={color}:s=64x36");
+  let mut args = vec!["-f", "lavfi", "-i", &source, "-frames:v", "1"];
+  if name.ends_with(".avif") {
+    args.extend(["-c:v", "libaom-av1", "-still-picture", "1"]);
+  }
+  ffmpeg(name, &args)
+}
+
+/// 64x36 JPEG photo whose EXIF says it has to be turned 90 degrees clockwise to be upright
+pub fn sideways_jpeg() -> Option<PathBuf> {
+  let source = image("red.jpg", "red")?;
+  let bytes = std::fs::read(source).ok()?;
+  // APP1 segment with a big endian TIFF header and a single IFD entry, Orientation (0x0112) = 6
+  let mut exif: Vec<u8> = b"Exif\0\0MM\0\x2a\0\0\0\x08".to_vec();
+  exif.extend_from_slice(&[0, 1, 0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0, 0, 0, 0, 0]);
+  let mut jpeg = bytes[..2].to_vec();
+  jpeg.extend_from_slice(&[0xFF, 0xE1]);
+  jpeg.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+  jpeg.extend_from_slice(&exif);
+  jpeg.extend_from_slice(&bytes[2..]);
+  let path = temp_dir().join("sideways.jpg");
+  std::fs::write(&path, jpeg).ok()?;
+  Some(path)
+}
+
+/// Camera RAW file with a 64x36 red JPEG preview, a little endian TIFF whose only IFD points to it
+pub fn raw_with_preview() -> Option<PathBuf> {
+  let source = image("red.jpg", "red")?;
+  let preview = std::fs::read(source).ok()?;
+  let preview_offset = 8 + 2 + 2 * 12 + 4u32;
+  let mut raw: Vec<u8> = b"II\x2a\0\x08\0\0\0".to_vec();
+  raw.extend_from_slice(&2u16.to_le_bytes());
+  for (tag, value) in [(0x0201u16, preview_offset), (0x0202, preview.len() as u32)] {
+    raw.extend_from_slice(&tag.to_le_bytes());
+    raw.extend_from_slice(&4u16.to_le_bytes());
+    raw.extend_from_slice(&1u32.to_le_bytes());
+    raw.extend_from_slice(&value.to_le_bytes());
+  }
+  raw.extend_from_slice(&0u32.to_le_bytes());
+  raw.extend_from_slice(&preview);
+  let path = temp_dir().join("red.nef");
+  std::fs::write(&path, raw).ok()?;
+  Some(path)
+}
+
+/// Second of FLAC audio with the red PNG embedded as its cover, tagged like a ripped CD track
+pub fn audio_with_art() -> Option<PathBuf> {
+  let cover = image("red.png", "red")?;
+  ffmpeg("album.flac", &[
+    "-f", "lavfi", "-i", "sine=d=1",
+    "-i", cover.to_str()?,
+    "-map", "0", "-map", "1", "-c:v", "copy", "-disposition:v", "attached_pic",
+    "-metadata", "ARTIST=The Sines", "-metadata", "ALBUM=Test Tones",
+    "-metadata", "TITLE=A440", "-metadata", "TRACKNUMBER=3/12", "-metadata", "DATE=2001-05-14",
+  ])
+}
+
+/// Comic book whose pages are named so only natural order puts the red one first, with the
+/// blue page also behind a macOS metadata entry that sorts before both
+pub fn comic_book() -> Option<PathBuf> {
+  let blue = image("blue.png", "blue")?;
+  let red = image("red.png", "red")?;
+  let path = temp_dir().join("comic.cbz");
+  write_zip(&path, &[
+    ("__MACOSX/page1.png", std::fs::read(&blue).ok()?),
+    ("page10.png", std::fs::read(&blue).ok()?),
+    ("page2.png", std::fs::read(&red).ok()?),
+  ])?;
+  Some(path)
+}
+
+/// EPUB 2 book whose package document names a red cover with `<meta name="cover">`, the
+/// blue picture in the book comes first
+pub fn ebook() -> Option<PathBuf> {
+  let blue = image("blue.png", "blue")?;
+  let red = image("red.png", "red")?;
+  let container = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>"#;
+  let package = r#"<?xml version="1.0"?>
+<package version="2.0" xmlns="http://www.idpf.org/2007/opf">
+  <metadata><meta name="cover" content="cover-image"/></metadata>
+  <manifest>
+    <item id="figure" href="images/a.png" media-type="image/png"/>
+    <item id="cover-image" href="../covers/cover.png" media-type="image/png"/>
+  </manifest>
+</package>"#;
+  let path = temp_dir().join("book.epub");
+  write_zip(&path, &[
+    ("mimetype", b"application/epub+zip".to_vec()),
+    ("META-INF/container.xml", container.as_bytes().to_vec()),
+    ("OEBPS/content.opf", package.as_bytes().to_vec()),
+    ("OEBPS/images/a.png", std::fs::read(&blue).ok()?),
+    ("covers/cover.png", std::fs::read(&red).ok()?),
+  ])?;
+  Some(path)
+}
+
+fn write_zip(path: &Path, entries: &[(&str, Vec<u8>)]) -> Option<()> {
+  let mut zip = zip::ZipWriter::new(std::fs::File::create(path).ok()?);
+  for (name, bytes) in entries {
+    zip.start_file(*name, zip::write::FileOptions::default()).ok()?;
+    zip.write_all(bytes).ok()?;
+  }
+  zip.finish().ok()?;
+  Some(())
+}
+
+/// Random bytes with a video extension
+pub fn garbage() -> PathBuf {
+  let path = temp_dir().join("garbage.mp4");
+  let bytes: Vec<u8> = (0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+  std::fs::write(&path, bytes).expect("Could not write garbage fixture");
+  path
+}
+
+/// First half of a valid file
+pub fn truncated(source: &Path) -> PathBuf {
+  let path = temp_dir().join("truncated.mkv");
+  let bytes = std::fs::read(source).expect("Could not read fixture");
+  std::fs::write(&path, &bytes[..bytes.len() / 2]).expect("Could not write truncated fixture");
+  path
+}
+
+/// Image thumbnail of `path` asked for `width` pixels wide, checked to be `width`x`height`.
+/// Returns its average RGB
+pub fn assert_thumbnail(path: &Path, width: u32, height: u32) -> [u8; 3] {
+  let thumbnail = fylvur::video::get_image_thumbnail(path, width, None, None, fylvur::video::WEBP_QUALITY)
+  .expect("Could not get thumbnail");
+  let (actual_width, actual_height, color) = decode_webp(&thumbnail);
+  assert_eq!((actual_width, actual_height), (width, height), "thumbnail of {path:?}");
+  color
+}
+
+/// Whether an average RGB is the red of the red fixtures, give or take encoding
+pub fn is_red([r, g, b]: [u8; 3]) -> bool {
+  r > 200 && g < 60 && b < 60
+}
+
+/// Decodes a webp image into `(width, height, average RGB)`
+pub fn decode_webp(bytes: &[u8]) -> (u32, u32, [u8; 3]) {
+  let image = webp::Decoder::new(bytes).decode().expect("Invalid webp");
+  let pixels = (image.width() * image.height()) as usize;
+  // RGB or RGBA, the decoded layout isn't exposed
+  let channels = image.len() / pixels.max(1);
+  let mut sum = [0usize; 3];
+  for pixel in image.chunks(channels) {
+    for (total, value) in sum.iter_mut().zip(pixel) {
+      *total += *value as usize;
+    }
+  }
+  let average = sum.map(|total| (total / pixels.max(1)) as u8);
+  (image.width(), image.height(), average)
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use fylvur::config::{Mount, ThumbnailPolicy};
+use fylvur::events::{Event, EventKind};
+use fylvur::{collisions, library, metadata};
+use rusqlite::Connection;
+
+fn columns(connection: &Connection, table: &str) -> Vec<String> {
+  connection
+  .prepare(&format!("SELECT name FROM pragma_table_info('{table}')"))
+  .unwrap()
+  .query_map([], |row| row.get(0))
+  .unwrap()
+  .collect::<rusqlite::Result<_>>()
+  .unwrap()
+}
+
+fn user_version(connection: &Connection) -> usize {
+  connection.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap()
+}
+
+#[test]
+fn index_from_before_migrations_is_upgraded() {
+  let mut connection = Connection::open(common::temp_dir().join("legacy.sqlite")).unwrap();
+  connection.execute_batch("
+    CREATE TABLE files (path TEXT PRIMARY KEY, kind TEXT NOT NULL, size INTEGER NOT NULL, mtime INTEGER NOT NULL, taken_at INTEGER);
+    INSERT INTO files VALUES ('a.mp4', 'video', 1, 100, NULL);
+  ").unwrap();
+
+  library::migrate(&mut connection).unwrap();
+  let version = user_version(&connection);
+  assert!(version > 0);
+  assert!(columns(&connection, "files").iter().any(|column| column == "duration_ms"));
+  assert!(!columns(&connection, "tags").is_empty());
+  // Probed again to fill in the new columns
+  let mtime: i64 = connection.query_row("SELECT mtime FROM files", [], |row| row.get(0)).unwrap();
+  assert_eq!(mtime, -1);
+
+  library::migrate(&mut connection).unwrap();
+  assert_eq!(user_version(&connection), version);
+}
+
+#[test]
+fn index_from_newer_version_is_left_alone() {
+  let mut connection = Connection::open(common::temp_dir().join("newer.sqlite")).unwrap();
+  connection.execute_batch("PRAGMA user_version = 9999").unwrap();
+  assert!(library::migrate(&mut connection).is_err());
+  assert_eq!(user_version(&connection), 9999);
+  assert!(columns(&connection, "files").is_empty());
+}
+
+#[test]
+fn changes_pick_up_after_cursor() {
+  let mut connection = Connection::open(common::temp_dir().join("changes.sqlite")).unwrap();
+  library::migrate(&mut connection).unwrap();
+  let change = |kind, path: &str| Event { kind, path: path.into() };
+  connection.execute_batch("INSERT INTO files (path, kind, size, mtime) VALUES ('a.mp4', 'video', 1, 100)").unwrap();
+  library::record_changes(&connection, &[change(EventKind::Added, "a.mp4"), change(EventKind::Added, "b.mp4")]).unwrap();
+
+  // Without a cursor the whole index is sent
+  let first = library::changes_since(&connection, 0, 10).unwrap();
+  assert!(first.reset && !first.more);
+  assert_eq!(first.changes.iter().map(|change| change.path.as_str()).collect::<Vec<_>>(), ["a.mp4"]);
+
+  connection.execute_batch("UPDATE files SET size = 2 WHERE path = 'a.mp4'").unwrap();
+  library::record_changes(&connection, &[
+    change(EventKind::Updated, "a.mp4"),
+    change(EventKind::Removed, "b.mp4"),
+    change(EventKind::Added, "c.mp4"),
+  ]).unwrap();
+  let page = library::changes_since(&connection, first.cursor, 2).unwrap();
+  assert!(!page.reset && page.more);
+  let changes: Vec<_> = page.changes.iter().map(|change| (change.path.as_str(), change.change.as_str(), change.size)).collect();
+  assert_eq!(changes, [("a.mp4", "updated", Some(2)), ("b.mp4", "removed", None)]);
+  let rest = library::changes_since(&connection, page.cursor, 2).unwrap();
+  assert!(!rest.more);
+  assert_eq!(rest.changes.len(), 1);
+  assert_eq!(library::changes_since(&connection, rest.cursor, 2).unwrap().changes.len(), 0);
+  // Cursors from another index start over
+  assert!(library::changes_since(&connection, rest.cursor + 100, 2).unwrap().reset);
+}
+
+#[test]
+fn case_and_normalization_collisions_are_reported() {
+  let mut connection = Connection::open(common::temp_dir().join("collisions.sqlite")).unwrap();
+  library::migrate(&mut connection).unwrap();
+  // "Café" written precomposed and with a combining accent
+  connection.execute_batch("
+    INSERT INTO files (path, kind, size, mtime) VALUES
+      ('Photos/a.jpg', 'image', 1, 100),
+      ('photos/b.jpg', 'image', 1, 100),
+      ('Caf\u{e9}.txt', 'file', 1, 100),
+      ('Cafe\u{301}.txt', 'file', 1, 100),
+      ('notes/Todo.md', 'file', 1, 100),
+      ('notes/todo.md', 'file', 1, 100),
+      ('unique.txt', 'file', 1, 100);
+  ").unwrap();
+
+  let collisions = collisions::report_in(&connection).unwrap();
+  let found: Vec<(&str, Vec<&str>)> = collisions
+  .iter()
+  .map(|collision| (collision.href.as_str(), collision.paths.iter().map(String::as_str).collect()))
+  .collect();
+  assert_eq!(found, [
+    ("caf\u{e9}.txt", vec!["Cafe\u{301}.txt", "Caf\u{e9}.txt"]),
+    ("notes/todo.md", vec!["notes/Todo.md", "notes/todo.md"]),
+    // Reported once for the folder, not again for each file in it
+    ("photos", vec!["Photos", "photos"]),
+  ]);
+}
+
+#[test]
+fn mounts_have_their_own_policies() {
+  let archive: Mount = toml::from_str(r#"
+    name = "archive"
+    path = "/mnt/archive"
+    read_only = true
+    scan_interval_secs = 86400
+    thumbnails = "off"
+  "#).unwrap();
+  assert_eq!((archive.scan_interval_secs, archive.thumbnails), (Some(86400), ThumbnailPolicy::Off));
+
+  let movies: Mount = toml::from_str("name = \"movies\"\npath = \"/mnt/movies\"").unwrap();
+  assert_eq!((movies.scan_interval_secs, movies.thumbnails), (None, ThumbnailPolicy::Prewarm));
+  assert!(toml::from_str::<Mount>("name = \"a\"\npath = \"/a\"\nthumbnails = \"never\"").is_err());
+  // Nested mounts go with the strictest
+  assert!(ThumbnailPolicy::Prewarm < ThumbnailPolicy::OnDemand && ThumbnailPolicy::OnDemand < ThumbnailPolicy::Off);
+}
+
+#[test]
+fn empty_file_chunks_are_rejected() {
+  let path = common::temp_dir().join("zero-chunks.toml");
+  let config = "public_folder = \"public\"\nmedia_folder = \"media\"\nhost = \"127.0.0.1\"\nport = 0\n";
+  std::fs::write(&path, format!("{config}file_chunk_bytes = 0\n")).unwrap();
+  assert_eq!(fylvur::config::load(&path).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+  std::fs::write(&path, format!("{config}file_chunk_bytes = 1\n")).unwrap();
+  assert!(fylvur::config::load(&path).is_ok());
+}
+
+#[test]
+fn capture_times_out_of_range_are_ignored() {
+  assert_eq!(metadata::parse_datetime("2021-03-04T05:06:07.000000Z"), Some(1614834367));
+  assert_eq!(metadata::parse_datetime("2021:03:04 05:06:07"), Some(1614834367));
+  assert_eq!(metadata::parse_datetime("2016-12-31 23:59:60"), Some(1483228800));
+  let crafted = [
+    "9223372036854775807-01-01", "0000-01-01", "2021-13-01", "2021-03-32", "2021-03-04 24:00:00", "2021-03-04 05:60:00",
+  ];
+  for time in crafted {
+    assert_eq!(metadata::parse_datetime(time), None, "{time}");
+  }
+}
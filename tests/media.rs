@@ -0,0 +1,339 @@
+mod common;
+
+use fylvur::{cache, file, intro, math, metadata};
+use fylvur::video::{self, AtlasPreset, ImageFormat, SeekTime, TileLayout};
+
+#[test]
+fn thumbnail_keeps_size_and_color() {
+  let Some(path) = common::solid_red() else { return };
+  let thumbnail = video::get_video_thumbnail(&path, 0, SeekTime::Seconds(1), None, video::WEBP_QUALITY)
+  .expect("Could not get thumbnail");
+  let (width, height, color) = common::decode_webp(&thumbnail);
+  assert_eq!((width, height), (64, 36));
+  assert!(common::is_red(color), "expected red, got {color:?}");
+}
+
+#[test]
+fn thumbnail_scales_to_width() {
+  let Some(path) = common::solid_red() else { return };
+  let thumbnail = video::get_video_thumbnail(&path, 32, SeekTime::Percentage(0.5), None, video::WEBP_QUALITY)
+  .expect("Could not get thumbnail");
+  let (width, height, _) = common::decode_webp(&thumbnail);
+  assert_eq!((width, height), (32, 18));
+}
+
+#[test]
+fn rotated_thumbnail_is_portrait() {
+  let Some(path) = common::rotated() else { return };
+  let thumbnail = video::get_video_thumbnail(&path, 0, SeekTime::Seconds(1), None, video::WEBP_QUALITY)
+  .expect("Could not get thumbnail");
+  let (width, height, _) = common::decode_webp(&thumbnail);
+  assert_eq!((width, height), (36, 64));
+
+  let info = video::get_info(&path).expect("Could not get info");
+  assert_eq!((info.width, info.height), (36, 64));
+}
+
+#[test]
+fn hdr_thumbnail() {
+  let Some(path) = common::hdr() else { return };
+  let thumbnail = video::get_video_thumbnail(&path, 0, SeekTime::Seconds(1), None, video::WEBP_QUALITY)
+  .expect("Could not get thumbnail");
+  let (width, height, _) = common::decode_webp(&thumbnail);
+  assert_eq!((width, height), (64, 36));
+}
+
+#[test]
+fn image_thumbnail_scales_down_only() {
+  let Some(path) = common::image("red.png", "red") else { return };
+  let color = common::assert_thumbnail(&path, 32, 18);
+  assert!(common::is_red(color), "expected red, got {color:?}");
+
+  let thumbnail = video::get_image_thumbnail(&path, 640, None, None, video::WEBP_QUALITY)
+  .expect("Could not get thumbnail");
+  let (width, height, _) = common::decode_webp(&thumbnail);
+  assert_eq!((width, height), (64, 36));
+}
+
+#[test]
+fn replaced_file_gets_a_new_thumbnail() {
+  let Some(red) = common::image("replaced.png", "red") else { return };
+  let Some(blue) = common::image("replacement.png", "blue") else { return };
+  let key = cache::ThumbnailKey::new(&file::get_relative_path(&red).unwrap(), 32, 0., false);
+  let (_, _, color) = common::decode_webp(&cache::thumbnail(&key, None).unwrap());
+  assert!(common::is_red(color), "expected red, got {color:?}");
+
+  std::fs::rename(&blue, &red).unwrap();
+  let modified = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+  std::fs::File::options().write(true).open(&red).unwrap().set_modified(modified).unwrap();
+  let (_, _, color) = common::decode_webp(&cache::thumbnail(&key, None).unwrap());
+  assert!(!common::is_red(color), "still the replaced thumbnail, got {color:?}");
+}
+
+#[test]
+fn image_thumbnail_follows_exif_orientation() {
+  let Some(path) = common::sideways_jpeg() else { return };
+  common::assert_thumbnail(&path, 36, 64);
+}
+
+#[test]
+fn inspect_frame_keeps_video_size() {
+  let Some(path) = common::solid_red() else { return };
+  let (frame, info) = video::get_inspect_frame(&path, SeekTime::Seconds(1), true)
+  .expect("Could not inspect frame");
+  let (width, height, _) = common::decode_webp(&frame);
+  assert_eq!((width, height), (64, 36));
+  assert_eq!((info.width, info.height), (64, 36));
+  assert!(info.seconds.is_some_and(|seconds| seconds >= 0.));
+}
+
+#[test]
+fn avif_thumbnail() {
+  let Some(path) = common::image("red.avif", "red") else { return };
+  assert!(video::is_heif(&path));
+  let color = common::assert_thumbnail(&path, 32, 18);
+  assert!(common::is_red(color), "expected red, got {color:?}");
+}
+
+#[test]
+fn raw_thumbnail_uses_preview() {
+  let Some(path) = common::raw_with_preview() else { return };
+  assert_eq!(fylvur::raw::preview(&path).map(|(offset, _)| offset).ok(), Some(38));
+  let color = common::assert_thumbnail(&path, 32, 18);
+  assert!(common::is_red(color), "expected red, got {color:?}");
+}
+
+#[test]
+fn album_art_thumbnail() {
+  let Some(path) = common::audio_with_art() else { return };
+  assert!(video::has_attached_picture(&path));
+  let color = common::assert_thumbnail(&path, 32, 18);
+  assert!(common::is_red(color), "expected red, got {color:?}");
+}
+
+#[test]
+fn waveform_covers_whole_audio() {
+  let Some(path) = common::audio_with_art() else { return };
+  let (duration_ms, peaks) = video::get_waveform(&path, 100).expect("Could not get waveform");
+  assert!((900..=1100).contains(&duration_ms), "unexpected duration {duration_ms}");
+  assert_eq!(peaks.len(), 100);
+  // The sine is at an eighth of full scale all the way through
+  assert!(peaks[..95].iter().all(|&[low, high]| low < -0.05 && high > 0.05), "{peaks:?}");
+  let png = video::encode_png(&video::draw_waveform(&peaks, 32).unwrap()).unwrap();
+  assert!(png.starts_with(b"\x89PNG"));
+}
+
+#[test]
+fn spectrogram_finds_sine_frequency() {
+  let Some(path) = common::audio_with_art() else { return };
+  let (_, spectra) = video::get_spectrum(&path, 10, 1024, 44100).expect("Could not get spectrum");
+  assert_eq!(spectra.len(), 10);
+  // The 440 Hz sine lands in the 10th bin of 43 Hz wide ones
+  for spectrum in &spectra[..9] {
+    let loudest = (0..spectrum.len()).max_by(|&a, &b| spectrum[a].total_cmp(&spectrum[b])).unwrap();
+    assert!((9..=11).contains(&loudest), "loudest bin {loudest}");
+  }
+  let png = video::encode_png(&video::draw_spectrogram(&spectra, 1024, 64).unwrap()).unwrap();
+  assert!(png.starts_with(b"\x89PNG"));
+}
+
+#[test]
+fn music_tags_of_audio() {
+  let Some(path) = common::audio_with_art() else { return };
+  let music = metadata::music_tags(&path).expect("Could not read music tags");
+  assert_eq!(music.artist.as_deref(), Some("The Sines"));
+  assert_eq!(music.album.as_deref(), Some("Test Tones"));
+  assert_eq!(music.title.as_deref(), Some("A440"));
+  assert_eq!((music.track, music.year, music.genre), (Some(3), Some(2001), None));
+}
+
+#[test]
+fn book_thumbnail_uses_cover() {
+  for path in [common::comic_book(), common::ebook()] {
+    let Some(path) = path else { return };
+    let color = common::assert_thumbnail(&path, 32, 18);
+    assert!(common::is_red(color), "expected red cover of {path:?}, got {color:?}");
+  }
+}
+
+#[test]
+fn multi_stream_uses_best_video_stream() {
+  let Some(path) = common::multi_stream() else { return };
+  let thumbnail = video::get_video_thumbnail(&path, 0, SeekTime::Seconds(1), None, video::WEBP_QUALITY)
+  .expect("Could not get thumbnail");
+  let (width, height, _) = common::decode_webp(&thumbnail);
+  assert_eq!((width, height), (64, 36));
+}
+
+#[test]
+fn multi_stream_subtitle_search() {
+  let Some(path) = common::multi_stream() else { return };
+  let cues = video::search_subtitles(&path, "HELLO").expect("Could not search subtitles");
+  assert_eq!(cues.len(), 1);
+  let cue = serde_json::to_value(&cues[0]).unwrap();
+  assert_eq!(cue["start_ms"], 500);
+  assert!(cue["text"].as_str().unwrap().contains("hello there"));
+}
+
+#[test]
+fn multi_stream_subtitle_tracks() {
+  let Some(path) = common::multi_stream() else { return };
+  let tracks = video::get_subtitle_tracks(&path).expect("Could not list subtitles");
+  assert_eq!(tracks.len(), 1);
+  let track = serde_json::to_value(&tracks[0]).unwrap();
+  assert_eq!(track["index"], 3);
+  assert_eq!(track["codec"], "subrip");
+  assert_eq!(track["bitmap"], false);
+}
+
+#[test]
+fn atlas_has_a_tile_per_second() {
+  let Some(path) = common::solid_red() else { return };
+  let atlas = video::get_video_atlas(&path, 0, 1, TileLayout::RowMajor, ImageFormat::Webp, false, AtlasPreset::Balanced, None)
+  .expect("Could not get atlas");
+  let (width, height, _) = common::decode_webp(&atlas);
+  assert_eq!(height, 45);
+  assert_eq!(width % 80, 0);
+}
+
+#[test]
+fn atlas_burns_timestamps() {
+  let Some(path) = common::solid_red() else { return };
+  let atlas = |burn| {
+    let atlas = video::get_video_atlas(&path, 0, 1, TileLayout::RowMajor, ImageFormat::Webp, burn, AtlasPreset::Balanced, None)
+    .expect("Could not get atlas");
+    common::decode_webp(&atlas).2
+  };
+  assert_ne!(atlas(true), atlas(false));
+}
+
+#[test]
+fn atlas_presets_keep_tile_size() {
+  let Some(path) = common::solid_red() else { return };
+  for preset in [AtlasPreset::Fast, AtlasPreset::Quality] {
+    let atlas = video::get_video_atlas(&path, 0, 1, TileLayout::RowMajor, ImageFormat::Webp, false, preset, None)
+    .expect("Could not get atlas");
+    let (width, height, [r, _, _]) = common::decode_webp(&atlas);
+    assert_eq!(height, 45);
+    assert_eq!(width % 80, 0);
+    assert!(r > 200, "expected red with {preset:?}");
+  }
+}
+
+#[test]
+fn atlas_as_jpeg() {
+  let Some(path) = common::solid_red() else { return };
+  let format = ImageFormat::parse(Some("jpeg"), Some(60)).unwrap();
+  let atlas = video::get_video_atlas(&path, 0, 1, TileLayout::RowMajor, format, false, AtlasPreset::Balanced, None)
+  .expect("Could not get atlas");
+  assert_eq!(&atlas[..3], &[0xFF, 0xD8, 0xFF]);
+  assert_eq!(ImageFormat::parse(Some("jpeg"), Some(0)), None);
+  assert_eq!(ImageFormat::parse(Some("gif"), None), None);
+}
+
+#[test]
+fn storyboard_tiles_follow_layout() {
+  let tiles = video::atlas_layout(150_500, TileLayout::ColumnMajor);
+  assert_eq!(tiles.len(), 151);
+  assert_eq!((tiles[1].page, tiles[1].x, tiles[1].y), (0, 0, 45));
+  assert_eq!((tiles[10].x, tiles[10].y), (80, 0));
+  let last = tiles.last().unwrap();
+  assert_eq!((last.page, last.seconds), (1, 150));
+}
+
+#[test]
+fn sprites_layout_in_columns() {
+  let Some(path) = common::solid_red() else { return };
+  let sprites = video::get_video_sprites(&path, 0, Some(1), 1, 1, None)
+  .expect("Could not get sprites");
+  let (width, height, _) = common::decode_webp(&sprites);
+  assert_eq!((width, height), (80, 90));
+}
+
+#[test]
+fn still_video_has_no_motion() {
+  let Some(path) = common::solid_red() else { return };
+  let scores = video::get_motion_scores(&path, 10).expect("Could not score motion");
+  assert!(!scores.is_empty());
+  assert!(scores.iter().all(|&(_, score)| score < 0.01), "got {scores:?}");
+}
+
+#[test]
+fn duration() {
+  let Some(path) = common::solid_red() else { return };
+  let duration = video::get_duration_from_path(&path).expect("Could not get duration");
+  assert!((1900..=2100).contains(&duration), "got {duration}ms");
+}
+
+#[test]
+fn garbage_is_an_error() {
+  common::setup();
+  let path = common::garbage();
+  assert!(video::get_video_thumbnail(&path, 0, SeekTime::Seconds(0), None, video::WEBP_QUALITY).is_err());
+  assert!(video::get_duration_from_path(&path).is_err());
+}
+
+#[test]
+fn truncated_file_does_not_panic() {
+  let Some(path) = common::solid_red() else { return };
+  let path = common::truncated(&path);
+  let _ = video::get_video_thumbnail(&path, 0, SeekTime::Percentage(0.9), None, video::WEBP_QUALITY);
+  let _ = video::get_video_atlas(&path, 0, 1, TileLayout::RowMajor, ImageFormat::Webp, false, AtlasPreset::Balanced, None);
+}
+
+#[test]
+fn missing_file_is_an_error() {
+  common::setup();
+  let path = common::temp_dir().join("missing.mp4");
+  assert!(video::get_video_thumbnail(&path, 0, SeekTime::Seconds(0), None, video::WEBP_QUALITY).is_err());
+}
+
+#[test]
+fn spectrum_peaks_at_the_tone() {
+  let len = 1024;
+  let tone: Vec<f32> = (0..len)
+  .map(|i| (std::f32::consts::TAU * 64. * i as f32 / len as f32).sin())
+  .collect();
+  let spectrum = math::power_spectrum(&tone);
+  assert_eq!(spectrum.len(), len / 2);
+  let peak = (0..spectrum.len()).max_by(|&a, &b| spectrum[a].total_cmp(&spectrum[b])).unwrap();
+  assert_eq!(peak, 64);
+  // The window only spreads it to the neighboring bins
+  assert!(spectrum[63] > spectrum[peak] / 8. && spectrum[65] > spectrum[peak] / 8.);
+  assert!(spectrum[100] < spectrum[peak] * 1e-6, "{}", spectrum[100]);
+
+  let constant = math::power_spectrum(&vec![0.5; len]);
+  assert!(constant[0] > constant[1] && constant[2] < constant[0] * 1e-6);
+}
+
+#[test]
+fn intro_is_found_where_the_audio_repeats() {
+  // Deterministic noise, different for each seed
+  let noise = |seed: u32, frames: usize| {
+    let mut state = seed;
+    (0..frames * intro::FRAME_LEN)
+    .map(|_| {
+      state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+      (state >> 8) as f32 / (1 << 24) as f32 - 0.5
+    })
+    .collect::<Vec<f32>>()
+  };
+  let theme = noise(7, 240);
+  let a = [noise(1, 100), theme.clone(), noise(2, 50)].concat();
+  let b = [noise(3, 30), theme, noise(4, 60)].concat();
+
+  let (first, len) = intro::longest_match(&intro::hash_frames(&a), &intro::hash_frames(&b)).unwrap();
+  // The first frame of the theme is compared against the noise before it
+  assert!((100..=101).contains(&first), "{first}");
+  assert!((239..=240).contains(&len), "{len}");
+
+  let silence = vec![0.; 200 * intro::FRAME_LEN];
+  let hashes = intro::hash_frames(&silence);
+  assert!(hashes.iter().all(Option::is_none));
+  assert!(intro::longest_match(&hashes, &hashes).is_none());
+  // Too short to be an intro
+  let jingle = noise(8, 20);
+  let a = [noise(5, 40), jingle.clone()].concat();
+  let b = [jingle, noise(6, 40)].concat();
+  assert!(intro::longest_match(&intro::hash_frames(&a), &intro::hash_frames(&b)).is_none());
+}
@@ -0,0 +1,51 @@
+use fylvur::metadata::MediaKind;
+use fylvur::tags::{Facts, TagRule};
+
+fn rule(path: Option<&str>, when: &[&str]) -> TagRule {
+  TagRule {
+    tag: "tag".into(),
+    path: path.map(String::from),
+    when: when.iter().map(|condition| condition.to_string()).collect(),
+  }
+}
+
+fn video(path: &str, height: u32) -> Facts<'_> {
+  Facts {
+    path,
+    kind: MediaKind::Video,
+    size: 1 << 30,
+    width: Some(height * 16 / 9),
+    height: Some(height),
+    duration_ms: Some(90_000),
+  }
+}
+
+#[test]
+fn conditions_all_have_to_hold() {
+  let uhd = rule(None, &["kind=video", "height>=2160"]);
+  assert!(uhd.matches(&video("movies/a.mkv", 2160)));
+  assert!(!uhd.matches(&video("movies/b.mkv", 1080)));
+  assert!(rule(None, &["duration < 120", "extension != MP4"]).matches(&video("c.mkv", 720)));
+}
+
+#[test]
+fn missing_fields_never_match() {
+  let song = Facts { path: "song.flac", kind: MediaKind::Audio, size: 1, width: None, height: None, duration_ms: None };
+  assert!(!rule(None, &["height<100"]).matches(&song));
+  assert!(!rule(None, &["height!=100"]).matches(&song));
+}
+
+#[test]
+fn path_is_a_pattern() {
+  let recordings = rule(Some("recordings/**"), &[]);
+  assert!(recordings.matches(&video("recordings/2024/screen.mp4", 1080)));
+  assert!(!recordings.matches(&video("movies/screen.mp4", 1080)));
+}
+
+#[test]
+fn invalid_conditions_are_caught() {
+  assert!(rule(None, &["height>=2160", "kind=image"]).check().is_ok());
+  for condition in ["height", "depth>2", "kind>video", "width>=wide"] {
+    assert!(rule(None, &[condition]).check().is_err(), "{condition:?} should be invalid");
+  }
+}